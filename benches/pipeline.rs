@@ -0,0 +1,128 @@
+#[macro_use]
+extern crate criterion;
+extern crate ls_service;
+extern crate lsp_rs;
+#[macro_use]
+extern crate serde_json;
+
+use criterion::{
+    Criterion,
+    Throughput
+};
+use ls_service::bench::{
+    self,
+    BenchClient
+};
+use ls_service::method::{
+    DidChangeConfigurationNotification
+};
+use ls_service::router::{
+    Router
+};
+use lsp_rs::{
+    ClientNotification,
+    PublishDiagnosticsParams,
+    Url
+};
+use serde_json::{
+    Value
+};
+
+/// Requests sent per iteration of the request benchmark
+const REQUESTS : usize = 100;
+/// Size of the document opened by the large message benchmarks
+const LARGE_DOCUMENT : usize = 1024 * 1024;
+/// Files the handler publishes diagnostics for per iteration of the fan-out benchmark
+const DIAGNOSTIC_FILES : usize = 100;
+
+fn hover( id : usize ) -> Value {
+    json!( { "jsonrpc" : "2.0", "id" : id, "method" : "textDocument/hover", "params" : {
+        "textDocument" : { "uri" : "file:///bench/main.rs" },
+        "position"     : { "line" : 0, "character" : 0 }
+    } } )
+}
+
+fn did_open( size : usize ) -> Value {
+    let text : String = ( 0..size / 16 ).map( | _ | "fn main( ) { }\n\n" ).collect( );
+
+    json!( { "jsonrpc" : "2.0", "method" : "textDocument/didOpen", "params" : {
+        "textDocument" : {
+            "uri"        : "file:///bench/large.rs",
+            "languageId" : "rust",
+            "version"    : 1,
+            "text"       : text
+        }
+    } } )
+}
+
+/// Requests answered by a router without routes, measuring the decoding, dispatch and encoding of the
+/// service rather than a handler.
+fn requests( c : &mut Criterion ) {
+    let mut client = BenchClient::new( Router::new( ).advertise_capabilities( ) ).unwrap( );
+    client.initialize( ).unwrap( );
+
+    let frames = bench::frames( &( 1..REQUESTS + 1 ).map( hover ).collect::< Vec< _ > >( ) );
+
+    let mut group = c.benchmark_group( "requests" );
+    group.throughput( Throughput::Elements( REQUESTS as u64 ) );
+    group.bench_function( "method_not_found", | b | b.iter( | | client.roundtrip( &frames, REQUESTS ).unwrap( ) ) );
+    group.finish( );
+}
+
+/// Decoding of a large didOpen notification, by the codec alone and through the whole service, waiting for
+/// the response to the request following it.
+fn large_message( c : &mut Criterion ) {
+    let mut frames = bench::frames( &[ did_open( LARGE_DOCUMENT ) ] );
+
+    let mut group = c.benchmark_group( "large_message" );
+    group.throughput( Throughput::Bytes( frames.len( ) as u64 ) );
+    group.bench_function( "decode", | b | b.iter( | | ls_service::fuzz::decode_bytes( &frames ) ) );
+
+    let mut client = BenchClient::new( Router::new( ).advertise_capabilities( ) ).unwrap( );
+    client.initialize( ).unwrap( );
+
+    frames.extend( bench::frames( &[ hover( 1 ) ] ) );
+    group.bench_function( "service", | b | b.iter( | | client.roundtrip( &frames, 1 ).unwrap( ) ) );
+    group.finish( );
+}
+
+/// Diagnostics published for many files in response to a single notification, one notification at a time
+/// and as a single batch.
+fn diagnostics_fan_out( c : &mut Criterion ) {
+    let frames = bench::frames( &[
+        json!( { "jsonrpc" : "2.0", "method" : "workspace/didChangeConfiguration", "params" : { "settings" : { } } } )
+    ] );
+
+    let mut group = c.benchmark_group( "diagnostics" );
+    group.throughput( Throughput::Elements( DIAGNOSTIC_FILES as u64 ) );
+    for &batched in &[ false, true ] {
+        let uris : Vec< Url > = ( 0..DIAGNOSTIC_FILES ).map( | file | {
+            Url::parse( &format!( "file:///bench/{}.rs", file ) ).unwrap( )
+        } ).collect( );
+        let router = Router::new( ).advertise_capabilities( ).on_notification::< DidChangeConfigurationNotification, _ >( move | _, context | {
+            let notifications = uris.iter( ).map( | uri | ClientNotification::PublishDiagnostics( PublishDiagnosticsParams {
+                uri         : uri.clone( ),
+                diagnostics : Vec::new( )
+            } ) );
+
+            if batched {
+                context.service( ).send_notifications( notifications.collect( ) );
+            }
+            else {
+                for notification in notifications {
+                    context.service( ).send_notification( notification );
+                }
+            }
+        } );
+
+        let mut client = BenchClient::new( router ).unwrap( );
+        client.initialize( ).unwrap( );
+
+        let name = if batched { "fan_out_batched" } else { "fan_out" };
+        group.bench_function( name, | b | b.iter( | | client.roundtrip( &frames, DIAGNOSTIC_FILES ).unwrap( ) ) );
+    }
+    group.finish( );
+}
+
+criterion_group!( benches, requests, large_message, diagnostics_fan_out );
+criterion_main!( benches );
@@ -0,0 +1,8 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate ls_service;
+
+fuzz_target!( | data : &[ u8 ] | {
+    let _ = ls_service::fuzz::decode_bytes( data );
+} );
@@ -0,0 +1,11 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate ls_service;
+extern crate serde_json;
+
+fuzz_target!( | data : &[ u8 ] | {
+    if let Ok( message ) = serde_json::from_slice( data ) {
+        let _ = ls_service::fuzz::process_message( message );
+    }
+} );
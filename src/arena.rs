@@ -0,0 +1,349 @@
+
+use std::mem;
+use std::ptr;
+use std::slice;
+use std::str;
+use std::sync::{
+    Arc,
+    Mutex
+};
+
+/// Chunks are allocated as words so every chunk starts aligned for any primitive type
+type Chunk = Box< [ u64 ] >;
+
+/// Size of the chunks allocated by a RequestArena, in words
+const CHUNK_WORDS : usize = 512;
+/// Number of chunks kept by an ArenaPool for the arenas of the next requests
+const POOLED_CHUNKS : usize = 64;
+
+/// Bump arena holding the transient allocations of a request, see Context::arena
+///
+/// Allocating bumps an offset into chunks recycled from the previous requests of the service, instead of
+/// going through the allocator. The allocations are released all at once, once every Context sharing the
+/// arena was dropped, their chunks going back to the pool of the service.
+///
+/// Only Copy values can be allocated, the destructors of values in the arena are never run.
+///
+/// ```ignore
+/// router.on_request::< CompletionRequest, _ >( | params, context | {
+///     let arena = context.arena( );
+///     let prefix = arena.alloc_str( &line[ start..end ] );
+///     let offsets = arena.alloc_slice( &word_offsets( prefix ) );
+///     ...
+/// } )
+/// ```
+pub struct RequestArena {
+    state : Mutex< ArenaState >,
+    pool  : ArenaPool
+}
+
+struct ArenaState {
+    chunks    : Vec< Chunk >,
+    /// Bytes used in the last chunk
+    used      : usize,
+    /// Chunks of the allocations larger than a chunk, never reused
+    large     : Vec< Chunk >,
+    allocated : usize
+}
+
+/// Arena of a Context, created by the first call to Context::arena
+///
+/// Clones made after the arena was created share it, clones made before create their own on first use.
+pub(crate) struct LazyArena {
+    pool  : ArenaPool,
+    arena : Mutex< Option< Arc< RequestArena > > >
+}
+
+/// Chunks of the arenas of finished requests, reused by the arenas of the next requests of a service
+#[derive( Clone, Default )]
+pub(crate) struct ArenaPool {
+    chunks : Arc< Mutex< Vec< Chunk > > >
+}
+
+impl ArenaPool {
+
+    pub fn new( ) -> Self {
+        ArenaPool::default( )
+    }
+
+    /// Creates an arena for a request, taking its chunks from the pool.
+    pub fn arena( &self ) -> RequestArena {
+        RequestArena {
+            state : Mutex::new( ArenaState {
+                chunks    : Vec::new( ),
+                used      : 0,
+                large     : Vec::new( ),
+                allocated : 0
+            } ),
+            pool  : self.clone( )
+        }
+    }
+
+    fn take( &self ) -> Chunk {
+        match self.chunks.lock( ).unwrap( ).pop( ) {
+            Some( chunk ) => chunk,
+            None => vec![ 0; CHUNK_WORDS ].into_boxed_slice( )
+        }
+    }
+
+    fn give_back< I : IntoIterator< Item = Chunk > >( &self, chunks : I ) {
+        let mut pooled = self.chunks.lock( ).unwrap( );
+        let room = POOLED_CHUNKS.saturating_sub( pooled.len( ) );
+
+        pooled.extend( chunks.into_iter( ).take( room ) );
+    }
+
+}
+
+impl LazyArena {
+
+    pub fn new( pool : ArenaPool ) -> Self {
+        LazyArena {
+            pool  : pool,
+            arena : Mutex::new( None )
+        }
+    }
+
+    /// Returns the arena, creating it from the pool on the first call.
+    pub fn get( &self ) -> Arc< RequestArena > {
+        let mut arena = self.arena.lock( ).unwrap( );
+        let pool = &self.pool;
+
+        arena.get_or_insert_with( || Arc::new( pool.arena( ) ) ).clone( )
+    }
+
+}
+
+impl Clone for LazyArena {
+
+    fn clone( &self ) -> Self {
+        LazyArena {
+            pool  : self.pool.clone( ),
+            arena : Mutex::new( self.arena.lock( ).unwrap( ).clone( ) )
+        }
+    }
+
+}
+
+impl RequestArena {
+
+    /// Creates an arena that does not belong to a service, e.g. to call handlers from tests.
+    pub fn new( ) -> Self {
+        ArenaPool::new( ).arena( )
+    }
+
+    /// Moves value into the arena.
+    pub fn alloc< T : Copy >( &self, value : T ) -> &mut T {
+        let pointer = self.allocate( mem::size_of::< T >( ), mem::align_of::< T >( ) ) as *mut T;
+
+        // The memory is aligned for T, is not handed out by any other allocation and lives as long as the
+        // arena, chunks are neither moved nor released before the arena is dropped
+        unsafe {
+            pointer.write( value );
+
+            &mut *pointer
+        }
+    }
+
+    /// Copies values into the arena.
+    pub fn alloc_slice< T : Copy >( &self, values : &[ T ] ) -> &mut [ T ] {
+        let pointer = self.allocate( mem::size_of_val( values ), mem::align_of::< T >( ) ) as *mut T;
+
+        // See RequestArena::alloc
+        unsafe {
+            ptr::copy_nonoverlapping( values.as_ptr( ), pointer, values.len( ) );
+
+            slice::from_raw_parts_mut( pointer, values.len( ) )
+        }
+    }
+
+    /// Copies value into the arena.
+    pub fn alloc_str( &self, value : &str ) -> &str {
+        let bytes = self.alloc_slice( value.as_bytes( ) );
+
+        // Copied from a str
+        unsafe { str::from_utf8_unchecked( bytes ) }
+    }
+
+    /// Returns the number of bytes allocated in the arena.
+    pub fn allocated_bytes( &self ) -> usize {
+        self.state.lock( ).unwrap( ).allocated
+    }
+
+    /// Returns a pointer to size bytes aligned to align, valid until the arena is dropped.
+    fn allocate( &self, size : usize, align : usize ) -> *mut u8 {
+        if size == 0 {
+            return align as *mut u8;
+        }
+
+        let mut state = self.state.lock( ).unwrap( );
+        state.allocated += size;
+        if let Some( pointer ) = state.bump( size, align ) {
+            return pointer;
+        }
+
+        if size + align > CHUNK_WORDS * mem::size_of::< u64 >( ) {
+            // Large allocations get a chunk of their own, leaving the last chunk to the next allocations
+            let words = ( size + align ) / mem::size_of::< u64 >( ) + 1;
+            let mut chunk : Chunk = vec![ 0; words ].into_boxed_slice( );
+            let base = chunk.as_mut_ptr( ) as *mut u8;
+            state.large.push( chunk );
+
+            return base.wrapping_add( align_offset( base as usize, align ) );
+        }
+
+        let chunk = self.pool.take( );
+        state.chunks.push( chunk );
+        state.used = 0;
+
+        state.bump( size, align ).expect( "A new chunk holds any allocation smaller than a chunk" )
+    }
+
+}
+
+impl Default for RequestArena {
+
+    fn default( ) -> Self {
+        RequestArena::new( )
+    }
+
+}
+
+impl Drop for RequestArena {
+
+    fn drop( &mut self ) {
+        let state = self.state.get_mut( ).unwrap( );
+
+        self.pool.give_back( state.chunks.drain( .. ) );
+    }
+
+}
+
+impl ArenaState {
+
+    /// Allocates size bytes aligned to align in the last chunk, None if it has no room left.
+    fn bump( &mut self, size : usize, align : usize ) -> Option< *mut u8 > {
+        let used = self.used;
+        let chunk = self.chunks.last_mut( )?;
+        let base = chunk.as_mut_ptr( ) as *mut u8;
+
+        let start = used + align_offset( base as usize + used, align );
+        let end = start.checked_add( size )?;
+        if end > chunk.len( ) * mem::size_of::< u64 >( ) {
+            return None;
+        }
+        self.used = end;
+
+        Some( base.wrapping_add( start ) )
+    }
+
+}
+
+/// Returns the number of bytes to skip from address to reach a multiple of align, a power of two.
+fn align_offset( address : usize, align : usize ) -> usize {
+    ( align - address % align ) % align
+}
+
+#[cfg( test )]
+mod tests {
+
+    use super::{
+        ArenaPool,
+        LazyArena,
+        RequestArena,
+        CHUNK_WORDS
+    };
+    use std::mem;
+    use std::sync::{
+        Arc
+    };
+
+    const CHUNK_BYTES : usize = CHUNK_WORDS * 8;
+
+    fn pooled( pool : &ArenaPool ) -> usize {
+        pool.chunks.lock( ).unwrap( ).len( )
+    }
+
+    fn chunks( arena : &RequestArena ) -> usize {
+        arena.state.lock( ).unwrap( ).chunks.len( )
+    }
+
+    #[test]
+    fn allocations_are_aligned_for_their_type( ) {
+        let arena = RequestArena::new( );
+
+        for _ in 0..16 {
+            let byte = arena.alloc( 1u8 ) as *mut u8 as usize;
+            let word = arena.alloc( 2u64 ) as *mut u64 as usize;
+            let half = arena.alloc_slice( &[ 3u16, 4, 5 ] ).as_ptr( ) as usize;
+            let wide = arena.alloc( 6u128 ) as *mut u128 as usize;
+
+            assert!( byte != 0 );
+            assert_eq!( word % mem::align_of::< u64 >( ), 0 );
+            assert_eq!( half % mem::align_of::< u16 >( ), 0 );
+            assert_eq!( wide % mem::align_of::< u128 >( ), 0 );
+        }
+    }
+
+    #[test]
+    fn allocations_grow_across_chunks_and_stay_intact( ) {
+        let arena = RequestArena::new( );
+
+        let values : Vec< &mut u64 > = ( 0..( CHUNK_BYTES / 8 ) as u64 * 3 ).map( | value | {
+            arena.alloc( value )
+        } ).collect( );
+        let text = arena.alloc_str( "spans the end of a chunk" );
+        let large = arena.alloc_slice( &vec![ 7u8; CHUNK_BYTES * 2 ] );
+
+        assert!( chunks( &arena ) >= 3 );
+        assert_eq!( arena.allocated_bytes( ), CHUNK_BYTES * 3 + text.len( ) + large.len( ) );
+        for ( expected, value ) in values.iter( ).enumerate( ) {
+            assert_eq!( **value, expected as u64 );
+        }
+        assert_eq!( text, "spans the end of a chunk" );
+        assert!( large.iter( ).all( | byte | *byte == 7 ) );
+    }
+
+    #[test]
+    fn dropped_arena_returns_its_chunks_to_the_pool( ) {
+        let pool = ArenaPool::new( );
+
+        let arena = pool.arena( );
+        arena.alloc_slice( &vec![ 1u64; CHUNK_WORDS + 1 ] );
+        arena.alloc_slice( &vec![ 1u8; CHUNK_BYTES / 2 ] );
+        arena.alloc_slice( &vec![ 1u8; CHUNK_BYTES / 2 + 1 ] );
+        assert_eq!( chunks( &arena ), 2 );
+        drop( arena );
+
+        // The large allocation has a chunk of its own that is not pooled
+        assert_eq!( pooled( &pool ), 2 );
+
+        let arena = pool.arena( );
+        assert_eq!( arena.allocated_bytes( ), 0 );
+        let value = arena.alloc( 42u64 );
+        assert_eq!( *value, 42 );
+        assert_eq!( pooled( &pool ), 1 );
+        assert_eq!( arena.allocated_bytes( ), 8 );
+    }
+
+    #[test]
+    fn lazy_arena_is_created_on_first_use( ) {
+        let pool = ArenaPool::new( );
+        pool.give_back( vec![ vec![ 0; CHUNK_WORDS ].into_boxed_slice( ) ] );
+
+        let lazy = LazyArena::new( pool.clone( ) );
+        let before = lazy.clone( );
+        assert!( lazy.arena.lock( ).unwrap( ).is_none( ) );
+
+        lazy.get( ).alloc( 1u8 );
+        assert_eq!( pooled( &pool ), 0 );
+
+        let after = lazy.clone( );
+        assert!( Arc::ptr_eq( &lazy.get( ), &after.get( ) ) );
+        assert!( !Arc::ptr_eq( &lazy.get( ), &before.get( ) ) );
+
+        drop( ( lazy, after ) );
+        assert_eq!( pooled( &pool ), 1 );
+    }
+
+}
@@ -0,0 +1,154 @@
+
+use clock::{
+    Clock
+};
+use std::collections::{
+    VecDeque
+};
+use std::time::{
+    Duration,
+    Instant
+};
+
+/// Outcome of a request sent to the client
+#[derive( Clone, Copy, Debug, PartialEq, Eq )]
+pub enum RequestOutcome {
+    /// The client has not yet responded to the request
+    Pending,
+    /// The client responded with a result
+    Result,
+    /// The client responded with an error with the given code
+    Error( i64 )
+}
+
+/// Record of a single request sent to the client through a ServiceHandle
+#[derive( Clone, Debug )]
+pub struct ClientRequestRecord {
+    pub id      : i64,
+    pub method  : &'static str,
+    pub sent    : Instant,
+    /// Time between the request being queued for writing and its response being read, None while pending
+    pub latency : Option< Duration >,
+    pub outcome : RequestOutcome
+}
+
+/// Record of a response written by the service, see ServiceHandle::response_order_log
+#[derive( Clone, Copy, Debug, PartialEq, Eq )]
+pub struct ResponseRecord {
+    pub request_id        : i64,
+    /// Position of the request among the requests received by the service, starting at 0
+    pub request_sequence  : u64,
+    /// Position of the response among the responses written by the service, starting at 0
+    pub response_sequence : u64
+}
+
+pub(crate) struct ClientRequestLog {
+    capacity : usize,
+    clock    : Clock,
+    records  : VecDeque< ClientRequestRecord >
+}
+
+impl ClientRequestLog {
+
+    pub fn new( capacity : usize, clock : Clock ) -> Self {
+        ClientRequestLog {
+            capacity : capacity,
+            clock    : clock,
+            records  : VecDeque::with_capacity( capacity )
+        }
+    }
+
+    pub fn record_sent( &mut self, id : i64, method : &'static str ) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len( ) == self.capacity {
+            self.records.pop_front( );
+        }
+
+        self.records.push_back( ClientRequestRecord {
+            id      : id,
+            method  : method,
+            sent    : self.clock.now( ),
+            latency : None,
+            outcome : RequestOutcome::Pending
+        } );
+    }
+
+    pub fn record_response( &mut self, id : i64, outcome : RequestOutcome ) {
+        // Records are pushed in id order, so recent requests are found quickly from the back
+        let clock = &self.clock;
+        if let Some( record ) = self.records.iter_mut( ).rev( ).find( | record | record.id == id ) {
+            record.latency = Some( clock.elapsed( record.sent ) );
+            record.outcome = outcome;
+        }
+    }
+
+    pub fn records( &self ) -> Vec< ClientRequestRecord > {
+        self.records.iter( ).cloned( ).collect( )
+    }
+
+}
+
+pub(crate) struct ResponseOrderLog {
+    capacity      : usize,
+    next_response : u64,
+    records       : VecDeque< ResponseRecord >
+}
+
+impl ResponseOrderLog {
+
+    pub fn new( capacity : usize ) -> Self {
+        ResponseOrderLog {
+            capacity      : capacity,
+            next_response : 0,
+            records       : VecDeque::with_capacity( capacity )
+        }
+    }
+
+    pub fn record_written( &mut self, request_id : i64, request_sequence : u64 ) {
+        let response_sequence = self.next_response;
+        self.next_response += 1;
+
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len( ) == self.capacity {
+            self.records.pop_front( );
+        }
+
+        self.records.push_back( ResponseRecord {
+            request_id        : request_id,
+            request_sequence  : request_sequence,
+            response_sequence : response_sequence
+        } );
+    }
+
+    pub fn records( &self ) -> Vec< ResponseRecord > {
+        self.records.iter( ).cloned( ).collect( )
+    }
+
+}
+
+/// Checks that responses were written in the order their requests were received, returning the first
+/// pair of records in the wrong order.
+///
+/// Requests canceled without a response do not have a record and do not break the order. Services
+/// configured with ResponseOrdering::Completion are expected to fail this check.
+///
+/// ```ignore
+/// client.run_until_stalled( )?;
+/// assert_eq!( audit::check_response_order( &client.service( ).response_order_log( ) ), Ok( ( ) ) );
+/// ```
+pub fn check_response_order( records : &[ ResponseRecord ] ) -> Result< ( ), ( ResponseRecord, ResponseRecord ) > {
+    let mut records = records.to_vec( );
+    records.sort_by_key( | record | record.response_sequence );
+
+    for pair in records.windows( 2 ) {
+        if pair[ 0 ].request_sequence > pair[ 1 ].request_sequence {
+            return Err( ( pair[ 0 ], pair[ 1 ] ) );
+        }
+    }
+
+    Ok( ( ) )
+}
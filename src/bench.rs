@@ -0,0 +1,195 @@
+
+use builder::{
+    ServiceBuilder
+};
+use futures::{
+    Async,
+    Future,
+    Poll
+};
+use futures::future;
+use mock_client::{
+    MockError
+};
+use serde_json::{
+    Value
+};
+use service::{
+    MessageHandler,
+    ServiceHandle
+};
+use std::io::{
+    self,
+    Read,
+    Write
+};
+use std::time::{
+    Duration
+};
+use tokio_core::reactor::{
+    Core,
+    Timeout
+};
+use transport::{
+    self,
+    MemoryStream
+};
+
+/// Time a BenchClient waits for the messages of the service by default
+const DEFAULT_TIMEOUT_MS : u64 = 30000;
+/// Size of the chunks read from the transport
+const READ_CHUNK : usize = 64 * 1024;
+
+/// Client driving a service over an in-memory transport for benchmarks, running the whole pipeline of the
+/// service without a socket
+///
+/// Unlike MockClient, the client neither parses nor keeps the messages of the service, it only counts
+/// them, so a benchmark measures the service rather than the client. Messages are framed ahead of time
+/// with bench::frames and sent as is.
+///
+/// ```ignore
+/// let mut client = BenchClient::new( Router::new( ).advertise_capabilities( ) )?;
+/// client.initialize( )?;
+///
+/// let requests = bench::frames( &( 1..101 ).map( | id | json!( { ... } ) ).collect::< Vec< _ > >( ) );
+/// b.iter( | | client.roundtrip( &requests, 100 ).unwrap( ) );
+/// ```
+pub struct BenchClient {
+    core     : Core,
+    service  : ServiceHandle,
+    io       : MemoryStream,
+    buffer   : Vec< u8 >,
+    timeout  : Duration,
+    received : u64
+}
+
+impl BenchClient {
+
+    /// Starts a service running handler, connected to a new client.
+    pub fn new< H : MessageHandler + 'static >( handler : H ) -> io::Result< Self > {
+        BenchClient::with_builder( handler, | builder | builder )
+    }
+
+    /// Starts a service running handler, configured by configure, connected to a new client.
+    pub fn with_builder< H, F >( handler : H, configure : F ) -> io::Result< Self >
+        where H : MessageHandler + 'static, F : FnOnce( ServiceBuilder ) -> ServiceBuilder {
+        let core = Core::new( )?;
+        let ( server_io, client_io ) = transport::memory_transport( );
+        let service = configure( ServiceBuilder::new( core.handle( ) ) ).start( handler, server_io );
+
+        Ok( BenchClient {
+            core     : core,
+            service  : service,
+            io       : client_io,
+            buffer   : Vec::with_capacity( READ_CHUNK ),
+            timeout  : Duration::from_millis( DEFAULT_TIMEOUT_MS ),
+            received : 0
+        } )
+    }
+
+    /// Sets the time BenchClient::receive waits for the messages of the service before failing with
+    /// MockError::Timeout.
+    pub fn set_timeout( &mut self, timeout : Duration ) {
+        self.timeout = timeout;
+    }
+
+    /// Returns the handle of the service the client is connected to.
+    pub fn service( &self ) -> &ServiceHandle {
+        &self.service
+    }
+
+    /// Returns the number of messages received from the service since the client was created.
+    pub fn received( &self ) -> u64 {
+        self.received
+    }
+
+    /// Sends the initialize request and the initialized notification, waiting for the response to
+    /// initialize.
+    pub fn initialize( &mut self ) -> Result< ( ), MockError > {
+        let handshake = frames( &[
+            json!( { "jsonrpc" : "2.0", "id" : 0, "method" : "initialize", "params" : {
+                "processId"    : null,
+                "rootUri"      : null,
+                "capabilities" : { }
+            } } ),
+            json!( { "jsonrpc" : "2.0", "method" : "initialized", "params" : { } } )
+        ] );
+
+        self.roundtrip( &handshake, 1 )
+    }
+
+    /// Sends bytes holding messages already framed, e.g. by bench::frames.
+    pub fn send_frames( &mut self, frames : &[ u8 ] ) -> Result< ( ), MockError > {
+        self.io.write_all( frames ).map_err( MockError::Io )
+    }
+
+    /// Sends frames and runs the service until count messages are received.
+    pub fn roundtrip( &mut self, frames : &[ u8 ], count : usize ) -> Result< ( ), MockError > {
+        self.send_frames( frames )?;
+
+        self.receive( count )
+    }
+
+    /// Runs the service until count messages are received, discarding them without parsing their body.
+    pub fn receive( &mut self, count : usize ) -> Result< ( ), MockError > {
+        let mut timeout = Timeout::new( self.timeout, &self.core.handle( ) ).map_err( MockError::Io )?;
+        let io = &mut self.io;
+        let buffer = &mut self.buffer;
+        let received = &mut self.received;
+        let mut remaining = count;
+        self.core.run( future::poll_fn( move | | -> Poll< ( ), MockError > {
+            loop {
+                remaining -= skip_frames( buffer, remaining )?;
+                if remaining == 0 {
+                    *received += count as u64;
+
+                    return Ok( Async::Ready( ( ) ) );
+                }
+
+                let mut chunk = [ 0; READ_CHUNK ];
+                match io.read( &mut chunk ) {
+                    Ok( 0 ) => return Err( MockError::Disconnected ),
+                    Ok( read ) => buffer.extend_from_slice( &chunk[ ..read ] ),
+                    Err( ref error ) if error.kind( ) == io::ErrorKind::WouldBlock => break,
+                    Err( error ) => return Err( MockError::Io( error ) )
+                }
+            }
+
+            match timeout.poll( ) {
+                Ok( Async::Ready( ( ) ) ) => Err( MockError::Timeout ),
+                Ok( Async::NotReady ) => Ok( Async::NotReady ),
+                Err( error ) => Err( MockError::Io( error ) )
+            }
+        } ) )
+    }
+
+}
+
+/// Frames messages with the Content-Length header expected by the service, concatenated so they can be
+/// sent at once.
+pub fn frames( messages : &[ Value ] ) -> Vec< u8 > {
+    let mut framed = Vec::new( );
+    for message in messages {
+        framed.extend( transport::frame_message( message ) );
+    }
+
+    framed
+}
+
+/// Removes up to count whole messages from the front of buffer, returning the number removed.
+fn skip_frames( buffer : &mut Vec< u8 >, count : usize ) -> Result< usize, MockError > {
+    let mut skipped = 0;
+    let mut consumed = 0;
+    while skipped < count {
+        match transport::frame_length( &buffer[ consumed.. ] ).map_err( MockError::InvalidMessage )? {
+            Some( length ) => {
+                consumed += length;
+                skipped += 1;
+            },
+            None => break
+        }
+    }
+    buffer.drain( ..consumed );
+
+    Ok( skipped )
+}
@@ -0,0 +1,62 @@
+
+//! Runs conversation scripts against a language server executable, see ls_service::script::Script.
+//!
+//! Usage: `ls_conformance SCRIPT... -- SERVER [ARGS...]`
+//!
+//! Every script runs against a new server process. Exits with status 1 if a script failed.
+
+extern crate ls_service;
+
+use ls_service::script::{
+    ProcessConversation,
+    Script
+};
+use std::env;
+use std::process::{
+    self,
+    Command
+};
+
+fn main( ) {
+    let args : Vec< String > = env::args( ).skip( 1 ).collect( );
+    let separator = match args.iter( ).position( | arg | arg == "--" ) {
+        Some( separator ) if separator > 0 && separator + 1 < args.len( ) => separator,
+        _ => {
+            eprintln!( "Usage: ls_conformance SCRIPT... -- SERVER [ARGS...]" );
+
+            process::exit( 2 );
+        }
+    };
+    let scripts = &args[ ..separator ];
+    let server = &args[ separator + 1 ];
+    let server_args = &args[ separator + 2.. ];
+
+    let mut failed = 0;
+    for path in scripts {
+        let script = match Script::load( path ) {
+            Ok( script ) => script,
+            Err( error ) => {
+                println!( "FAILED {}: {}", path, error );
+                failed += 1;
+
+                continue;
+            }
+        };
+
+        let result = ProcessConversation::spawn( Command::new( server ).args( server_args ) )
+            .map_err( | error | format!( "Error starting {}: {}", server, error ) )
+            .and_then( | mut conversation | script.run( &mut conversation ).map_err( | error | error.to_string( ) ) );
+        match result {
+            Ok( ( ) ) => println!( "ok {}", script.name( ) ),
+            Err( error ) => {
+                println!( "FAILED {}: {}", script.name( ), error );
+                failed += 1;
+            }
+        }
+    }
+
+    println!( "{} scripts, {} failed", scripts.len( ), failed );
+    if failed > 0 {
+        process::exit( 1 );
+    }
+}
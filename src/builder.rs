@@ -0,0 +1,284 @@
+
+use clock::{
+    Clock
+};
+use context::{
+    StateMap
+};
+use listener::{
+    self,
+    ConnectionInfo,
+    Listener
+};
+use loopback::{
+    self,
+    LoopbackClient
+};
+use queue::{
+    QueueCapacity
+};
+use service::{
+    self,
+    DroppedResponsePolicy,
+    MessageHandler,
+    PinnedDocumentPolicy,
+    ResponseOrdering,
+    ServiceHandle,
+    WriteRetryPolicy
+};
+use std::any::{
+    Any
+};
+use std::sync::{
+    Arc
+};
+use std::time::{
+    Duration
+};
+use tokio_core::io::{
+    Io
+};
+use tokio_core::net::{
+    TcpListener
+};
+use tokio_core::reactor::{
+    Handle
+};
+
+/// Builder used to configure optional behaviour of a service before starting it
+pub struct ServiceBuilder {
+    handle : Handle,
+    config : ServiceConfig
+}
+
+#[derive( Clone )]
+pub(crate) struct ServiceConfig {
+    pub slow_request_progress       : Option< Duration >,
+    pub client_request_log_capacity : usize,
+    pub response_order_log_capacity : usize,
+    pub heartbeat_interval          : Option< Duration >,
+    pub dropped_response_policy     : DroppedResponsePolicy,
+    pub pinned_document_policy      : PinnedDocumentPolicy,
+    pub response_ordering           : ResponseOrdering,
+    pub response_queue_capacity     : QueueCapacity,
+    pub write_queue_capacity        : QueueCapacity,
+    pub write_retry                 : Option< WriteRetryPolicy >,
+    pub quarantine_threshold        : usize,
+    pub cancel_on_change            : Vec< &'static str >,
+    pub state_map                   : StateMap,
+    pub clock                       : Clock
+}
+
+impl ServiceBuilder {
+
+    /// Creates a new builder for a service that will run on the given tokio Handle.
+    pub fn new( handle : Handle ) -> Self {
+        ServiceBuilder {
+            handle : handle,
+            config : ServiceConfig::default( )
+        }
+    }
+
+    /// Shows a generic work done progress indicator in the client for requests that have been pending for
+    /// longer than the given threshold. The indicator is removed when the response for the request is sent.
+    ///
+    /// Progress is only reported if the client advertised support for window/workDoneProgress in its
+    /// initialize request.
+    pub fn slow_request_progress( mut self, threshold : Duration ) -> Self {
+        self.config.slow_request_progress = Some( threshold );
+
+        self
+    }
+
+    /// Sets the number of requests sent to the client that are kept in the log returned by
+    /// ServiceHandle::client_request_log. Defaults to 64, a capacity of 0 disables the log.
+    pub fn client_request_log_capacity( mut self, capacity : usize ) -> Self {
+        self.config.client_request_log_capacity = capacity;
+
+        self
+    }
+
+    /// Sets the number of written responses kept in the log returned by ServiceHandle::response_order_log,
+    /// so tests can verify the order responses are written in. Defaults to 0, disabling the log.
+    pub fn response_order_log_capacity( mut self, capacity : usize ) -> Self {
+        self.config.response_order_log_capacity = capacity;
+
+        self
+    }
+
+    /// Sends a telemetry/event notification to the client at the given interval while the service is running.
+    ///
+    /// The event is an object of the form
+    /// `{ "type" : "ls_service/heartbeat", "sequence" : n, "uptime_ms" : ms, "pending_requests" : count }`,
+    /// allowing a supervising extension to detect a server that is alive but no longer processing messages.
+    pub fn heartbeat( mut self, interval : Duration ) -> Self {
+        self.config.heartbeat_interval = Some( interval );
+
+        self
+    }
+
+    /// Sets the action taken when a ResponseOutput is dropped without a response being sent. Defaults to
+    /// DroppedResponsePolicy::InternalError.
+    pub fn dropped_response_policy( mut self, policy : DroppedResponsePolicy ) -> Self {
+        self.config.dropped_response_policy = policy;
+
+        self
+    }
+
+    /// Sets the action taken when a document pinned through Context::pin_document changes before the
+    /// request is completed. Defaults to PinnedDocumentPolicy::ContentModified.
+    pub fn pinned_document_policy( mut self, policy : PinnedDocumentPolicy ) -> Self {
+        self.config.pinned_document_policy = policy;
+
+        self
+    }
+
+    /// Sets the order in which the responses of requests are written. Defaults to ResponseOrdering::Received,
+    /// ResponseOrdering::Completion keeps a slow request from delaying the responses of later requests.
+    pub fn response_ordering( mut self, ordering : ResponseOrdering ) -> Self {
+        self.config.response_ordering = ordering;
+
+        self
+    }
+
+    /// Sets the capacity of the queue of requests waiting for their response to be written. Defaults to
+    /// QueueCapacity::Bounded( 1024 ), the service stops reading messages while the queue is full.
+    pub fn response_queue_capacity( mut self, capacity : QueueCapacity ) -> Self {
+        self.config.response_queue_capacity = capacity;
+
+        self
+    }
+
+    /// Sets the capacity of the queue of messages waiting to be written to the client. Defaults to
+    /// QueueCapacity::Bounded( 1024 ), tasks sending messages wait while the queue is full.
+    ///
+    /// Servers publishing bursts of notifications, e.g. diagnostics for a whole workspace, can use
+    /// QueueCapacity::Unbounded or QueueCapacity::Adaptive to keep the burst from stalling the service.
+    pub fn write_queue_capacity( mut self, capacity : QueueCapacity ) -> Self {
+        self.config.write_queue_capacity = capacity;
+
+        self
+    }
+
+    /// Retries writes to the client failing with a transient error according to policy instead of failing
+    /// the service right away. Writes are not retried by default.
+    ///
+    /// ```ignore
+    /// let service = ServiceBuilder::new( core.handle( ) )
+    ///     .write_retry( WriteRetryPolicy::new( 5, Duration::from_millis( 10 ) ) )
+    ///     .start( handler, transport );
+    /// ```
+    pub fn write_retry( mut self, policy : WriteRetryPolicy ) -> Self {
+        self.config.write_retry = Some( policy );
+
+        self
+    }
+
+    /// Skips messages that cannot be decoded instead of closing the connection, keeping them in the log
+    /// returned by ServiceHandle::quarantined_messages. The stream is declared unrecoverable once more than
+    /// threshold messages in a row fail to decode, or when a frame header is malformed and the next message
+    /// cannot be found. Defaults to 0, closing the connection on the first malformed message.
+    ///
+    /// A skipped message that is a request, e.g. a request whose params do not match its method, is
+    /// answered with an INVALID_PARAMS error.
+    pub fn quarantine_malformed_messages( mut self, threshold : usize ) -> Self {
+        self.config.quarantine_threshold = threshold;
+
+        self
+    }
+
+    /// Cancels pending requests of the given methods when the document they operate on is changed, as if
+    /// every such request pinned the version of its document current when it was received through
+    /// Context::pin_document. The canceled requests are answered according to the PinnedDocumentPolicy.
+    ///
+    /// ```ignore
+    /// let service = ServiceBuilder::new( core.handle( ) )
+    ///     .cancel_on_change( &[ CompletionRequest::METHOD, SignatureHelpRequest::METHOD ] )
+    ///     .start( handler, stdio );
+    /// ```
+    pub fn cancel_on_change( mut self, methods : &[ &'static str ] ) -> Self {
+        self.config.cancel_on_change.extend_from_slice( methods );
+
+        self
+    }
+
+    /// Sets the clock used for deadlines, heartbeats, slow request detection and the timestamps of the
+    /// service. Defaults to the system clock, tests can give the clock of a ManualClock to advance time
+    /// without sleeping.
+    pub fn clock( mut self, clock : Clock ) -> Self {
+        self.config.clock = clock;
+
+        self
+    }
+
+    /// Registers a shared state value, retrievable by handlers through Context::extension or
+    /// ServiceHandle::extension.
+    #[deprecated( note = "Use ServiceBuilder::insert_extension, which takes the value itself" )]
+    pub fn shared_state< T : Any + Send + Sync >( mut self, value : Arc< T > ) -> Self {
+        self.config.state_map.insert( value );
+
+        self
+    }
+
+    /// Inserts a typed extension value, retrievable by handlers and middlewares through Context::extension or
+    /// ServiceHandle::extension. Inserting a second value of the same type replaces the first.
+    pub fn insert_extension< T : Any + Send + Sync >( mut self, value : T ) -> Self {
+        self.config.state_map.insert( Arc::new( value ) );
+
+        self
+    }
+
+    /// Starts the configured service, see service::start_service.
+    pub fn start< H : MessageHandler + 'static, I : Io + 'static >( self, message_handler : H, io : I ) -> ServiceHandle {
+        service::start_service_with_config( self.handle, self.config, message_handler, io )
+    }
+
+    /// Starts the configured service connected to a LoopbackClient running on the same event loop, see
+    /// LoopbackClient.
+    pub fn loopback< H : MessageHandler + 'static >( self, message_handler : H ) -> ( ServiceHandle, LoopbackClient ) {
+        loopback::connect( self.handle, self.config, message_handler )
+    }
+
+    /// Accepts client connections on listener, starting a configured service for each of them.
+    ///
+    /// The message handler of every connection is created by calling factory with the information of the
+    /// connection. shared_state is inserted as an extension of every service, retrievable as
+    /// Context::extension::< S >( ).
+    ///
+    /// ```ignore
+    /// let listener = ServiceBuilder::new( core.handle( ) )
+    ///     .listen( tcp_listener, Arc::new( IndexCache::new( ) ), | connection | {
+    ///         Server::new( connection.id )
+    ///     } );
+    ///
+    /// core.run( listener ).unwrap( );
+    /// ```
+    pub fn listen< S, F, H >( self, listener : TcpListener, shared_state : Arc< S >, factory : F ) -> Listener< F >
+        where S : Any + Send + Sync, F : Fn( &ConnectionInfo ) -> H, H : MessageHandler + 'static {
+        listener::listen( self.handle, self.config, listener, shared_state, factory )
+    }
+
+}
+
+impl Default for ServiceConfig {
+
+    fn default( ) -> Self {
+        ServiceConfig {
+            slow_request_progress       : None,
+            client_request_log_capacity : 64,
+            response_order_log_capacity : 0,
+            heartbeat_interval          : None,
+            dropped_response_policy     : DroppedResponsePolicy::InternalError,
+            pinned_document_policy      : PinnedDocumentPolicy::ContentModified,
+            response_ordering           : ResponseOrdering::Received,
+            response_queue_capacity     : QueueCapacity::Bounded( 1024 ),
+            write_queue_capacity        : QueueCapacity::Bounded( 1024 ),
+            write_retry                 : None,
+            quarantine_threshold        : 0,
+            cancel_on_change            : Vec::new( ),
+            state_map                   : StateMap::new( ),
+            clock                       : Clock::system( )
+        }
+    }
+
+}
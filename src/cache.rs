@@ -0,0 +1,278 @@
+
+use document::{
+    DocumentSnapshot
+};
+use logging::{
+    Component
+};
+use lsp_rs::{
+    ServerNotification,
+    Url
+};
+use memory::{
+    MemoryBudget,
+    MemoryConsumer
+};
+use std::collections::{
+    HashMap
+};
+use std::sync::{
+    Arc,
+    Mutex
+};
+
+/// Cache of a value computed from a version of a document, such as a parse tree or a symbol table
+///
+/// The cache holds at most one value per document, computed for a given version. Values are dropped when
+/// their document is changed or closed, and the least recently used value is evicted once the cache holds
+/// more than capacity documents, or more than the size limit set with ArtifactCache::size_limit. The cache
+/// is cheap to clone, clones share the same values:
+///
+/// ```ignore
+/// let parse_trees = ArtifactCache::new( 32 );
+///
+/// // In handle_notification
+/// parse_trees.observe( &notification );
+///
+/// // In a request handler
+/// let tree = parse_trees.get_or_compute( &snapshot, | snapshot | parse( snapshot.rope( ) ) );
+/// ```
+pub struct ArtifactCache< T > {
+    inner : Arc< Mutex< ArtifactCacheInner< T > > >
+}
+
+struct ArtifactCacheInner< T > {
+    capacity   : usize,
+    size_limit : usize,
+    size       : usize,
+    weigher    : Option< Box< dyn Fn( &T ) -> usize + Send > >,
+    clock      : u64,
+    entries    : HashMap< Url, CacheEntry< T > >,
+    budget     : Option< MemoryBudget >
+}
+
+struct CacheEntry< T > {
+    version   : i64,
+    value     : Arc< T >,
+    size      : usize,
+    last_used : u64
+}
+
+impl < T > ArtifactCache< T > {
+
+    /// Creates a cache holding the values of at most capacity documents.
+    pub fn new( capacity : usize ) -> Self {
+        ArtifactCache {
+            inner : Arc::new( Mutex::new( ArtifactCacheInner {
+                capacity   : capacity.max( 1 ),
+                size_limit : usize::max_value( ),
+                size       : 0,
+                weigher    : None,
+                clock      : 0,
+                entries    : HashMap::new( ),
+                budget     : None
+            } ) )
+        }
+    }
+
+    /// Bounds the total size of the cached values to limit, the size of a value being returned by weigher,
+    /// e.g. an estimate of the bytes it holds. Values are evicted in least recently used order once the
+    /// cache exceeds either its capacity or its size limit.
+    ///
+    /// ```ignore
+    /// let line_tables = ArtifactCache::new( 256 ).size_limit( 16 << 20, | lines : &Vec< usize > | {
+    ///     lines.len( ) * mem::size_of::< usize >( )
+    /// } );
+    /// ```
+    pub fn size_limit< F >( self, limit : usize, weigher : F ) -> Self where F : Fn( &T ) -> usize + Send + 'static {
+        {
+            let mut inner = self.inner.lock( ).unwrap( );
+            inner.size_limit = limit;
+            inner.weigher = Some( Box::new( weigher ) );
+            inner.evict( );
+        }
+
+        self
+    }
+
+    /// Returns the value computed for version of the document uri, None if it is not cached.
+    pub fn get( &self, uri : &Url, version : i64 ) -> Option< Arc< T > > {
+        let mut inner = self.inner.lock( ).unwrap( );
+        inner.clock += 1;
+
+        let clock = inner.clock;
+        match inner.entries.get_mut( uri ) {
+            Some( ref mut entry ) if entry.version == version => {
+                entry.last_used = clock;
+
+                Some( entry.value.clone( ) )
+            },
+            _ => None
+        }
+    }
+
+    /// Caches the value computed for version of the document uri, replacing the value of any other version.
+    pub fn insert( &self, uri : Url, version : i64, value : T ) -> Arc< T > {
+        let value = Arc::new( value );
+
+        let mut inner = self.inner.lock( ).unwrap( );
+        inner.remove( &uri );
+        inner.clock += 1;
+
+        let clock = inner.clock;
+        let size = inner.weigher.as_ref( ).map( | weigher | weigher( &value ) ).unwrap_or( 0 );
+        inner.size += size;
+        inner.entries.insert( uri, CacheEntry {
+            version   : version,
+            value     : value.clone( ),
+            size      : size,
+            last_used : clock
+        } );
+        inner.evict( );
+
+        let budget = inner.budget.clone( );
+        drop( inner );
+        if let Some( budget ) = budget {
+            budget.enforce( );
+        }
+
+        value
+    }
+
+    /// Returns the value cached for the version of snapshot, computing and caching it if needed.
+    ///
+    /// The value is computed without holding the lock of the cache, two threads missing the cache at the
+    /// same time both compute the value.
+    pub fn get_or_compute< F >( &self, snapshot : &DocumentSnapshot, compute : F ) -> Arc< T > where F : FnOnce( &DocumentSnapshot ) -> T {
+        if let Some( value ) = self.get( snapshot.uri( ), snapshot.version( ) ) {
+            return value;
+        }
+
+        self.insert( snapshot.uri( ).clone( ), snapshot.version( ), compute( snapshot ) )
+    }
+
+    /// Drops the value cached for the document uri.
+    pub fn invalidate( &self, uri : &Url ) {
+        self.inner.lock( ).unwrap( ).remove( uri );
+    }
+
+    pub fn clear( &self ) {
+        let mut inner = self.inner.lock( ).unwrap( );
+        inner.entries.clear( );
+        inner.size = 0;
+    }
+
+    /// Returns the number of documents with a cached value.
+    pub fn len( &self ) -> usize {
+        self.inner.lock( ).unwrap( ).entries.len( )
+    }
+
+    pub fn is_empty( &self ) -> bool {
+        self.len( ) == 0
+    }
+
+    /// Returns the total size of the cached values, as returned by the weigher given to
+    /// ArtifactCache::size_limit. Always 0 without a size limit.
+    pub fn memory_usage( &self ) -> usize {
+        self.inner.lock( ).unwrap( ).size
+    }
+
+    /// Drops the values of documents changed to another version or closed by the client, ignoring other
+    /// notifications.
+    pub fn observe( &self, notification : &ServerNotification ) {
+        match *notification {
+            ServerNotification::DidChangeTextDocument( ref params ) => {
+                let document = &params.text_document;
+
+                let mut inner = self.inner.lock( ).unwrap( );
+                let outdated = inner.entries.get( &document.uri ).map( | entry | entry.version != document.version ).unwrap_or( false );
+                if outdated {
+                    inner.remove( &document.uri );
+                }
+            },
+            ServerNotification::DidCloseTextDocument( ref params ) => self.invalidate( &params.text_document.uri ),
+            _ => { }
+        }
+    }
+
+}
+
+impl < T : Send + Sync + 'static > ArtifactCache< T > {
+
+    /// Registers the cache with budget under name, so the least recently used values are evicted when the
+    /// components registered with the budget exceed it. The memory of the cache is the size of its values,
+    /// the cache needs a size limit for its values to be accounted, see ArtifactCache::size_limit.
+    pub fn memory_budget( self, name : &'static str, budget : &MemoryBudget ) -> Self {
+        self.inner.lock( ).unwrap( ).budget = Some( budget.clone( ) );
+        budget.register( name, &self.inner );
+
+        self
+    }
+
+}
+
+impl < T > Clone for ArtifactCache< T > {
+
+    fn clone( &self ) -> Self {
+        ArtifactCache {
+            inner : self.inner.clone( )
+        }
+    }
+
+}
+
+impl < T > ArtifactCacheInner< T > {
+
+    fn remove( &mut self, uri : &Url ) {
+        if let Some( entry ) = self.entries.remove( uri ) {
+            self.size -= entry.size;
+        }
+    }
+
+    /// Evicts the least recently used values until the cache holds at most capacity documents and its
+    /// values fit in its size limit.
+    fn evict( &mut self ) {
+        while self.entries.len( ) > self.capacity || self.size > self.size_limit {
+            if !self.evict_oldest( ) {
+                return;
+            }
+        }
+    }
+
+    /// Evicts the least recently used value, returns false if the cache is empty.
+    fn evict_oldest( &mut self ) -> bool {
+        let oldest = self.entries.iter( ).min_by_key( | &( _, entry ) | entry.last_used ).map( | ( uri, _ ) | uri.clone( ) );
+        match oldest {
+            Some( uri ) => {
+                component_trace!( Component::Document, "Evicting cached value of {}.", uri );
+
+                self.remove( &uri );
+
+                true
+            },
+            None => false
+        }
+    }
+
+}
+
+impl < T : Send + Sync > MemoryConsumer for Mutex< ArtifactCacheInner< T > > {
+
+    fn memory_usage( &self ) -> usize {
+        self.lock( ).unwrap( ).size
+    }
+
+    fn evict( &self, bytes : usize ) -> usize {
+        let mut inner = self.lock( ).unwrap( );
+        let target = inner.size.saturating_sub( bytes );
+        let size = inner.size;
+        while inner.size > target {
+            if !inner.evict_oldest( ) {
+                break;
+            }
+        }
+
+        size - inner.size
+    }
+
+}
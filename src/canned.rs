@@ -0,0 +1,316 @@
+
+use lsp_rs::{
+    MessageEnvelope,
+    OutgoingMessage,
+    OutgoingServerMessage,
+    ResponseMessage
+};
+use serde::ser::{
+    self,
+    Impossible,
+    Serialize,
+    SerializeSeq,
+    Serializer
+};
+use std::error;
+use std::fmt::{
+    self,
+    Display
+};
+use std::io::{
+    Write
+};
+use transport::{
+    self,
+    HEADER_CAPACITY
+};
+
+/// Size of the buffer the id of a response is formatted in, enough for any i64
+const ID_CAPACITY : usize = 20;
+
+/// Body of a canned response up to its id
+const RESPONSE_PREFIX : &[ u8 ] = br#"{"jsonrpc":"2.0","id":"#;
+/// Body of a response with a null result following its id
+const NULL_SUFFIX : &[ u8 ] = br#","result":null}"#;
+/// Body of a response with an empty array result following its id
+const EMPTY_ARRAY_SUFFIX : &[ u8 ] = br#","result":[]}"#;
+
+/// Result common enough to be answered from a pre-encoded response, only the id of the request being
+/// substituted
+///
+/// Most hover, definition or reference requests find nothing. The service recognizes these results when
+/// writing a response and frames the response from static buffers, skipping the serialization of the
+/// response and any allocation.
+#[derive( Clone, Copy, Debug, PartialEq, Eq )]
+pub enum CannedResult {
+    /// `null`, e.g. a hover miss or the response to shutdown
+    Null,
+    /// `[]`, e.g. a definition or references miss
+    EmptyArray
+}
+
+/// Serializer telling whether a value serializes to a CannedResult, giving up at the first value that does
+/// not
+struct ResultShape;
+
+/// Error of ResultShape for values that do not serialize to a CannedResult
+#[derive( Debug )]
+struct NotCanned;
+
+/// Sequence serialized by ResultShape, empty unless an element is serialized
+struct EmptySequence;
+
+/// Body of a canned response, the static prefix and suffix around the formatted id of the request
+pub(crate) struct CannedBody {
+    digits     : [ u8; ID_CAPACITY ],
+    digits_len : usize,
+    suffix     : &'static [ u8 ]
+}
+
+impl CannedResult {
+
+    /// Returns the CannedResult result serializes to, None if the result has to be serialized.
+    ///
+    /// Serializing stops at the first value that is neither null nor an empty sequence, so the cost does not
+    /// depend on the size of the result.
+    pub fn of< T : Serialize + ?Sized >( result : &T ) -> Option< CannedResult > {
+        result.serialize( ResultShape ).ok( )
+    }
+
+    fn suffix( self ) -> &'static [ u8 ] {
+        match self {
+            CannedResult::Null => NULL_SUFFIX,
+            CannedResult::EmptyArray => EMPTY_ARRAY_SUFFIX
+        }
+    }
+
+}
+
+impl CannedBody {
+
+    pub fn new( id : i64, result : CannedResult ) -> Self {
+        let mut digits = [ 0; ID_CAPACITY ];
+        let digits_len = {
+            let mut cursor = &mut digits[ .. ];
+            write!( cursor, "{}", id ).expect( "Id buffer holds any i64" );
+
+            ID_CAPACITY - cursor.len( )
+        };
+
+        CannedBody {
+            digits     : digits,
+            digits_len : digits_len,
+            suffix     : result.suffix( )
+        }
+    }
+
+    pub fn len( &self ) -> usize {
+        RESPONSE_PREFIX.len( ) + self.digits_len + self.suffix.len( )
+    }
+
+    /// Returns the buffers of the body in order, to be written without concatenating them.
+    pub fn parts( &self ) -> [ &[ u8 ]; 3 ] {
+        [ RESPONSE_PREFIX, &self.digits[ ..self.digits_len ], self.suffix ]
+    }
+
+}
+
+/// Appends the response to request id with result to buffer, framed with its Content-Length header.
+pub fn encode_response( id : i64, result : CannedResult, buffer : &mut Vec< u8 > ) {
+    let body = CannedBody::new( id, result );
+    let mut header = [ 0; HEADER_CAPACITY ];
+    let header_len = transport::format_header( &mut header, body.len( ) );
+
+    buffer.reserve( header_len + body.len( ) );
+    buffer.extend_from_slice( &header[ ..header_len ] );
+    for part in &body.parts( ) {
+        buffer.extend_from_slice( part );
+    }
+}
+
+/// Returns the id and the CannedResult of a response that can be written from static buffers.
+pub(crate) fn canned_response( envelope : &MessageEnvelope< OutgoingServerMessage > ) -> Option< ( i64, CannedResult ) > {
+    if !envelope.headers.is_empty( ) {
+        return None;
+    }
+
+    match envelope.message {
+        OutgoingMessage::Response( ResponseMessage { id, result : Some( ref result ), error : None } ) => {
+            CannedResult::of( result ).map( | canned | ( id, canned ) )
+        },
+        _ => None
+    }
+}
+
+impl Display for NotCanned {
+
+    fn fmt( &self, f : &mut fmt::Formatter ) -> fmt::Result {
+        write!( f, "Value is neither null nor an empty sequence" )
+    }
+
+}
+
+impl error::Error for NotCanned {
+
+    fn description( &self ) -> &str {
+        "Value is neither null nor an empty sequence"
+    }
+
+}
+
+impl ser::Error for NotCanned {
+
+    fn custom< T : Display >( _msg : T ) -> Self {
+        NotCanned
+    }
+
+}
+
+impl SerializeSeq for EmptySequence {
+
+    type Ok    = CannedResult;
+    type Error = NotCanned;
+
+    fn serialize_element< T : Serialize + ?Sized >( &mut self, _value : &T ) -> Result< ( ), NotCanned > {
+        Err( NotCanned )
+    }
+
+    fn end( self ) -> Result< CannedResult, NotCanned > {
+        Ok( CannedResult::EmptyArray )
+    }
+
+}
+
+impl Serializer for ResultShape {
+
+    type Ok                     = CannedResult;
+    type Error                  = NotCanned;
+    type SerializeSeq           = EmptySequence;
+    type SerializeTuple         = Impossible< CannedResult, NotCanned >;
+    type SerializeTupleStruct   = Impossible< CannedResult, NotCanned >;
+    type SerializeTupleVariant  = Impossible< CannedResult, NotCanned >;
+    type SerializeMap           = Impossible< CannedResult, NotCanned >;
+    type SerializeStruct        = Impossible< CannedResult, NotCanned >;
+    type SerializeStructVariant = Impossible< CannedResult, NotCanned >;
+
+    fn serialize_none( self ) -> Result< CannedResult, NotCanned > {
+        Ok( CannedResult::Null )
+    }
+
+    fn serialize_unit( self ) -> Result< CannedResult, NotCanned > {
+        Ok( CannedResult::Null )
+    }
+
+    fn serialize_unit_struct( self, _name : &'static str ) -> Result< CannedResult, NotCanned > {
+        Ok( CannedResult::Null )
+    }
+
+    fn serialize_some< T : Serialize + ?Sized >( self, value : &T ) -> Result< CannedResult, NotCanned > {
+        value.serialize( self )
+    }
+
+    fn serialize_newtype_struct< T : Serialize + ?Sized >( self, _name : &'static str, value : &T ) -> Result< CannedResult, NotCanned > {
+        value.serialize( self )
+    }
+
+    fn serialize_seq( self, len : Option< usize > ) -> Result< EmptySequence, NotCanned > {
+        match len {
+            Some( 0 ) | None => Ok( EmptySequence ),
+            Some( _ ) => Err( NotCanned )
+        }
+    }
+
+    fn serialize_bool( self, _value : bool ) -> Result< CannedResult, NotCanned > {
+        Err( NotCanned )
+    }
+
+    fn serialize_i8( self, _value : i8 ) -> Result< CannedResult, NotCanned > {
+        Err( NotCanned )
+    }
+
+    fn serialize_i16( self, _value : i16 ) -> Result< CannedResult, NotCanned > {
+        Err( NotCanned )
+    }
+
+    fn serialize_i32( self, _value : i32 ) -> Result< CannedResult, NotCanned > {
+        Err( NotCanned )
+    }
+
+    fn serialize_i64( self, _value : i64 ) -> Result< CannedResult, NotCanned > {
+        Err( NotCanned )
+    }
+
+    fn serialize_u8( self, _value : u8 ) -> Result< CannedResult, NotCanned > {
+        Err( NotCanned )
+    }
+
+    fn serialize_u16( self, _value : u16 ) -> Result< CannedResult, NotCanned > {
+        Err( NotCanned )
+    }
+
+    fn serialize_u32( self, _value : u32 ) -> Result< CannedResult, NotCanned > {
+        Err( NotCanned )
+    }
+
+    fn serialize_u64( self, _value : u64 ) -> Result< CannedResult, NotCanned > {
+        Err( NotCanned )
+    }
+
+    fn serialize_f32( self, _value : f32 ) -> Result< CannedResult, NotCanned > {
+        Err( NotCanned )
+    }
+
+    fn serialize_f64( self, _value : f64 ) -> Result< CannedResult, NotCanned > {
+        Err( NotCanned )
+    }
+
+    fn serialize_char( self, _value : char ) -> Result< CannedResult, NotCanned > {
+        Err( NotCanned )
+    }
+
+    fn serialize_str( self, _value : &str ) -> Result< CannedResult, NotCanned > {
+        Err( NotCanned )
+    }
+
+    fn serialize_bytes( self, _value : &[ u8 ] ) -> Result< CannedResult, NotCanned > {
+        Err( NotCanned )
+    }
+
+    fn serialize_unit_variant( self, _name : &'static str, _index : u32, _variant : &'static str ) -> Result< CannedResult, NotCanned > {
+        Err( NotCanned )
+    }
+
+    fn serialize_newtype_variant< T : Serialize + ?Sized >( self, _name : &'static str, _index : u32, _variant : &'static str, _value : &T ) -> Result< CannedResult, NotCanned > {
+        Err( NotCanned )
+    }
+
+    fn serialize_tuple( self, _len : usize ) -> Result< Self::SerializeTuple, NotCanned > {
+        Err( NotCanned )
+    }
+
+    fn serialize_tuple_struct( self, _name : &'static str, _len : usize ) -> Result< Self::SerializeTupleStruct, NotCanned > {
+        Err( NotCanned )
+    }
+
+    fn serialize_tuple_variant( self, _name : &'static str, _index : u32, _variant : &'static str, _len : usize ) -> Result< Self::SerializeTupleVariant, NotCanned > {
+        Err( NotCanned )
+    }
+
+    fn serialize_map( self, _len : Option< usize > ) -> Result< Self::SerializeMap, NotCanned > {
+        Err( NotCanned )
+    }
+
+    fn serialize_struct( self, _name : &'static str, _len : usize ) -> Result< Self::SerializeStruct, NotCanned > {
+        Err( NotCanned )
+    }
+
+    fn serialize_struct_variant( self, _name : &'static str, _index : u32, _variant : &'static str, _len : usize ) -> Result< Self::SerializeStructVariant, NotCanned > {
+        Err( NotCanned )
+    }
+
+    // The default formats value into a String before serializing it
+    fn collect_str< T : Display + ?Sized >( self, _value : &T ) -> Result< CannedResult, NotCanned > {
+        Err( NotCanned )
+    }
+
+}
@@ -0,0 +1,93 @@
+
+use line_index::{
+    PositionEncoding
+};
+use lsp_rs::{
+    ClientCapabilities,
+    MarkupKind
+};
+
+/// Predicates over the capabilities sent by the client in its initialize request
+///
+/// Every predicate answers false, or the default of the protocol, when the capability was not sent.
+///
+/// ```ignore
+/// let snippets = context.service( ).client_capabilities( ).map( | capabilities | {
+///     capabilities.supports_snippet_completions( )
+/// } ).unwrap_or( false );
+/// ```
+pub trait ClientCapabilitiesExt {
+
+    /// Returns true if completion items may be snippets.
+    fn supports_snippet_completions( &self ) -> bool;
+
+    /// Returns true if the server may create work done progress through window/workDoneProgress/create.
+    fn supports_work_done_progress( &self ) -> bool;
+
+    /// Returns the markup kinds supported in hover contents, in the order of preference of the client.
+    fn supported_markup_kinds( &self ) -> Vec< MarkupKind >;
+
+    /// Returns the encoding of the positions exchanged with the client, the first encoding offered by the
+    /// client or UTF-16 if it offered none.
+    fn position_encoding( &self ) -> PositionEncoding;
+
+    /// Returns true if the server may send workspace/applyEdit requests.
+    fn supports_apply_edit( &self ) -> bool;
+
+    /// Returns true if the client sends textDocument/willSaveWaitUntil requests and applies the returned edits
+    /// before saving.
+    fn supports_will_save_wait_until( &self ) -> bool;
+
+}
+
+impl ClientCapabilitiesExt for ClientCapabilities {
+
+    fn supports_snippet_completions( &self ) -> bool {
+        self.text_document.as_ref( )
+            .and_then( | text_document | text_document.completion.as_ref( ) )
+            .and_then( | completion | completion.completion_item.as_ref( ) )
+            .and_then( | completion_item | completion_item.snippet_support )
+            .unwrap_or( false )
+    }
+
+    fn supports_work_done_progress( &self ) -> bool {
+        self.window.as_ref( )
+            .and_then( | window | window.work_done_progress )
+            .unwrap_or( false )
+    }
+
+    fn supported_markup_kinds( &self ) -> Vec< MarkupKind > {
+        self.text_document.as_ref( )
+            .and_then( | text_document | text_document.hover.as_ref( ) )
+            .and_then( | hover | hover.content_format.clone( ) )
+            .unwrap_or_else( | | vec![ MarkupKind::PlainText ] )
+    }
+
+    fn position_encoding( &self ) -> PositionEncoding {
+        let encodings = self.general.as_ref( ).and_then( | general | general.position_encodings.as_ref( ) );
+        for encoding in encodings.into_iter( ).flat_map( | encodings | encodings.iter( ) ) {
+            match encoding.as_str( ) {
+                "utf-8" => return PositionEncoding::Utf8,
+                "utf-16" => return PositionEncoding::Utf16,
+                "utf-32" => return PositionEncoding::Utf32,
+                _ => { }
+            }
+        }
+
+        PositionEncoding::Utf16
+    }
+
+    fn supports_apply_edit( &self ) -> bool {
+        self.workspace.as_ref( )
+            .and_then( | workspace | workspace.apply_edit )
+            .unwrap_or( false )
+    }
+
+    fn supports_will_save_wait_until( &self ) -> bool {
+        self.text_document.as_ref( )
+            .and_then( | text_document | text_document.synchronization.as_ref( ) )
+            .and_then( | synchronization | synchronization.will_save_wait_until )
+            .unwrap_or( false )
+    }
+
+}
@@ -0,0 +1,452 @@
+
+use futures::task;
+use std::collections::{
+    VecDeque
+};
+use std::io::{
+    self,
+    Read,
+    Write
+};
+use tokio_core::io::{
+    Io
+};
+
+/// Fault injected by a FaultyStream, see FaultSchedule
+#[derive( Clone, Debug )]
+pub enum Fault {
+    /// The next operation transfers at most the given number of bytes (at least 1)
+    Partial( usize ),
+    /// The given number of operations fail with WouldBlock. The current task is notified, so the stream is
+    /// polled again as if it became ready right away
+    WouldBlock( usize ),
+    /// The connection is closed, reads return end of file and writes fail with BrokenPipe from then on
+    Disconnect,
+    /// The next operation fails with an error of the given kind
+    Error( io::ErrorKind ),
+    /// The given number of flushes fail with WouldBlock, the current task being notified. Usually scheduled
+    /// with FaultSchedule::on_write
+    DelayFlush( usize )
+}
+
+/// Faults injected by a FaultyStream, each at an offset in the bytes read or written by the stream
+///
+/// A fault triggers once the stream read (or wrote) offset bytes. Operations are shortened so they stop at
+/// the offset of the next fault, so a fault lands at an exact position of the stream, e.g. in the middle of
+/// the header or the body of a message.
+#[derive( Clone, Debug, Default )]
+pub struct FaultSchedule {
+    read  : Vec< ( usize, Fault ) >,
+    write : Vec< ( usize, Fault ) >
+}
+
+/// Wrapper around the Io of a service injecting the faults of a FaultSchedule, so the handling of read
+/// errors, write errors and disconnects by the service can be tested
+///
+/// Faults notifying the current task must be injected from within a task, which is always the case for the
+/// Io of a service.
+///
+/// ```ignore
+/// // Drops the connection in the middle of the first response, after a storm of spurious WouldBlock
+/// let schedule = FaultSchedule::new( )
+///     .on_read( 0, Fault::WouldBlock( 16 ) )
+///     .on_write( 40, Fault::Disconnect );
+///
+/// let ( server_io, client_io ) = transport::memory_transport( );
+/// let service = ServiceBuilder::new( core.handle( ) ).start( handler, FaultyStream::new( server_io, schedule ) );
+/// ```
+pub struct FaultyStream< S > {
+    inner         : S,
+    read          : FaultState,
+    write         : FaultState,
+    delayed_flush : usize
+}
+
+/// Faults of one direction of a FaultyStream and the progress of the stream in that direction
+struct FaultState {
+    faults       : VecDeque< ( usize, Fault ) >,
+    position     : usize,
+    partial      : Option< usize >,
+    would_block  : usize,
+    disconnected : bool,
+    injected     : usize
+}
+
+/// Outcome of the faults due before an operation
+enum Step {
+    /// The operation may transfer up to the given number of bytes
+    Proceed( usize ),
+    Fail( io::Error ),
+    Disconnected
+}
+
+impl FaultSchedule {
+
+    /// Creates a schedule without faults.
+    pub fn new( ) -> Self {
+        FaultSchedule::default( )
+    }
+
+    /// Injects fault once offset bytes were read.
+    pub fn on_read( mut self, offset : usize, fault : Fault ) -> Self {
+        self.read.push( ( offset, fault ) );
+
+        self
+    }
+
+    /// Injects fault once offset bytes were written.
+    pub fn on_write( mut self, offset : usize, fault : Fault ) -> Self {
+        self.write.push( ( offset, fault ) );
+
+        self
+    }
+
+}
+
+impl < S > FaultyStream< S > {
+
+    /// Wraps inner, injecting the faults of schedule.
+    pub fn new( inner : S, schedule : FaultSchedule ) -> Self {
+        FaultyStream {
+            inner         : inner,
+            read          : FaultState::new( schedule.read ),
+            write         : FaultState::new( schedule.write ),
+            delayed_flush : 0
+        }
+    }
+
+    /// Returns the number of bytes read through the stream.
+    pub fn bytes_read( &self ) -> usize {
+        self.read.position
+    }
+
+    /// Returns the number of bytes written through the stream.
+    pub fn bytes_written( &self ) -> usize {
+        self.write.position
+    }
+
+    /// Returns the number of faults that were triggered.
+    pub fn faults_injected( &self ) -> usize {
+        self.read.injected + self.write.injected
+    }
+
+    pub fn get_ref( &self ) -> &S {
+        &self.inner
+    }
+
+    pub fn into_inner( self ) -> S {
+        self.inner
+    }
+
+}
+
+impl FaultState {
+
+    fn new( mut faults : Vec< ( usize, Fault ) > ) -> Self {
+        // Stable, faults at the same offset trigger in the order they were scheduled
+        faults.sort_by_key( | &( offset, _ ) | offset );
+
+        FaultState {
+            faults       : faults.into_iter( ).collect( ),
+            position     : 0,
+            partial      : None,
+            would_block  : 0,
+            disconnected : false,
+            injected     : 0
+        }
+    }
+
+    /// Triggers the faults due at the current position, returning how the next operation of len bytes
+    /// proceeds. DelayFlush faults are added to delayed_flush.
+    fn before_operation( &mut self, len : usize, delayed_flush : &mut usize ) -> Step {
+        loop {
+            if self.disconnected {
+                return Step::Disconnected;
+            }
+            if self.would_block > 0 {
+                self.would_block -= 1;
+                task::current( ).notify( );
+
+                return Step::Fail( io::Error::new( io::ErrorKind::WouldBlock, "Injected WouldBlock" ) );
+            }
+
+            let due = self.faults.front( ).map( | &( offset, _ ) | offset <= self.position ).unwrap_or( false );
+            if !due {
+                break;
+            }
+
+            let ( _, fault ) = self.faults.pop_front( ).unwrap( );
+            self.injected += 1;
+            match fault {
+                Fault::Partial( count ) => self.partial = Some( count.max( 1 ) ),
+                Fault::WouldBlock( count ) => self.would_block += count,
+                Fault::Disconnect => self.disconnected = true,
+                Fault::Error( kind ) => return Step::Fail( io::Error::new( kind, "Injected error" ) ),
+                Fault::DelayFlush( count ) => *delayed_flush += count
+            }
+        }
+
+        let mut limit = len;
+        if let Some( partial ) = self.partial.take( ) {
+            limit = limit.min( partial );
+        }
+        if let Some( &( offset, _ ) ) = self.faults.front( ) {
+            limit = limit.min( offset - self.position );
+        }
+
+        Step::Proceed( limit )
+    }
+
+}
+
+impl < S : Read > Read for FaultyStream< S > {
+
+    fn read( &mut self, buf : &mut [ u8 ] ) -> io::Result< usize > {
+        let limit = match self.read.before_operation( buf.len( ), &mut self.delayed_flush ) {
+            Step::Proceed( limit ) => limit,
+            Step::Fail( error ) => return Err( error ),
+            Step::Disconnected => return Ok( 0 )
+        };
+
+        let count = self.inner.read( &mut buf[ ..limit ] )?;
+        self.read.position += count;
+
+        Ok( count )
+    }
+
+}
+
+impl < S : Write > Write for FaultyStream< S > {
+
+    fn write( &mut self, buf : &[ u8 ] ) -> io::Result< usize > {
+        let limit = match self.write.before_operation( buf.len( ), &mut self.delayed_flush ) {
+            Step::Proceed( limit ) => limit,
+            Step::Fail( error ) => return Err( error ),
+            Step::Disconnected => return Err( io::Error::new( io::ErrorKind::BrokenPipe, "Injected disconnect" ) )
+        };
+
+        let count = self.inner.write( &buf[ ..limit ] )?;
+        self.write.position += count;
+
+        Ok( count )
+    }
+
+    fn flush( &mut self ) -> io::Result< ( ) > {
+        if self.write.disconnected {
+            return Err( io::Error::new( io::ErrorKind::BrokenPipe, "Injected disconnect" ) );
+        }
+        if self.delayed_flush > 0 {
+            self.delayed_flush -= 1;
+            task::current( ).notify( );
+
+            return Err( io::Error::new( io::ErrorKind::WouldBlock, "Injected flush delay" ) );
+        }
+
+        self.inner.flush( )
+    }
+
+}
+
+impl < S : Io > Io for FaultyStream< S > {
+}
+
+#[cfg( test )]
+mod tests {
+
+    use mock_client::{
+        MockClient
+    };
+    use serde_json::{
+        Value
+    };
+    use service::{
+        WriteRetryPolicy
+    };
+    use std::io;
+    use std::sync::atomic::{
+        Ordering
+    };
+    use std::time::{
+        Duration
+    };
+    use super::{
+        Fault,
+        FaultSchedule,
+        FaultyStream
+    };
+    use testing::{
+        self,
+        NullHandler,
+        Outcome
+    };
+
+    fn connect( schedule : FaultSchedule ) -> MockClient {
+        MockClient::with_server_io( NullHandler, | builder | builder, | server_io | {
+            FaultyStream::new( server_io, schedule )
+        } ).unwrap( )
+    }
+
+    fn connect_retrying( schedule : FaultSchedule ) -> MockClient {
+        MockClient::with_server_io( NullHandler, | builder | {
+            builder.write_retry( WriteRetryPolicy::new( 3, Duration::from_millis( 1 ) ) )
+        }, | server_io | {
+            FaultyStream::new( server_io, schedule )
+        } ).unwrap( )
+    }
+
+    /// Returns the number of responses to request id client received.
+    fn responses( client : &MockClient, id : i64 ) -> usize {
+        client.transcript( ).iter( ).filter( | message | message[ "id" ] == id && message.get( "method" ).is_none( ) ).count( )
+    }
+
+    #[test]
+    fn read_error_fails_the_service( ) {
+        let schedule = FaultSchedule::new( ).on_read( testing::framed_len( &testing::hover( 1 ) ), Fault::Error( io::ErrorKind::Other ) );
+        let mut client = connect( schedule );
+        let outcome = testing::watch_shutdown( &client );
+
+        client.send_message( &testing::hover( 1 ) ).unwrap( );
+        let result : Value = client.response( 1 ).unwrap( );
+        assert_eq!( result, Value::Null );
+
+        testing::run_until_stalled( &mut client );
+        assert_eq!( *outcome.lock( ).unwrap( ), Some( Outcome::ReadError ) );
+    }
+
+    #[test]
+    fn connection_reset_on_read_is_a_disconnect( ) {
+        let mut client = connect( FaultSchedule::new( ).on_read( 0, Fault::Error( io::ErrorKind::ConnectionReset ) ) );
+        let outcome = testing::watch_shutdown( &client );
+        let disconnected = testing::watch_disconnect( &client );
+
+        testing::run_until_stalled( &mut client );
+        assert_eq!( *outcome.lock( ).unwrap( ), Some( Outcome::Disconnected ) );
+        assert!( disconnected.load( Ordering::SeqCst ) );
+    }
+
+    #[test]
+    fn disconnect_between_messages_is_a_clean_shutdown( ) {
+        let schedule = FaultSchedule::new( ).on_read( testing::framed_len( &testing::hover( 1 ) ), Fault::Disconnect );
+        let mut client = connect( schedule );
+        let outcome = testing::watch_shutdown( &client );
+
+        client.send_message( &testing::hover( 1 ) ).unwrap( );
+        client.send_message( &testing::hover( 2 ) ).unwrap( );
+        let result : Value = client.response( 1 ).unwrap( );
+        assert_eq!( result, Value::Null );
+
+        testing::run_until_stalled( &mut client );
+        assert_eq!( *outcome.lock( ).unwrap( ), Some( Outcome::ClientDisconnected ) );
+        assert!( !client.has_response( 2 ) );
+    }
+
+    #[test]
+    fn disconnect_in_the_middle_of_a_message_is_a_disconnect( ) {
+        let mut client = connect( FaultSchedule::new( ).on_read( 24, Fault::Disconnect ) );
+        let outcome = testing::watch_shutdown( &client );
+
+        client.send_message( &testing::hover( 1 ) ).unwrap( );
+
+        testing::run_until_stalled( &mut client );
+        assert_eq!( *outcome.lock( ).unwrap( ), Some( Outcome::Disconnected ) );
+    }
+
+    #[test]
+    fn write_error_fails_the_service( ) {
+        let mut client = connect( FaultSchedule::new( ).on_write( 0, Fault::Error( io::ErrorKind::Other ) ) );
+        let outcome = testing::watch_shutdown( &client );
+        let disconnected = testing::watch_disconnect( &client );
+
+        client.send_message( &testing::hover( 1 ) ).unwrap( );
+
+        testing::run_until_stalled( &mut client );
+        assert_eq!( *outcome.lock( ).unwrap( ), Some( Outcome::WriteError ) );
+        assert!( !client.has_response( 1 ) );
+        assert!( !disconnected.load( Ordering::SeqCst ) );
+    }
+
+    #[test]
+    fn broken_pipe_on_write_is_a_disconnect( ) {
+        let mut client = connect( FaultSchedule::new( ).on_write( 10, Fault::Disconnect ) );
+        let outcome = testing::watch_shutdown( &client );
+        let disconnected = testing::watch_disconnect( &client );
+
+        client.send_message( &testing::hover( 1 ) ).unwrap( );
+
+        testing::run_until_stalled( &mut client );
+        assert_eq!( *outcome.lock( ).unwrap( ), Some( Outcome::Disconnected ) );
+        assert!( disconnected.load( Ordering::SeqCst ) );
+    }
+
+    #[test]
+    fn connection_reset_on_write_runs_the_disconnect_hooks( ) {
+        let mut client = connect( FaultSchedule::new( ).on_write( 0, Fault::Error( io::ErrorKind::ConnectionReset ) ) );
+        let outcome = testing::watch_shutdown( &client );
+        let disconnected = testing::watch_disconnect( &client );
+
+        client.send_message( &testing::hover( 1 ) ).unwrap( );
+
+        testing::run_until_stalled( &mut client );
+        assert_eq!( *outcome.lock( ).unwrap( ), Some( Outcome::Disconnected ) );
+        assert!( disconnected.load( Ordering::SeqCst ) );
+    }
+
+    #[test]
+    fn write_failing_once_is_retried_without_losing_or_duplicating_responses( ) {
+        let mut client = connect_retrying( FaultSchedule::new( ).on_write( 0, Fault::Error( io::ErrorKind::TimedOut ) ) );
+        let outcome = testing::watch_shutdown( &client );
+
+        for id in 1..4 {
+            client.send_message( &testing::hover( id ) ).unwrap( );
+        }
+        for id in 1..4 {
+            let result : Value = client.response( id ).unwrap( );
+            assert_eq!( result, Value::Null );
+        }
+
+        testing::run_until_stalled( &mut client );
+        assert_eq!( *outcome.lock( ).unwrap( ), None );
+        for id in 1..4 {
+            assert_eq!( responses( &client, id ), 1 );
+        }
+    }
+
+    #[test]
+    fn write_failing_in_the_middle_of_a_message_is_not_retried( ) {
+        let mut client = connect_retrying( FaultSchedule::new( ).on_write( 10, Fault::Error( io::ErrorKind::TimedOut ) ) );
+        let outcome = testing::watch_shutdown( &client );
+
+        client.send_message( &testing::hover( 1 ) ).unwrap( );
+
+        testing::run_until_stalled( &mut client );
+        assert_eq!( *outcome.lock( ).unwrap( ), Some( Outcome::WriteError ) );
+        assert_eq!( responses( &client, 1 ), 0 );
+    }
+
+    #[test]
+    fn partial_operations_and_would_block_storms_are_not_errors( ) {
+        let schedule = FaultSchedule::new( )
+            .on_read( 0, Fault::WouldBlock( 16 ) )
+            .on_read( 5, Fault::Partial( 3 ) )
+            .on_read( 40, Fault::Partial( 1 ) )
+            .on_read( 41, Fault::WouldBlock( 4 ) )
+            .on_write( 0, Fault::WouldBlock( 8 ) )
+            .on_write( 0, Fault::DelayFlush( 4 ) )
+            .on_write( 10, Fault::Partial( 4 ) )
+            .on_write( 60, Fault::Partial( 1 ) );
+        let mut client = connect( schedule );
+        let outcome = testing::watch_shutdown( &client );
+
+        for id in 1..4 {
+            client.send_message( &testing::hover( id ) ).unwrap( );
+        }
+        for id in 1..4 {
+            let result : Value = client.response( id ).unwrap( );
+            assert_eq!( result, Value::Null );
+        }
+
+        testing::run_until_stalled( &mut client );
+        assert_eq!( *outcome.lock( ).unwrap( ), None );
+    }
+
+}
@@ -0,0 +1,283 @@
+
+use futures::{
+    Async,
+    Future,
+    Poll,
+    Stream
+};
+use futures::task::{
+    self,
+    Task
+};
+use std::io;
+use std::sync::{
+    Arc,
+    Mutex
+};
+use std::time::{
+    Duration,
+    Instant
+};
+use tokio_core::reactor::{
+    Handle,
+    Interval,
+    Timeout
+};
+
+/// Source of the time used by the service for timeouts, deadlines, heartbeats and slow request detection
+///
+/// The system clock, used by default, follows the real time and waits on tokio timers. A manual clock only
+/// moves when ManualClock::advance is called, so tests can exercise time based features without sleeping:
+///
+/// ```ignore
+/// let clock = ManualClock::new( );
+/// let service = ServiceBuilder::new( core.handle( ) )
+///     .clock( clock.clock( ) )
+///     .slow_request_progress( Duration::from_secs( 1 ) )
+///     .start( handler, server_io );
+///
+/// clock.advance( Duration::from_secs( 2 ) );
+/// ```
+#[derive( Clone )]
+pub struct Clock {
+    manual : Option< Arc< Mutex< ManualTime > > >
+}
+
+/// Handle controlling the time of a manual Clock, see Clock
+#[derive( Clone )]
+pub struct ManualClock {
+    time : Arc< Mutex< ManualTime > >
+}
+
+struct ManualTime {
+    start   : Instant,
+    elapsed : Duration,
+    waiters : Vec< Task >
+}
+
+/// Future resolving once a duration elapsed on a Clock, created by Clock::sleep
+pub struct Sleep {
+    timer : Timer< Timeout >
+}
+
+/// Stream yielding every time a period elapsed on a Clock, created by Clock::interval
+pub struct Ticks {
+    timer  : Timer< Interval >,
+    period : Duration
+}
+
+enum Timer< T > {
+    System( T ),
+    Manual( Arc< Mutex< ManualTime > >, Instant )
+}
+
+impl Clock {
+
+    /// Returns the clock following the real time.
+    pub fn system( ) -> Self {
+        Clock {
+            manual : None
+        }
+    }
+
+    /// Returns true if the clock is controlled by a ManualClock.
+    pub fn is_manual( &self ) -> bool {
+        self.manual.is_some( )
+    }
+
+    /// Returns the current time of the clock.
+    pub fn now( &self ) -> Instant {
+        match self.manual {
+            Some( ref time ) => time.lock( ).unwrap( ).now( ),
+            None => Instant::now( )
+        }
+    }
+
+    /// Returns the time elapsed on the clock since earlier, zero if earlier is later than the current time.
+    pub fn elapsed( &self, earlier : Instant ) -> Duration {
+        let now = self.now( );
+        if now > earlier {
+            now.duration_since( earlier )
+        }
+        else {
+            Duration::from_secs( 0 )
+        }
+    }
+
+    /// Returns the time left on the clock until later, zero if later is not after the current time.
+    pub fn until( &self, later : Instant ) -> Duration {
+        let now = self.now( );
+        if later > now {
+            later.duration_since( now )
+        }
+        else {
+            Duration::from_secs( 0 )
+        }
+    }
+
+    /// Returns a future resolving once duration elapsed on the clock. Timers of the system clock run on
+    /// the event loop of handle.
+    pub fn sleep( &self, duration : Duration, handle : &Handle ) -> io::Result< Sleep > {
+        let timer = match self.manual {
+            Some( ref time ) => {
+                let deadline = time.lock( ).unwrap( ).now( ) + duration;
+
+                Timer::Manual( time.clone( ), deadline )
+            },
+            None => Timer::System( Timeout::new( duration, handle )? )
+        };
+
+        Ok( Sleep {
+            timer : timer
+        } )
+    }
+
+    /// Returns a stream yielding every time period elapsed on the clock, starting period from now. Timers of
+    /// the system clock run on the event loop of handle.
+    pub fn interval( &self, period : Duration, handle : &Handle ) -> io::Result< Ticks > {
+        let timer = match self.manual {
+            Some( ref time ) => {
+                let next = time.lock( ).unwrap( ).now( ) + period;
+
+                Timer::Manual( time.clone( ), next )
+            },
+            None => Timer::System( Interval::new( period, handle )? )
+        };
+
+        Ok( Ticks {
+            timer  : timer,
+            period : period
+        } )
+    }
+
+}
+
+impl Default for Clock {
+
+    fn default( ) -> Self {
+        Clock::system( )
+    }
+
+}
+
+impl ManualClock {
+
+    /// Creates a manual clock starting at the current real time.
+    pub fn new( ) -> Self {
+        ManualClock {
+            time : Arc::new( Mutex::new( ManualTime {
+                start   : Instant::now( ),
+                elapsed : Duration::from_secs( 0 ),
+                waiters : Vec::new( )
+            } ) )
+        }
+    }
+
+    /// Returns a Clock following the time of this manual clock, to give to ServiceBuilder::clock.
+    pub fn clock( &self ) -> Clock {
+        Clock {
+            manual : Some( self.time.clone( ) )
+        }
+    }
+
+    /// Moves the time of the clock forward by duration, waking the timers that expired.
+    ///
+    /// Expired timers resolve the next time the event loop runs, e.g. through MockClient::step.
+    pub fn advance( &self, duration : Duration ) {
+        let waiters = {
+            let mut time = self.time.lock( ).unwrap( );
+            time.elapsed += duration;
+
+            // Timers that did not expire register again when polled
+            time.waiters.drain( .. ).collect::< Vec< _ > >( )
+        };
+
+        for waiter in waiters {
+            waiter.notify( );
+        }
+    }
+
+    /// Returns the time elapsed on the clock since it was created.
+    pub fn elapsed( &self ) -> Duration {
+        self.time.lock( ).unwrap( ).elapsed
+    }
+
+}
+
+impl Default for ManualClock {
+
+    fn default( ) -> Self {
+        ManualClock::new( )
+    }
+
+}
+
+impl ManualTime {
+
+    fn now( &self ) -> Instant {
+        self.start + self.elapsed
+    }
+
+}
+
+impl < T > Timer< T > {
+
+    /// Polls a manual timer, registering the current task to be woken by ManualClock::advance if it has not
+    /// expired.
+    fn poll_manual( time : &Arc< Mutex< ManualTime > >, deadline : Instant ) -> bool {
+        let mut time = time.lock( ).unwrap( );
+        if time.now( ) >= deadline {
+            return true;
+        }
+
+        time.waiters.push( task::current( ) );
+
+        false
+    }
+
+}
+
+impl Future for Sleep {
+
+    type Item  = ( );
+    type Error = io::Error;
+
+    fn poll( &mut self ) -> Poll< Self::Item, Self::Error > {
+        match self.timer {
+            Timer::System( ref mut timeout ) => timeout.poll( ),
+            Timer::Manual( ref time, deadline ) => {
+                if Timer::< Timeout >::poll_manual( time, deadline ) {
+                    Ok( Async::Ready( ( ) ) )
+                }
+                else {
+                    Ok( Async::NotReady )
+                }
+            }
+        }
+    }
+
+}
+
+impl Stream for Ticks {
+
+    type Item  = ( );
+    type Error = io::Error;
+
+    fn poll( &mut self ) -> Poll< Option< Self::Item >, Self::Error > {
+        match self.timer {
+            Timer::System( ref mut interval ) => interval.poll( ),
+            Timer::Manual( ref time, ref mut next ) => {
+                if Timer::< Interval >::poll_manual( time, *next ) {
+                    // Advancing the clock by several periods yields one tick per period
+                    *next += self.period;
+
+                    Ok( Async::Ready( Some( ( ) ) ) )
+                }
+                else {
+                    Ok( Async::NotReady )
+                }
+            }
+        }
+    }
+
+}
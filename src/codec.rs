@@ -0,0 +1,597 @@
+
+use canned::{
+    self,
+    CannedBody
+};
+use clock::{
+    Clock
+};
+use logging::{
+    Component
+};
+use lsp_rs::{
+    MessageEnvelope,
+    OutgoingServerMessage,
+    ServerCodec
+};
+use std::cell::{
+    RefCell
+};
+use std::collections::{
+    VecDeque
+};
+use std::error::{
+    Error
+};
+use std::fmt;
+use std::io::{
+    self,
+    IoSlice,
+    Write
+};
+use std::rc::{
+    Rc
+};
+use std::sync::{
+    Arc,
+    Mutex
+};
+use std::time::{
+    Instant
+};
+use tokio_core::io::{
+    Codec,
+    EasyBuf
+};
+use transport::{
+    self,
+    HEADER_CAPACITY
+};
+
+/// Number of quarantined messages kept by DeadLetters
+const DEAD_LETTER_CAPACITY : usize = 32;
+
+/// Maximum number of buffers handed to the transport by a single vectored write
+const MAX_WRITE_SLICES : usize = 32;
+
+/// Number of buffers of written frames kept by a FrameQueue to encode the next frames into
+const SPARE_BUFFERS : usize = 16;
+
+/// Codec of the transport of a service, wrapping the ServerCodec of lsp_rs
+///
+/// Frames are found with the memchr header scan of transport::frame_length, the ServerCodec is only handed
+/// complete frames instead of parsing the header again every time part of a large message is read. Responses
+/// with a CannedResult are written from static buffers, see canned::CannedResult. Errors decoding a frame
+/// are wrapped in a ProtocolError, so the service can tell a malformed message from the transport failing.
+///
+/// With a quarantine threshold, a frame that cannot be decoded is skipped and kept in DeadLetters instead,
+/// see ServiceBuilder::quarantine_malformed_messages.
+pub(crate) struct ServiceCodec {
+    inner         : ServerCodec,
+    quarantine    : Quarantine,
+    clock         : Clock,
+    /// Time the first bytes of the frame being received were read, usually along with its whole header
+    frame_started : Option< Instant >
+}
+
+/// Message decoded by a ServiceCodec, with the time the first bytes of its frame were read
+pub(crate) struct ReceivedMessage {
+    pub envelope : < ServerCodec as Codec >::In,
+    pub received : Instant
+}
+
+/// Message skipped by the service because it could not be decoded
+#[derive( Clone, Debug )]
+pub struct QuarantinedMessage {
+    /// Frame of the message, header included
+    pub frame : Vec< u8 >,
+    /// Error decoding the message
+    pub error : String
+}
+
+/// Ids of the requests quarantined by the codec of a service, answered by its reader with an INVALID_PARAMS
+/// error so the client does not wait for their response
+pub(crate) type RejectedRequests = Rc< RefCell< VecDeque< i64 > > >;
+
+/// Most recent messages quarantined by the codec of a service, shared with its ServiceHandle
+#[derive( Clone )]
+pub(crate) struct DeadLetters {
+    messages : Arc< Mutex< VecDeque< QuarantinedMessage > > >
+}
+
+/// Messages encoded for the transport of a service and not entirely written yet
+///
+/// Frames are written with vectored writes, the buffers of consecutive frames are handed to the transport at
+/// once instead of being copied into a write buffer first. Canned responses are written from the header and
+/// the static buffers of their body, see canned::CannedResult, other messages from the buffer they were
+/// encoded into by the ServerCodec, which is reused by the next frames once written.
+pub(crate) struct FrameQueue {
+    encoder : ServerCodec,
+    frames  : VecDeque< Frame >,
+    /// Bytes of the queued frames not written yet
+    len     : usize,
+    spare   : Vec< Vec< u8 > >
+}
+
+/// Message encoded by a FrameQueue
+struct Frame {
+    header     : [ u8; HEADER_CAPACITY ],
+    header_len : usize,
+    body       : FrameBody,
+    /// Bytes of the frame already written
+    written    : usize
+}
+
+enum FrameBody {
+    /// Message encoded by the ServerCodec, header included
+    Encoded( Vec< u8 > ),
+    Canned( CannedBody )
+}
+
+/// Tracks the malformed frames skipped in a row by ServiceCodec
+struct Quarantine {
+    /// Number of consecutive frames that can be quarantined, 0 if malformed frames are fatal
+    threshold    : usize,
+    consecutive  : usize,
+    dead_letters : DeadLetters,
+    rejected     : RejectedRequests
+}
+
+/// Error of a frame the ServerCodec could not decode, carried by the io::Error returned by ServiceCodec
+#[derive( Debug )]
+pub(crate) struct ProtocolError {
+    error : io::Error
+}
+
+impl ServiceCodec {
+
+    pub fn new( quarantine_threshold : usize, dead_letters : DeadLetters, rejected : RejectedRequests, clock : Clock ) -> Self {
+        ServiceCodec {
+            inner         : ServerCodec::new( ),
+            quarantine    : Quarantine {
+                threshold    : quarantine_threshold,
+                consecutive  : 0,
+                dead_letters : dead_letters,
+                rejected     : rejected
+            },
+            clock         : clock,
+            frame_started : None
+        }
+    }
+
+}
+
+impl FrameQueue {
+
+    pub fn new( ) -> Self {
+        FrameQueue {
+            encoder : ServerCodec::new( ),
+            frames  : VecDeque::new( ),
+            len     : 0,
+            spare   : Vec::new( )
+        }
+    }
+
+    /// Returns the number of bytes of the queued frames not written yet.
+    pub fn len( &self ) -> usize {
+        self.len
+    }
+
+    pub fn is_empty( &self ) -> bool {
+        self.frames.is_empty( )
+    }
+
+    /// Returns true if the frame at the front was partially written, its remaining bytes have to be written
+    /// before anything else can be written to the transport.
+    pub fn is_partially_written( &self ) -> bool {
+        self.frames.front( ).map_or( false, | frame | frame.written > 0 )
+    }
+
+    /// Encodes the message of envelope as a frame queued after the other frames.
+    pub fn encode( &mut self, envelope : MessageEnvelope< OutgoingServerMessage > ) -> io::Result< ( ) > {
+        let mut frame = Frame {
+            header     : [ 0; HEADER_CAPACITY ],
+            header_len : 0,
+            body       : FrameBody::Encoded( Vec::new( ) ),
+            written    : 0
+        };
+
+        if let Some( ( id, result ) ) = canned::canned_response( &envelope ) {
+            component_trace!( Component::Codec, "Encoding canned response {:?} for request {}.", result, id );
+
+            let body = CannedBody::new( id, result );
+            frame.header_len = transport::format_header( &mut frame.header, body.len( ) );
+            frame.body = FrameBody::Canned( body );
+        }
+        else {
+            let mut buffer = self.spare.pop( ).unwrap_or_default( );
+            if let Err( error ) = self.encoder.encode( envelope, &mut buffer ) {
+                buffer.clear( );
+                self.spare.push( buffer );
+
+                return Err( error );
+            }
+            frame.body = FrameBody::Encoded( buffer );
+        }
+
+        self.len += frame.len( );
+        self.frames.push_back( frame );
+
+        Ok( ( ) )
+    }
+
+    /// Writes the queued frames to writer with a single vectored write, returning the number of bytes
+    /// written.
+    pub fn write_to< W : Write >( &mut self, writer : &mut W ) -> io::Result< usize > {
+        let written = {
+            let mut slices = [ IoSlice::new( &[ ] ); MAX_WRITE_SLICES ];
+            let mut count = 0;
+            'frames: for frame in &self.frames {
+                for part in frame.unwritten_parts( ).iter( ).filter( | part | !part.is_empty( ) ) {
+                    if count == MAX_WRITE_SLICES {
+                        break 'frames;
+                    }
+
+                    slices[ count ] = IoSlice::new( part );
+                    count += 1;
+                }
+            }
+
+            match writer.write_vectored( &slices[ ..count ] )? {
+                0 if count > 0 => return Err( io::Error::new( io::ErrorKind::WriteZero, "Failed to write frame to the transport" ) ),
+                written => written
+            }
+        };
+
+        self.advance( written );
+
+        Ok( written )
+    }
+
+    /// Marks written bytes of the frames at the front as written, removing the frames entirely written.
+    fn advance( &mut self, mut written : usize ) {
+        self.len -= written;
+
+        while let Some( remaining ) = self.frames.front( ).map( | frame | frame.len( ) ) {
+            if written < remaining {
+                self.frames[ 0 ].written += written;
+
+                return;
+            }
+
+            written -= remaining;
+            let frame = self.frames.pop_front( ).unwrap( );
+            if let FrameBody::Encoded( mut buffer ) = frame.body {
+                if self.spare.len( ) < SPARE_BUFFERS {
+                    buffer.clear( );
+                    self.spare.push( buffer );
+                }
+            }
+        }
+    }
+
+}
+
+impl Frame {
+
+    /// Returns the number of bytes of the frame not written yet.
+    fn len( &self ) -> usize {
+        let total = self.header_len + match self.body {
+            FrameBody::Encoded( ref buffer ) => buffer.len( ),
+            FrameBody::Canned( ref body ) => body.len( )
+        };
+
+        total - self.written
+    }
+
+    /// Returns the buffers of the frame not written yet, in order.
+    fn unwritten_parts( &self ) -> [ &[ u8 ]; 4 ] {
+        let mut parts = match self.body {
+            FrameBody::Encoded( ref buffer ) => [ &self.header[ ..self.header_len ], &buffer[ .. ], &[ ][ .. ], &[ ][ .. ] ],
+            FrameBody::Canned( ref body ) => {
+                let [ prefix, id, suffix ] = body.parts( );
+
+                [ &self.header[ ..self.header_len ], prefix, id, suffix ]
+            }
+        };
+
+        let mut skipped = self.written;
+        for part in parts.iter_mut( ) {
+            let skip = skipped.min( part.len( ) );
+            *part = &part[ skip.. ];
+            skipped -= skip;
+        }
+
+        parts
+    }
+
+}
+
+impl DeadLetters {
+
+    pub fn new( ) -> Self {
+        DeadLetters {
+            messages : Arc::new( Mutex::new( VecDeque::with_capacity( DEAD_LETTER_CAPACITY ) ) )
+        }
+    }
+
+    fn push( &self, message : QuarantinedMessage ) {
+        let mut messages = self.messages.lock( ).unwrap( );
+        if messages.len( ) == DEAD_LETTER_CAPACITY {
+            messages.pop_front( );
+        }
+
+        messages.push_back( message );
+    }
+
+    pub fn messages( &self ) -> Vec< QuarantinedMessage > {
+        self.messages.lock( ).unwrap( ).iter( ).cloned( ).collect( )
+    }
+
+}
+
+impl Quarantine {
+
+    /// Quarantines frame, returning false once more frames than the threshold failed to decode in a row.
+    fn admit( &mut self, frame : &[ u8 ], error : &io::Error ) -> bool {
+        self.consecutive += 1;
+        if self.consecutive > self.threshold {
+            component_error!( Component::Codec, "{} malformed messages in a row, giving up on the stream.", self.consecutive );
+
+            return false;
+        }
+
+        component_error!( Component::Codec, "Quarantining malformed message ({} of {} in a row): {}", self.consecutive, self.threshold, error );
+        // A well-formed request whose params do not match its method still gets a response
+        if let Some( id ) = transport::request_id( frame ) {
+            self.rejected.borrow_mut( ).push_back( id );
+        }
+        self.dead_letters.push( QuarantinedMessage {
+            frame : frame.to_vec( ),
+            error : error.to_string( )
+        } );
+
+        true
+    }
+
+}
+
+impl Codec for ServiceCodec {
+
+    type In  = ReceivedMessage;
+    type Out = MessageEnvelope< OutgoingServerMessage >;
+
+    fn decode( &mut self, buffer : &mut EasyBuf ) -> io::Result< Option< Self::In > > {
+        loop {
+            // Decoding is attempted after every read, so the first attempt for a frame directly follows the
+            // read of its first bytes
+            if self.frame_started.is_none( ) && !buffer.as_slice( ).is_empty( ) {
+                self.frame_started = Some( self.clock.now( ) );
+            }
+
+            // Frames are found here with a memchr scan of the header, the ServerCodec is only handed complete
+            // frames instead of parsing the header again every time part of a large message is read
+            let length = match transport::frame_length( buffer.as_slice( ) ) {
+                Ok( Some( length ) ) => length,
+                Ok( None ) => return Ok( None ),
+                // Without a valid header the next frame cannot be found
+                Err( error ) => return Err( protocol_error( io::Error::new( io::ErrorKind::InvalidData, error ) ) )
+            };
+            let mut frame = buffer.drain_to( length );
+            let received = self.frame_started.take( ).unwrap_or_else( | | self.clock.now( ) );
+
+            // Sharing the frame is cheap, it is kept around in case it fails to decode
+            let quarantined = if self.quarantine.threshold > 0 {
+                Some( frame.clone( ) )
+            }
+            else {
+                None
+            };
+            let error = match self.inner.decode( &mut frame ) {
+                Ok( Some( message ) ) => {
+                    self.quarantine.consecutive = 0;
+
+                    return Ok( Some( ReceivedMessage {
+                        envelope : message,
+                        received : received
+                    } ) );
+                },
+                Ok( None ) => io::Error::new( io::ErrorKind::InvalidData, "Incomplete frame" ),
+                Err( error ) => error
+            };
+
+            match quarantined {
+                Some( ref frame ) if self.quarantine.admit( frame.as_slice( ), &error ) => { },
+                _ => return Err( protocol_error( error ) )
+            }
+        }
+    }
+
+    fn decode_eof( &mut self, buffer : &mut EasyBuf ) -> io::Result< Self::In > {
+        // Only called with bytes left in the buffer, a frame cut short by the client closing the
+        // stream is a disconnect rather than a protocol error
+        match self.decode( buffer )? {
+            Some( message ) => Ok( message ),
+            None => Err( io::Error::new( io::ErrorKind::UnexpectedEof, "Client disconnected in the middle of a message" ) )
+        }
+    }
+
+    fn encode( &mut self, envelope : Self::Out, buffer : &mut Vec< u8 > ) -> io::Result< ( ) > {
+        if let Some( ( id, result ) ) = canned::canned_response( &envelope ) {
+            component_trace!( Component::Codec, "Encoding canned response {:?} for request {}.", result, id );
+            canned::encode_response( id, result, buffer );
+
+            return Ok( ( ) );
+        }
+
+        self.inner.encode( envelope, buffer )
+    }
+
+}
+
+impl fmt::Display for ProtocolError {
+
+    fn fmt( &self, f : &mut fmt::Formatter ) -> fmt::Result {
+        write!( f, "Malformed message: {}", self.error )
+    }
+
+}
+
+impl Error for ProtocolError {
+
+    fn description( &self ) -> &str {
+        "Malformed message"
+    }
+
+}
+
+/// Returns true if error was returned by ServiceCodec for a frame that could not be decoded.
+pub(crate) fn is_protocol_error( error : &io::Error ) -> bool {
+    error.get_ref( ).map_or( false, | inner | inner.is::< ProtocolError >( ) )
+}
+
+fn protocol_error( error : io::Error ) -> io::Error {
+    io::Error::new( io::ErrorKind::InvalidData, ProtocolError {
+        error : error
+    } )
+}
+
+#[cfg( test )]
+mod tests {
+
+    use canned::{
+        self,
+        CannedResult
+    };
+    use lsp_rs::{
+        INVALID_PARAMS,
+        MessageEnvelope,
+        OutgoingMessage,
+        OutgoingServerMessage,
+        ResponseMessage,
+        ServerResponse
+    };
+    use mock_client::{
+        MockClient,
+        MockError
+    };
+    use serde_json::{
+        Value
+    };
+    use std::collections::{
+        HashMap
+    };
+    use std::io::{
+        self,
+        Write
+    };
+    use super::{
+        FrameQueue
+    };
+    use testing::{
+        self,
+        NullHandler
+    };
+
+    /// Writer accepting at most max bytes per write
+    struct Trickle {
+        written : Vec< u8 >,
+        max     : usize
+    }
+
+    impl Write for Trickle {
+
+        fn write( &mut self, buf : &[ u8 ] ) -> io::Result< usize > {
+            let length = buf.len( ).min( self.max );
+            self.written.extend_from_slice( &buf[ ..length ] );
+
+            Ok( length )
+        }
+
+        fn flush( &mut self ) -> io::Result< ( ) > {
+            Ok( ( ) )
+        }
+
+    }
+
+    fn null_response( id : i64 ) -> MessageEnvelope< OutgoingServerMessage > {
+        MessageEnvelope {
+            headers : HashMap::new( ),
+            message : OutgoingMessage::Response( ResponseMessage {
+                id     : id,
+                result : Some( ServerResponse::Shutdown ),
+                error  : None
+            } )
+        }
+    }
+
+    #[test]
+    fn frames_written_in_pieces_are_written_whole_and_in_order( ) {
+        let mut frames = FrameQueue::new( );
+        let mut expected = Vec::new( );
+        for &id in &[ 1, 22, 333 ] {
+            frames.encode( null_response( id ) ).unwrap( );
+            canned::encode_response( id, CannedResult::Null, &mut expected );
+        }
+        assert_eq!( frames.len( ), expected.len( ) );
+
+        let mut writer = Trickle {
+            written : Vec::new( ),
+            max     : 5
+        };
+        assert_eq!( frames.write_to( &mut writer ).unwrap( ), 5 );
+        assert!( frames.is_partially_written( ) );
+
+        while !frames.is_empty( ) {
+            frames.write_to( &mut writer ).unwrap( );
+        }
+
+        assert_eq!( frames.len( ), 0 );
+        assert!( !frames.is_partially_written( ) );
+        assert_eq!( writer.written, expected );
+    }
+
+    #[test]
+    fn request_that_cannot_be_decoded_is_answered_with_invalid_params( ) {
+        let mut client = MockClient::with_builder( NullHandler, | builder | builder.quarantine_malformed_messages( 3 ) ).unwrap( );
+        let outcome = testing::watch_shutdown( &client );
+
+        let mut malformed = testing::hover( 1 );
+        malformed[ "params" ][ "position" ] = Value::from( "start" );
+        client.send_message( &malformed ).unwrap( );
+        client.send_message( &testing::hover( 2 ) ).unwrap( );
+
+        match client.response::< Value >( 1 ) {
+            Err( MockError::Response( error ) ) => assert_eq!( error.code, INVALID_PARAMS ),
+            other => panic!( "Expected an INVALID_PARAMS error, got {:?}", other )
+        }
+        let result : Value = client.response( 2 ).unwrap( );
+        assert_eq!( result, Value::Null );
+
+        // Answered in the order the requests were received
+        let ids : Vec< Value > = client.transcript( ).iter( ).map( | message | message[ "id" ].clone( ) ).collect( );
+        assert_eq!( ids, vec![ Value::from( 1 ), Value::from( 2 ) ] );
+        assert_eq!( client.service( ).quarantined_messages( ).len( ), 1 );
+        assert_eq!( *outcome.lock( ).unwrap( ), None );
+    }
+
+    #[test]
+    fn notification_that_cannot_be_decoded_is_skipped( ) {
+        let mut client = MockClient::with_builder( NullHandler, | builder | builder.quarantine_malformed_messages( 3 ) ).unwrap( );
+
+        client.send_message( &json!( {
+            "jsonrpc" : "2.0",
+            "method"  : "textDocument/didOpen",
+            "params"  : { "textDocument" : 42 }
+        } ) ).unwrap( );
+        client.send_message( &testing::hover( 1 ) ).unwrap( );
+
+        let result : Value = client.response( 1 ).unwrap( );
+        assert_eq!( result, Value::Null );
+
+        testing::run_until_stalled( &mut client );
+        assert_eq!( client.transcript( ).len( ), 1 );
+        assert_eq!( client.service( ).quarantined_messages( ).len( ), 1 );
+    }
+
+}
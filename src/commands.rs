@@ -0,0 +1,201 @@
+
+use composite::{
+    MethodComponent
+};
+use context::{
+    Context
+};
+use futures::{
+    Future,
+    IntoFuture
+};
+use futures::future;
+use handler::{
+    method_not_found,
+    HandlerError
+};
+use logging::{
+    Component
+};
+use lsp_rs::{
+    INTERNAL_ERROR,
+    INVALID_PARAMS,
+    ExecuteCommandOptions,
+    ExecuteCommandParams,
+    ResponseError,
+    ServerCapabilities,
+    ServerNotification,
+    ServerRequest,
+    ServerResponse
+};
+use method::{
+    ExecuteCommandRequest,
+    MethodName,
+    RequestMethod
+};
+use serde::{
+    Serialize
+};
+use serde::de::{
+    DeserializeOwned
+};
+use serde_json::{
+    self,
+    Value
+};
+use service::{
+    MessageHandler,
+    ResponseOutput,
+    ServiceHandle
+};
+use std::collections::{
+    HashMap
+};
+
+type CommandResult  = Box< dyn Future< Item = Option< Value >, Error = ResponseError > + Send >;
+type CommandHandler = Box< dyn Fn( Vec< Value >, Context ) -> CommandResult >;
+
+const METHODS : &[ &str ] = &[ ExecuteCommandRequest::METHOD ];
+
+/// Registry of the commands executed through workspace/executeCommand
+///
+/// Each command is registered with a handler receiving the arguments of the command deserialized into a
+/// typed value. The arguments are deserialized from the JSON array sent by the client, so a command taking
+/// several arguments deserializes them into a tuple. Commands with arguments that cannot be deserialized
+/// and unknown commands are answered with an INVALID_PARAMS error, errors returned by a handler are sent as
+/// is. The result of the handler is serialized into the result of the request.
+///
+/// The registry is a MethodComponent handling workspace/executeCommand, a Composite advertises its
+/// commands in the executeCommandProvider capability:
+///
+/// ```ignore
+/// let commands = CommandRegistry::new( )
+///     .command( "mylang.organizeImports", | ( uri, ) : ( Url, ), context | {
+///         organize_imports( &uri, &context )
+///     } );
+///
+/// let handler = CompositeBuilder::new( )
+///     .component( commands )
+///     .component( router )
+///     .build( )?;
+/// ```
+pub struct CommandRegistry {
+    commands : HashMap< String, CommandHandler >
+}
+
+impl CommandRegistry {
+
+    pub fn new( ) -> Self {
+        CommandRegistry {
+            commands : HashMap::new( )
+        }
+    }
+
+    /// Registers the handler of command, replacing any handler previously registered for it.
+    pub fn command< A, F, R >( mut self, command : &str, handler : F ) -> Self
+        where A : DeserializeOwned,
+              F : Fn( A, Context ) -> R + 'static,
+              R : IntoFuture< Error = HandlerError >,
+              R::Future : Send + 'static,
+              R::Item : Serialize {
+        let name = command.to_string( );
+        let command_handler : CommandHandler = Box::new( move | arguments, context | -> CommandResult {
+            let arguments = match serde_json::from_value::< A >( Value::Array( arguments ) ) {
+                Ok( arguments ) => arguments,
+                Err( error ) => {
+                    return Box::new( future::err( ResponseError {
+                        code    : INVALID_PARAMS,
+                        message : format!( "Invalid arguments for command {}: {}", name, error )
+                    } ) );
+                }
+            };
+
+            Box::new( handler( arguments, context ).into_future( ).then( | result | {
+                match result {
+                    Ok( result ) => serde_json::to_value( result ).map( | value | {
+                        if value.is_null( ) { None } else { Some( value ) }
+                    } ).map_err( | error | ResponseError {
+                        code    : INTERNAL_ERROR,
+                        message : format!( "Error serializing command result: {}", error )
+                    } ),
+                    Err( error ) => Err( error.response_error( ).clone( ) )
+                }
+            } ) )
+        } );
+        self.commands.insert( command.to_string( ), command_handler );
+
+        self
+    }
+
+    /// Returns the ids of the registered commands.
+    pub fn commands( &self ) -> Vec< String > {
+        self.commands.keys( ).cloned( ).collect( )
+    }
+
+    /// Returns the executeCommandProvider capability advertising the registered commands, for servers that
+    /// answer the initialize request themselves.
+    pub fn options( &self ) -> ExecuteCommandOptions {
+        let mut commands = self.commands( );
+        commands.sort( );
+
+        ExecuteCommandOptions {
+            commands : commands
+        }
+    }
+
+    fn execute( &self, params : ExecuteCommandParams, context : Context, output : ResponseOutput ) {
+        let handler = match self.commands.get( &params.command ) {
+            Some( handler ) => handler,
+            None => {
+                return output.send_error( ResponseError {
+                    code    : INVALID_PARAMS,
+                    message : format!( "Unknown command {}", params.command )
+                } );
+            }
+        };
+
+        output.complete_with( handler( params.arguments, context ).map( ServerResponse::ExecuteCommand ) );
+    }
+
+}
+
+impl MessageHandler for CommandRegistry {
+
+    fn handle_request( &self, service : ServiceHandle, request : ServerRequest, output : ResponseOutput ) {
+        match ExecuteCommandRequest::from_request( request ) {
+            Ok( params ) => {
+                let context = Context::for_request( service, ExecuteCommandRequest::METHOD, &output );
+
+                self.execute( params, context, output );
+            },
+            Err( request ) => {
+                component_error!( Component::Router, "Request {} sent to CommandRegistry.", request.method_name( ) );
+
+                output.send_error( method_not_found( request.method_name( ) ) );
+            }
+        }
+    }
+
+    fn handle_notification( &self, _service : ServiceHandle, _notification : ServerNotification ) {
+    }
+
+    fn claims_request( &self, request : &ServerRequest ) -> bool {
+        match *request {
+            ServerRequest::ExecuteCommand( ref params ) => self.commands.contains_key( &params.command ),
+            _ => false
+        }
+    }
+
+}
+
+impl MethodComponent for CommandRegistry {
+
+    fn methods( &self ) -> &[ &'static str ] {
+        METHODS
+    }
+
+    fn contribute_capabilities( &self, capabilities : &mut ServerCapabilities ) {
+        capabilities.execute_command_provider = Some( self.options( ) );
+    }
+
+}
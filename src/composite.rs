@@ -0,0 +1,213 @@
+
+use handler::{
+    method_not_found
+};
+use logging::{
+    Component
+};
+use lsp_rs::{
+    InitializeParams,
+    InitializeResult,
+    ServerCapabilities,
+    ServerNotification,
+    ServerRequest
+};
+use method::{
+    InitializeRequest,
+    MethodName,
+    RequestMethod
+};
+use router::{
+    implied_capabilities
+};
+use service::{
+    MessageHandler,
+    ResponseOutput,
+    ServiceHandle,
+    ShutdownReason
+};
+use std::collections::{
+    HashMap
+};
+use std::error::{
+    Error
+};
+use std::fmt;
+
+/// MessageHandler that declares the methods it serves, allowing it to be assembled with other components
+/// into a Composite
+pub trait MethodComponent : MessageHandler {
+
+    /// Returns the names of the request and notification methods handled by this component.
+    fn methods( &self ) -> &[ &'static str ];
+
+    /// Adds the capabilities that cannot be implied from the methods of this component, such as the commands
+    /// of workspace/executeCommand, to the capabilities advertised by a Composite.
+    fn contribute_capabilities( &self, _capabilities : &mut ServerCapabilities ) {
+    }
+
+}
+
+/// MessageHandler dispatching each message to the component that declared its method, created by
+/// CompositeBuilder::build
+///
+/// Requests for methods no component declared are answered with a METHOD_NOT_FOUND error, notifications
+/// for methods no component declared are ignored. Unless a component declares `"initialize"`, the
+/// initialize request is answered with the capabilities implied by the methods of all components, extended
+/// by MethodComponent::contribute_capabilities.
+pub struct Composite {
+    components : Vec< Box< dyn MethodComponent > >,
+    dispatch   : HashMap< &'static str, usize >
+}
+
+/// Builder assembling MethodComponents into a Composite
+///
+/// ```ignore
+/// let handler = CompositeBuilder::new( )
+///     .component( completion_plugin )
+///     .component( formatting_plugin )
+///     .build( )?;
+/// ```
+pub struct CompositeBuilder {
+    components : Vec< Box< dyn MethodComponent > >
+}
+
+/// Error returned by CompositeBuilder::build when two components declare the same method
+#[derive( Clone, Debug )]
+pub struct MethodConflict {
+    /// Name of the method declared by both components
+    pub method : &'static str,
+    /// Index of the first component declaring the method, in the order the components were added
+    pub first  : usize,
+    /// Index of the second component declaring the method
+    pub second : usize
+}
+
+impl CompositeBuilder {
+
+    pub fn new( ) -> Self {
+        CompositeBuilder {
+            components : Vec::new( )
+        }
+    }
+
+    /// Adds a component to the composite.
+    pub fn component< C : MethodComponent + 'static >( mut self, component : C ) -> Self {
+        self.components.push( Box::new( component ) );
+
+        self
+    }
+
+    /// Assembles the dispatch table of the added components, failing if a method is declared by more than
+    /// one component.
+    pub fn build( self ) -> Result< Composite, MethodConflict > {
+        let mut dispatch = HashMap::new( );
+        for ( index, component ) in self.components.iter( ).enumerate( ) {
+            for &method in component.methods( ) {
+                if let Some( &first ) = dispatch.get( method ) {
+                    return Err( MethodConflict {
+                        method : method,
+                        first  : first,
+                        second : index
+                    } );
+                }
+
+                dispatch.insert( method, index );
+            }
+        }
+
+        Ok( Composite {
+            components : self.components,
+            dispatch   : dispatch
+        } )
+    }
+
+}
+
+impl Composite {
+
+    fn component( &self, method : &str ) -> Option< &dyn MethodComponent > {
+        self.dispatch.get( method ).map( | &index | &*self.components[ index ] )
+    }
+
+}
+
+impl MessageHandler for Composite {
+
+    fn handle_request( &self, service : ServiceHandle, request : ServerRequest, output : ResponseOutput ) {
+        let method = request.method_name( );
+        match self.component( method ) {
+            Some( component ) => component.handle_request( service, request, output ),
+            None => output.send_error( method_not_found( method ) )
+        }
+    }
+
+    fn handle_notification( &self, service : ServiceHandle, notification : ServerNotification ) {
+        let method = notification.method_name( );
+        match self.component( method ) {
+            Some( component ) => component.handle_notification( service, notification ),
+            None => component_trace!( Component::Router, "Ignoring notification {} without a component.", method )
+        }
+    }
+
+    fn claims_request( &self, request : &ServerRequest ) -> bool {
+        self.dispatch.contains_key( request.method_name( ) )
+    }
+
+    fn claims_notification( &self, notification : &ServerNotification ) -> bool {
+        self.dispatch.contains_key( notification.method_name( ) )
+    }
+
+    fn on_initialize( &self, service : ServiceHandle, params : &InitializeParams ) -> Option< InitializeResult > {
+        if let Some( component ) = self.component( InitializeRequest::METHOD ) {
+            return component.on_initialize( service, params );
+        }
+
+        let mut capabilities = implied_capabilities( | method | self.dispatch.contains_key( method ) );
+        for component in &self.components {
+            component.contribute_capabilities( &mut capabilities );
+        }
+
+        Some( InitializeResult {
+            capabilities : capabilities
+        } )
+    }
+
+    fn on_initialized( &self, service : ServiceHandle ) {
+        for component in &self.components {
+            component.on_initialized( service.clone( ) );
+        }
+    }
+
+    fn on_shutdown_request( &self, service : ServiceHandle ) -> bool {
+        let mut handled = false;
+        for component in &self.components {
+            handled |= component.on_shutdown_request( service.clone( ) );
+        }
+
+        handled
+    }
+
+    fn on_shutdown( &self, service : ServiceHandle, reason : &ShutdownReason ) {
+        for component in &self.components {
+            component.on_shutdown( service.clone( ), reason );
+        }
+    }
+
+}
+
+impl fmt::Display for MethodConflict {
+
+    fn fmt( &self, f : &mut fmt::Formatter ) -> fmt::Result {
+        write!( f, "Method {} is declared by components {} and {}", self.method, self.first, self.second )
+    }
+
+}
+
+impl Error for MethodConflict {
+
+    fn description( &self ) -> &str {
+        "Method declared by more than one component"
+    }
+
+}
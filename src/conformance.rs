@@ -0,0 +1,260 @@
+
+use lsp_rs::{
+    INVALID_REQUEST
+};
+use mock_client::{
+    MockClient,
+    MockError
+};
+use serde_json::{
+    Value
+};
+use service::{
+    LifecycleState,
+    MessageHandler,
+    SERVER_NOT_INITIALIZED
+};
+use std::fmt;
+
+/// Lifecycle scenario of the LSP specification checked by a LifecycleSuite
+#[derive( Clone, Copy, Debug, PartialEq, Eq )]
+pub enum Scenario {
+    /// A request received before the initialize request is answered with a SERVER_NOT_INITIALIZED error
+    RequestBeforeInitialize,
+    /// A second initialize request is answered with an error
+    DuplicateInitialize,
+    /// The shutdown request is answered with a null result, and requests received afterwards with an
+    /// INVALID_REQUEST error
+    RequestAfterShutdown,
+    /// The exit notification stops the service after the shutdown request
+    ShutdownThenExit,
+    /// The exit notification stops the service even if the shutdown request was not received
+    ExitWithoutShutdown
+}
+
+/// Scenario a MessageHandler did not handle as required by the specification
+#[derive( Clone, Debug )]
+pub struct Violation {
+    pub scenario    : Scenario,
+    pub description : String
+}
+
+/// Outcome of LifecycleSuite::run
+#[derive( Clone, Debug, Default )]
+pub struct ConformanceReport {
+    pub passed     : Vec< Scenario >,
+    pub violations : Vec< Violation >
+}
+
+/// Runs a MessageHandler through the lifecycle scenarios of the LSP specification, reporting the scenarios
+/// it does not handle as required
+///
+/// Every scenario runs a new service over a MockClient, with a handler created by the factory of the suite.
+/// Requests probing the lifecycle are textDocument/hover requests unless set with LifecycleSuite::probe.
+///
+/// ```ignore
+/// #[test]
+/// fn lifecycle( ) {
+///     LifecycleSuite::new( | | Server::new( ) ).run( ).assert_conformant( );
+/// }
+/// ```
+pub struct LifecycleSuite< F > {
+    factory           : F,
+    initialize_params : Value,
+    probe_method      : String,
+    probe_params      : Value
+}
+
+const SCENARIOS : &[ Scenario ] = &[
+    Scenario::RequestBeforeInitialize,
+    Scenario::DuplicateInitialize,
+    Scenario::RequestAfterShutdown,
+    Scenario::ShutdownThenExit,
+    Scenario::ExitWithoutShutdown
+];
+
+impl < F, H > LifecycleSuite< F > where F : Fn( ) -> H, H : MessageHandler + 'static {
+
+    /// Creates a suite running the handlers created by factory.
+    pub fn new( factory : F ) -> Self {
+        LifecycleSuite {
+            factory           : factory,
+            initialize_params : json!( {
+                "processId"    : null,
+                "rootUri"      : null,
+                "capabilities" : { }
+            } ),
+            probe_method      : "textDocument/hover".to_string( ),
+            probe_params      : json!( {
+                "textDocument" : { "uri" : "file:///conformance.txt" },
+                "position"     : { "line" : 0, "character" : 0 }
+            } )
+        }
+    }
+
+    /// Sets the parameters of the initialize requests sent by the suite.
+    pub fn initialize_params( mut self, params : Value ) -> Self {
+        self.initialize_params = params;
+
+        self
+    }
+
+    /// Sets the request sent to probe whether the handler answers requests, for handlers that do not
+    /// support textDocument/hover.
+    pub fn probe( mut self, method : &str, params : Value ) -> Self {
+        self.probe_method = method.to_string( );
+        self.probe_params = params;
+
+        self
+    }
+
+    /// Runs every scenario, returning the scenarios that passed and the violations.
+    pub fn run( &self ) -> ConformanceReport {
+        let mut report = ConformanceReport::default( );
+        for &scenario in SCENARIOS {
+            match self.run_scenario( scenario ) {
+                Ok( ( ) ) => report.passed.push( scenario ),
+                Err( description ) => report.violations.push( Violation {
+                    scenario    : scenario,
+                    description : description
+                } )
+            }
+        }
+
+        report
+    }
+
+    fn run_scenario( &self, scenario : Scenario ) -> Result< ( ), String > {
+        let mut client = MockClient::new( ( self.factory )( ) ).map_err( | error | format!( "Error starting the service: {}", error ) )?;
+
+        match scenario {
+            Scenario::RequestBeforeInitialize => {
+                let result = self.request( &mut client, 1, &self.probe_method, &self.probe_params );
+                expect_error( result, Some( SERVER_NOT_INITIALIZED ), &self.probe_method )
+            },
+            Scenario::DuplicateInitialize => {
+                self.initialize( &mut client )?;
+
+                let result = self.request( &mut client, 2, "initialize", &self.initialize_params );
+                expect_error( result, None, "initialize" )
+            },
+            Scenario::RequestAfterShutdown => {
+                self.initialize( &mut client )?;
+                self.shutdown( &mut client )?;
+
+                let result = self.request( &mut client, 3, &self.probe_method, &self.probe_params );
+                expect_error( result, Some( INVALID_REQUEST ), &self.probe_method )
+            },
+            Scenario::ShutdownThenExit => {
+                self.initialize( &mut client )?;
+                self.shutdown( &mut client )?;
+
+                exit( &mut client )
+            },
+            Scenario::ExitWithoutShutdown => {
+                self.initialize( &mut client )?;
+
+                exit( &mut client )
+            }
+        }
+    }
+
+    fn initialize( &self, client : &mut MockClient ) -> Result< ( ), String > {
+        self.request( client, 1, "initialize", &self.initialize_params ).map_err( | error | {
+            format!( "initialize failed: {}", error )
+        } )?;
+
+        send( client, json!( {
+            "jsonrpc" : "2.0",
+            "method"  : "initialized",
+            "params"  : { }
+        } ) )
+    }
+
+    fn shutdown( &self, client : &mut MockClient ) -> Result< ( ), String > {
+        match self.request( client, 2, "shutdown", &Value::Null ) {
+            Ok( Value::Null ) => Ok( ( ) ),
+            Ok( result ) => Err( format!( "shutdown answered with {} instead of null", result ) ),
+            Err( error ) => Err( format!( "shutdown failed: {}", error ) )
+        }
+    }
+
+    fn request( &self, client : &mut MockClient, id : i64, method : &str, params : &Value ) -> Result< Value, MockError > {
+        let mut message = json!( {
+            "jsonrpc" : "2.0",
+            "id"      : id,
+            "method"  : method
+        } );
+        if !params.is_null( ) {
+            message[ "params" ] = params.clone( );
+        }
+        client.send_message( &message )?;
+
+        client.response( id )
+    }
+
+}
+
+impl ConformanceReport {
+
+    /// Returns true if no scenario was violated.
+    pub fn is_conformant( &self ) -> bool {
+        self.violations.is_empty( )
+    }
+
+    /// Panics listing the violations if any scenario was violated.
+    pub fn assert_conformant( &self ) {
+        if !self.is_conformant( ) {
+            panic!( "{}", self );
+        }
+    }
+
+}
+
+fn send( client : &mut MockClient, message : Value ) -> Result< ( ), String > {
+    client.send_message( &message ).map_err( | error | format!( "Error sending message: {}", error ) )
+}
+
+/// Sends the exit notification, checking the service stopped.
+fn exit( client : &mut MockClient ) -> Result< ( ), String > {
+    send( client, json!( {
+        "jsonrpc" : "2.0",
+        "method"  : "exit"
+    } ) )?;
+
+    match client.run_until_stalled( ) {
+        Ok( _ ) | Err( MockError::Disconnected ) => { },
+        Err( error ) => return Err( format!( "Error running the service after exit: {}", error ) )
+    }
+
+    if client.service( ).debug_dump( ).lifecycle == LifecycleState::Running {
+        return Err( "The service is still running after the exit notification".to_string( ) );
+    }
+
+    Ok( ( ) )
+}
+
+/// Checks a request was answered with an error, of the given code if any.
+fn expect_error( result : Result< Value, MockError >, code : Option< i64 >, method : &str ) -> Result< ( ), String > {
+    match ( result, code ) {
+        ( Err( MockError::Response( ref error ) ), Some( code ) ) if error.code != code => {
+            Err( format!( "{} answered with error {} instead of {}", method, error.code, code ) )
+        },
+        ( Err( MockError::Response( _ ) ), _ ) => Ok( ( ) ),
+        ( Ok( result ), _ ) => Err( format!( "{} answered with result {} instead of an error", method, result ) ),
+        ( Err( error ), _ ) => Err( format!( "{} was not answered: {}", method, error ) )
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+
+    fn fmt( &self, f : &mut fmt::Formatter ) -> fmt::Result {
+        write!( f, "{} lifecycle scenarios passed, {} violated", self.passed.len( ), self.violations.len( ) )?;
+        for violation in &self.violations {
+            write!( f, "\n{:?}: {}", violation.scenario, violation.description )?;
+        }
+
+        Ok( ( ) )
+    }
+
+}
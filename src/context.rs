@@ -0,0 +1,177 @@
+
+use arena::{
+    LazyArena,
+    RequestArena
+};
+use logging::{
+    CorrelationId
+};
+use lsp_rs::{
+    Url
+};
+use service::{
+    ResponseOutput,
+    ServiceHandle
+};
+use std::any::{
+    Any,
+    TypeId
+};
+use std::collections::{
+    HashMap
+};
+use std::sync::{
+    Arc
+};
+
+/// Map of the extensions of a service keyed by their type, inserted through ServiceBuilder::insert_extension
+#[derive( Clone, Default )]
+pub struct StateMap {
+    values : HashMap< TypeId, Arc< dyn Any + Send + Sync > >
+}
+
+/// Metadata of the request a Context was created for
+#[derive( Clone, Copy, Debug )]
+pub struct RequestMetadata {
+    /// Id of the request as sent by the client
+    pub id             : i64,
+    pub correlation_id : CorrelationId
+}
+
+/// Context passed to handlers registered on a Router, bundling the ServiceHandle, the extensions
+/// registered on the service and metadata of the message being handled
+#[derive( Clone )]
+pub struct Context {
+    service : ServiceHandle,
+    method  : &'static str,
+    request : Option< RequestMetadata >,
+    arena   : LazyArena
+}
+
+impl StateMap {
+
+    pub fn new( ) -> Self {
+        StateMap::default( )
+    }
+
+    /// Inserts a value into the map, replacing any value of the same type.
+    pub fn insert< T : Any + Send + Sync >( &mut self, value : Arc< T > ) {
+        self.values.insert( TypeId::of::< T >( ), value );
+    }
+
+    /// Returns the value of type T, if one was inserted.
+    pub fn get< T : Any + Send + Sync >( &self ) -> Option< Arc< T > > {
+        self.values.get( &TypeId::of::< T >( ) ).and_then( | value | {
+            value.clone( ).downcast::< T >( ).ok( )
+        } )
+    }
+
+}
+
+impl Context {
+
+    /// Creates the context for a request handled with the given ResponseOutput.
+    pub fn for_request( service : ServiceHandle, method : &'static str, output : &ResponseOutput ) -> Self {
+        let arena = LazyArena::new( service.arena_pool( ).clone( ) );
+
+        Context {
+            service : service,
+            method  : method,
+            request : Some( RequestMetadata {
+                id             : output.request_id( ),
+                correlation_id : output.correlation_id( )
+            } ),
+            arena   : arena
+        }
+    }
+
+    /// Creates the context for a notification.
+    pub fn for_notification( service : ServiceHandle, method : &'static str ) -> Self {
+        let arena = LazyArena::new( service.arena_pool( ).clone( ) );
+
+        Context {
+            service : service,
+            method  : method,
+            request : None,
+            arena   : arena
+        }
+    }
+
+    /// Returns the handle to the service the message was received by.
+    pub fn service( &self ) -> &ServiceHandle {
+        &self.service
+    }
+
+    /// Returns the method name of the message being handled.
+    pub fn method( &self ) -> &'static str {
+        self.method
+    }
+
+    /// Returns the metadata of the request being handled, None when handling a notification.
+    pub fn request( &self ) -> Option< &RequestMetadata > {
+        self.request.as_ref( )
+    }
+
+    /// Returns the arena for the transient allocations made while handling the message, created by the
+    /// first call. The arena is shared with the clones of the context made after that call and released
+    /// once every context and every returned Arc was dropped, see RequestArena.
+    pub fn arena( &self ) -> Arc< RequestArena > {
+        self.arena.get( )
+    }
+
+    /// Pins the request being handled to the given version of a document. If the client changes the
+    /// document before the request is completed, the request is canceled according to the service's
+    /// PinnedDocumentPolicy.
+    ///
+    /// Does nothing when handling a notification.
+    pub fn pin_document( &self, uri : Url, version : i64 ) {
+        if let Some( request ) = self.request {
+            self.service.pin_document( request.id, uri, version );
+        }
+    }
+
+    /// Returns the shared state of type T registered on the service, if any.
+    #[deprecated( note = "Use Context::extension, shared state and extensions are the same values" )]
+    pub fn state< T : Any + Send + Sync >( &self ) -> Option< Arc< T > > {
+        self.extension::< T >( )
+    }
+
+    /// Returns the extension of type T inserted on the service, if any.
+    pub fn extension< T : Any + Send + Sync >( &self ) -> Option< Arc< T > > {
+        self.service.extension::< T >( )
+    }
+
+}
+
+#[cfg( test )]
+mod tests {
+
+    use mock_client::{
+        MockClient
+    };
+    use std::sync::{
+        Arc
+    };
+    use testing::{
+        NullHandler
+    };
+
+    struct IndexCache {
+        entries : usize
+    }
+
+    #[test]
+    #[allow( deprecated )]
+    fn extensions_and_shared_state_are_the_same_values( ) {
+        let client = MockClient::with_builder( NullHandler, | builder | {
+            builder.insert_extension( IndexCache { entries : 3 } ).shared_state( Arc::new( 7u32 ) )
+        } ).unwrap( );
+        let service = client.service( );
+
+        assert_eq!( service.extension::< IndexCache >( ).unwrap( ).entries, 3 );
+        assert_eq!( service.shared_state::< IndexCache >( ).unwrap( ).entries, 3 );
+        assert_eq!( *service.extension::< u32 >( ).unwrap( ), 7 );
+        assert!( service.extension::< String >( ).is_none( ) );
+    }
+
+}
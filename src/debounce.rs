@@ -0,0 +1,150 @@
+
+use clock::{
+    Clock
+};
+use futures::{
+    Future
+};
+use logging::{
+    Component
+};
+use lsp_rs::{
+    ServerNotification,
+    Url
+};
+use std::cell::{
+    RefCell
+};
+use std::collections::{
+    HashMap
+};
+use std::rc::{
+    Rc,
+    Weak
+};
+use std::time::{
+    Duration
+};
+use tokio_core::reactor::{
+    Handle
+};
+
+/// Coalesces bursts of changes to a document, calling a callback once no change was made to the document
+/// for a quiet period
+///
+/// Every call to trigger restarts the quiet period of the document. The callback runs on the event loop of
+/// the Handle given to Debouncer::new, pending callbacks are canceled when the Debouncer is dropped.
+///
+/// ```ignore
+/// let debouncer = Debouncer::new( core.handle( ), Duration::from_millis( 300 ), move | uri | {
+///     analyze( &documents, uri );
+/// } );
+///
+/// // In handle_notification
+/// debouncer.observe( &notification );
+/// ```
+pub struct Debouncer {
+    inner : Rc< DebouncerInner >
+}
+
+struct DebouncerInner {
+    handle      : Handle,
+    clock       : Clock,
+    quiet       : Duration,
+    callback    : Box< dyn Fn( Url ) >,
+    generations : RefCell< HashMap< Url, u64 > >,
+
+    next_generation : RefCell< u64 >
+}
+
+impl Debouncer {
+
+    /// Creates a debouncer calling callback with the uri of a document once it has not been triggered for
+    /// the document for the quiet duration.
+    pub fn new< F >( handle : Handle, quiet : Duration, callback : F ) -> Self where F : Fn( Url ) + 'static {
+        Debouncer::with_clock( handle, Clock::system( ), quiet, callback )
+    }
+
+    /// Creates a debouncer like Debouncer::new measuring the quiet period on clock, e.g. the clock of the
+    /// service returned by ServiceHandle::clock.
+    pub fn with_clock< F >( handle : Handle, clock : Clock, quiet : Duration, callback : F ) -> Self where F : Fn( Url ) + 'static {
+        Debouncer {
+            inner : Rc::new( DebouncerInner {
+                handle      : handle,
+                clock       : clock,
+                quiet       : quiet,
+                callback    : Box::new( callback ),
+                generations : RefCell::new( HashMap::new( ) ),
+
+                next_generation : RefCell::new( 0 )
+            } )
+        }
+    }
+
+    /// Triggers the debouncer for didChange notifications and cancels the pending callback of a document on
+    /// didClose, ignoring other notifications.
+    pub fn observe( &self, notification : &ServerNotification ) {
+        match *notification {
+            ServerNotification::DidChangeTextDocument( ref params ) => self.trigger( params.text_document.uri.clone( ) ),
+            ServerNotification::DidCloseTextDocument( ref params ) => self.cancel( &params.text_document.uri ),
+            _ => { }
+        }
+    }
+
+    /// Restarts the quiet period of the document uri, scheduling the callback if none is pending.
+    pub fn trigger( &self, uri : Url ) {
+        let generation = {
+            let mut next_generation = self.inner.next_generation.borrow_mut( );
+            *next_generation += 1;
+
+            *next_generation
+        };
+        self.inner.generations.borrow_mut( ).insert( uri.clone( ), generation );
+
+        let timeout = match self.inner.clock.sleep( self.inner.quiet, &self.inner.handle ) {
+            Ok( timeout ) => timeout,
+            Err( error ) => {
+                component_error!( Component::Document, "Error creating debounce timer for {}: {}", uri, error );
+
+                return;
+            }
+        };
+
+        let inner = Rc::downgrade( &self.inner );
+        self.inner.handle.spawn( timeout.then( move | _ | {
+            DebouncerInner::fire( inner, uri, generation );
+
+            Ok( ( ) )
+        } ) );
+    }
+
+    /// Cancels the pending callback of the document uri, if any.
+    pub fn cancel( &self, uri : &Url ) {
+        self.inner.generations.borrow_mut( ).remove( uri );
+    }
+
+    /// Returns true if a callback is pending for the document uri.
+    pub fn is_pending( &self, uri : &Url ) -> bool {
+        self.inner.generations.borrow( ).contains_key( uri )
+    }
+
+}
+
+impl DebouncerInner {
+
+    fn fire( this : Weak< Self >, uri : Url, generation : u64 ) {
+        let this = match this.upgrade( ) {
+            Some( this ) => this,
+            None => return
+        };
+
+        // Only the timer of the last trigger calls the callback
+        let current = this.generations.borrow( ).get( &uri ) == Some( &generation );
+        if current {
+            this.generations.borrow_mut( ).remove( &uri );
+
+            ( this.callback )( uri );
+        }
+    }
+
+}
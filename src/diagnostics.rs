@@ -0,0 +1,167 @@
+
+use logging::{
+    Component
+};
+use lsp_rs::{
+    Diagnostic,
+    FileChangeType,
+    ServerNotification,
+    Url
+};
+use service::{
+    ServiceHandle
+};
+use std::collections::{
+    HashMap
+};
+use std::sync::{
+    Arc,
+    Mutex
+};
+
+/// Store of the diagnostics published for each file, publishing them to the client through a ServiceHandle
+///
+/// The manager only sends publishDiagnostics when the diagnostics of a file actually change, drops
+/// diagnostics computed for a version older than the version already published and clears the diagnostics
+/// of files that are closed or deleted. The manager is cheap to clone, clones share the same diagnostics so
+/// background analysis can publish through its own clone:
+///
+/// ```ignore
+/// // In handle_notification
+/// diagnostics.observe( &service, &notification );
+///
+/// // Once analysis of a snapshot completes
+/// diagnostics.publish( &service, snapshot.uri( ).clone( ), Some( snapshot.version( ) ), results );
+/// ```
+#[derive( Clone, Default )]
+pub struct DiagnosticsManager {
+    files : Arc< Mutex< HashMap< Url, FileDiagnostics > > >
+}
+
+/// Diagnostics published for a file
+#[derive( Clone, Debug )]
+pub struct FileDiagnostics {
+    /// Version of the document the diagnostics were computed for, None if computed from the file on disk
+    pub version     : Option< i64 >,
+    pub diagnostics : Vec< Diagnostic >
+}
+
+/// Difference between the diagnostics published for a file and a new set of diagnostics
+#[derive( Clone, Debug, Default )]
+pub struct DiagnosticsDelta {
+    /// Diagnostics in the new set that were not published
+    pub added   : Vec< Diagnostic >,
+    /// Published diagnostics that are not in the new set
+    pub removed : Vec< Diagnostic >
+}
+
+impl DiagnosticsManager {
+
+    pub fn new( ) -> Self {
+        DiagnosticsManager::default( )
+    }
+
+    /// Returns the diagnostics published for uri, None if no diagnostics are published for it.
+    pub fn get( &self, uri : &Url ) -> Option< FileDiagnostics > {
+        self.files.lock( ).unwrap( ).get( uri ).cloned( )
+    }
+
+    /// Returns the uris of all files with published diagnostics.
+    pub fn uris( &self ) -> Vec< Url > {
+        self.files.lock( ).unwrap( ).keys( ).cloned( ).collect( )
+    }
+
+    /// Computes the difference between the diagnostics published for uri and diagnostics.
+    pub fn delta( &self, uri : &Url, diagnostics : &[ Diagnostic ] ) -> DiagnosticsDelta {
+        let files = self.files.lock( ).unwrap( );
+        let published : &[ Diagnostic ] = files.get( uri ).map( | file | &file.diagnostics[ .. ] ).unwrap_or( &[ ] );
+
+        DiagnosticsDelta {
+            added   : diagnostics.iter( ).filter( | diagnostic | !published.contains( diagnostic ) ).cloned( ).collect( ),
+            removed : published.iter( ).filter( | diagnostic | !diagnostics.contains( diagnostic ) ).cloned( ).collect( )
+        }
+    }
+
+    /// Replaces the diagnostics of uri, publishing them to the client if they differ from the published
+    /// diagnostics.
+    ///
+    /// Diagnostics computed for a version older than the version of the published diagnostics are dropped.
+    /// Returns true if the diagnostics were published.
+    pub fn publish( &self, service : &ServiceHandle, uri : Url, version : Option< i64 >, diagnostics : Vec< Diagnostic > ) -> bool {
+        {
+            let mut files = self.files.lock( ).unwrap( );
+            if let Some( file ) = files.get_mut( &uri ) {
+                if let ( Some( published ), Some( version ) ) = ( file.version, version ) {
+                    if version < published {
+                        component_trace!( Component::Document, "Dropping diagnostics of {} for outdated version {}.", uri, version );
+
+                        return false;
+                    }
+                }
+
+                file.version = version;
+                if file.diagnostics == diagnostics {
+                    return false;
+                }
+            }
+
+            if diagnostics.is_empty( ) {
+                if files.remove( &uri ).is_none( ) {
+                    return false;
+                }
+            }
+            else {
+                files.insert( uri.clone( ), FileDiagnostics {
+                    version     : version,
+                    diagnostics : diagnostics.clone( )
+                } );
+            }
+        }
+
+        service.publish_diagnostics( uri, diagnostics );
+
+        true
+    }
+
+    /// Clears the diagnostics of uri, publishing an empty set to the client if diagnostics were published.
+    pub fn clear( &self, service : &ServiceHandle, uri : &Url ) {
+        let removed = self.files.lock( ).unwrap( ).remove( uri ).is_some( );
+        if removed {
+            service.publish_diagnostics( uri.clone( ), Vec::new( ) );
+        }
+    }
+
+    /// Clears the diagnostics of every file, e.g. before reanalyzing the whole workspace.
+    pub fn clear_all( &self, service : &ServiceHandle ) {
+        let uris : Vec< Url > = self.files.lock( ).unwrap( ).drain( ).map( | ( uri, _ ) | uri ).collect( );
+        for uri in uris {
+            service.publish_diagnostics( uri, Vec::new( ) );
+        }
+    }
+
+    /// Clears the diagnostics of documents closed by the client and of files deleted from disk, ignoring
+    /// other notifications.
+    pub fn observe( &self, service : &ServiceHandle, notification : &ServerNotification ) {
+        match *notification {
+            ServerNotification::DidCloseTextDocument( ref params ) => self.clear( service, &params.text_document.uri ),
+            ServerNotification::DidChangeWatchedFiles( ref params ) => {
+                for change in &params.changes {
+                    if change.typ == FileChangeType::Deleted {
+                        self.clear( service, &change.uri );
+                    }
+                }
+            },
+            _ => { }
+        }
+    }
+
+}
+
+impl DiagnosticsDelta {
+
+    /// Returns true if the new set of diagnostics is the same as the published one.
+    pub fn is_empty( &self ) -> bool {
+        self.added.is_empty( ) && self.removed.is_empty( )
+    }
+
+}
@@ -0,0 +1,749 @@
+
+use line_index::{
+    LineIndex,
+    PositionEncoding
+};
+use logging::{
+    Component
+};
+use lsp_rs::{
+    INVALID_PARAMS,
+    DidChangeTextDocumentParams,
+    DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams,
+    InitializeParams,
+    InitializeResult,
+    Position,
+    Range,
+    ResponseError,
+    ServerNotification,
+    ServerRequest,
+    TextDocumentSyncKind,
+    Url
+};
+use memory::{
+    MemoryBudget,
+    MemoryConsumer
+};
+use ropey::{
+    Rope,
+    RopeSlice
+};
+use service::{
+    MessageHandler,
+    ResponseOutput,
+    ServiceHandle,
+    ShutdownReason
+};
+use std::collections::{
+    HashMap
+};
+use std::error::{
+    Error
+};
+use std::fmt;
+use std::sync::{
+    Arc,
+    Mutex
+};
+use uri;
+
+/// Store of the text documents opened by the client, kept up to date from the didOpen, didChange and
+/// didClose notifications
+///
+/// The store is cheap to clone, clones share the same documents. It is usually installed in front of the
+/// server's handler with with_documents and inserted as an extension so handlers can retrieve it.
+/// Documents are keyed by their normalized uri, see uri::normalize, so lookups succeed whatever escaping the
+/// client used for the uri.
+#[derive( Clone, Default )]
+pub struct TextDocumentStore {
+    documents : Arc< Mutex< HashMap< Url, DocumentSnapshot > > >,
+    budget    : Option< MemoryBudget >
+}
+
+/// Immutable view of the content of a document at a given version
+///
+/// The text is stored in a rope, so edits to large documents do not copy the whole text. Snapshots share
+/// the nodes of the rope with the store, they are cheap to clone and Send, so they can be moved into
+/// background threads without copying the text of the document:
+///
+/// ```ignore
+/// let snapshot = documents.get( &uri ).unwrap( );
+/// thread::spawn( move | | {
+///     let diagnostics = analyze( snapshot.rope( ) );
+///     ...
+/// } );
+/// ```
+///
+/// Edits applied to the store after a snapshot was taken are not visible through the snapshot.
+#[derive( Clone, Debug )]
+pub struct DocumentSnapshot {
+    uri         : Url,
+    language_id : String,
+    version     : i64,
+    text        : Rope,
+    line_index  : Arc< Mutex< Option< Arc< LineIndex > > > >
+}
+
+/// Errors returned when a notification cannot be applied to the store
+#[derive( Clone, Debug )]
+pub enum DocumentError {
+    /// The document was changed or closed without being opened first
+    NotOpen( Url ),
+    /// The version of a change is not greater than the version of the stored document
+    OutOfOrderVersion {
+        uri      : Url,
+        current  : i64,
+        received : i64
+    }
+}
+
+/// Action taken by DocumentSnapshot::validate_position for positions outside the document
+#[derive( Clone, Copy, Debug, PartialEq, Eq )]
+pub enum PositionPolicy {
+    /// Positions past the end of their line are moved to the end of the line, positions past the last line
+    /// are moved to the end of the document
+    Clamp,
+    /// Positions outside the document are rejected with a PositionError
+    Reject
+}
+
+/// Error returned when a position or range received from the client does not fit the document
+#[derive( Clone, Debug )]
+pub enum PositionError {
+    /// The line of the position is past the last line of the document
+    LineOutOfRange {
+        line       : u64,
+        line_count : u64
+    },
+    /// The character of the position is past the end of its line, in UTF-16 code units
+    CharacterOutOfRange {
+        line        : u64,
+        character   : u64,
+        line_length : u64
+    },
+    /// The start of the range is after its end
+    InvertedRange( Range )
+}
+
+/// MessageHandler that applies document notifications to a TextDocumentStore before forwarding every message
+/// to an inner handler, created by with_documents
+pub struct DocumentLayer< H > {
+    store   : TextDocumentStore,
+    handler : H
+}
+
+/// Installs store in front of handler, so the store is updated before handler receives a document
+/// notification. The capabilities handler answers initialize with advertise incremental synchronization.
+///
+/// ```ignore
+/// let documents = TextDocumentStore::new( );
+/// let handler = document::with_documents( documents.clone( ), server );
+///
+/// ServiceBuilder::new( core.handle( ) )
+///     .insert_extension( documents )
+///     .start( handler, stdio );
+/// ```
+pub fn with_documents< H : MessageHandler >( store : TextDocumentStore, handler : H ) -> DocumentLayer< H > {
+    DocumentLayer {
+        store   : store,
+        handler : handler
+    }
+}
+
+impl TextDocumentStore {
+
+    pub fn new( ) -> Self {
+        TextDocumentStore::default( )
+    }
+
+    /// Registers the open documents with budget, so the budget is enforced whenever a document is opened
+    /// or changed. Open documents are never evicted, the other components registered with the budget are
+    /// evicted to make room for them.
+    pub fn memory_budget( mut self, budget : &MemoryBudget ) -> Self {
+        budget.register( "open documents", &self.documents );
+        self.budget = Some( budget.clone( ) );
+
+        self
+    }
+
+    /// Returns a snapshot of the document uri, None if the document is not open.
+    pub fn get( &self, uri : &Url ) -> Option< DocumentSnapshot > {
+        self.documents.lock( ).unwrap( ).get( &uri::normalize( uri ) ).cloned( )
+    }
+
+    /// Returns the uris of all open documents.
+    pub fn uris( &self ) -> Vec< Url > {
+        self.documents.lock( ).unwrap( ).keys( ).cloned( ).collect( )
+    }
+
+    /// Returns a snapshot of every open document, e.g. to analyze the whole workspace on a background
+    /// thread.
+    pub fn snapshots( &self ) -> Vec< DocumentSnapshot > {
+        self.documents.lock( ).unwrap( ).values( ).cloned( ).collect( )
+    }
+
+    /// Returns the number of bytes of text held by the open documents.
+    pub fn memory_usage( &self ) -> usize {
+        self.documents.lock( ).unwrap( ).values( ).map( | document | document.text.len_bytes( ) ).sum( )
+    }
+
+    /// Returns true if snapshot is still the latest version of its document, allowing background analysis to
+    /// drop results computed from an outdated snapshot.
+    pub fn is_current( &self, snapshot : &DocumentSnapshot ) -> bool {
+        self.documents.lock( ).unwrap( ).get( &uri::normalize( &snapshot.uri ) ).map( | document | {
+            document.version == snapshot.version
+        } ).unwrap_or( false )
+    }
+
+    /// Applies a document notification to the store, ignoring notifications unrelated to documents.
+    pub fn apply( &self, notification : &ServerNotification ) -> Result< ( ), DocumentError > {
+        match *notification {
+            ServerNotification::DidOpenTextDocument( ref params ) => {
+                self.open( params );
+
+                Ok( ( ) )
+            },
+            ServerNotification::DidChangeTextDocument( ref params ) => self.change( params ),
+            ServerNotification::DidCloseTextDocument( ref params ) => self.close( params ),
+            _ => Ok( ( ) )
+        }
+    }
+
+    /// Stores the document opened by the client, replacing any document with the same uri.
+    pub fn open( &self, params : &DidOpenTextDocumentParams ) {
+        let document = &params.text_document;
+
+        self.documents.lock( ).unwrap( ).insert( uri::normalize( &document.uri ), DocumentSnapshot {
+            uri         : document.uri.clone( ),
+            language_id : document.language_id.clone( ),
+            version     : document.version,
+            text        : Rope::from_str( &document.text ),
+            line_index  : Arc::new( Mutex::new( None ) )
+        } );
+
+        self.enforce_budget( );
+    }
+
+    /// Applies the changes of a didChange notification to the stored document.
+    ///
+    /// Both full and incremental changes are supported, incremental changes are applied in order with their
+    /// ranges interpreted as UTF-16 positions. The version of the change must be greater than the version
+    /// of the stored document, changes that are out of order are rejected without modifying the document.
+    pub fn change( &self, params : &DidChangeTextDocumentParams ) -> Result< ( ), DocumentError > {
+        let uri = &params.text_document.uri;
+        let version = params.text_document.version;
+
+        let mut documents = self.documents.lock( ).unwrap( );
+        let document = match documents.get_mut( &uri::normalize( uri ) ) {
+            Some( document ) => document,
+            None => return Err( DocumentError::NotOpen( uri.clone( ) ) )
+        };
+        if version <= document.version {
+            return Err( DocumentError::OutOfOrderVersion {
+                uri      : uri.clone( ),
+                current  : document.version,
+                received : version
+            } );
+        }
+
+        for change in &params.content_changes {
+            match change.range {
+                Some( ref range ) => apply_edit( &mut document.text, range, &change.text ),
+                None => document.text = Rope::from_str( &change.text )
+            }
+        }
+        document.version = version;
+        document.line_index = Arc::new( Mutex::new( None ) );
+        drop( documents );
+
+        self.enforce_budget( );
+
+        Ok( ( ) )
+    }
+
+    /// Removes the document closed by the client.
+    pub fn close( &self, params : &DidCloseTextDocumentParams ) -> Result< ( ), DocumentError > {
+        let uri = &params.text_document.uri;
+
+        match self.documents.lock( ).unwrap( ).remove( &uri::normalize( uri ) ) {
+            Some( _ ) => Ok( ( ) ),
+            None => Err( DocumentError::NotOpen( uri.clone( ) ) )
+        }
+    }
+
+    fn enforce_budget( &self ) {
+        if let Some( ref budget ) = self.budget {
+            budget.enforce( );
+        }
+    }
+
+}
+
+impl MemoryConsumer for Mutex< HashMap< Url, DocumentSnapshot > > {
+
+    fn memory_usage( &self ) -> usize {
+        self.lock( ).unwrap( ).values( ).map( | document | document.text.len_bytes( ) ).sum( )
+    }
+
+    fn evict( &self, _bytes : usize ) -> usize {
+        // The documents open in the editor cannot be dropped
+        0
+    }
+
+}
+
+impl DocumentSnapshot {
+
+    pub fn uri( &self ) -> &Url {
+        &self.uri
+    }
+
+    pub fn language_id( &self ) -> &str {
+        &self.language_id
+    }
+
+    /// Returns the version of the document this snapshot was taken at.
+    pub fn version( &self ) -> i64 {
+        self.version
+    }
+
+    /// Returns the rope holding the text of the document.
+    pub fn rope( &self ) -> &Rope {
+        &self.text
+    }
+
+    /// Returns the text of the document covered by range, without copying it.
+    pub fn slice( &self, range : &Range ) -> RopeSlice {
+        let start = position_to_char( &self.text, &range.start );
+        let end = position_to_char( &self.text, &range.end ).max( start );
+
+        self.text.slice( start..end )
+    }
+
+    /// Returns the LineIndex of the text of this version of the document.
+    ///
+    /// The index is built on first use and shared by every snapshot of the same version, as long as it is
+    /// requested with the same encoding.
+    pub fn line_index( &self, encoding : PositionEncoding ) -> Arc< LineIndex > {
+        let mut line_index = self.line_index.lock( ).unwrap( );
+        if let Some( ref line_index ) = *line_index {
+            if line_index.encoding( ) == encoding {
+                return line_index.clone( );
+            }
+        }
+
+        let index = Arc::new( LineIndex::from_rope( &self.text, encoding ) );
+        *line_index = Some( index.clone( ) );
+
+        index
+    }
+
+    /// Checks that position, with its character counted in UTF-16 code units, lies within the document,
+    /// clamping or rejecting it according to policy.
+    ///
+    /// Clients may send positions computed against a version of the document the server has not received
+    /// yet, handlers should validate positions before using them to index into the text.
+    pub fn validate_position( &self, position : &Position, policy : PositionPolicy ) -> Result< Position, PositionError > {
+        let line_count = self.text.len_lines( ) as u64;
+        if position.line >= line_count {
+            if policy == PositionPolicy::Reject {
+                return Err( PositionError::LineOutOfRange {
+                    line       : position.line,
+                    line_count : line_count
+                } );
+            }
+
+            let last_line = line_count - 1;
+            return Ok( Position {
+                line      : last_line,
+                character : self.line_length( last_line as usize )
+            } );
+        }
+
+        let line_length = self.line_length( position.line as usize );
+        if position.character > line_length {
+            if policy == PositionPolicy::Reject {
+                return Err( PositionError::CharacterOutOfRange {
+                    line        : position.line,
+                    character   : position.character,
+                    line_length : line_length
+                } );
+            }
+
+            return Ok( Position {
+                line      : position.line,
+                character : line_length
+            } );
+        }
+
+        Ok( position.clone( ) )
+    }
+
+    /// Checks that both ends of range lie within the document and that its start is not after its end,
+    /// clamping or rejecting the ends according to policy. Inverted ranges are always rejected.
+    pub fn validate_range( &self, range : &Range, policy : PositionPolicy ) -> Result< Range, PositionError > {
+        let start = self.validate_position( &range.start, policy )?;
+        let end = self.validate_position( &range.end, policy )?;
+        if ( start.line, start.character ) > ( end.line, end.character ) {
+            return Err( PositionError::InvertedRange( range.clone( ) ) );
+        }
+
+        Ok( Range {
+            start : start,
+            end   : end
+        } )
+    }
+
+    /// Returns the length of line in UTF-16 code units, without its line break.
+    fn line_length( &self, line : usize ) -> u64 {
+        self.text.line( line ).chars( )
+            .take_while( | &character | character != '\n' && character != '\r' )
+            .map( | character | character.len_utf16( ) as u64 )
+            .sum( )
+    }
+
+    /// Copies the whole text of the document into a String.
+    pub fn text( &self ) -> String {
+        self.text.to_string( )
+    }
+
+}
+
+impl < H : MessageHandler > MessageHandler for DocumentLayer< H > {
+
+    fn handle_request( &self, service : ServiceHandle, request : ServerRequest, output : ResponseOutput ) {
+        self.handler.handle_request( service, request, output );
+    }
+
+    fn handle_notification( &self, service : ServiceHandle, notification : ServerNotification ) {
+        if let Err( error ) = self.store.apply( &notification ) {
+            component_error!( Component::Document, "Error applying document notification: {}", error );
+        }
+
+        self.handler.handle_notification( service, notification );
+    }
+
+    fn claims_request( &self, request : &ServerRequest ) -> bool {
+        self.handler.claims_request( request )
+    }
+
+    /// Claims the document notifications in addition to the notifications claimed by the inner handler, so
+    /// the store is kept up to date when the layer is chained with other handlers.
+    fn claims_notification( &self, notification : &ServerNotification ) -> bool {
+        match *notification {
+            ServerNotification::DidOpenTextDocument( .. ) |
+            ServerNotification::DidChangeTextDocument( .. ) |
+            ServerNotification::DidCloseTextDocument( .. ) => true,
+            _ => self.handler.claims_notification( notification )
+        }
+    }
+
+    /// Advertises incremental synchronization in the capabilities returned by the inner handler, the store
+    /// applies the ranges of incremental changes itself.
+    fn on_initialize( &self, service : ServiceHandle, params : &InitializeParams ) -> Option< InitializeResult > {
+        self.handler.on_initialize( service, params ).map( | mut result | {
+            result.capabilities.text_document_sync = Some( TextDocumentSyncKind::Incremental );
+
+            result
+        } )
+    }
+
+    fn on_initialized( &self, service : ServiceHandle ) {
+        self.handler.on_initialized( service );
+    }
+
+    fn on_shutdown_request( &self, service : ServiceHandle ) -> bool {
+        self.handler.on_shutdown_request( service )
+    }
+
+    fn on_shutdown( &self, service : ServiceHandle, reason : &ShutdownReason ) {
+        self.handler.on_shutdown( service, reason );
+    }
+
+}
+
+impl fmt::Display for DocumentError {
+
+    fn fmt( &self, f : &mut fmt::Formatter ) -> fmt::Result {
+        match *self {
+            DocumentError::NotOpen( ref uri ) => write!( f, "Document {} is not open", uri ),
+            DocumentError::OutOfOrderVersion { ref uri, current, received } => {
+                write!( f, "Received version {} of document {} at version {}", received, uri, current )
+            }
+        }
+    }
+
+}
+
+impl Error for DocumentError {
+
+    fn description( &self ) -> &str {
+        match *self {
+            DocumentError::NotOpen( _ ) => "Document is not open",
+            DocumentError::OutOfOrderVersion { .. } => "Document change out of order"
+        }
+    }
+
+}
+
+impl PositionError {
+
+    /// Returns the INVALID_PARAMS error answering a request with this position.
+    pub fn response_error( &self ) -> ResponseError {
+        ResponseError {
+            code    : INVALID_PARAMS,
+            message : self.to_string( )
+        }
+    }
+
+}
+
+impl fmt::Display for PositionError {
+
+    fn fmt( &self, f : &mut fmt::Formatter ) -> fmt::Result {
+        match *self {
+            PositionError::LineOutOfRange { line, line_count } => {
+                write!( f, "Line {} is past the end of the document of {} lines", line, line_count )
+            },
+            PositionError::CharacterOutOfRange { line, character, line_length } => {
+                write!( f, "Character {} is past the end of line {} of length {}", character, line, line_length )
+            },
+            PositionError::InvertedRange( ref range ) => {
+                write!( f, "Range starts at {}:{} after its end at {}:{}", range.start.line, range.start.character, range.end.line, range.end.character )
+            }
+        }
+    }
+
+}
+
+impl Error for PositionError {
+
+    fn description( &self ) -> &str {
+        match *self {
+            PositionError::LineOutOfRange { .. } => "Line out of range",
+            PositionError::CharacterOutOfRange { .. } => "Character out of range",
+            PositionError::InvertedRange( _ ) => "Inverted range"
+        }
+    }
+
+}
+
+/// Replaces the text covered by range with new_text.
+pub(crate) fn apply_edit( text : &mut Rope, range : &Range, new_text : &str ) {
+    let start = position_to_char( text, &range.start );
+    let end = position_to_char( text, &range.end ).max( start );
+
+    text.remove( start..end );
+    text.insert( start, new_text );
+}
+
+/// Converts a position, with its character counted in UTF-16 code units, to a char index into text.
+///
+/// Lines end with `\n`, `\r\n` or `\r`. Positions past the end of their line are clamped to the end of the
+/// line, positions past the last line are clamped to the end of the text.
+fn position_to_char( text : &Rope, position : &Position ) -> usize {
+    let line = position.line as usize;
+    if line >= text.len_lines( ) {
+        return text.len_chars( );
+    }
+
+    let line_start = text.line_to_char( line );
+    let mut utf16_offset = 0;
+    for ( offset, character ) in text.line( line ).chars( ).enumerate( ) {
+        if utf16_offset >= position.character as usize || character == '\n' || character == '\r' {
+            return line_start + offset;
+        }
+
+        utf16_offset += character.len_utf16( );
+    }
+
+    line_start + text.line( line ).len_chars( )
+}
+
+#[cfg( test )]
+mod tests {
+
+    use lsp_rs::{
+        DidChangeTextDocumentParams,
+        DidOpenTextDocumentParams,
+        Position,
+        Range,
+        TextDocumentContentChangeEvent,
+        TextDocumentItem,
+        Url,
+        VersionedTextDocumentIdentifier
+    };
+    use method::{
+        HoverRequest
+    };
+    use mock_client::{
+        MockClient
+    };
+    use router::{
+        Router
+    };
+    use serde_json::{
+        Value
+    };
+    use super::{
+        with_documents,
+        PositionError,
+        PositionPolicy,
+        TextDocumentStore
+    };
+
+    fn open( text : &str ) -> ( TextDocumentStore, Url ) {
+        let store = TextDocumentStore::new( );
+        let uri = Url::parse( "file:///workspace/main.rs" ).unwrap( );
+        store.open( &DidOpenTextDocumentParams {
+            text_document : TextDocumentItem {
+                uri         : uri.clone( ),
+                language_id : "rust".to_string( ),
+                version     : 1,
+                text        : text.to_string( )
+            }
+        } );
+
+        ( store, uri )
+    }
+
+    fn position( line : u64, character : u64 ) -> Position {
+        Position {
+            line      : line,
+            character : character
+        }
+    }
+
+    fn range( start : ( u64, u64 ), end : ( u64, u64 ) ) -> Range {
+        Range {
+            start : position( start.0, start.1 ),
+            end   : position( end.0, end.1 )
+        }
+    }
+
+    fn edit( range : Option< Range >, text : &str ) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range        : range,
+            range_length : None,
+            text         : text.to_string( )
+        }
+    }
+
+    fn change( store : &TextDocumentStore, uri : &Url, version : i64, changes : Vec< TextDocumentContentChangeEvent > ) -> String {
+        store.change( &DidChangeTextDocumentParams {
+            text_document   : VersionedTextDocumentIdentifier {
+                uri     : uri.clone( ),
+                version : version
+            },
+            content_changes : changes
+        } ).unwrap( );
+
+        store.get( uri ).unwrap( ).text( )
+    }
+
+    #[test]
+    fn edits_of_a_change_are_applied_in_order( ) {
+        let ( store, uri ) = open( "hello world\nsecond line\n" );
+
+        // Every edit is relative to the text left by the edits before it
+        let text = change( &store, &uri, 2, vec![
+            edit( Some( range( ( 0, 0 ), ( 0, 5 ) ) ), "goodbye" ),
+            edit( Some( range( ( 0, 8 ), ( 0, 13 ) ) ), "moon" ),
+            edit( Some( range( ( 1, 0 ), ( 1, 0 ) ) ), "a " ),
+            edit( Some( range( ( 0, 12 ), ( 1, 2 ) ) ), " " )
+        ] );
+
+        assert_eq!( text, "goodbye moon second line\n" );
+        assert_eq!( store.get( &uri ).unwrap( ).version( ), 2 );
+    }
+
+    #[test]
+    fn full_change_resets_the_text_for_the_following_edits( ) {
+        let ( store, uri ) = open( "old text" );
+
+        let text = change( &store, &uri, 2, vec![
+            edit( Some( range( ( 0, 0 ), ( 0, 3 ) ) ), "new" ),
+            edit( None, "replaced\n" ),
+            edit( Some( range( ( 1, 0 ), ( 1, 0 ) ) ), "appended" )
+        ] );
+
+        assert_eq!( text, "replaced\nappended" );
+    }
+
+    #[test]
+    fn positions_past_the_end_of_a_line_are_clamped( ) {
+        let ( store, uri ) = open( "abc\ndef" );
+
+        assert_eq!( change( &store, &uri, 2, vec![ edit( Some( range( ( 0, 10 ), ( 0, 20 ) ) ), "X" ) ] ), "abcX\ndef" );
+        assert_eq!( change( &store, &uri, 3, vec![ edit( Some( range( ( 0, 2 ), ( 0, 99 ) ) ), "" ) ] ), "ab\ndef" );
+        assert_eq!( change( &store, &uri, 4, vec![ edit( Some( range( ( 5, 0 ), ( 5, 0 ) ) ), "!" ) ] ), "ab\ndef!" );
+
+        let snapshot = store.get( &uri ).unwrap( );
+        let clamped = snapshot.validate_position( &position( 0, 10 ), PositionPolicy::Clamp ).unwrap( );
+        assert_eq!( ( clamped.line, clamped.character ), ( 0, 2 ) );
+        let clamped = snapshot.validate_position( &position( 7, 0 ), PositionPolicy::Clamp ).unwrap( );
+        assert_eq!( ( clamped.line, clamped.character ), ( 1, 4 ) );
+
+        match snapshot.validate_position( &position( 0, 10 ), PositionPolicy::Reject ) {
+            Err( PositionError::CharacterOutOfRange { line : 0, character : 10, line_length : 2 } ) => { },
+            result => panic!( "Unexpected result {:?}", result )
+        }
+        match snapshot.validate_range( &range( ( 1, 1 ), ( 0, 1 ) ), PositionPolicy::Clamp ) {
+            Err( PositionError::InvertedRange( _ ) ) => { },
+            result => panic!( "Unexpected result {:?}", result )
+        }
+    }
+
+    #[test]
+    fn characters_are_counted_in_utf16_code_units( ) {
+        // U+1F600 is a surrogate pair, two UTF-16 code units for a single char
+        let ( store, uri ) = open( "a\u{1F600}b\n\u{E9}\u{1F600}" );
+
+        assert_eq!( change( &store, &uri, 2, vec![ edit( Some( range( ( 0, 3 ), ( 0, 4 ) ) ), "c" ) ] ), "a\u{1F600}c\n\u{E9}\u{1F600}" );
+        assert_eq!( change( &store, &uri, 3, vec![ edit( Some( range( ( 1, 1 ), ( 1, 3 ) ) ), "e" ) ] ), "a\u{1F600}c\n\u{E9}e" );
+        assert_eq!( change( &store, &uri, 4, vec![ edit( Some( range( ( 0, 1 ), ( 0, 3 ) ) ), "" ) ] ), "ac\n\u{E9}e" );
+
+        let ( store, uri ) = open( "\u{1F600}\u{1F600}" );
+        let snapshot = store.get( &uri ).unwrap( );
+        let clamped = snapshot.validate_position( &position( 0, 9 ), PositionPolicy::Clamp ).unwrap( );
+        assert_eq!( clamped.character, 4 );
+        assert_eq!( snapshot.slice( &range( ( 0, 2 ), ( 0, 4 ) ) ).to_string( ), "\u{1F600}" );
+    }
+
+    #[test]
+    fn crlf_line_endings_are_not_split_by_edits( ) {
+        let ( store, uri ) = open( "one\r\ntwo\r\nthree" );
+
+        assert_eq!( change( &store, &uri, 2, vec![ edit( Some( range( ( 1, 0 ), ( 1, 3 ) ) ), "TWO" ) ] ), "one\r\nTWO\r\nthree" );
+        // Past the end of the line is before the \r\n, not between its \r and \n
+        assert_eq!( change( &store, &uri, 3, vec![ edit( Some( range( ( 0, 10 ), ( 0, 10 ) ) ), "!" ) ] ), "one!\r\nTWO\r\nthree" );
+        // Joining two lines removes the whole line break
+        assert_eq!( change( &store, &uri, 4, vec![ edit( Some( range( ( 1, 3 ), ( 2, 0 ) ) ), " " ) ] ), "one!\r\nTWO three" );
+        assert_eq!( change( &store, &uri, 5, vec![
+            edit( Some( range( ( 0, 4 ), ( 0, 4 ) ) ), "\r\nnew" ),
+            edit( Some( range( ( 1, 3 ), ( 2, 0 ) ) ), "" )
+        ] ), "one!\r\nnewTWO three" );
+
+        let snapshot = store.get( &uri ).unwrap( );
+        let clamped = snapshot.validate_position( &position( 0, 7 ), PositionPolicy::Clamp ).unwrap( );
+        assert_eq!( ( clamped.line, clamped.character ), ( 0, 4 ) );
+    }
+
+    #[test]
+    fn document_layer_advertises_incremental_synchronization( ) {
+        let router = Router::new( ).advertise_capabilities( ).on_request::< HoverRequest, _ >( | _, _, _ | { } );
+        let mut client = MockClient::new( with_documents( TextDocumentStore::new( ), router ) ).unwrap( );
+
+        client.send_message( &json!( {
+            "jsonrpc" : "2.0",
+            "id"      : 1,
+            "method"  : "initialize",
+            "params"  : { "processId" : null, "rootUri" : null, "capabilities" : { } }
+        } ) ).unwrap( );
+        let result : Value = client.response( 1 ).unwrap( );
+        assert_eq!( result[ "capabilities" ][ "textDocumentSync" ], json!( 2 ) );
+        assert_eq!( result[ "capabilities" ][ "hoverProvider" ], json!( true ) );
+    }
+
+}
@@ -0,0 +1,221 @@
+
+use document::{
+    apply_edit,
+    DocumentError,
+    TextDocumentStore
+};
+use lsp_rs::{
+    DidChangeTextDocumentParams,
+    DidOpenTextDocumentParams,
+    Range,
+    ServerNotification,
+    TextDocumentContentChangeEvent,
+    TextDocumentItem,
+    Url,
+    VersionedTextDocumentIdentifier
+};
+use method::{
+    MethodName
+};
+use ropey::{
+    Rope
+};
+use serde_json::{
+    self,
+    Value
+};
+use std::collections::{
+    HashMap
+};
+use std::io::{
+    self,
+    Write
+};
+use transport;
+
+/// Workspace built in memory for tests, holding files and the documents opened by a simulated client
+///
+/// The fixture records the didOpen and didChange notifications a client would send to open and edit its
+/// documents, in the order they were added, and sends them to a service through its transport or applies
+/// them to a TextDocumentStore directly. Paths are relative to the root of the workspace,
+/// `file:///workspace/` unless set with WorkspaceFixture::root:
+///
+/// ```ignore
+/// let workspace = WorkspaceFixture::new( )
+///     .file( "src/lib.rs", "mod parser;" )
+///     .open( "src/parser.rs", "rust", 1, "fn parse( ) { }" )
+///     .change( "src/parser.rs", 2, "fn parse( input : &str ) { }" );
+///
+/// let ( server_io, mut client_io ) = transport::memory_transport( );
+/// let service = ServiceBuilder::new( core.handle( ) ).start( handler, server_io );
+/// workspace.send( &mut client_io )?;
+/// ```
+#[derive( Clone, Debug )]
+pub struct WorkspaceFixture {
+    root          : Url,
+    files         : HashMap< Url, String >,
+    documents     : HashMap< Url, FixtureDocument >,
+    notifications : Vec< ServerNotification >
+}
+
+#[derive( Clone, Debug )]
+struct FixtureDocument {
+    version : i64,
+    text    : Rope
+}
+
+impl WorkspaceFixture {
+
+    /// Creates an empty workspace rooted at `file:///workspace/`.
+    pub fn new( ) -> Self {
+        WorkspaceFixture {
+            root          : Url::parse( "file:///workspace/" ).expect( "Invalid default workspace root" ),
+            files         : HashMap::new( ),
+            documents     : HashMap::new( ),
+            notifications : Vec::new( )
+        }
+    }
+
+    /// Sets the root the paths of the workspace are relative to. Must be called before adding files.
+    pub fn root( mut self, root : Url ) -> Self {
+        self.root = root;
+
+        self
+    }
+
+    /// Adds a file that is not open in the client.
+    pub fn file( mut self, path : &str, text : &str ) -> Self {
+        let uri = self.uri( path );
+        self.files.insert( uri, text.to_string( ) );
+
+        self
+    }
+
+    /// Opens a document at version, recording its didOpen notification.
+    pub fn open( mut self, path : &str, language_id : &str, version : i64, text : &str ) -> Self {
+        let uri = self.uri( path );
+        self.documents.insert( uri.clone( ), FixtureDocument {
+            version : version,
+            text    : Rope::from_str( text )
+        } );
+        self.notifications.push( ServerNotification::DidOpenTextDocument( DidOpenTextDocumentParams {
+            text_document : TextDocumentItem {
+                uri         : uri,
+                language_id : language_id.to_string( ),
+                version     : version,
+                text        : text.to_string( )
+            }
+        } ) );
+
+        self
+    }
+
+    /// Replaces the whole text of an open document, recording its didChange notification.
+    ///
+    /// Panics if the document is not open, as a client never changes a document it did not open.
+    pub fn change( self, path : &str, version : i64, text : &str ) -> Self {
+        self.push_change( path, version, TextDocumentContentChangeEvent {
+            range        : None,
+            range_length : None,
+            text         : text.to_string( )
+        } )
+    }
+
+    /// Replaces range of an open document with new_text, recording the incremental didChange notification.
+    ///
+    /// Panics if the document is not open, as a client never changes a document it did not open.
+    pub fn edit( self, path : &str, version : i64, range : Range, new_text : &str ) -> Self {
+        self.push_change( path, version, TextDocumentContentChangeEvent {
+            range        : Some( range ),
+            range_length : None,
+            text         : new_text.to_string( )
+        } )
+    }
+
+    /// Returns the uri of a path of the workspace.
+    pub fn uri( &self, path : &str ) -> Url {
+        self.root.join( path ).expect( "Invalid workspace path" )
+    }
+
+    /// Returns the text of a path, from the open document if the path is open and from the files otherwise.
+    pub fn text( &self, path : &str ) -> Option< String > {
+        let uri = self.uri( path );
+        match self.documents.get( &uri ) {
+            Some( document ) => Some( document.text.to_string( ) ),
+            None => self.files.get( &uri ).cloned( )
+        }
+    }
+
+    /// Returns the version of an open document, None if the path is not open.
+    pub fn version( &self, path : &str ) -> Option< i64 > {
+        self.documents.get( &self.uri( path ) ).map( | document | document.version )
+    }
+
+    /// Returns the files that are not open, keyed by uri.
+    pub fn files( &self ) -> &HashMap< Url, String > {
+        &self.files
+    }
+
+    /// Returns the notifications opening and editing the documents, in the order they were recorded.
+    pub fn notifications( &self ) -> &[ ServerNotification ] {
+        &self.notifications
+    }
+
+    /// Returns the notifications as JSON-RPC notification messages.
+    pub fn messages( &self ) -> Vec< Value > {
+        self.notifications.iter( ).map( | notification | {
+            let params = match *notification {
+                ServerNotification::DidOpenTextDocument( ref params ) => serde_json::to_value( params ),
+                ServerNotification::DidChangeTextDocument( ref params ) => serde_json::to_value( params ),
+                _ => unreachable!( "WorkspaceFixture only records didOpen and didChange notifications" )
+            };
+
+            json!( {
+                "jsonrpc" : "2.0",
+                "method"  : notification.method_name( ),
+                "params"  : params.expect( "Error serializing notification parameters" )
+            } )
+        } ).collect( )
+    }
+
+    /// Writes the notifications to the client end of a transport, so the service receives them as if a
+    /// client had opened and edited the documents.
+    pub fn send< W : Write >( &self, writer : &mut W ) -> io::Result< ( ) > {
+        for message in self.messages( ) {
+            transport::write_message( writer, &message )?;
+        }
+
+        writer.flush( )
+    }
+
+    /// Applies the notifications to store, for tests calling handlers without a service.
+    pub fn apply( &self, store : &TextDocumentStore ) -> Result< ( ), DocumentError > {
+        for notification in &self.notifications {
+            store.apply( notification )?;
+        }
+
+        Ok( ( ) )
+    }
+
+    fn push_change( mut self, path : &str, version : i64, change : TextDocumentContentChangeEvent ) -> Self {
+        let uri = self.uri( path );
+        {
+            let document = self.documents.get_mut( &uri ).unwrap_or_else( | | panic!( "Changing {} before opening it", path ) );
+            match change.range {
+                Some( ref range ) => apply_edit( &mut document.text, range, &change.text ),
+                None => document.text = Rope::from_str( &change.text )
+            }
+            document.version = version;
+        }
+        self.notifications.push( ServerNotification::DidChangeTextDocument( DidChangeTextDocumentParams {
+            text_document   : VersionedTextDocumentIdentifier {
+                uri     : uri,
+                version : version
+            },
+            content_changes : vec![ change ]
+        } ) );
+
+        self
+    }
+
+}
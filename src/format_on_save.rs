@@ -0,0 +1,270 @@
+
+use capabilities::{
+    ClientCapabilitiesExt
+};
+use composite::{
+    MethodComponent
+};
+use context::{
+    Context
+};
+use futures::{
+    Future,
+    IntoFuture
+};
+use futures::future::{
+    self,
+    Either
+};
+use handler::{
+    method_not_found,
+    HandlerError
+};
+use logging::{
+    Component
+};
+use lsp_rs::{
+    ApplyWorkspaceEditParams,
+    DidSaveTextDocumentParams,
+    DocumentFormattingParams,
+    FormattingOptions,
+    ResponseError,
+    ServerNotification,
+    ServerRequest,
+    ServerResponse,
+    TextDocumentIdentifier,
+    TextEdit,
+    Url,
+    WillSaveTextDocumentParams,
+    WorkspaceEdit
+};
+use method::{
+    ApplyEditRequest,
+    DidSaveTextDocumentNotification,
+    FormattingRequest,
+    MethodName,
+    NotificationMethod,
+    RequestMethod,
+    WillSaveWaitUntilRequest
+};
+use service::{
+    MessageHandler,
+    ResponseOutput,
+    ServiceHandle
+};
+use std::collections::{
+    HashMap,
+    HashSet
+};
+use std::sync::{
+    Arc,
+    Mutex
+};
+use std::time::{
+    Duration
+};
+
+type Formatter = Box< dyn Fn( DocumentFormattingParams, Context ) -> Box< dyn Future< Item = Vec< TextEdit >, Error = ResponseError > + Send > >;
+
+/// Time given to the formatter to answer a willSaveWaitUntil request by default
+const DEFAULT_DEADLINE_MS : u64 = 750;
+
+const METHODS : &[ &str ] = &[
+    FormattingRequest::METHOD,
+    WillSaveWaitUntilRequest::METHOD,
+    DidSaveTextDocumentNotification::METHOD
+];
+
+/// Component formatting documents when the client saves them, with a single formatter answering
+/// textDocument/formatting requests as well
+///
+/// Each save is formatted once, in the first of these ways that succeeds:
+///
+/// * The formatting edits are returned from textDocument/willSaveWaitUntil, so the client applies them
+///   before writing the document. Edits that are not computed within the deadline are dropped and the
+///   request is answered with no edits, so the client does not drop the save.
+/// * When the client did not send willSaveWaitUntil for the save, or the edits missed the deadline, the
+///   document is formatted after textDocument/didSave and the edits are sent through workspace/applyEdit.
+///   The edits are only sent if the client supports workspace/applyEdit and did not change the document
+///   while they were computed, the formatted document is left unsaved for the user to save again.
+///
+/// Clients only send willSaveWaitUntil to servers advertising it in their text document synchronization
+/// options, servers that do not answer initialize themselves are formatted after didSave.
+///
+/// ```ignore
+/// let format_on_save = FormatOnSave::new( options, | params, context | format_document( params, context ) )
+///     .deadline( Duration::from_millis( 500 ) );
+///
+/// let handler = CompositeBuilder::new( )
+///     .component( format_on_save )
+///     .component( router )
+///     .build( )?;
+/// ```
+pub struct FormatOnSave {
+    formatter : Formatter,
+    options   : FormattingOptions,
+    deadline  : Duration,
+    /// Documents whose last save was formatted through willSaveWaitUntil
+    formatted : Arc< Mutex< HashSet< Url > > >
+}
+
+impl FormatOnSave {
+
+    /// Creates the component formatting documents with formatter. options are the formatting options passed
+    /// to formatter when formatting a saved document, formatting requests are formatted with the options
+    /// sent by the client.
+    pub fn new< F, R >( options : FormattingOptions, formatter : F ) -> Self
+        where F : Fn( DocumentFormattingParams, Context ) -> R + 'static,
+              R : IntoFuture< Item = Vec< TextEdit >, Error = HandlerError >,
+              R::Future : Send + 'static {
+        FormatOnSave {
+            formatter : Box::new( move | params, context | {
+                Box::new( formatter( params, context ).into_future( ).map_err( | error | error.response_error( ).clone( ) ) )
+            } ),
+            options   : options,
+            deadline  : Duration::from_millis( DEFAULT_DEADLINE_MS ),
+            formatted : Arc::new( Mutex::new( HashSet::new( ) ) )
+        }
+    }
+
+    /// Sets the time given to the formatter to answer a willSaveWaitUntil request.
+    pub fn deadline( mut self, deadline : Duration ) -> Self {
+        self.deadline = deadline;
+
+        self
+    }
+
+    fn save_params( &self, uri : Url ) -> DocumentFormattingParams {
+        DocumentFormattingParams {
+            text_document : TextDocumentIdentifier {
+                uri : uri
+            },
+            options       : self.options.clone( )
+        }
+    }
+
+    fn will_save_wait_until( &self, params : WillSaveTextDocumentParams, context : Context, output : ResponseOutput ) {
+        let uri = params.text_document.uri;
+        self.formatted.lock( ).unwrap( ).remove( &uri );
+
+        let clock = context.service( ).clock( ).clone( );
+        let started = clock.now( );
+        let deadline = self.deadline;
+        let formatted = self.formatted.clone( );
+        let edits = ( self.formatter )( self.save_params( uri.clone( ) ), context ).map( move | edits | {
+            // Edits computed after the deadline are not sent, the document is formatted after didSave instead
+            if clock.elapsed( started ) < deadline {
+                formatted.lock( ).unwrap( ).insert( uri );
+            }
+
+            ServerResponse::WillSaveWaitUntil( edits )
+        } );
+
+        output.complete_with_deadline( edits, deadline, ServerResponse::WillSaveWaitUntil( Vec::new( ) ) );
+    }
+
+    fn did_save( &self, params : DidSaveTextDocumentParams, context : Context ) {
+        let uri = params.text_document.uri;
+        if self.formatted.lock( ).unwrap( ).remove( &uri ) {
+            return;
+        }
+
+        let service = context.service( ).clone( );
+        if !service.client_capabilities( ).map( | capabilities | capabilities.supports_apply_edit( ) ).unwrap_or( false ) {
+            component_trace!( Component::Document, "Not formatting {} after save, the client does not support workspace/applyEdit.", uri );
+
+            return;
+        }
+        let version = match service.document_version( &uri ) {
+            Some( version ) => version,
+            None => return
+        };
+
+        let edits = ( self.formatter )( self.save_params( uri.clone( ) ), context );
+        service.clone( ).spawn( edits.then( move | result | {
+            let edits = match result {
+                Ok( edits ) => edits,
+                Err( error ) => {
+                    component_error!( Component::Document, "Error formatting {} after save: {}", uri, error.message );
+
+                    return Either::A( future::ok( ( ) ) );
+                }
+            };
+
+            // The edits were computed for the saved content and no longer apply once the document changed
+            if edits.is_empty( ) || service.document_version( &uri ) != Some( version ) {
+                return Either::A( future::ok( ( ) ) );
+            }
+
+            let mut changes = HashMap::new( );
+            changes.insert( uri.clone( ), edits );
+            let edit = WorkspaceEdit {
+                changes          : Some( changes ),
+                document_changes : None
+            };
+
+            Either::B( service.request::< ApplyEditRequest >( ApplyWorkspaceEditParams { edit : edit } ).then( move | result | {
+                match result {
+                    Ok( ref response ) if !response.applied => component_trace!( Component::Document, "Client did not apply the formatting of {}.", uri ),
+                    Ok( _ ) => { },
+                    Err( error ) => component_error!( Component::Document, "Error applying the formatting of {}: {:?}", uri, error )
+                }
+
+                Ok( ( ) )
+            } ) )
+        } ) );
+    }
+
+}
+
+impl MessageHandler for FormatOnSave {
+
+    fn handle_request( &self, service : ServiceHandle, request : ServerRequest, output : ResponseOutput ) {
+        match request {
+            ServerRequest::Formatting( params ) => {
+                let context = Context::for_request( service, FormattingRequest::METHOD, &output );
+
+                output.complete_with( ( self.formatter )( params, context ).map( ServerResponse::Formatting ) );
+            },
+            ServerRequest::WillSaveWaitUntil( params ) => {
+                let context = Context::for_request( service, WillSaveWaitUntilRequest::METHOD, &output );
+
+                self.will_save_wait_until( params, context, output );
+            },
+            request => {
+                component_error!( Component::Document, "Request {} sent to FormatOnSave.", request.method_name( ) );
+
+                output.send_error( method_not_found( request.method_name( ) ) );
+            }
+        }
+    }
+
+    fn handle_notification( &self, service : ServiceHandle, notification : ServerNotification ) {
+        if let ServerNotification::DidSaveTextDocument( params ) = notification {
+            self.did_save( params, Context::for_notification( service, DidSaveTextDocumentNotification::METHOD ) );
+        }
+    }
+
+    fn claims_request( &self, request : &ServerRequest ) -> bool {
+        match *request {
+            ServerRequest::Formatting( .. ) | ServerRequest::WillSaveWaitUntil( .. ) => true,
+            _ => false
+        }
+    }
+
+    fn claims_notification( &self, notification : &ServerNotification ) -> bool {
+        match *notification {
+            ServerNotification::DidSaveTextDocument( .. ) => true,
+            _ => false
+        }
+    }
+
+}
+
+impl MethodComponent for FormatOnSave {
+
+    fn methods( &self ) -> &[ &'static str ] {
+        METHODS
+    }
+
+}
@@ -0,0 +1,60 @@
+
+use lsp_rs::{
+    IncomingServerMessage,
+    ServerCodec
+};
+use mock_client::{
+    MockClient,
+    MockError
+};
+use router::{
+    Router
+};
+use serde_json::{
+    Value
+};
+use tokio_core::io::{
+    Codec,
+    EasyBuf
+};
+
+/// Decodes arbitrary bytes received from a client, returning the messages decoded before the first error
+/// and the error if any.
+///
+/// Never panics, whatever the bytes. Decoding stops at the first error, as the service closes the
+/// connection when a message cannot be decoded, or once the remaining bytes do not hold a whole message.
+pub fn decode_bytes( bytes : &[ u8 ] ) -> ( Vec< IncomingServerMessage >, Option< String > ) {
+    let mut codec = ServerCodec::new( );
+    let mut buffer = EasyBuf::from( bytes.to_vec( ) );
+
+    let mut messages = Vec::new( );
+    loop {
+        let remaining = buffer.len( );
+        match codec.decode( &mut buffer ) {
+            Ok( Some( envelope ) ) => messages.push( envelope.message ),
+            Ok( None ) => return ( messages, None ),
+            Err( error ) => return ( messages, Some( error.to_string( ) ) )
+        }
+
+        // A codec decoding a message without consuming any byte would decode it forever
+        if buffer.len( ) == remaining {
+            return ( messages, None );
+        }
+    }
+}
+
+/// Sends an arbitrary JSON value to a service as a message of the client, returning the messages the
+/// service sent back.
+///
+/// The service runs a Router without handlers, so the value goes through the codec, the lifecycle checks
+/// and the dispatcher of the service. Never panics, whatever the value, the service answering invalid
+/// messages with errors or closing the connection.
+pub fn process_message( message : Value ) -> Result< Vec< Value >, MockError > {
+    let mut client = MockClient::new( Router::new( ) ).map_err( MockError::Io )?;
+    client.send_message( &message )?;
+
+    match client.run_until_stalled( ) {
+        Ok( _ ) | Err( MockError::Disconnected ) => Ok( client.transcript( ).to_vec( ) ),
+        Err( error ) => Err( error )
+    }
+}
@@ -0,0 +1,260 @@
+
+use serde_json::{
+    self,
+    Value
+};
+use std::collections::{
+    HashMap
+};
+use std::env;
+use std::error::{
+    Error
+};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{
+    Path,
+    PathBuf
+};
+
+/// Environment variable regenerating the golden files instead of comparing against them when set
+pub const UPDATE_ENV_VAR : &str = "LS_SERVICE_UPDATE_GOLDEN";
+
+/// Methods of the notifications whose messages are free text that may contain durations
+const LOG_METHODS : &[ &str ] = &[ "window/logMessage", "window/showMessage", "$/logTrace" ];
+
+/// Checked-in snapshot of the messages sent by a service during a test session
+///
+/// The messages are normalized before being compared, see normalize, so the snapshot only changes when the
+/// protocol flow changes. Setting the LS_SERVICE_UPDATE_GOLDEN environment variable, or calling
+/// GoldenFile::update, writes the messages of the session to the snapshot instead of comparing them:
+///
+/// ```ignore
+/// let mut client = MockClient::new( handler )?;
+/// client.request::< InitializeRequest, Value >( params )?;
+/// client.open_workspace( &workspace )?;
+/// client.expect_notification( | method, _ | method == "textDocument/publishDiagnostics" )?;
+///
+/// client.check_golden( &GoldenFile::new( "tests/golden/diagnostics_on_open.json" ) )?;
+/// ```
+#[derive( Clone, Debug )]
+pub struct GoldenFile {
+    path   : PathBuf,
+    update : bool
+}
+
+/// Errors returned by GoldenFile::check
+#[derive( Debug )]
+pub enum GoldenError {
+    /// The snapshot could not be read or written
+    Io( PathBuf, io::Error ),
+    /// The snapshot is not a JSON array of messages
+    Json( PathBuf, serde_json::Error ),
+    /// The message at index differs from the snapshot, None if the session or the snapshot has fewer
+    /// messages
+    Mismatch {
+        path     : PathBuf,
+        index    : usize,
+        expected : Option< Value >,
+        actual   : Option< Value >
+    }
+}
+
+impl GoldenFile {
+
+    /// Creates a snapshot stored at path, regenerated if the LS_SERVICE_UPDATE_GOLDEN environment variable
+    /// is set.
+    pub fn new< P : Into< PathBuf > >( path : P ) -> Self {
+        GoldenFile {
+            path   : path.into( ),
+            update : env::var_os( UPDATE_ENV_VAR ).is_some( )
+        }
+    }
+
+    /// Sets whether GoldenFile::check regenerates the snapshot instead of comparing against it.
+    pub fn update( mut self, update : bool ) -> Self {
+        self.update = update;
+
+        self
+    }
+
+    pub fn path( &self ) -> &Path {
+        &self.path
+    }
+
+    /// Compares the normalized messages against the snapshot, or writes them to the snapshot in update mode.
+    pub fn check( &self, messages : &[ Value ] ) -> Result< ( ), GoldenError > {
+        let actual = normalize( messages );
+        if self.update {
+            return self.write( &actual );
+        }
+
+        let text = fs::read_to_string( &self.path ).map_err( | error | GoldenError::Io( self.path.clone( ), error ) )?;
+        let expected : Vec< Value > = serde_json::from_str( &text ).map_err( | error | GoldenError::Json( self.path.clone( ), error ) )?;
+
+        for index in 0..expected.len( ).max( actual.len( ) ) {
+            if expected.get( index ) != actual.get( index ) {
+                return Err( GoldenError::Mismatch {
+                    path     : self.path.clone( ),
+                    index    : index,
+                    expected : expected.get( index ).cloned( ),
+                    actual   : actual.get( index ).cloned( )
+                } );
+            }
+        }
+
+        Ok( ( ) )
+    }
+
+    fn write( &self, messages : &[ Value ] ) -> Result< ( ), GoldenError > {
+        if let Some( parent ) = self.path.parent( ) {
+            fs::create_dir_all( parent ).map_err( | error | GoldenError::Io( self.path.clone( ), error ) )?;
+        }
+
+        let mut text = serde_json::to_string_pretty( messages ).map_err( | error | GoldenError::Json( self.path.clone( ), error ) )?;
+        text.push( '\n' );
+
+        fs::write( &self.path, text ).map_err( | error | GoldenError::Io( self.path.clone( ), error ) )
+    }
+
+}
+
+/// Normalizes the messages sent by a service so they do not depend on the timing of the session.
+///
+/// The ids of the requests sent by the service and the progress tokens are replaced by placeholders
+/// numbered in order of appearance (`<id:1>`, `<token:1>`), so the same id or token gets the same
+/// placeholder throughout the session. Durations in the text of log and trace messages are replaced by
+/// `<duration>`. The ids of responses are left as is, they are chosen by the test.
+pub fn normalize( messages : &[ Value ] ) -> Vec< Value > {
+    let mut ids = Placeholders::new( "id" );
+    let mut tokens = Placeholders::new( "token" );
+
+    messages.iter( ).map( | message | {
+        let mut message = message.clone( );
+        let method = message.get( "method" ).and_then( | method | method.as_str( ) ).map( | method | method.to_string( ) );
+
+        if let Some( ref method ) = method {
+            if let Some( id ) = message.get_mut( "id" ) {
+                *id = ids.replace( id );
+            }
+            if let Some( token ) = message.get_mut( "params" ).and_then( | params | params.get_mut( "token" ) ) {
+                *token = tokens.replace( token );
+            }
+            if LOG_METHODS.contains( &method.as_str( ) ) {
+                if let Some( params ) = message.get_mut( "params" ) {
+                    redact_durations( params );
+                }
+            }
+        }
+
+        message
+    } ).collect( )
+}
+
+/// Placeholders numbered in order of appearance of the values they replace
+struct Placeholders {
+    kind   : &'static str,
+    values : HashMap< String, usize >
+}
+
+impl Placeholders {
+
+    fn new( kind : &'static str ) -> Self {
+        Placeholders {
+            kind   : kind,
+            values : HashMap::new( )
+        }
+    }
+
+    fn replace( &mut self, value : &Value ) -> Value {
+        let next = self.values.len( ) + 1;
+        let number = *self.values.entry( value.to_string( ) ).or_insert( next );
+
+        Value::String( format!( "<{}:{}>", self.kind, number ) )
+    }
+
+}
+
+/// Replaces the durations in every string of value, e.g. `12.5ms`.
+fn redact_durations( value : &mut Value ) {
+    match *value {
+        Value::String( ref mut text ) => *text = redact_duration_text( text ),
+        Value::Array( ref mut values ) => {
+            for value in values {
+                redact_durations( value );
+            }
+        },
+        Value::Object( ref mut fields ) => {
+            for ( _, value ) in fields.iter_mut( ) {
+                redact_durations( value );
+            }
+        },
+        _ => { }
+    }
+}
+
+fn redact_duration_text( text : &str ) -> String {
+    const UNITS : &[ &str ] = &[ "ns", "µs", "us", "ms", "s" ];
+
+    let mut redacted = String::with_capacity( text.len( ) );
+    let mut rest = text;
+    while let Some( start ) = rest.find( | c : char | c.is_ascii_digit( ) ) {
+        // Digits that are part of a word, e.g. an identifier, are kept
+        let part_of_word = rest[ ..start ].chars( ).next_back( ).map( | c | c.is_alphanumeric( ) || c == '_' ).unwrap_or( false );
+        redacted.push_str( &rest[ ..start ] );
+        rest = &rest[ start.. ];
+
+        let number_len = rest.find( | c : char | !c.is_ascii_digit( ) && c != '.' ).unwrap_or( rest.len( ) );
+        let unit = UNITS.iter( ).find( | unit | {
+            rest[ number_len.. ].starts_with( **unit ) &&
+                !rest[ number_len + unit.len( ).. ].chars( ).next( ).map( | c | c.is_alphanumeric( ) ).unwrap_or( false )
+        } );
+
+        match unit {
+            Some( unit ) if !part_of_word => {
+                redacted.push_str( "<duration>" );
+                rest = &rest[ number_len + unit.len( ).. ];
+            },
+            _ => {
+                redacted.push_str( &rest[ ..number_len ] );
+                rest = &rest[ number_len.. ];
+            }
+        }
+    }
+    redacted.push_str( rest );
+
+    redacted
+}
+
+impl fmt::Display for GoldenError {
+
+    fn fmt( &self, f : &mut fmt::Formatter ) -> fmt::Result {
+        match *self {
+            GoldenError::Io( ref path, ref error ) => write!( f, "Error accessing golden file {}: {}", path.display( ), error ),
+            GoldenError::Json( ref path, ref error ) => write!( f, "Invalid golden file {}: {}", path.display( ), error ),
+            GoldenError::Mismatch { ref path, index, ref expected, ref actual } => {
+                let show = | message : &Option< Value > | match *message {
+                    Some( ref message ) => serde_json::to_string_pretty( message ).unwrap_or_else( | _ | message.to_string( ) ),
+                    None => "<no message>".to_string( )
+                };
+
+                write!( f, "Message {} differs from golden file {} (set {} to regenerate it)\nexpected: {}\nactual: {}",
+                    index, path.display( ), UPDATE_ENV_VAR, show( expected ), show( actual ) )
+            }
+        }
+    }
+
+}
+
+impl Error for GoldenError {
+
+    fn description( &self ) -> &str {
+        match *self {
+            GoldenError::Io( _, ref error ) => error.description( ),
+            GoldenError::Json( _, ref error ) => error.description( ),
+            GoldenError::Mismatch { .. } => "Messages differ from the golden file"
+        }
+    }
+
+}
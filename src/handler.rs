@@ -0,0 +1,475 @@
+
+use logging::{
+    Component
+};
+use lsp_rs::{
+    METHOD_NOT_FOUND,
+    InitializeParams,
+    InitializeResult,
+    ResponseError,
+    ServerNotification,
+    ServerRequest
+};
+use method::{
+    MethodName
+};
+use response::{
+    internal_error
+};
+use service::{
+    MessageHandler,
+    ResponseOutput,
+    ServiceHandle,
+    ShutdownReason
+};
+use std::cell::{
+    RefCell
+};
+use std::fmt;
+use std::io;
+
+/// Variant of MessageHandler taking &mut self, for stateful handlers that do not want to use interior
+/// mutability. Wrap implementations with exclusive to start a service with them.
+pub trait MessageHandlerMut {
+
+    /// Called when a new RequestMessage has been received from the client, see
+    /// MessageHandler::handle_request.
+    fn handle_request( &mut self, service : ServiceHandle, request : ServerRequest, output : ResponseOutput );
+
+    /// Called when a new NotificationMessage has been received from the client, see
+    /// MessageHandler::handle_notification.
+    fn handle_notification( &mut self, service : ServiceHandle, notification : ServerNotification );
+
+    /// Called by handler combinators to decide whether this handler should receive a request, see
+    /// MessageHandler::claims_request. Defaults to claiming every request.
+    fn claims_request( &self, _request : &ServerRequest ) -> bool {
+        true
+    }
+
+    /// Called by handler combinators to decide whether this handler should receive a notification, see
+    /// MessageHandler::claims_notification. Defaults to claiming every notification.
+    fn claims_notification( &self, _notification : &ServerNotification ) -> bool {
+        true
+    }
+
+    /// Called for the initialize request before it is dispatched to handle_request, see
+    /// MessageHandler::on_initialize.
+    fn on_initialize( &mut self, _service : ServiceHandle, _params : &InitializeParams ) -> Option< InitializeResult > {
+        None
+    }
+
+    /// Called when the initialized notification is received, see MessageHandler::on_initialized.
+    fn on_initialized( &mut self, _service : ServiceHandle ) {
+    }
+
+    /// Called for the shutdown request before it is dispatched to handle_request, see
+    /// MessageHandler::on_shutdown_request.
+    fn on_shutdown_request( &mut self, _service : ServiceHandle ) -> bool {
+        false
+    }
+
+    /// Called when the service begins shutting down, see MessageHandler::on_shutdown.
+    fn on_shutdown( &mut self, _service : ServiceHandle, _reason : &ShutdownReason ) {
+    }
+
+}
+
+/// Variant of MessageHandler whose methods return a Result, allowing handlers to use ? to bail out early.
+/// Wrap implementations with fallible to start a service with them.
+pub trait FallibleMessageHandler {
+
+    /// Called when a new RequestMessage has been received from the client, see
+    /// MessageHandler::handle_request.
+    ///
+    /// If an error is returned before a response was sent through output, the request is answered with the
+    /// ResponseError of the error. Errors returned after a response was sent are logged.
+    fn handle_request( &self, service : ServiceHandle, request : ServerRequest, output : ResponseOutput ) -> Result< ( ), HandlerError >;
+
+    /// Called when a new NotificationMessage has been received from the client, see
+    /// MessageHandler::handle_notification. Returned errors are logged.
+    fn handle_notification( &self, service : ServiceHandle, notification : ServerNotification ) -> Result< ( ), HandlerError >;
+
+    /// Called by handler combinators to decide whether this handler should receive a request, see
+    /// MessageHandler::claims_request. Defaults to claiming every request.
+    fn claims_request( &self, _request : &ServerRequest ) -> bool {
+        true
+    }
+
+    /// Called by handler combinators to decide whether this handler should receive a notification, see
+    /// MessageHandler::claims_notification. Defaults to claiming every notification.
+    fn claims_notification( &self, _notification : &ServerNotification ) -> bool {
+        true
+    }
+
+    /// Called for the initialize request before it is dispatched to handle_request, see
+    /// MessageHandler::on_initialize.
+    fn on_initialize( &self, _service : ServiceHandle, _params : &InitializeParams ) -> Option< InitializeResult > {
+        None
+    }
+
+    /// Called when the initialized notification is received, see MessageHandler::on_initialized.
+    fn on_initialized( &self, _service : ServiceHandle ) {
+    }
+
+    /// Called for the shutdown request before it is dispatched to handle_request, see
+    /// MessageHandler::on_shutdown_request.
+    fn on_shutdown_request( &self, _service : ServiceHandle ) -> bool {
+        false
+    }
+
+    /// Called when the service begins shutting down, see MessageHandler::on_shutdown.
+    fn on_shutdown( &self, _service : ServiceHandle, _reason : &ShutdownReason ) {
+    }
+
+}
+
+/// Error returned by a FallibleMessageHandler, converted into the ResponseError sent to the client
+///
+/// Errors converted from a ResponseError are sent as is, other errors are sent as an INTERNAL_ERROR
+/// containing their Display text.
+#[derive( Debug )]
+pub struct HandlerError {
+    error : ResponseError
+}
+
+/// MessageHandler answering requests with the errors returned by a FallibleMessageHandler, created by
+/// fallible
+pub struct Fallible< H > {
+    handler : H
+}
+
+/// MessageHandler dispatching to a MessageHandlerMut one message at a time, created by exclusive
+pub struct Exclusive< H > {
+    handler : RefCell< H >
+}
+
+/// MessageHandler that answers every request with a METHOD_NOT_FOUND error and ignores every notification
+///
+/// Useful as the final fallback of a partially implemented server, so clients never wait on a response to a
+/// method the server does not implement yet.
+#[derive( Clone, Copy, Debug, Default )]
+pub struct DefaultHandler;
+
+/// MessageHandler that offers every message to a first handler, falling through to a second handler for
+/// messages the first does not claim, created by chain
+pub struct Chain< A, B > {
+    first  : A,
+    second : B
+}
+
+/// MessageHandler that forwards requests and notifications to a pair of closures, created by from_fns
+pub struct FnHandler< R, N > {
+    request_fn      : R,
+    notification_fn : N
+}
+
+/// Creates a MessageHandler that calls request_fn for every request and notification_fn for every
+/// notification received by the service.
+///
+/// ```ignore
+/// let handler = handler::from_fns( | _, _, output | {
+///     output.send_error( ... );
+/// }, | _, _ | { } );
+///
+/// service::start_service( core.handle( ), handler, stdio );
+/// ```
+pub fn from_fns< R, N >( request_fn : R, notification_fn : N ) -> FnHandler< R, N >
+    where R : Fn( ServiceHandle, ServerRequest, ResponseOutput ), N : Fn( ServiceHandle, ServerNotification ) {
+    FnHandler {
+        request_fn      : request_fn,
+        notification_fn : notification_fn
+    }
+}
+
+impl < R, N > MessageHandler for FnHandler< R, N >
+    where R : Fn( ServiceHandle, ServerRequest, ResponseOutput ), N : Fn( ServiceHandle, ServerNotification ) {
+
+    fn handle_request( &self, service : ServiceHandle, request : ServerRequest, output : ResponseOutput ) {
+        ( self.request_fn )( service, request, output );
+    }
+
+    fn handle_notification( &self, service : ServiceHandle, notification : ServerNotification ) {
+        ( self.notification_fn )( service, notification );
+    }
+
+}
+
+/// Wraps a MessageHandlerMut so it can be used as a MessageHandler.
+///
+/// The service dispatches messages one at a time on its event loop, so the wrapped handler is never
+/// borrowed twice. Responses completed asynchronously must not call back into the handler.
+pub fn exclusive< H : MessageHandlerMut >( handler : H ) -> Exclusive< H > {
+    Exclusive {
+        handler : RefCell::new( handler )
+    }
+}
+
+/// Creates a MessageHandlerMut that calls request_fn for every request and notification_fn for every
+/// notification, allowing the closures to mutate their captured state.
+pub fn from_fns_mut< R, N >( request_fn : R, notification_fn : N ) -> Exclusive< FnHandler< R, N > >
+    where R : FnMut( ServiceHandle, ServerRequest, ResponseOutput ), N : FnMut( ServiceHandle, ServerNotification ) {
+    exclusive( FnHandler {
+        request_fn      : request_fn,
+        notification_fn : notification_fn
+    } )
+}
+
+/// Wraps a FallibleMessageHandler so it can be used as a MessageHandler.
+///
+/// ```ignore
+/// fn handle_request( &self, service : ServiceHandle, request : ServerRequest, output : ResponseOutput ) -> Result< ( ), HandlerError > {
+///     let document = self.documents.get( &uri ).ok_or( "Document not open" )?;
+///     ...
+/// }
+/// ```
+pub fn fallible< H : FallibleMessageHandler >( handler : H ) -> Fallible< H > {
+    Fallible {
+        handler : handler
+    }
+}
+
+/// Chains two handlers, dispatching each message to the first handler that claims it.
+///
+/// Requests claimed by neither handler are answered with a METHOD_NOT_FOUND error, notifications claimed by
+/// neither handler are ignored. Chains can be nested to assemble a server from any number of components:
+///
+/// ```ignore
+/// let handler = handler::chain( core_router, handler::chain( experimental_router, DefaultHandler ) );
+/// ```
+pub fn chain< A, B >( first : A, second : B ) -> Chain< A, B > where A : MessageHandler, B : MessageHandler {
+    Chain {
+        first  : first,
+        second : second
+    }
+}
+
+impl < A, B > MessageHandler for Chain< A, B > where A : MessageHandler, B : MessageHandler {
+
+    fn handle_request( &self, service : ServiceHandle, request : ServerRequest, output : ResponseOutput ) {
+        if self.first.claims_request( &request ) {
+            self.first.handle_request( service, request, output );
+        }
+        else if self.second.claims_request( &request ) {
+            self.second.handle_request( service, request, output );
+        }
+        else {
+            output.send_error( method_not_found( request.method_name( ) ) );
+        }
+    }
+
+    fn handle_notification( &self, service : ServiceHandle, notification : ServerNotification ) {
+        if self.first.claims_notification( &notification ) {
+            self.first.handle_notification( service, notification );
+        }
+        else if self.second.claims_notification( &notification ) {
+            self.second.handle_notification( service, notification );
+        }
+        else {
+            component_trace!( Component::Router, "Ignoring unclaimed notification {}.", notification.method_name( ) );
+        }
+    }
+
+    fn claims_request( &self, request : &ServerRequest ) -> bool {
+        self.first.claims_request( request ) || self.second.claims_request( request )
+    }
+
+    fn claims_notification( &self, notification : &ServerNotification ) -> bool {
+        self.first.claims_notification( notification ) || self.second.claims_notification( notification )
+    }
+
+    fn on_initialize( &self, service : ServiceHandle, params : &InitializeParams ) -> Option< InitializeResult > {
+        self.first.on_initialize( service.clone( ), params ).or_else( | | {
+            self.second.on_initialize( service, params )
+        } )
+    }
+
+    fn on_initialized( &self, service : ServiceHandle ) {
+        self.first.on_initialized( service.clone( ) );
+        self.second.on_initialized( service );
+    }
+
+    fn on_shutdown_request( &self, service : ServiceHandle ) -> bool {
+        let first = self.first.on_shutdown_request( service.clone( ) );
+        let second = self.second.on_shutdown_request( service );
+
+        first || second
+    }
+
+    fn on_shutdown( &self, service : ServiceHandle, reason : &ShutdownReason ) {
+        self.first.on_shutdown( service.clone( ), reason );
+        self.second.on_shutdown( service, reason );
+    }
+
+}
+
+impl < R, N > MessageHandlerMut for FnHandler< R, N >
+    where R : FnMut( ServiceHandle, ServerRequest, ResponseOutput ), N : FnMut( ServiceHandle, ServerNotification ) {
+
+    fn handle_request( &mut self, service : ServiceHandle, request : ServerRequest, output : ResponseOutput ) {
+        ( self.request_fn )( service, request, output );
+    }
+
+    fn handle_notification( &mut self, service : ServiceHandle, notification : ServerNotification ) {
+        ( self.notification_fn )( service, notification );
+    }
+
+}
+
+impl < H : MessageHandlerMut > MessageHandler for Exclusive< H > {
+
+    fn handle_request( &self, service : ServiceHandle, request : ServerRequest, output : ResponseOutput ) {
+        self.handler.borrow_mut( ).handle_request( service, request, output );
+    }
+
+    fn handle_notification( &self, service : ServiceHandle, notification : ServerNotification ) {
+        self.handler.borrow_mut( ).handle_notification( service, notification );
+    }
+
+    fn claims_request( &self, request : &ServerRequest ) -> bool {
+        self.handler.borrow( ).claims_request( request )
+    }
+
+    fn claims_notification( &self, notification : &ServerNotification ) -> bool {
+        self.handler.borrow( ).claims_notification( notification )
+    }
+
+    fn on_initialize( &self, service : ServiceHandle, params : &InitializeParams ) -> Option< InitializeResult > {
+        self.handler.borrow_mut( ).on_initialize( service, params )
+    }
+
+    fn on_initialized( &self, service : ServiceHandle ) {
+        self.handler.borrow_mut( ).on_initialized( service );
+    }
+
+    fn on_shutdown_request( &self, service : ServiceHandle ) -> bool {
+        self.handler.borrow_mut( ).on_shutdown_request( service )
+    }
+
+    fn on_shutdown( &self, service : ServiceHandle, reason : &ShutdownReason ) {
+        self.handler.borrow_mut( ).on_shutdown( service, reason );
+    }
+
+}
+
+impl < H : FallibleMessageHandler > MessageHandler for Fallible< H > {
+
+    fn handle_request( &self, service : ServiceHandle, request : ServerRequest, output : ResponseOutput ) {
+        let method = request.method_name( );
+        let responder = output.error_responder( );
+
+        if let Err( error ) = self.handler.handle_request( service, request, output ) {
+            if !responder.send_error( error.error.clone( ) ) {
+                component_error!( Component::Router, "Error returned by handler for {} after a response was sent: {}", method, error );
+            }
+        }
+    }
+
+    fn handle_notification( &self, service : ServiceHandle, notification : ServerNotification ) {
+        let method = notification.method_name( );
+
+        if let Err( error ) = self.handler.handle_notification( service, notification ) {
+            component_error!( Component::Router, "Error handling notification {}: {}", method, error );
+        }
+    }
+
+    fn claims_request( &self, request : &ServerRequest ) -> bool {
+        self.handler.claims_request( request )
+    }
+
+    fn claims_notification( &self, notification : &ServerNotification ) -> bool {
+        self.handler.claims_notification( notification )
+    }
+
+    fn on_initialize( &self, service : ServiceHandle, params : &InitializeParams ) -> Option< InitializeResult > {
+        self.handler.on_initialize( service, params )
+    }
+
+    fn on_initialized( &self, service : ServiceHandle ) {
+        self.handler.on_initialized( service );
+    }
+
+    fn on_shutdown_request( &self, service : ServiceHandle ) -> bool {
+        self.handler.on_shutdown_request( service )
+    }
+
+    fn on_shutdown( &self, service : ServiceHandle, reason : &ShutdownReason ) {
+        self.handler.on_shutdown( service, reason );
+    }
+
+}
+
+impl HandlerError {
+
+    /// Returns the ResponseError sent to the client for this error.
+    pub fn response_error( &self ) -> &ResponseError {
+        &self.error
+    }
+
+}
+
+impl fmt::Display for HandlerError {
+
+    fn fmt( &self, f : &mut fmt::Formatter ) -> fmt::Result {
+        write!( f, "{} ({})", self.error.message, self.error.code )
+    }
+
+}
+
+impl From< ResponseError > for HandlerError {
+
+    fn from( error : ResponseError ) -> Self {
+        HandlerError {
+            error : error
+        }
+    }
+
+}
+
+impl From< io::Error > for HandlerError {
+
+    fn from( error : io::Error ) -> Self {
+        HandlerError {
+            error : internal_error( error )
+        }
+    }
+
+}
+
+impl From< String > for HandlerError {
+
+    fn from( error : String ) -> Self {
+        HandlerError {
+            error : internal_error( error )
+        }
+    }
+
+}
+
+impl < 'a > From< &'a str > for HandlerError {
+
+    fn from( error : &'a str ) -> Self {
+        HandlerError {
+            error : internal_error( error )
+        }
+    }
+
+}
+
+impl MessageHandler for DefaultHandler {
+
+    fn handle_request( &self, _ : ServiceHandle, request : ServerRequest, output : ResponseOutput ) {
+        output.send_error( method_not_found( request.method_name( ) ) );
+    }
+
+    fn handle_notification( &self, _ : ServiceHandle, notification : ServerNotification ) {
+        component_trace!( Component::Router, "Ignoring notification {}.", notification.method_name( ) );
+    }
+
+}
+
+pub(crate) fn method_not_found( method : &str ) -> ResponseError {
+    ResponseError {
+        code    : METHOD_NOT_FOUND,
+        message : format!( "Method not found: {}", method )
+    }
+}
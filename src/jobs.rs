@@ -0,0 +1,232 @@
+
+use futures::{
+    Future,
+    IntoFuture
+};
+use progress::{
+    CancellationToken,
+    ProgressReporter,
+    ProgressTokens
+};
+use service::{
+    ServiceHandle
+};
+use std::cell::{
+    RefCell
+};
+use std::collections::{
+    VecDeque
+};
+use std::rc::{
+    Rc
+};
+use tokio_core::reactor::{
+    Handle
+};
+
+type JobStart = Box< dyn FnOnce( JobContext ) -> Box< dyn Future< Item = ( ), Error = ( ) > > >;
+
+/// Queue of named background jobs run on the event loop, such as indexing or linting the whole workspace
+///
+/// Every job reports its progress under a token allocated from ProgressTokens and receives a
+/// CancellationToken, signalled when the job is canceled through the queue or the user cancels its progress.
+/// At most max_concurrent jobs run at once and jobs sharing a key run one after the other, in the order
+/// they were enqueued. Jobs are expected to check their cancellation token and stop early once it is
+/// signalled, jobs canceled before they started are never run.
+///
+/// ```ignore
+/// let jobs = JobQueue::new( core.handle( ), service.clone( ), progress_tokens, 2 );
+///
+/// // In handle_notification, on didSave
+/// jobs.cancel_key( "lint" );
+/// jobs.enqueue( "lint", "Linting workspace", move | context | {
+///     lint_workspace( &documents, context )
+/// } );
+/// ```
+#[derive( Clone )]
+pub struct JobQueue {
+    inner : Rc< RefCell< JobQueueInner > >
+}
+
+struct JobQueueInner {
+    handle         : Handle,
+    service        : ServiceHandle,
+    progress       : ProgressTokens,
+    max_concurrent : usize,
+    next_job       : u64,
+    running        : Vec< RunningJob >,
+    queue          : VecDeque< QueuedJob >
+}
+
+struct QueuedJob {
+    key    : String,
+    title  : String,
+    cancel : CancellationToken,
+    start  : JobStart
+}
+
+struct RunningJob {
+    id     : u64,
+    key    : String,
+    cancel : CancellationToken
+}
+
+/// Progress reporter and cancellation token handed to a job when it starts
+pub struct JobContext {
+    key      : String,
+    progress : ProgressReporter,
+    cancel   : CancellationToken
+}
+
+impl JobQueue {
+
+    /// Creates a queue running at most max_concurrent jobs at once on the event loop of handle.
+    pub fn new( handle : Handle, service : ServiceHandle, progress : ProgressTokens, max_concurrent : usize ) -> Self {
+        JobQueue {
+            inner : Rc::new( RefCell::new( JobQueueInner {
+                handle         : handle,
+                service        : service,
+                progress       : progress,
+                max_concurrent : max_concurrent.max( 1 ),
+                next_job       : 0,
+                running        : Vec::new( ),
+                queue          : VecDeque::new( )
+            } ) )
+        }
+    }
+
+    /// Enqueues a job under key, running it once a slot is free and no other job with the same key is
+    /// running. Returns the cancellation token of the job.
+    pub fn enqueue< K, T, F, R >( &self, key : K, title : T, job : F ) -> CancellationToken
+        where K : Into< String >,
+              T : Into< String >,
+              F : FnOnce( JobContext ) -> R + 'static,
+              R : IntoFuture< Item = ( ), Error = ( ) > + 'static {
+        let cancel = CancellationToken::new( );
+        self.inner.borrow_mut( ).queue.push_back( QueuedJob {
+            key    : key.into( ),
+            title  : title.into( ),
+            cancel : cancel.clone( ),
+            start  : Box::new( move | context | Box::new( job( context ).into_future( ) ) )
+        } );
+
+        self.pump( );
+
+        cancel
+    }
+
+    /// Cancels every queued and running job with key, e.g. before enqueuing a job superseding them.
+    pub fn cancel_key( &self, key : &str ) {
+        let mut inner = self.inner.borrow_mut( );
+        for job in &inner.queue {
+            if job.key == key {
+                job.cancel.cancel( );
+            }
+        }
+        for job in &inner.running {
+            if job.key == key {
+                job.cancel.cancel( );
+            }
+        }
+        inner.queue.retain( | job | job.key != key );
+    }
+
+    /// Returns the number of jobs waiting to start.
+    pub fn queued( &self ) -> usize {
+        self.inner.borrow( ).queue.len( )
+    }
+
+    /// Returns the number of running jobs.
+    pub fn running( &self ) -> usize {
+        self.inner.borrow( ).running.len( )
+    }
+
+    /// Starts queued jobs until the concurrency limit is reached.
+    fn pump( &self ) {
+        loop {
+            let ( id, job ) = {
+                let mut inner = self.inner.borrow_mut( );
+                // Jobs canceled while queued are dropped without running
+                inner.queue.retain( | job | !job.cancel.is_canceled( ) );
+                if inner.running.len( ) >= inner.max_concurrent {
+                    return;
+                }
+
+                let next = {
+                    let running = &inner.running;
+                    inner.queue.iter( ).position( | job | !running.iter( ).any( | running | running.key == job.key ) )
+                };
+                let job = match next.and_then( | index | inner.queue.remove( index ) ) {
+                    Some( job ) => job,
+                    None => return
+                };
+
+                inner.next_job += 1;
+                let id = inner.next_job;
+                inner.running.push( RunningJob {
+                    id     : id,
+                    key    : job.key.clone( ),
+                    cancel : job.cancel.clone( )
+                } );
+
+                ( id, job )
+            };
+
+            self.start( id, job );
+        }
+    }
+
+    fn start( &self, id : u64, job : QueuedJob ) {
+        let ( handle, begin ) = {
+            let inner = self.inner.borrow( );
+            let begin = inner.progress.begin_with_cancellation( &inner.service, job.title, job.cancel.clone( ) );
+
+            ( inner.handle.clone( ), begin )
+        };
+
+        let queue = self.clone( );
+        let key = job.key;
+        let cancel = job.cancel;
+        let start = job.start;
+        handle.spawn( begin.and_then( move | progress | {
+            start( JobContext {
+                key      : key,
+                progress : progress,
+                cancel   : cancel
+            } )
+        } ).then( move | _ | {
+            queue.finish( id );
+
+            Ok( ( ) )
+        } ) );
+    }
+
+    fn finish( &self, id : u64 ) {
+        self.inner.borrow_mut( ).running.retain( | job | job.id != id );
+
+        self.pump( );
+    }
+
+}
+
+impl JobContext {
+
+    pub fn key( &self ) -> &str {
+        &self.key
+    }
+
+    /// Returns the reporter of the progress of the job, the progress ends when the context is dropped.
+    pub fn progress( &self ) -> &ProgressReporter {
+        &self.progress
+    }
+
+    /// Returns the token signalled when the job is canceled.
+    pub fn cancellation_token( &self ) -> CancellationToken {
+        self.cancel.clone( )
+    }
+
+    pub fn is_canceled( &self ) -> bool {
+        self.cancel.is_canceled( )
+    }
+
+}
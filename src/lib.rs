@@ -2,6 +2,70 @@ extern crate futures;
 #[macro_use]
 extern crate log;
 extern crate lsp_rs;
+extern crate memchr;
+#[cfg( feature = "watch" )]
+extern crate notify;
+extern crate ropey;
+extern crate serde;
+#[macro_use]
+extern crate serde_json;
+#[cfg( feature = "yaml" )]
+extern crate serde_yaml;
 extern crate tokio_core;
+#[cfg( feature = "signal" )]
+extern crate tokio_signal;
 
-pub mod service;
\ No newline at end of file
+#[macro_use]
+pub mod logging;
+pub mod arena;
+pub mod audit;
+pub mod bench;
+pub mod builder;
+pub mod cache;
+pub mod canned;
+pub mod capabilities;
+pub mod chaos;
+pub mod clock;
+pub mod codec;
+pub mod commands;
+pub mod composite;
+pub mod conformance;
+pub mod context;
+pub mod debounce;
+pub mod diagnostics;
+pub mod document;
+pub mod fixture;
+pub mod format_on_save;
+pub mod fuzz;
+pub mod golden;
+pub mod handler;
+pub mod jobs;
+pub mod line_index;
+pub mod listener;
+pub mod loopback;
+pub mod matchers;
+pub mod memory;
+pub mod method;
+pub mod metrics;
+pub mod mock_client;
+pub mod progress;
+pub mod queue;
+pub mod resolve;
+pub mod response;
+pub mod response_slots;
+pub mod router;
+pub mod script;
+pub mod semantic_tokens;
+pub mod service;
+pub mod settings;
+pub mod shaping;
+pub mod spy;
+#[cfg( feature = "leak-check" )]
+pub mod tasks;
+#[cfg( test )]
+mod testing;
+pub mod transport;
+pub mod uri;
+pub mod vfs;
+pub mod workspace_edit;
+pub mod workspace_layout;
\ No newline at end of file
@@ -0,0 +1,329 @@
+
+use lsp_rs::{
+    Position
+};
+use ropey::{
+    Rope
+};
+
+/// Unit in which the character of a Position is counted
+#[derive( Clone, Copy, Debug, PartialEq, Eq )]
+pub enum PositionEncoding {
+    /// Characters are counted in UTF-8 code units, i.e. bytes
+    Utf8,
+    /// Characters are counted in UTF-16 code units, the default of the Language Server Protocol
+    Utf16,
+    /// Characters are counted in unicode scalar values, i.e. Rust chars
+    Utf32
+}
+
+/// Index of the lines of a text, converting between byte offsets, char offsets and Positions
+///
+/// Lines end with `\n`, `\r\n` or `\r`. Conversions clamp out of range input: offsets past the end of the
+/// text map to the end of the text, positions past the end of their line map to the end of the line and
+/// positions past the last line map to the end of the text. Offsets and positions inside a multi-unit
+/// character map to the start of the character.
+#[derive( Clone, Debug )]
+pub struct LineIndex {
+    encoding    : PositionEncoding,
+    /// Byte offset of the start of each line
+    line_starts : Vec< usize >,
+    /// Char offset of the start of each line
+    char_starts : Vec< usize >,
+    /// Byte offset of the end of the content of each line, before its line break
+    line_ends   : Vec< usize >,
+    /// Non-ASCII characters of each line, ordered by offset
+    wide_chars  : Vec< Vec< WideChar > >,
+    len         : usize
+}
+
+#[derive( Clone, Copy, Debug )]
+struct WideChar {
+    /// Byte offset of the character from the start of its line
+    start     : usize,
+    len_utf8  : usize,
+    len_utf16 : usize
+}
+
+impl LineIndex {
+
+    /// Builds the index of text, with positions counted in encoding.
+    pub fn new( text : &str, encoding : PositionEncoding ) -> Self {
+        LineIndex::from_chars( text.chars( ), encoding )
+    }
+
+    /// Builds the index of the text held by a rope, with positions counted in encoding.
+    pub fn from_rope( text : &Rope, encoding : PositionEncoding ) -> Self {
+        LineIndex::from_chars( text.chars( ), encoding )
+    }
+
+    /// Returns the encoding positions are counted in.
+    pub fn encoding( &self ) -> PositionEncoding {
+        self.encoding
+    }
+
+    /// Returns the number of lines of the text, a text ending with a line break ends with an empty line.
+    pub fn line_count( &self ) -> usize {
+        self.line_starts.len( )
+    }
+
+    /// Converts a byte offset to a Position.
+    pub fn position( &self, offset : usize ) -> Position {
+        let offset = offset.min( self.len );
+        let line = match self.line_starts.binary_search( &offset ) {
+            Ok( line ) => line,
+            Err( next_line ) => next_line - 1
+        };
+        let column = offset.min( self.line_ends[ line ] ) - self.line_starts[ line ];
+
+        Position {
+            line      : line as u64,
+            character : self.encode_column( line, column ) as u64
+        }
+    }
+
+    /// Converts a Position to a byte offset.
+    pub fn offset( &self, position : &Position ) -> usize {
+        let line = position.line as usize;
+        if line >= self.line_count( ) {
+            return self.len;
+        }
+
+        self.line_starts[ line ] + self.decode_column( line, position.character as usize )
+    }
+
+    /// Converts a char offset to a Position.
+    pub fn char_position( &self, char_offset : usize ) -> Position {
+        let line = match self.char_starts.binary_search( &char_offset ) {
+            Ok( line ) => line,
+            Err( next_line ) => next_line - 1
+        };
+
+        let mut column = char_offset - self.char_starts[ line ];
+        for wide_char in &self.wide_chars[ line ] {
+            if wide_char.start >= column {
+                break;
+            }
+            column += wide_char.len_utf8 - 1;
+        }
+
+        self.position( self.line_starts[ line ] + column )
+    }
+
+    /// Converts a Position to a char offset.
+    pub fn char_offset( &self, position : &Position ) -> usize {
+        let line = position.line as usize;
+        if line >= self.line_count( ) {
+            let last_line = self.line_count( ) - 1;
+
+            return self.char_starts[ last_line ] + self.chars_in_column( last_line, self.line_ends[ last_line ] - self.line_starts[ last_line ] );
+        }
+
+        self.char_starts[ line ] + self.chars_in_column( line, self.decode_column( line, position.character as usize ) )
+    }
+
+    fn from_chars< I : Iterator< Item = char > >( chars : I, encoding : PositionEncoding ) -> Self {
+        let mut index = LineIndex {
+            encoding    : encoding,
+            line_starts : vec![ 0 ],
+            char_starts : vec![ 0 ],
+            line_ends   : Vec::new( ),
+            wide_chars  : vec![ Vec::new( ) ],
+            len         : 0
+        };
+
+        let mut offset = 0;
+        let mut char_offset = 0;
+        let mut after_cr = false;
+        for character in chars {
+            if after_cr && character == '\n' {
+                // Second half of a \r\n line break, the line was already ended by the \r
+                offset += 1;
+                char_offset += 1;
+                *index.line_starts.last_mut( ).unwrap( ) = offset;
+                *index.char_starts.last_mut( ).unwrap( ) = char_offset;
+                after_cr = false;
+
+                continue;
+            }
+
+            after_cr = character == '\r';
+            if character == '\n' || character == '\r' {
+                index.line_ends.push( offset );
+                offset += 1;
+                char_offset += 1;
+
+                index.line_starts.push( offset );
+                index.char_starts.push( char_offset );
+                index.wide_chars.push( Vec::new( ) );
+
+                continue;
+            }
+
+            if !character.is_ascii( ) {
+                let line_start = *index.line_starts.last( ).unwrap( );
+                index.wide_chars.last_mut( ).unwrap( ).push( WideChar {
+                    start     : offset - line_start,
+                    len_utf8  : character.len_utf8( ),
+                    len_utf16 : character.len_utf16( )
+                } );
+            }
+            offset += character.len_utf8( );
+            char_offset += 1;
+        }
+        index.line_ends.push( offset );
+        index.len = offset;
+
+        index
+    }
+
+    /// Returns the length of a wide char in the encoding of the index.
+    fn encoded_len( &self, wide_char : &WideChar ) -> usize {
+        match self.encoding {
+            PositionEncoding::Utf8 => wide_char.len_utf8,
+            PositionEncoding::Utf16 => wide_char.len_utf16,
+            PositionEncoding::Utf32 => 1
+        }
+    }
+
+    /// Converts a byte column of line to a column in the encoding of the index.
+    fn encode_column( &self, line : usize, column : usize ) -> usize {
+        let mut encoded = column;
+        for wide_char in &self.wide_chars[ line ] {
+            if wide_char.start >= column {
+                break;
+            }
+            if wide_char.start + wide_char.len_utf8 > column {
+                // Inside the character, count from its start
+                encoded -= column - wide_char.start;
+                break;
+            }
+            encoded -= wide_char.len_utf8 - self.encoded_len( wide_char );
+        }
+
+        encoded
+    }
+
+    /// Converts a column of line in the encoding of the index to a byte column, clamped to the line.
+    fn decode_column( &self, line : usize, column : usize ) -> usize {
+        let mut decoded = column;
+        for wide_char in &self.wide_chars[ line ] {
+            let encoded_start = wide_char.start + column - decoded;
+            if encoded_start >= column {
+                break;
+            }
+            if encoded_start + self.encoded_len( wide_char ) > column {
+                decoded = wide_char.start;
+                break;
+            }
+            decoded += wide_char.len_utf8 - self.encoded_len( wide_char );
+        }
+
+        decoded.min( self.line_ends[ line ] - self.line_starts[ line ] )
+    }
+
+    /// Returns the number of chars in the first column bytes of line.
+    fn chars_in_column( &self, line : usize, column : usize ) -> usize {
+        let mut chars = column;
+        for wide_char in &self.wide_chars[ line ] {
+            if wide_char.start >= column {
+                break;
+            }
+            chars -= wide_char.len_utf8 - 1;
+        }
+
+        chars
+    }
+
+}
+
+#[cfg( test )]
+mod tests {
+
+    use lsp_rs::{
+        Position
+    };
+    use super::{
+        LineIndex,
+        PositionEncoding
+    };
+
+    /// Text with a character outside the basic multilingual plane, encoded as a surrogate pair in UTF-16, and
+    /// a CRLF line break
+    const TEXT : &str = "a\u{1F600}b\r\nc";
+
+    fn position( line : u64, character : u64 ) -> Position {
+        Position {
+            line      : line,
+            character : character
+        }
+    }
+
+    fn pair( position : Position ) -> ( u64, u64 ) {
+        ( position.line, position.character )
+    }
+
+    #[test]
+    fn surrogate_pairs_count_as_two_utf16_units( ) {
+        let index = LineIndex::new( TEXT, PositionEncoding::Utf16 );
+
+        assert_eq!( pair( index.position( 5 ) ), ( 0, 3 ) );
+        assert_eq!( index.offset( &position( 0, 3 ) ), 5 );
+        assert_eq!( pair( index.char_position( 2 ) ), ( 0, 3 ) );
+        assert_eq!( index.char_offset( &position( 0, 3 ) ), 2 );
+    }
+
+    #[test]
+    fn positions_inside_a_surrogate_pair_map_to_its_start( ) {
+        let index = LineIndex::new( TEXT, PositionEncoding::Utf16 );
+
+        assert_eq!( index.offset( &position( 0, 2 ) ), 1 );
+        assert_eq!( pair( index.position( 3 ) ), ( 0, 1 ) );
+    }
+
+    #[test]
+    fn columns_are_counted_in_the_encoding_of_the_index( ) {
+        assert_eq!( pair( LineIndex::new( TEXT, PositionEncoding::Utf8 ).position( 5 ) ), ( 0, 5 ) );
+        assert_eq!( pair( LineIndex::new( TEXT, PositionEncoding::Utf16 ).position( 5 ) ), ( 0, 3 ) );
+        assert_eq!( pair( LineIndex::new( TEXT, PositionEncoding::Utf32 ).position( 5 ) ), ( 0, 2 ) );
+    }
+
+    #[test]
+    fn crlf_is_a_single_line_break( ) {
+        let index = LineIndex::new( TEXT, PositionEncoding::Utf16 );
+
+        assert_eq!( index.line_count( ), 2 );
+        assert_eq!( pair( index.position( 8 ) ), ( 1, 0 ) );
+        assert_eq!( index.offset( &position( 1, 0 ) ), 8 );
+        assert_eq!( index.char_offset( &position( 1, 1 ) ), 6 );
+
+        let index = LineIndex::new( "a\r\n\r\nb", PositionEncoding::Utf16 );
+        assert_eq!( index.line_count( ), 3 );
+        assert_eq!( pair( index.position( 3 ) ), ( 1, 0 ) );
+        assert_eq!( pair( index.position( 5 ) ), ( 2, 0 ) );
+    }
+
+    #[test]
+    fn offsets_between_cr_and_lf_map_to_the_end_of_the_line( ) {
+        let index = LineIndex::new( TEXT, PositionEncoding::Utf16 );
+
+        assert_eq!( pair( index.position( 7 ) ), ( 0, 4 ) );
+        assert_eq!( index.offset( &position( 0, 10 ) ), 6 );
+    }
+
+    #[test]
+    fn lone_cr_and_trailing_line_breaks_end_lines( ) {
+        assert_eq!( pair( LineIndex::new( "a\rb", PositionEncoding::Utf16 ).position( 2 ) ), ( 1, 0 ) );
+        assert_eq!( LineIndex::new( "a\n", PositionEncoding::Utf16 ).line_count( ), 2 );
+        assert_eq!( LineIndex::new( "a\r\n", PositionEncoding::Utf16 ).line_count( ), 2 );
+    }
+
+    #[test]
+    fn positions_past_the_last_line_map_to_the_end_of_the_text( ) {
+        let index = LineIndex::new( TEXT, PositionEncoding::Utf16 );
+
+        assert_eq!( index.offset( &position( 5, 0 ) ), TEXT.len( ) );
+        assert_eq!( pair( index.position( 100 ) ), ( 1, 1 ) );
+    }
+
+}
@@ -0,0 +1,107 @@
+
+use builder::{
+    ServiceConfig
+};
+use futures::{
+    Async,
+    Future,
+    Poll,
+    Stream
+};
+use logging::{
+    Component
+};
+use service::{
+    self,
+    MessageHandler
+};
+use std::any::{
+    Any
+};
+use std::io;
+use std::net::{
+    SocketAddr
+};
+use std::sync::{
+    Arc
+};
+use tokio_core::net::{
+    Incoming,
+    TcpListener
+};
+use tokio_core::reactor::{
+    Handle
+};
+
+/// Information about a client connection accepted by a Listener
+#[derive( Clone, Copy, Debug )]
+pub struct ConnectionInfo {
+    /// Sequential id of the connection, starting at 0 for the first accepted connection
+    pub id        : u64,
+    pub peer_addr : SocketAddr
+}
+
+/// Future accepting client connections on a TCP socket and starting a service for each of them, created
+/// by ServiceBuilder::listen
+///
+/// Every connection gets its own MessageHandler, created by the factory passed to ServiceBuilder::listen, so
+/// state local to a client (open documents, client capabilities) is never shared between connections.
+/// State shared by all connections (index caches, configuration) is registered once and retrievable by
+/// every handler through Context::extension or ServiceHandle::extension.
+///
+/// Resolves when the listening socket is closed, services already started keep running.
+pub struct Listener< F > {
+    handle          : Handle,
+    config          : ServiceConfig,
+    incoming        : Incoming,
+    factory         : F,
+
+    next_connection : u64
+}
+
+pub(crate) fn listen< S, F, H >( handle : Handle, mut config : ServiceConfig, listener : TcpListener, shared_state : Arc< S >, factory : F ) -> Listener< F >
+    where S : Any + Send + Sync, F : Fn( &ConnectionInfo ) -> H, H : MessageHandler + 'static {
+    config.state_map.insert( shared_state );
+
+    Listener {
+        handle          : handle,
+        config          : config,
+        incoming        : listener.incoming( ),
+        factory         : factory,
+
+        next_connection : 0
+    }
+}
+
+impl < F, H > Future for Listener< F > where F : Fn( &ConnectionInfo ) -> H, H : MessageHandler + 'static {
+
+    type Item  = ( );
+    type Error = io::Error;
+
+    fn poll( &mut self ) -> Poll< Self::Item, Self::Error > {
+        loop {
+            let ( stream, peer_addr ) = match self.incoming.poll( ) {
+                Ok( Async::Ready( Some( connection ) ) ) => connection,
+                Ok( Async::Ready( None ) ) => return Ok( Async::Ready( ( ) ) ),
+                Ok( Async::NotReady ) => return Ok( Async::NotReady ),
+                Err( error ) => {
+                    component_error!( Component::Service, "Error accepting connection: {}", error );
+
+                    return Err( error )
+                }
+            };
+
+            let info = ConnectionInfo {
+                id        : self.next_connection,
+                peer_addr : peer_addr
+            };
+            self.next_connection += 1;
+
+            component_trace!( Component::Service, "Accepted connection {} from {}.", info.id, info.peer_addr );
+
+            let message_handler = ( self.factory )( &info );
+            service::start_service_with_config( self.handle.clone( ), self.config.clone( ), message_handler, stream );
+        }
+    }
+
+}
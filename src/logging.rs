@@ -0,0 +1,171 @@
+
+use log::{
+    LogLevel,
+    LogLevelFilter
+};
+use std::fmt::{
+    self,
+    Display,
+    Formatter
+};
+use std::sync::atomic::{
+    AtomicUsize,
+    Ordering
+};
+
+/// Logs a message to the log target of the given Component, if the Component's filter allows it.
+macro_rules! component_log {
+    (
+        $component : expr, $level : expr, $( $arg : tt )+
+    ) => {
+        if $crate::logging::is_enabled( $component, $level ) {
+            log!( target : $component.target( ), $level, $( $arg )+ );
+        }
+    };
+}
+
+macro_rules! component_error {
+    (
+        $component : expr, $( $arg : tt )+
+    ) => {
+        component_log!( $component, ::log::LogLevel::Error, $( $arg )+ )
+    };
+}
+
+macro_rules! component_info {
+    (
+        $component : expr, $( $arg : tt )+
+    ) => {
+        component_log!( $component, ::log::LogLevel::Info, $( $arg )+ )
+    };
+}
+
+macro_rules! component_debug {
+    (
+        $component : expr, $( $arg : tt )+
+    ) => {
+        component_log!( $component, ::log::LogLevel::Debug, $( $arg )+ )
+    };
+}
+
+macro_rules! component_trace {
+    (
+        $component : expr, $( $arg : tt )+
+    ) => {
+        component_log!( $component, ::log::LogLevel::Trace, $( $arg )+ )
+    };
+}
+
+/// Components of the service that write to their own log target
+#[derive( Clone, Copy, Debug, PartialEq, Eq, Hash )]
+pub enum Component {
+    /// Service lifecycle and handle operations, logged to `ls_service::service`
+    Service,
+    /// Reading and dispatching of incoming messages, logged to `ls_service::reader`
+    Reader,
+    /// Ordering and writing of responses, logged to `ls_service::writer`
+    Writer,
+    /// Processing of commands sent through a ServiceHandle, logged to `ls_service::commands`
+    Commands,
+    /// Framing of messages read from and written to the IO stream, logged to `ls_service::codec`
+    Codec,
+    /// Dispatching of requests and notifications to the handlers of a Router or composite handler, logged to
+    /// `ls_service::router`
+    Router,
+    /// Open documents and the workspace state derived from them, such as cached files, settings and
+    /// diagnostics, logged to `ls_service::document`
+    Document
+}
+
+/// Identifier assigned to every request received by any service in this process.
+///
+/// Unlike the JSON-RPC request id, which is chosen by the client and may be reused across connections, a
+/// correlation id is unique for the lifetime of the process. Every log line emitted by the service while
+/// processing a request is prefixed with its correlation id.
+#[derive( Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord )]
+pub struct CorrelationId( u64 );
+
+static NEXT_CORRELATION_ID : AtomicUsize = AtomicUsize::new( 1 );
+
+static SERVICE_FILTER  : AtomicUsize = AtomicUsize::new( LogLevelFilter::Trace as usize );
+static READER_FILTER   : AtomicUsize = AtomicUsize::new( LogLevelFilter::Trace as usize );
+static WRITER_FILTER   : AtomicUsize = AtomicUsize::new( LogLevelFilter::Trace as usize );
+static COMMANDS_FILTER : AtomicUsize = AtomicUsize::new( LogLevelFilter::Trace as usize );
+static CODEC_FILTER    : AtomicUsize = AtomicUsize::new( LogLevelFilter::Trace as usize );
+static ROUTER_FILTER   : AtomicUsize = AtomicUsize::new( LogLevelFilter::Trace as usize );
+static DOCUMENT_FILTER : AtomicUsize = AtomicUsize::new( LogLevelFilter::Trace as usize );
+
+/// Sets the maximum level of messages logged by the given component.
+///
+/// This filter is applied before the installed logger's own filtering, so it can only be used to further
+/// restrict the output of a component. All components default to LogLevelFilter::Trace.
+pub fn set_component_filter( component : Component, filter : LogLevelFilter ) {
+    component.filter( ).store( filter as usize, Ordering::Relaxed );
+}
+
+/// Returns the current maximum level of messages logged by the given component.
+pub fn component_filter( component : Component ) -> LogLevelFilter {
+    match component.filter( ).load( Ordering::Relaxed ) {
+        0 => LogLevelFilter::Off,
+        1 => LogLevelFilter::Error,
+        2 => LogLevelFilter::Warn,
+        3 => LogLevelFilter::Info,
+        4 => LogLevelFilter::Debug,
+        _ => LogLevelFilter::Trace
+    }
+}
+
+/// Returns whether a message at the given level would be logged by the given component.
+pub fn is_enabled( component : Component, level : LogLevel ) -> bool {
+    level <= component_filter( component )
+}
+
+impl CorrelationId {
+
+    pub(crate) fn next( ) -> Self {
+        CorrelationId( NEXT_CORRELATION_ID.fetch_add( 1, Ordering::Relaxed ) as u64 )
+    }
+
+    /// Returns the numeric value of this correlation id.
+    pub fn value( &self ) -> u64 {
+        self.0
+    }
+
+}
+
+impl Display for CorrelationId {
+
+    fn fmt( &self, f : &mut Formatter ) -> fmt::Result {
+        write!( f, "req-{}", self.0 )
+    }
+
+}
+
+impl Component {
+
+    /// Returns the log target used for messages logged by this component.
+    pub fn target( &self ) -> &'static str {
+        match *self {
+            Component::Service  => "ls_service::service",
+            Component::Reader   => "ls_service::reader",
+            Component::Writer   => "ls_service::writer",
+            Component::Commands => "ls_service::commands",
+            Component::Codec    => "ls_service::codec",
+            Component::Router   => "ls_service::router",
+            Component::Document => "ls_service::document"
+        }
+    }
+
+    fn filter( &self ) -> &'static AtomicUsize {
+        match *self {
+            Component::Service  => &SERVICE_FILTER,
+            Component::Reader   => &READER_FILTER,
+            Component::Writer   => &WRITER_FILTER,
+            Component::Commands => &COMMANDS_FILTER,
+            Component::Codec    => &CODEC_FILTER,
+            Component::Router   => &ROUTER_FILTER,
+            Component::Document => &DOCUMENT_FILTER
+        }
+    }
+
+}
@@ -0,0 +1,354 @@
+
+use builder::{
+    ServiceConfig
+};
+use futures::{
+    Async,
+    Future,
+    Poll
+};
+use futures::future;
+use futures::sync::{
+    mpsc,
+    oneshot
+};
+use lsp_rs::{
+    ResponseError
+};
+use serde_json::{
+    Value
+};
+use service::{
+    self,
+    MessageHandler,
+    ServiceHandle
+};
+use std::cell::{
+    RefCell
+};
+use std::collections::{
+    HashMap
+};
+use std::error::{
+    Error
+};
+use std::fmt;
+use std::io::{
+    self,
+    Read
+};
+use std::rc::{
+    Rc
+};
+use tokio_core::reactor::{
+    Handle
+};
+use transport::{
+    self,
+    MemoryStream
+};
+
+/// Error code of the response sent for a request of the service the client has no handler for
+const METHOD_NOT_FOUND : i64 = -32601;
+
+type RequestHandler = Box< dyn Fn( &str, Value ) -> Result< Value, ResponseError > >;
+
+/// Client connected to a service running on the same event loop, created by ServiceBuilder::loopback
+///
+/// The client and the service exchange messages over an in-memory transport instead of OS pipes, so a
+/// language server can be embedded in an editor written in Rust, or driven by asynchronous tests sharing the
+/// event loop of the service. Unlike MockClient, the client does not own the event loop, its requests are
+/// futures resolved as the loop runs:
+///
+/// ```ignore
+/// let ( service, client ) = ServiceBuilder::new( core.handle( ) ).loopback( Server::new( ) );
+/// client.on_request( | method, _params | match method {
+///     "workspace/configuration" => Ok( json!( [ { } ] ) ),
+///     _ => Err( ResponseError { code : -32601, message : method.to_string( ) } )
+/// } );
+///
+/// let capabilities = core.run( client.request( "initialize", initialize_params ) )?;
+/// client.notify( "initialized", json!( { } ) )?;
+/// ```
+///
+/// The client is not Send, it must be used from the thread running the event loop of the service. The
+/// transport stays open until the service shuts down, e.g. after the exit notification.
+#[derive( Clone )]
+pub struct LoopbackClient {
+    inner : Rc< RefCell< ClientState > >
+}
+
+struct ClientState {
+    stream        : MemoryStream,
+    buffer        : Vec< u8 >,
+    next_id       : i64,
+    pending       : HashMap< i64, oneshot::Sender< Result< Value, ResponseError > > >,
+    notifications : Option< mpsc::UnboundedSender< ( String, Value ) > >,
+    on_request    : Option< RequestHandler >,
+    disconnected  : bool
+}
+
+/// Errors resolving the requests of a LoopbackClient
+#[derive( Debug )]
+pub enum LoopbackError {
+    /// The service answered the request with an error
+    Response( ResponseError ),
+    /// The service closed the transport before answering the request
+    Disconnected,
+    Io( io::Error )
+}
+
+pub(crate) fn connect< H : MessageHandler + 'static >( handle : Handle, config : ServiceConfig, message_handler : H ) -> ( ServiceHandle, LoopbackClient ) {
+    let ( server_io, client_io ) = transport::memory_transport( );
+    let service = service::start_service_with_config( handle.clone( ), config, message_handler, server_io );
+
+    let client = LoopbackClient {
+        inner : Rc::new( RefCell::new( ClientState {
+            stream        : client_io,
+            buffer        : Vec::new( ),
+            next_id       : 0,
+            pending       : HashMap::new( ),
+            notifications : None,
+            on_request    : None,
+            disconnected  : false
+        } ) )
+    };
+
+    // Reads the messages of the service until it closes the transport
+    let reader = client.clone( );
+    handle.spawn( future::poll_fn( move | | -> Poll< ( ), ( ) > {
+        reader.poll_messages( )
+    } ) );
+
+    ( service, client )
+}
+
+impl LoopbackClient {
+
+    /// Sends a request to the service, resolving with the result of its response.
+    pub fn request( &self, method : &str, params : Value ) -> Box< dyn Future< Item = Value, Error = LoopbackError > > {
+        let ( response_send, response_read ) = oneshot::channel( );
+        let sent = {
+            let mut state = self.inner.borrow_mut( );
+            state.next_id += 1;
+            let id = state.next_id;
+
+            let mut message = json!( {
+                "jsonrpc" : "2.0",
+                "id"      : id,
+                "method"  : method
+            } );
+            if !params.is_null( ) {
+                message[ "params" ] = params;
+            }
+
+            state.pending.insert( id, response_send );
+            state.write( &message )
+        };
+        if let Err( error ) = sent {
+            return Box::new( future::err( error ) );
+        }
+
+        Box::new( response_read.then( | result | {
+            match result {
+                Ok( Ok( result ) ) => Ok( result ),
+                Ok( Err( error ) ) => Err( LoopbackError::Response( error ) ),
+                Err( _ ) => Err( LoopbackError::Disconnected )
+            }
+        } ) )
+    }
+
+    /// Sends a notification to the service.
+    pub fn notify( &self, method : &str, params : Value ) -> Result< ( ), LoopbackError > {
+        let mut message = json!( {
+            "jsonrpc" : "2.0",
+            "method"  : method
+        } );
+        if !params.is_null( ) {
+            message[ "params" ] = params;
+        }
+
+        self.inner.borrow_mut( ).write( &message )
+    }
+
+    /// Returns a stream of the method and parameters of the notifications sent by the service from now on.
+    /// Notifications are dropped while no stream is alive, a new stream replaces the previous one.
+    pub fn notifications( &self ) -> mpsc::UnboundedReceiver< ( String, Value ) > {
+        let ( notification_send, notification_read ) = mpsc::unbounded( );
+        self.inner.borrow_mut( ).notifications = Some( notification_send );
+
+        notification_read
+    }
+
+    /// Sets the handler answering the requests sent by the service, given their method and parameters.
+    /// Requests are answered with a method not found error while no handler is set.
+    pub fn on_request< F >( &self, handler : F ) where F : Fn( &str, Value ) -> Result< Value, ResponseError > + 'static {
+        self.inner.borrow_mut( ).on_request = Some( Box::new( handler ) );
+    }
+
+    /// Returns true once the service closed the transport.
+    pub fn is_disconnected( &self ) -> bool {
+        self.inner.borrow( ).disconnected
+    }
+
+    fn poll_messages( &self ) -> Poll< ( ), ( ) > {
+        loop {
+            let message = {
+                let mut state = self.inner.borrow_mut( );
+                match state.poll_message( ) {
+                    Ok( Async::Ready( Some( message ) ) ) => message,
+                    Ok( Async::Ready( None ) ) => {
+                        state.disconnect( );
+
+                        return Ok( Async::Ready( ( ) ) );
+                    },
+                    Ok( Async::NotReady ) => return Ok( Async::NotReady ),
+                    Err( error ) => {
+                        error!( "Loopback client closing after invalid message: {}", error );
+                        state.disconnect( );
+
+                        return Ok( Async::Ready( ( ) ) );
+                    }
+                }
+            };
+
+            self.dispatch( message );
+        }
+    }
+
+    fn dispatch( &self, mut message : Value ) {
+        let method = message.get( "method" ).and_then( | method | method.as_str( ) ).map( | method | method.to_string( ) );
+        let params = message[ "params" ].take( );
+
+        match ( method, message.get( "id" ).cloned( ) ) {
+            ( Some( method ), Some( id ) ) => {
+                // The handler runs without the state borrowed, so it can use the client
+                let handler = self.inner.borrow_mut( ).on_request.take( );
+                let result = match handler {
+                    Some( ref handler ) => handler( &method, params ),
+                    None => Err( ResponseError {
+                        code    : METHOD_NOT_FOUND,
+                        message : format!( "Loopback client cannot handle {}", method )
+                    } )
+                };
+
+                let mut state = self.inner.borrow_mut( );
+                if state.on_request.is_none( ) {
+                    state.on_request = handler;
+                }
+
+                let mut response = json!( {
+                    "jsonrpc" : "2.0",
+                    "id"      : id
+                } );
+                match result {
+                    Ok( result ) => response[ "result" ] = result,
+                    Err( error ) => response[ "error" ] = json!( {
+                        "code"    : error.code,
+                        "message" : error.message
+                    } )
+                }
+                if let Err( error ) = state.write( &response ) {
+                    error!( "Loopback client failed to answer {}: {}", method, error );
+                }
+            },
+            ( Some( method ), None ) => {
+                let mut state = self.inner.borrow_mut( );
+                let delivered = match state.notifications {
+                    Some( ref notifications ) => notifications.unbounded_send( ( method, params ) ).is_ok( ),
+                    None => true
+                };
+                if !delivered {
+                    state.notifications = None;
+                }
+            },
+            ( None, Some( id ) ) => {
+                let response_send = match id.as_i64( ) {
+                    Some( id ) => self.inner.borrow_mut( ).pending.remove( &id ),
+                    None => None
+                };
+                let response_send = match response_send {
+                    Some( response_send ) => response_send,
+                    None => {
+                        error!( "Loopback client received response for unknown request {}.", id );
+
+                        return;
+                    }
+                };
+
+                let result = match message.get( "error" ) {
+                    Some( error ) => Err( ResponseError {
+                        code    : error[ "code" ].as_i64( ).unwrap_or( 0 ),
+                        message : error[ "message" ].as_str( ).unwrap_or( "" ).to_string( )
+                    } ),
+                    None => Ok( message[ "result" ].take( ) )
+                };
+                let _ = response_send.send( result );
+            },
+            ( None, None ) => {
+                error!( "Loopback client received message that is neither a request, a notification nor a response." );
+            }
+        }
+    }
+
+}
+
+impl ClientState {
+
+    fn write( &mut self, message : &Value ) -> Result< ( ), LoopbackError > {
+        if self.disconnected {
+            return Err( LoopbackError::Disconnected );
+        }
+
+        transport::write_message( &mut self.stream, message ).map_err( LoopbackError::Io )
+    }
+
+    /// Reads the next message of the service, None once the service closed the transport.
+    fn poll_message( &mut self ) -> Poll< Option< Value >, String > {
+        loop {
+            if let Some( message ) = transport::take_message( &mut self.buffer )? {
+                return Ok( Async::Ready( Some( message ) ) );
+            }
+
+            let mut chunk = [ 0; 4096 ];
+            match self.stream.read( &mut chunk ) {
+                Ok( 0 ) => return Ok( Async::Ready( None ) ),
+                Ok( count ) => self.buffer.extend_from_slice( &chunk[ ..count ] ),
+                Err( ref error ) if error.kind( ) == io::ErrorKind::WouldBlock => return Ok( Async::NotReady ),
+                Err( error ) => return Err( error.to_string( ) )
+            }
+        }
+    }
+
+    /// Fails the pending requests once the service closed the transport.
+    fn disconnect( &mut self ) {
+        self.disconnected = true;
+        self.pending.clear( );
+        self.notifications = None;
+    }
+
+}
+
+impl fmt::Display for LoopbackError {
+
+    fn fmt( &self, f : &mut fmt::Formatter ) -> fmt::Result {
+        match *self {
+            LoopbackError::Response( ref error ) => write!( f, "Service answered with error {}: {}", error.code, error.message ),
+            LoopbackError::Disconnected => write!( f, "Service closed the transport" ),
+            LoopbackError::Io( ref error ) => write!( f, "IO error: {}", error )
+        }
+    }
+
+}
+
+impl Error for LoopbackError {
+
+    fn description( &self ) -> &str {
+        match *self {
+            LoopbackError::Response( _ ) => "Service answered with an error",
+            LoopbackError::Disconnected => "Service closed the transport",
+            LoopbackError::Io( ref error ) => error.description( )
+        }
+    }
+
+}
@@ -0,0 +1,85 @@
+
+use serde_json::{
+    Value
+};
+
+/// Condition on the diagnostics of a textDocument/publishDiagnostics notification, see
+/// MockClient::assert_published_diagnostics
+///
+/// The diagnostics are matched as JSON, so matchers work with any diagnostic code or data type:
+///
+/// ```ignore
+/// client.assert_published_diagnostics( &uri, contains( "E0308" ).and( count( 1 ) ) );
+/// client.assert_published_diagnostics( &uri, empty( ) );
+/// ```
+pub struct DiagnosticsMatcher {
+    description : String,
+    predicate   : Box< dyn Fn( &[ Value ] ) -> bool >
+}
+
+impl DiagnosticsMatcher {
+
+    /// Creates a matcher from a predicate, described by description in assertion failures.
+    pub fn new< F >( description : &str, predicate : F ) -> Self where F : Fn( &[ Value ] ) -> bool + 'static {
+        DiagnosticsMatcher {
+            description : description.to_string( ),
+            predicate   : Box::new( predicate )
+        }
+    }
+
+    /// Returns a matcher matching diagnostics matched by both this matcher and other.
+    pub fn and( self, other : DiagnosticsMatcher ) -> Self {
+        let description = format!( "{} and {}", self.description, other.description );
+        let ( first, second ) = ( self.predicate, other.predicate );
+
+        DiagnosticsMatcher {
+            description : description,
+            predicate   : Box::new( move | diagnostics | first( diagnostics ) && second( diagnostics ) )
+        }
+    }
+
+    pub fn matches( &self, diagnostics : &[ Value ] ) -> bool {
+        ( self.predicate )( diagnostics )
+    }
+
+    pub fn description( &self ) -> &str {
+        &self.description
+    }
+
+}
+
+/// Matches diagnostics containing a diagnostic with the given code, e.g. `contains( "E0308" )` or
+/// `contains( 42 )`.
+pub fn contains< C : Into< Value > >( code : C ) -> DiagnosticsMatcher {
+    let code = code.into( );
+    let description = format!( "containing code {}", code );
+
+    DiagnosticsMatcher::new( &description, move | diagnostics | {
+        diagnostics.iter( ).any( | diagnostic | diagnostic[ "code" ] == code )
+    } )
+}
+
+/// Matches diagnostics containing a diagnostic whose message contains text.
+pub fn contains_message( text : &str ) -> DiagnosticsMatcher {
+    let text = text.to_string( );
+    let description = format!( "containing a message with {:?}", text );
+
+    DiagnosticsMatcher::new( &description, move | diagnostics | {
+        diagnostics.iter( ).any( | diagnostic | diagnostic[ "message" ].as_str( ).map( | message | message.contains( &*text ) ).unwrap_or( false ) )
+    } )
+}
+
+/// Matches exactly count diagnostics.
+pub fn count( count : usize ) -> DiagnosticsMatcher {
+    DiagnosticsMatcher::new( &format!( "with {} diagnostics", count ), move | diagnostics | diagnostics.len( ) == count )
+}
+
+/// Matches an empty list of diagnostics, published when the problems of a document are fixed.
+pub fn empty( ) -> DiagnosticsMatcher {
+    DiagnosticsMatcher::new( "without diagnostics", | diagnostics | diagnostics.is_empty( ) )
+}
+
+/// Matches any diagnostics.
+pub fn any( ) -> DiagnosticsMatcher {
+    DiagnosticsMatcher::new( "with any diagnostics", | _ | true )
+}
@@ -0,0 +1,172 @@
+
+use logging::{
+    Component
+};
+use std::sync::{
+    Arc,
+    Mutex,
+    Weak
+};
+
+type ExceededHook = Arc< dyn Fn( usize, usize ) + Send + Sync >;
+
+/// Component holding memory accounted by a MemoryBudget, such as a cache or the open documents
+pub trait MemoryConsumer : Send + Sync {
+
+    /// Returns the number of bytes held by the component.
+    fn memory_usage( &self ) -> usize;
+
+    /// Frees at least bytes if possible, returning the number of bytes actually freed. Components that
+    /// cannot free memory, e.g. the documents open in the editor, return 0.
+    fn evict( &self, bytes : usize ) -> usize;
+
+}
+
+/// Memory budget shared by the components of one or more services, e.g. every language server hosted by a
+/// process
+///
+/// Components register with the budget and enforce it whenever they grow. Once the memory held by the
+/// registered components exceeds the limit of the budget, the components holding the most memory are
+/// asked to evict until the budget is met. The budget is cheap to clone, clones share the same limit and
+/// components:
+///
+/// ```ignore
+/// let budget = MemoryBudget::new( 256 << 20 );
+/// budget.on_exceeded( | usage, limit | warn!( "Holding {} bytes over a budget of {}.", usage, limit ) );
+///
+/// let documents = TextDocumentStore::new( ).memory_budget( &budget );
+/// let vfs = Vfs::new( documents.clone( ) ).disk_cache_limit( 64 << 20 ).memory_budget( &budget );
+/// let symbols = ArtifactCache::new( 1024 ).size_limit( 64 << 20, weigh_symbols ).memory_budget( "symbols", &budget );
+/// ```
+#[derive( Clone )]
+pub struct MemoryBudget {
+    inner : Arc< Mutex< BudgetState > >
+}
+
+/// Memory held by a component registered with a MemoryBudget, returned by MemoryBudget::consumers
+#[derive( Clone, Debug, PartialEq, Eq )]
+pub struct ConsumerUsage {
+    pub name  : &'static str,
+    pub bytes : usize
+}
+
+struct BudgetState {
+    limit          : usize,
+    consumers      : Vec< RegisteredConsumer >,
+    exceeded_hooks : Vec< ExceededHook >
+}
+
+struct RegisteredConsumer {
+    name     : &'static str,
+    /// Unregistered once the component is dropped
+    consumer : Weak< dyn MemoryConsumer >
+}
+
+impl MemoryBudget {
+
+    /// Creates a budget of limit bytes.
+    pub fn new( limit : usize ) -> Self {
+        MemoryBudget {
+            inner : Arc::new( Mutex::new( BudgetState {
+                limit          : limit,
+                consumers      : Vec::new( ),
+                exceeded_hooks : Vec::new( )
+            } ) )
+        }
+    }
+
+    pub fn limit( &self ) -> usize {
+        self.inner.lock( ).unwrap( ).limit
+    }
+
+    /// Changes the limit of the budget, evicting right away if the components exceed the new limit.
+    pub fn set_limit( &self, limit : usize ) {
+        self.inner.lock( ).unwrap( ).limit = limit;
+
+        self.enforce( );
+    }
+
+    /// Registers a component with the budget. The budget only keeps a weak reference to the component, it
+    /// is unregistered once dropped.
+    pub fn register< C : MemoryConsumer + 'static >( &self, name : &'static str, consumer : &Arc< C > ) {
+        let consumer : Arc< dyn MemoryConsumer > = consumer.clone( );
+
+        self.inner.lock( ).unwrap( ).consumers.push( RegisteredConsumer {
+            name     : name,
+            consumer : Arc::downgrade( &consumer )
+        } );
+    }
+
+    /// Calls hook with the memory held by the components and the limit of the budget whenever evicting could
+    /// not bring the components within the budget.
+    pub fn on_exceeded< F >( &self, hook : F ) where F : Fn( usize, usize ) + Send + Sync + 'static {
+        self.inner.lock( ).unwrap( ).exceeded_hooks.push( Arc::new( hook ) );
+    }
+
+    /// Returns the number of bytes held by the registered components.
+    pub fn usage( &self ) -> usize {
+        self.live_consumers( ).iter( ).map( | &( _, ref consumer ) | consumer.memory_usage( ) ).sum( )
+    }
+
+    /// Returns the memory held by every registered component, in the order they were registered.
+    pub fn consumers( &self ) -> Vec< ConsumerUsage > {
+        self.live_consumers( ).iter( ).map( | &( name, ref consumer ) | ConsumerUsage {
+            name  : name,
+            bytes : consumer.memory_usage( )
+        } ).collect( )
+    }
+
+    /// Evicts from the components holding the most memory first until the components are within the
+    /// budget, returning the number of bytes freed. Called by the components whenever they grow.
+    ///
+    /// Components are called without the budget being locked, so they can enforce the budget while being
+    /// asked to evict without deadlocking.
+    pub fn enforce( &self ) -> usize {
+        let limit = self.limit( );
+        let mut usages : Vec< ( usize, Arc< dyn MemoryConsumer > ) > = self.live_consumers( ).into_iter( ).map( | ( _, consumer ) | {
+            ( consumer.memory_usage( ), consumer )
+        } ).collect( );
+
+        let usage : usize = usages.iter( ).map( | &( bytes, _ ) | bytes ).sum( );
+        if usage <= limit {
+            return 0;
+        }
+
+        component_debug!( Component::Document, "Memory usage of {} bytes exceeds the budget of {}, evicting.", usage, limit );
+
+        usages.sort_by( | first, second | second.0.cmp( &first.0 ) );
+        let mut remaining = usage;
+        for &( _, ref consumer ) in &usages {
+            if remaining <= limit {
+                break;
+            }
+
+            remaining = remaining.saturating_sub( consumer.evict( remaining - limit ) );
+        }
+
+        if remaining > limit {
+            let hooks = self.inner.lock( ).unwrap( ).exceeded_hooks.clone( );
+            for hook in hooks {
+                hook( remaining, limit );
+            }
+        }
+
+        usage - remaining
+    }
+
+    /// Returns the registered components still alive, unregistering the dropped ones.
+    fn live_consumers( &self ) -> Vec< ( &'static str, Arc< dyn MemoryConsumer > ) > {
+        let mut live = Vec::new( );
+        self.inner.lock( ).unwrap( ).consumers.retain( | registered | match registered.consumer.upgrade( ) {
+            Some( consumer ) => {
+                live.push( ( registered.name, consumer ) );
+
+                true
+            },
+            None => false
+        } );
+
+        live
+    }
+
+}
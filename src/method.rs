@@ -0,0 +1,458 @@
+
+use lsp_rs::{
+    ApplyWorkspaceEditParams,
+    ApplyWorkspaceEditResponse,
+    CancelParams,
+    ClientNotification,
+    ClientRequest,
+    ClientResponse,
+    CodeAction,
+    CodeActionParams,
+    CodeLens,
+    CodeLensParams,
+    CompletionItem,
+    DidChangeConfigurationParams,
+    DidChangeTextDocumentParams,
+    DidChangeWatchedFilesParams,
+    DidChangeWorkspaceFoldersParams,
+    DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams,
+    DidSaveTextDocumentParams,
+    DocumentFormattingParams,
+    DocumentOnTypeFormattingParams,
+    DocumentRangeFormattingParams,
+    DocumentSymbolParams,
+    ExecuteCommandParams,
+    InitializeParams,
+    InitializedParams,
+    MessageActionItem,
+    ReferenceParams,
+    RegistrationParams,
+    RenameParams,
+    ServerNotification,
+    ServerRequest,
+    SetTraceParams,
+    ShowMessageRequestParams,
+    TextDocumentPositionParams,
+    UnregistrationParams,
+    Url,
+    WillSaveTextDocumentParams,
+    WorkDoneProgressCancelParams,
+    WorkDoneProgressCreateParams,
+    WorkspaceSymbolParams
+};
+
+/// Trait implemented by protocol messages to retrieve the JSON-RPC method name they are sent with
+pub trait MethodName {
+
+    /// Returns the method name of this message as defined by the Language Server Protocol
+    fn method_name( &self ) -> &'static str;
+
+}
+
+impl MethodName for ServerRequest {
+
+    fn method_name( &self ) -> &'static str {
+        match *self {
+            ServerRequest::Initialize( .. )            => "initialize",
+            ServerRequest::Shutdown                    => "shutdown",
+            ServerRequest::Completion( .. )            => "textDocument/completion",
+            ServerRequest::ResolveCompletionItem( .. ) => "completionItem/resolve",
+            ServerRequest::Hover( .. )                 => "textDocument/hover",
+            ServerRequest::SignatureHelp( .. )         => "textDocument/signatureHelp",
+            ServerRequest::GotoDefinition( .. )        => "textDocument/definition",
+            ServerRequest::FindReferences( .. )        => "textDocument/references",
+            ServerRequest::DocumentHighlight( .. )     => "textDocument/documentHighlight",
+            ServerRequest::DocumentSymbols( .. )       => "textDocument/documentSymbol",
+            ServerRequest::WorkspaceSymbols( .. )      => "workspace/symbol",
+            ServerRequest::CodeAction( .. )            => "textDocument/codeAction",
+            ServerRequest::CodeLens( .. )              => "textDocument/codeLens",
+            ServerRequest::ResolveCodeLens( .. )       => "codeLens/resolve",
+            ServerRequest::Formatting( .. )            => "textDocument/formatting",
+            ServerRequest::RangeFormatting( .. )       => "textDocument/rangeFormatting",
+            ServerRequest::OnTypeFormatting( .. )      => "textDocument/onTypeFormatting",
+            ServerRequest::Rename( .. )                => "textDocument/rename",
+            ServerRequest::WillSaveWaitUntil( .. )     => "textDocument/willSaveWaitUntil",
+            ServerRequest::ExecuteCommand( .. )        => "workspace/executeCommand",
+            ServerRequest::ResolveCodeAction( .. )     => "codeAction/resolve"
+        }
+    }
+
+}
+
+impl MethodName for ServerNotification {
+
+    fn method_name( &self ) -> &'static str {
+        match *self {
+            ServerNotification::Initialized( .. )            => "initialized",
+            ServerNotification::Exit                         => "exit",
+            ServerNotification::Cancel( .. )                 => "$/cancelRequest",
+            ServerNotification::SetTrace( .. )               => "$/setTrace",
+            ServerNotification::DidChangeConfiguration( .. ) => "workspace/didChangeConfiguration",
+            ServerNotification::DidOpenTextDocument( .. )    => "textDocument/didOpen",
+            ServerNotification::DidChangeTextDocument( .. )  => "textDocument/didChange",
+            ServerNotification::DidCloseTextDocument( .. )   => "textDocument/didClose",
+            ServerNotification::DidSaveTextDocument( .. )    => "textDocument/didSave",
+            ServerNotification::DidChangeWatchedFiles( .. )  => "workspace/didChangeWatchedFiles",
+            ServerNotification::WorkDoneProgressCancel( .. ) => "window/workDoneProgress/cancel",
+            ServerNotification::DidChangeWorkspaceFolders( .. ) => "workspace/didChangeWorkspaceFolders"
+        }
+    }
+
+}
+
+impl MethodName for ClientNotification {
+
+    fn method_name( &self ) -> &'static str {
+        match *self {
+            ClientNotification::ShowMessage( .. )        => "window/showMessage",
+            ClientNotification::LogMessage( .. )         => "window/logMessage",
+            ClientNotification::Telemetry( .. )          => "telemetry/event",
+            ClientNotification::PublishDiagnostics( .. ) => "textDocument/publishDiagnostics",
+            ClientNotification::LogTrace( .. )           => "$/logTrace",
+            ClientNotification::Progress( .. )           => "$/progress"
+        }
+    }
+
+}
+
+impl MethodName for ClientRequest {
+
+    fn method_name( &self ) -> &'static str {
+        match *self {
+            ClientRequest::ShowMessageRequest( .. )     => "window/showMessageRequest",
+            ClientRequest::WorkDoneProgressCreate( .. ) => "window/workDoneProgress/create",
+            ClientRequest::RegisterCapability( .. )     => "client/registerCapability",
+            ClientRequest::UnregisterCapability( .. )   => "client/unregisterCapability",
+            ClientRequest::ApplyEdit( .. )              => "workspace/applyEdit"
+        }
+    }
+
+}
+
+/// Returns the uri of the document a request operates on, None for requests that are not tied to a
+/// document.
+pub fn document_uri( request : &ServerRequest ) -> Option< &Url > {
+    match *request {
+        ServerRequest::Completion( ref params )        => Some( &params.text_document.uri ),
+        ServerRequest::Hover( ref params )             => Some( &params.text_document.uri ),
+        ServerRequest::SignatureHelp( ref params )     => Some( &params.text_document.uri ),
+        ServerRequest::GotoDefinition( ref params )    => Some( &params.text_document.uri ),
+        ServerRequest::FindReferences( ref params )    => Some( &params.text_document.uri ),
+        ServerRequest::DocumentHighlight( ref params ) => Some( &params.text_document.uri ),
+        ServerRequest::DocumentSymbols( ref params )   => Some( &params.text_document.uri ),
+        ServerRequest::CodeAction( ref params )        => Some( &params.text_document.uri ),
+        ServerRequest::CodeLens( ref params )          => Some( &params.text_document.uri ),
+        ServerRequest::Formatting( ref params )        => Some( &params.text_document.uri ),
+        ServerRequest::RangeFormatting( ref params )   => Some( &params.text_document.uri ),
+        ServerRequest::OnTypeFormatting( ref params )  => Some( &params.text_document.uri ),
+        ServerRequest::Rename( ref params )            => Some( &params.text_document.uri ),
+        ServerRequest::WillSaveWaitUntil( ref params ) => Some( &params.text_document.uri ),
+        _ => None
+    }
+}
+
+/// Trait implemented by marker types for each request method, used to extract typed parameters from a
+/// ServerRequest
+pub trait RequestMethod {
+
+    /// Method name of the request
+    const METHOD : &'static str;
+
+    /// Parameters sent with the request
+    type Params;
+
+    /// Extracts the parameters of this method from the request, or returns the request if it is for a
+    /// different method.
+    fn from_request( request : ServerRequest ) -> Result< Self::Params, ServerRequest >;
+
+}
+
+/// Trait implemented by marker types for each notification method, used to extract typed parameters from a
+/// ServerNotification
+pub trait NotificationMethod {
+
+    /// Method name of the notification
+    const METHOD : &'static str;
+
+    /// Parameters sent with the notification
+    type Params;
+
+    /// Extracts the parameters of this method from the notification, or returns the notification if it is
+    /// for a different method.
+    fn from_notification( notification : ServerNotification ) -> Result< Self::Params, ServerNotification >;
+
+}
+
+/// Trait implemented by marker types for each request the server can send to the client, used to build
+/// the ClientRequest and to extract the typed result from the response, see ServiceHandle::request
+pub trait ClientRequestMethod {
+
+    /// Method name of the request
+    const METHOD : &'static str;
+
+    /// Parameters sent with the request
+    type Params;
+
+    /// Result sent by the client in its response
+    type Result;
+
+    /// Builds the request sent to the client from its parameters.
+    fn into_request( params : Self::Params ) -> ClientRequest;
+
+    /// Extracts the result of this method from the response of the client, or returns the response if it
+    /// does not match this method. A response of None is a null result.
+    fn from_response( response : Option< ClientResponse > ) -> Result< Self::Result, Option< ClientResponse > >;
+
+}
+
+macro_rules! request_methods {
+    (
+        $( $marker : ident => $variant : ident ( $params : ty ), $method : tt; )*
+    ) => {
+        $(
+            #[doc = "Marker type for the `"]
+            #[doc = $method]
+            #[doc = "` request"]
+            pub enum $marker { }
+
+            impl RequestMethod for $marker {
+
+                const METHOD : &'static str = $method;
+
+                type Params = $params;
+
+                fn from_request( request : ServerRequest ) -> Result< Self::Params, ServerRequest > {
+                    match request {
+                        ServerRequest::$variant( params ) => Ok( params ),
+                        request => Err( request )
+                    }
+                }
+
+            }
+        )*
+    };
+}
+
+macro_rules! notification_methods {
+    (
+        $( $marker : ident => $variant : ident ( $params : ty ), $method : tt; )*
+    ) => {
+        $(
+            #[doc = "Marker type for the `"]
+            #[doc = $method]
+            #[doc = "` notification"]
+            pub enum $marker { }
+
+            impl NotificationMethod for $marker {
+
+                const METHOD : &'static str = $method;
+
+                type Params = $params;
+
+                fn from_notification( notification : ServerNotification ) -> Result< Self::Params, ServerNotification > {
+                    match notification {
+                        ServerNotification::$variant( params ) => Ok( params ),
+                        notification => Err( notification )
+                    }
+                }
+
+            }
+        )*
+    };
+}
+
+request_methods! {
+    InitializeRequest            => Initialize( InitializeParams ), "initialize";
+    CompletionRequest            => Completion( TextDocumentPositionParams ), "textDocument/completion";
+    ResolveCompletionItemRequest => ResolveCompletionItem( CompletionItem ), "completionItem/resolve";
+    HoverRequest                 => Hover( TextDocumentPositionParams ), "textDocument/hover";
+    SignatureHelpRequest         => SignatureHelp( TextDocumentPositionParams ), "textDocument/signatureHelp";
+    GotoDefinitionRequest        => GotoDefinition( TextDocumentPositionParams ), "textDocument/definition";
+    FindReferencesRequest        => FindReferences( ReferenceParams ), "textDocument/references";
+    DocumentHighlightRequest     => DocumentHighlight( TextDocumentPositionParams ), "textDocument/documentHighlight";
+    DocumentSymbolsRequest       => DocumentSymbols( DocumentSymbolParams ), "textDocument/documentSymbol";
+    WorkspaceSymbolsRequest      => WorkspaceSymbols( WorkspaceSymbolParams ), "workspace/symbol";
+    CodeActionRequest            => CodeAction( CodeActionParams ), "textDocument/codeAction";
+    CodeLensRequest              => CodeLens( CodeLensParams ), "textDocument/codeLens";
+    ResolveCodeLensRequest       => ResolveCodeLens( CodeLens ), "codeLens/resolve";
+    FormattingRequest            => Formatting( DocumentFormattingParams ), "textDocument/formatting";
+    RangeFormattingRequest       => RangeFormatting( DocumentRangeFormattingParams ), "textDocument/rangeFormatting";
+    OnTypeFormattingRequest      => OnTypeFormatting( DocumentOnTypeFormattingParams ), "textDocument/onTypeFormatting";
+    RenameRequest                => Rename( RenameParams ), "textDocument/rename";
+    WillSaveWaitUntilRequest     => WillSaveWaitUntil( WillSaveTextDocumentParams ), "textDocument/willSaveWaitUntil";
+    ExecuteCommandRequest        => ExecuteCommand( ExecuteCommandParams ), "workspace/executeCommand";
+    ResolveCodeActionRequest     => ResolveCodeAction( CodeAction ), "codeAction/resolve";
+}
+
+notification_methods! {
+    InitializedNotification            => Initialized( InitializedParams ), "initialized";
+    CancelNotification                 => Cancel( CancelParams ), "$/cancelRequest";
+    SetTraceNotification               => SetTrace( SetTraceParams ), "$/setTrace";
+    DidChangeConfigurationNotification => DidChangeConfiguration( DidChangeConfigurationParams ), "workspace/didChangeConfiguration";
+    DidOpenTextDocumentNotification    => DidOpenTextDocument( DidOpenTextDocumentParams ), "textDocument/didOpen";
+    DidChangeTextDocumentNotification  => DidChangeTextDocument( DidChangeTextDocumentParams ), "textDocument/didChange";
+    DidCloseTextDocumentNotification   => DidCloseTextDocument( DidCloseTextDocumentParams ), "textDocument/didClose";
+    DidSaveTextDocumentNotification    => DidSaveTextDocument( DidSaveTextDocumentParams ), "textDocument/didSave";
+    DidChangeWatchedFilesNotification  => DidChangeWatchedFiles( DidChangeWatchedFilesParams ), "workspace/didChangeWatchedFiles";
+    WorkDoneProgressCancelNotification => WorkDoneProgressCancel( WorkDoneProgressCancelParams ), "window/workDoneProgress/cancel";
+    DidChangeWorkspaceFoldersNotification => DidChangeWorkspaceFolders( DidChangeWorkspaceFoldersParams ), "workspace/didChangeWorkspaceFolders";
+}
+
+/// Marker type for the `window/showMessageRequest` request
+pub enum ShowMessageRequest { }
+
+/// Marker type for the `window/workDoneProgress/create` request
+pub enum WorkDoneProgressCreateRequest { }
+
+/// Marker type for the `client/registerCapability` request
+pub enum RegisterCapabilityRequest { }
+
+/// Marker type for the `client/unregisterCapability` request
+pub enum UnregisterCapabilityRequest { }
+
+/// Marker type for the `workspace/applyEdit` request
+pub enum ApplyEditRequest { }
+
+/// Marker type for the `shutdown` request
+pub enum ShutdownRequest { }
+
+/// Marker type for the `exit` notification
+pub enum ExitNotification { }
+
+impl RequestMethod for ShutdownRequest {
+
+    const METHOD : &'static str = "shutdown";
+
+    type Params = ( );
+
+    fn from_request( request : ServerRequest ) -> Result< Self::Params, ServerRequest > {
+        match request {
+            ServerRequest::Shutdown => Ok( ( ) ),
+            request => Err( request )
+        }
+    }
+
+}
+
+impl NotificationMethod for ExitNotification {
+
+    const METHOD : &'static str = "exit";
+
+    type Params = ( );
+
+    fn from_notification( notification : ServerNotification ) -> Result< Self::Params, ServerNotification > {
+        match notification {
+            ServerNotification::Exit => Ok( ( ) ),
+            notification => Err( notification )
+        }
+    }
+
+}
+
+impl ClientRequestMethod for ShowMessageRequest {
+
+    const METHOD : &'static str = "window/showMessageRequest";
+
+    type Params = ShowMessageRequestParams;
+
+    /// Action selected by the user, None if the message was dismissed
+    type Result = Option< MessageActionItem >;
+
+    fn into_request( params : Self::Params ) -> ClientRequest {
+        ClientRequest::ShowMessageRequest( params )
+    }
+
+    fn from_response( response : Option< ClientResponse > ) -> Result< Self::Result, Option< ClientResponse > > {
+        match response {
+            None => Ok( None ),
+            Some( ClientResponse::ShowMessageRequest( item ) ) => Ok( Some( item ) ),
+            response => Err( response )
+        }
+    }
+
+}
+
+impl ClientRequestMethod for WorkDoneProgressCreateRequest {
+
+    const METHOD : &'static str = "window/workDoneProgress/create";
+
+    type Params = WorkDoneProgressCreateParams;
+
+    type Result = ( );
+
+    fn into_request( params : Self::Params ) -> ClientRequest {
+        ClientRequest::WorkDoneProgressCreate( params )
+    }
+
+    fn from_response( response : Option< ClientResponse > ) -> Result< Self::Result, Option< ClientResponse > > {
+        match response {
+            None => Ok( ( ) ),
+            response => Err( response )
+        }
+    }
+
+}
+
+impl ClientRequestMethod for RegisterCapabilityRequest {
+
+    const METHOD : &'static str = "client/registerCapability";
+
+    type Params = RegistrationParams;
+
+    type Result = ( );
+
+    fn into_request( params : Self::Params ) -> ClientRequest {
+        ClientRequest::RegisterCapability( params )
+    }
+
+    fn from_response( response : Option< ClientResponse > ) -> Result< Self::Result, Option< ClientResponse > > {
+        match response {
+            None => Ok( ( ) ),
+            response => Err( response )
+        }
+    }
+
+}
+
+impl ClientRequestMethod for UnregisterCapabilityRequest {
+
+    const METHOD : &'static str = "client/unregisterCapability";
+
+    type Params = UnregistrationParams;
+
+    type Result = ( );
+
+    fn into_request( params : Self::Params ) -> ClientRequest {
+        ClientRequest::UnregisterCapability( params )
+    }
+
+    fn from_response( response : Option< ClientResponse > ) -> Result< Self::Result, Option< ClientResponse > > {
+        match response {
+            None => Ok( ( ) ),
+            response => Err( response )
+        }
+    }
+
+}
+
+impl ClientRequestMethod for ApplyEditRequest {
+
+    const METHOD : &'static str = "workspace/applyEdit";
+
+    type Params = ApplyWorkspaceEditParams;
+
+    type Result = ApplyWorkspaceEditResponse;
+
+    fn into_request( params : Self::Params ) -> ClientRequest {
+        ClientRequest::ApplyEdit( params )
+    }
+
+    fn from_response( response : Option< ClientResponse > ) -> Result< Self::Result, Option< ClientResponse > > {
+        match response {
+            Some( ClientResponse::ApplyEdit( result ) ) => Ok( result ),
+            response => Err( response )
+        }
+    }
+
+}
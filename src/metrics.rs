@@ -0,0 +1,241 @@
+
+use std::collections::{
+    HashMap
+};
+use std::time::{
+    Duration
+};
+
+/// Number of buckets per power of two of a LatencyHistogram, bounding the error of a bucket to 1/8th of
+/// its values
+const SUB_BUCKET_BITS : u32 = 3;
+const SUB_BUCKETS : usize = 1 << SUB_BUCKET_BITS;
+
+/// Counters of the outcomes of requests for a single method
+#[derive( Clone, Debug, Default )]
+pub struct MethodCounters {
+    /// Number of requests answered with a result
+    pub successes     : u64,
+    /// Number of requests answered with an error, keyed by the error code of the response
+    pub errors        : HashMap< i64, u64 >,
+    /// Number of requests that were never answered, because their ResponseOutput was dropped under
+    /// DroppedResponsePolicy::Cancel
+    pub cancellations : u64,
+    /// Time spent by the requests in each stage of the pipeline of the service
+    pub latency       : MethodLatency
+}
+
+/// Latency histograms of the stages of the pipeline a request goes through, from its message starting to
+/// arrive to its response being flushed to the transport
+///
+/// Summing the stages attributes the latency seen by the client to the service, e.g. a request waiting
+/// behind an earlier request under ResponseOrdering::Received shows up in flush rather than in response.
+#[derive( Clone, Debug, Default )]
+pub struct MethodLatency {
+    /// From the first bytes of the request being read, usually along with its header, to it being handed to
+    /// the MessageHandler, including the time spent receiving and decoding its body and waiting behind the
+    /// requests skipped by the codec before it
+    pub dispatch : LatencyHistogram,
+    /// From the request being handed to the MessageHandler to its response reaching the service
+    pub response : LatencyHistogram,
+    /// From the response reaching the service to it being flushed to the transport, including the time
+    /// spent waiting for the responses ordered before it and in the write queue
+    pub flush    : LatencyHistogram
+}
+
+/// Stage of the pipeline of the service a latency is recorded for, see MethodLatency
+#[derive( Clone, Copy, Debug, PartialEq, Eq )]
+pub enum LatencyStage {
+    Dispatch,
+    Response,
+    Flush
+}
+
+/// Histogram of latencies with HDR-style buckets
+///
+/// Latencies are recorded in microseconds, in buckets exact below 8us and then splitting every power of two
+/// in 8 buckets, so any recorded latency is reported within 12.5% of its value whatever its magnitude.
+/// Buckets are allocated up to the largest latency recorded.
+#[derive( Clone, Debug, Default, PartialEq, Eq )]
+pub struct LatencyHistogram {
+    counts       : Vec< u64 >,
+    count        : u64,
+    total_micros : u64,
+    max_micros   : u64
+}
+
+/// Snapshot of the metrics collected by a service
+#[derive( Clone, Debug, Default )]
+pub struct MetricsSnapshot {
+    /// Request counters keyed by method name
+    pub methods : HashMap< &'static str, MethodCounters >
+}
+
+pub(crate) struct Metrics {
+    methods : HashMap< &'static str, MethodCounters >
+}
+
+impl MethodCounters {
+
+    /// Returns the number of requests answered with an error, regardless of error code.
+    pub fn total_errors( &self ) -> u64 {
+        self.errors.values( ).sum( )
+    }
+
+    /// Returns the number of requests that were completed, either with a response or by cancellation.
+    pub fn total( &self ) -> u64 {
+        self.successes + self.total_errors( ) + self.cancellations
+    }
+
+}
+
+impl MethodLatency {
+
+    pub fn stage( &self, stage : LatencyStage ) -> &LatencyHistogram {
+        match stage {
+            LatencyStage::Dispatch => &self.dispatch,
+            LatencyStage::Response => &self.response,
+            LatencyStage::Flush => &self.flush
+        }
+    }
+
+    fn stage_mut( &mut self, stage : LatencyStage ) -> &mut LatencyHistogram {
+        match stage {
+            LatencyStage::Dispatch => &mut self.dispatch,
+            LatencyStage::Response => &mut self.response,
+            LatencyStage::Flush => &mut self.flush
+        }
+    }
+
+}
+
+impl LatencyHistogram {
+
+    pub fn new( ) -> Self {
+        LatencyHistogram::default( )
+    }
+
+    pub fn record( &mut self, latency : Duration ) {
+        let micros = latency.as_secs( ).saturating_mul( 1_000_000 ).saturating_add( u64::from( latency.subsec_micros( ) ) );
+        let index = bucket_index( micros );
+        if index >= self.counts.len( ) {
+            self.counts.resize( index + 1, 0 );
+        }
+
+        self.counts[ index ] += 1;
+        self.count += 1;
+        self.total_micros = self.total_micros.saturating_add( micros );
+        self.max_micros = self.max_micros.max( micros );
+    }
+
+    /// Returns the number of latencies recorded.
+    pub fn count( &self ) -> u64 {
+        self.count
+    }
+
+    pub fn mean( &self ) -> Duration {
+        match self.count {
+            0 => Duration::from_secs( 0 ),
+            count => Duration::from_micros( self.total_micros / count )
+        }
+    }
+
+    pub fn max( &self ) -> Duration {
+        Duration::from_micros( self.max_micros )
+    }
+
+    /// Returns the latency below which percentile percent of the recorded latencies fall, e.g. 99.0 for the
+    /// p99, as the upper bound of its bucket.
+    pub fn percentile( &self, percentile : f64 ) -> Duration {
+        if self.count == 0 {
+            return Duration::from_secs( 0 );
+        }
+
+        let rank = ( ( percentile.max( 0.0 ).min( 100.0 ) / 100.0 ) * self.count as f64 ).ceil( ).max( 1.0 ) as u64;
+        let mut seen = 0;
+        for ( index, &count ) in self.counts.iter( ).enumerate( ) {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_micros( bucket_upper_bound( index ).min( self.max_micros ) );
+            }
+        }
+
+        self.max( )
+    }
+
+    /// Returns the non-empty buckets as their upper bound and the number of latencies they hold, from the
+    /// shortest latencies to the longest.
+    pub fn buckets( &self ) -> Vec< ( Duration, u64 ) > {
+        self.counts.iter( ).enumerate( ).filter( | &( _, &count ) | count > 0 ).map( | ( index, &count ) | {
+            ( Duration::from_micros( bucket_upper_bound( index ) ), count )
+        } ).collect( )
+    }
+
+}
+
+impl MetricsSnapshot {
+
+    /// Returns the counters for the given method, if any request for it has completed.
+    pub fn method( &self, method : &str ) -> Option< &MethodCounters > {
+        self.methods.get( method )
+    }
+
+}
+
+impl Metrics {
+
+    pub fn new( ) -> Self {
+        Metrics {
+            methods : HashMap::new( )
+        }
+    }
+
+    pub fn record_success( &mut self, method : &'static str ) {
+        self.counters( method ).successes += 1;
+    }
+
+    pub fn record_error( &mut self, method : &'static str, code : i64 ) {
+        *self.counters( method ).errors.entry( code ).or_insert( 0 ) += 1;
+    }
+
+    pub fn record_cancellation( &mut self, method : &'static str ) {
+        self.counters( method ).cancellations += 1;
+    }
+
+    pub fn record_latency( &mut self, method : &'static str, stage : LatencyStage, latency : Duration ) {
+        self.counters( method ).latency.stage_mut( stage ).record( latency );
+    }
+
+    pub fn snapshot( &self ) -> MetricsSnapshot {
+        MetricsSnapshot {
+            methods : self.methods.clone( )
+        }
+    }
+
+    fn counters( &mut self, method : &'static str ) -> &mut MethodCounters {
+        self.methods.entry( method ).or_insert_with( MethodCounters::default )
+    }
+
+}
+
+/// Returns the bucket of a LatencyHistogram holding micros.
+fn bucket_index( micros : u64 ) -> usize {
+    if micros < SUB_BUCKETS as u64 {
+        return micros as usize;
+    }
+
+    let shift = 63 - micros.leading_zeros( ) - SUB_BUCKET_BITS;
+    ( shift as usize + 1 ) * SUB_BUCKETS + ( ( micros >> shift ) as usize - SUB_BUCKETS )
+}
+
+/// Returns the largest latency in microseconds held by a bucket of a LatencyHistogram.
+fn bucket_upper_bound( index : usize ) -> u64 {
+    if index < SUB_BUCKETS {
+        return index as u64;
+    }
+
+    let shift = ( index / SUB_BUCKETS - 1 ) as u32;
+    let lower = ( ( SUB_BUCKETS + index % SUB_BUCKETS ) as u64 ) << shift;
+
+    lower + ( ( 1u64 << shift ) - 1 )
+}
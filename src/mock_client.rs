@@ -0,0 +1,533 @@
+
+use builder::{
+    ServiceBuilder
+};
+use fixture::{
+    WorkspaceFixture
+};
+use futures::{
+    Async,
+    Future,
+    Poll
+};
+use futures::future;
+use golden::{
+    GoldenError,
+    GoldenFile
+};
+use lsp_rs::{
+    ResponseError,
+    Url
+};
+use matchers::{
+    DiagnosticsMatcher
+};
+use method::{
+    NotificationMethod,
+    RequestMethod
+};
+use serde::{
+    Serialize
+};
+use serde::de::{
+    DeserializeOwned
+};
+use serde_json::{
+    self,
+    Value
+};
+use service::{
+    MessageHandler,
+    ServiceHandle
+};
+use std::collections::{
+    VecDeque
+};
+use std::error::{
+    Error
+};
+use std::fmt;
+use std::io::{
+    self,
+    Read
+};
+use std::time::{
+    Duration
+};
+use tokio_core::io::{
+    Io
+};
+use tokio_core::reactor::{
+    Core,
+    Timeout
+};
+use transport::{
+    self,
+    MemoryStream
+};
+
+/// Time a MockClient waits for a message by default
+const DEFAULT_TIMEOUT_MS : u64 = 5000;
+/// Consecutive turns without any message after which MockClient::run_until_stalled considers the service
+/// stalled
+const STALLED_TURNS : usize = 8;
+
+/// Client driving a service over an in-memory transport, for end to end tests of handlers
+///
+/// The client owns the event loop of the service, the service only runs while the client waits for a
+/// message, so every helper waiting for a message fails with MockError::Timeout instead of blocking forever.
+/// Messages received while waiting for another message are kept until a helper asks for them.
+///
+/// ```ignore
+/// let mut client = MockClient::new( router )?;
+/// client.open_workspace( &WorkspaceFixture::new( ).open( "main.rs", "rust", 1, "fn main( ) { }" ) )?;
+///
+/// let hover : Option< Hover > = client.request::< HoverRequest, _ >( TextDocumentPositionParams { .. } )?;
+/// let diagnostics = client.expect_notification( | method, _ | method == "textDocument/publishDiagnostics" )?;
+/// ```
+///
+/// Tests asserting intermediate states run the event loop turn by turn instead, nothing runs between two
+/// calls to MockClient::step:
+///
+/// ```ignore
+/// let slow = client.send_request::< DocumentSymbolsRequest >( symbols_params )?;
+/// let fast = client.send_request::< HoverRequest >( hover_params )?;
+/// client.run_until_stalled( )?;
+///
+/// // Responses are written in order, hover waits for the symbols
+/// assert!( !client.has_response( fast ) );
+/// ```
+pub struct MockClient {
+    core       : Core,
+    service    : ServiceHandle,
+    connection : Connection,
+    timeout    : Duration,
+    next_id    : i64
+}
+
+/// Notification received from the service
+#[derive( Clone, Debug )]
+pub struct MockNotification {
+    pub method : String,
+    pub params : Value
+}
+
+/// Request received from the service, answered with MockClient::respond
+#[derive( Clone, Debug )]
+pub struct MockRequest {
+    pub id     : Value,
+    pub method : String,
+    pub params : Value
+}
+
+/// Errors returned by the helpers of a MockClient
+#[derive( Debug )]
+pub enum MockError {
+    /// No matching message was received within the timeout of the client
+    Timeout,
+    /// The service closed the transport
+    Disconnected,
+    /// The service answered the request with an error
+    Response( ResponseError ),
+    /// A message could not be serialized, or the result of a request deserialized
+    Json( serde_json::Error ),
+    /// The service sent a message that is not valid JSON-RPC
+    InvalidMessage( String ),
+    Io( io::Error )
+}
+
+/// Client end of the transport, with the bytes and messages received but not yet consumed
+struct Connection {
+    io         : MemoryStream,
+    buffer     : Vec< u8 >,
+    received   : VecDeque< Value >,
+    /// Every message received, consumed or not
+    transcript : Vec< Value >
+}
+
+impl MockClient {
+
+    /// Starts a service running handler, connected to a new client.
+    pub fn new< H : MessageHandler + 'static >( handler : H ) -> io::Result< Self > {
+        MockClient::with_builder( handler, | builder | builder )
+    }
+
+    /// Starts a service running handler, configured by configure, connected to a new client.
+    pub fn with_builder< H, F >( handler : H, configure : F ) -> io::Result< Self >
+        where H : MessageHandler + 'static, F : FnOnce( ServiceBuilder ) -> ServiceBuilder {
+        MockClient::with_server_io( handler, configure, | server_io | server_io )
+    }
+
+    /// Starts a service running handler, configured by configure, on the server end of the transport wrapped
+    /// by wrap, e.g. in a chaos::FaultyStream to test how the service handles a failing connection.
+    pub fn with_server_io< H, F, W, I >( handler : H, configure : F, wrap : W ) -> io::Result< Self >
+        where H : MessageHandler + 'static, F : FnOnce( ServiceBuilder ) -> ServiceBuilder, W : FnOnce( MemoryStream ) -> I, I : Io + 'static {
+        let core = Core::new( )?;
+        let ( server_io, client_io ) = transport::memory_transport( );
+        let service = configure( ServiceBuilder::new( core.handle( ) ) ).start( handler, wrap( server_io ) );
+
+        Ok( MockClient {
+            core       : core,
+            service    : service,
+            connection : Connection {
+                io         : client_io,
+                buffer     : Vec::new( ),
+                received   : VecDeque::new( ),
+                transcript : Vec::new( )
+            },
+            timeout    : Duration::from_millis( DEFAULT_TIMEOUT_MS ),
+            next_id    : 0
+        } )
+    }
+
+    /// Sets the time the helpers wait for a message before failing with MockError::Timeout.
+    pub fn set_timeout( &mut self, timeout : Duration ) {
+        self.timeout = timeout;
+    }
+
+    /// Returns the handle of the service the client is connected to.
+    pub fn service( &self ) -> &ServiceHandle {
+        &self.service
+    }
+
+    /// Sends a request of method R and waits for its response, deserializing the result into T.
+    pub fn request< R, T >( &mut self, params : R::Params ) -> Result< T, MockError >
+        where R : RequestMethod, R::Params : Serialize, T : DeserializeOwned {
+        let id = self.send_request::< R >( params )?;
+
+        self.response( id )
+    }
+
+    /// Sends a request of method R without waiting for its response, returning the id of the request.
+    pub fn send_request< R >( &mut self, params : R::Params ) -> Result< i64, MockError >
+        where R : RequestMethod, R::Params : Serialize {
+        self.next_id += 1;
+        let id = self.next_id;
+
+        let mut message = json!( {
+            "jsonrpc" : "2.0",
+            "id"      : id,
+            "method"  : R::METHOD
+        } );
+        let params = serde_json::to_value( params ).map_err( MockError::Json )?;
+        if !params.is_null( ) {
+            message[ "params" ] = params;
+        }
+        self.send_message( &message )?;
+
+        Ok( id )
+    }
+
+    /// Waits for the response to the request with the given id, deserializing the result into T.
+    pub fn response< T : DeserializeOwned >( &mut self, id : i64 ) -> Result< T, MockError > {
+        let mut response = self.wait_for( | message | is_response( message, id ) )?;
+        if let Some( error ) = response.get( "error" ) {
+            return Err( MockError::Response( ResponseError {
+                code    : error[ "code" ].as_i64( ).unwrap_or( 0 ),
+                message : error[ "message" ].as_str( ).unwrap_or( "" ).to_string( )
+            } ) );
+        }
+
+        serde_json::from_value( response[ "result" ].take( ) ).map_err( MockError::Json )
+    }
+
+    /// Sends a notification of method N.
+    pub fn notify< N >( &mut self, params : N::Params ) -> Result< ( ), MockError >
+        where N : NotificationMethod, N::Params : Serialize {
+        let mut message = json!( {
+            "jsonrpc" : "2.0",
+            "method"  : N::METHOD
+        } );
+        let params = serde_json::to_value( params ).map_err( MockError::Json )?;
+        if !params.is_null( ) {
+            message[ "params" ] = params;
+        }
+
+        self.send_message( &message )
+    }
+
+    /// Sends the didOpen and didChange notifications recorded by workspace.
+    pub fn open_workspace( &mut self, workspace : &WorkspaceFixture ) -> Result< ( ), MockError > {
+        workspace.send( &mut self.connection.io ).map_err( MockError::Io )
+    }
+
+    /// Waits for a notification for which predicate returns true, given the method and the parameters of
+    /// the notification.
+    pub fn expect_notification< F >( &mut self, predicate : F ) -> Result< MockNotification, MockError >
+        where F : Fn( &str, &Value ) -> bool {
+        let mut message = self.wait_for( | message | {
+            message.get( "id" ).is_none( ) && predicate( message[ "method" ].as_str( ).unwrap_or( "" ), &message[ "params" ] )
+        } )?;
+
+        Ok( MockNotification {
+            method : message[ "method" ].as_str( ).unwrap_or( "" ).to_string( ),
+            params : message[ "params" ].take( )
+        } )
+    }
+
+    /// Waits for a request sent by the service for which predicate returns true, given the method and the
+    /// parameters of the request.
+    pub fn expect_request< F >( &mut self, predicate : F ) -> Result< MockRequest, MockError >
+        where F : Fn( &str, &Value ) -> bool {
+        let mut message = self.wait_for( | message | {
+            message.get( "id" ).is_some( ) && message.get( "method" ).map( | method | {
+                predicate( method.as_str( ).unwrap_or( "" ), &message[ "params" ] )
+            } ).unwrap_or( false )
+        } )?;
+
+        Ok( MockRequest {
+            id     : message[ "id" ].take( ),
+            method : message[ "method" ].as_str( ).unwrap_or( "" ).to_string( ),
+            params : message[ "params" ].take( )
+        } )
+    }
+
+    /// Answers a request sent by the service with result.
+    pub fn respond< T : Serialize >( &mut self, request : &MockRequest, result : T ) -> Result< ( ), MockError > {
+        let result = serde_json::to_value( result ).map_err( MockError::Json )?;
+
+        self.send_message( &json!( {
+            "jsonrpc" : "2.0",
+            "id"      : request.id,
+            "result"  : result
+        } ) )
+    }
+
+    /// Waits for a textDocument/publishDiagnostics notification for uri whose diagnostics are matched by
+    /// matcher, returning the diagnostics.
+    ///
+    /// Panics if no such notification is received within the timeout of the client, listing the diagnostics
+    /// that were published for uri.
+    pub fn assert_published_diagnostics( &mut self, uri : &Url, matcher : DiagnosticsMatcher ) -> Vec< Value > {
+        let uri = uri.as_str( );
+        let result = self.expect_notification( | method, params | {
+            method == "textDocument/publishDiagnostics" && params[ "uri" ] == uri &&
+                params[ "diagnostics" ].as_array( ).map( | diagnostics | matcher.matches( diagnostics ) ).unwrap_or( false )
+        } );
+
+        match result {
+            Ok( mut notification ) => match notification.params[ "diagnostics" ].take( ) {
+                Value::Array( diagnostics ) => diagnostics,
+                _ => unreachable!( "Matched diagnostics are an array" )
+            },
+            Err( error ) => {
+                let published : Vec< &Value > = self.connection.transcript.iter( )
+                    .filter( | message | message[ "method" ] == "textDocument/publishDiagnostics" && message[ "params" ][ "uri" ] == uri )
+                    .map( | message | &message[ "params" ][ "diagnostics" ] )
+                    .collect( );
+
+                panic!( "No diagnostics {} published for {}: {}\npublished: {}", matcher.description( ), uri, error,
+                    serde_json::to_string_pretty( &published ).unwrap_or_default( ) );
+            }
+        }
+    }
+
+    /// Waits for the $/progress notification ending the work done progress with token, returning the values
+    /// of the $/progress notifications of the token in the order they were received.
+    ///
+    /// Panics if the progress does not end within the timeout of the client, or if it did not begin first.
+    pub fn assert_progress_reported< T : Into< Value > >( &mut self, token : T ) -> Vec< Value > {
+        let token = token.into( );
+        let result = self.expect_notification( | method, params | {
+            method == "$/progress" && params[ "token" ] == token && params[ "value" ][ "kind" ] == "end"
+        } );
+        if let Err( error ) = result {
+            panic!( "Progress {} did not end: {}", token, error );
+        }
+
+        let values : Vec< Value > = self.connection.transcript.iter( )
+            .filter( | message | message[ "method" ] == "$/progress" && message[ "params" ][ "token" ] == token )
+            .map( | message | message[ "params" ][ "value" ].clone( ) )
+            .collect( );
+        if values.first( ).map( | value | value[ "kind" ] != "begin" ).unwrap_or( true ) {
+            panic!( "Progress {} ended without beginning: {:?}", token, values );
+        }
+
+        values
+    }
+
+    /// Runs the service until it stalls, see MockClient::run_until_stalled, then takes the notifications of
+    /// method received and not consumed yet, in the order they were received.
+    pub fn collect_notifications( &mut self, method : &str ) -> Result< Vec< MockNotification >, MockError > {
+        self.run_until_stalled( )?;
+
+        let ( collected, kept ) : ( VecDeque< Value >, VecDeque< Value > ) = self.connection.received.drain( .. ).partition( | message | {
+            message.get( "id" ).is_none( ) && message[ "method" ] == method
+        } );
+        self.connection.received = kept;
+
+        Ok( collected.into_iter( ).map( | mut message | MockNotification {
+            method : method.to_string( ),
+            params : message[ "params" ].take( )
+        } ).collect( ) )
+    }
+
+    /// Runs a single turn of the event loop of the service without blocking, returning the number of
+    /// messages the service sent during the turn.
+    pub fn step( &mut self ) -> Result< usize, MockError > {
+        self.core.turn( Some( Duration::from_millis( 0 ) ) );
+
+        // Reading the transport registers the current task, so it must happen within a future
+        let connection = &mut self.connection;
+        self.core.run( future::poll_fn( move | | -> Poll< usize, MockError > {
+            let mut count = 0;
+            while let Async::Ready( message ) = connection.poll_message( )? {
+                connection.received.push_back( message );
+                count += 1;
+            }
+
+            Ok( Async::Ready( count ) )
+        } ) )
+    }
+
+    /// Steps the event loop until the service sends no message for several consecutive turns, returning
+    /// the number of messages received.
+    ///
+    /// Work waiting on a timer, a background thread or the client is not waited for.
+    pub fn run_until_stalled( &mut self ) -> Result< usize, MockError > {
+        let mut received = 0;
+        let mut idle_turns = 0;
+        while idle_turns < STALLED_TURNS {
+            let count = self.step( )?;
+            if count == 0 {
+                idle_turns += 1;
+            }
+            else {
+                idle_turns = 0;
+                received += count;
+            }
+        }
+
+        Ok( received )
+    }
+
+    /// Returns true if the response to the request with the given id was received and not consumed yet.
+    pub fn has_response( &self, id : i64 ) -> bool {
+        self.connection.received.iter( ).any( | message | is_response( message, id ) )
+    }
+
+    /// Returns the messages received from the service that no helper consumed yet, in the order they were
+    /// received.
+    pub fn received( &self ) -> Vec< Value > {
+        self.connection.received.iter( ).cloned( ).collect( )
+    }
+
+    /// Returns every message received from the service since the client was created, in the order they
+    /// were received.
+    pub fn transcript( &self ) -> &[ Value ] {
+        &self.connection.transcript
+    }
+
+    /// Compares the messages received from the service against golden, see GoldenFile::check.
+    pub fn check_golden( &self, golden : &GoldenFile ) -> Result< ( ), GoldenError > {
+        golden.check( &self.connection.transcript )
+    }
+
+    /// Sends a message as is, e.g. a message that is not valid JSON-RPC.
+    pub fn send_message( &mut self, message : &Value ) -> Result< ( ), MockError > {
+        transport::write_message( &mut self.connection.io, message ).map_err( MockError::Io )
+    }
+
+    /// Waits up to timeout for the next message of the service, returning messages received earlier first.
+    pub fn next_message( &mut self, timeout : Duration ) -> Result< Value, MockError > {
+        self.wait_for_within( | _ | true, timeout )
+    }
+
+    /// Runs the service until a message matching predicate is received, returning messages received
+    /// earlier first.
+    fn wait_for< F >( &mut self, predicate : F ) -> Result< Value, MockError > where F : Fn( &Value ) -> bool {
+        let timeout = self.timeout;
+
+        self.wait_for_within( predicate, timeout )
+    }
+
+    fn wait_for_within< F >( &mut self, predicate : F, timeout : Duration ) -> Result< Value, MockError > where F : Fn( &Value ) -> bool {
+        if let Some( index ) = self.connection.received.iter( ).position( | message | predicate( message ) ) {
+            return Ok( self.connection.received.remove( index ).unwrap( ) );
+        }
+
+        let mut timeout = Timeout::new( timeout, &self.core.handle( ) ).map_err( MockError::Io )?;
+        let connection = &mut self.connection;
+        self.core.run( future::poll_fn( move | | -> Poll< Value, MockError > {
+            while let Async::Ready( message ) = connection.poll_message( )? {
+                if predicate( &message ) {
+                    return Ok( Async::Ready( message ) );
+                }
+
+                connection.received.push_back( message );
+            }
+
+            match timeout.poll( ) {
+                Ok( Async::Ready( ( ) ) ) => Err( MockError::Timeout ),
+                Ok( Async::NotReady ) => Ok( Async::NotReady ),
+                Err( error ) => Err( MockError::Io( error ) )
+            }
+        } ) )
+    }
+
+}
+
+fn is_response( message : &Value, id : i64 ) -> bool {
+    message.get( "method" ).is_none( ) && message[ "id" ] == id
+}
+
+impl Connection {
+
+    /// Reads the next message sent by the service, NotReady until a whole message was received.
+    fn poll_message( &mut self ) -> Poll< Value, MockError > {
+        loop {
+            if let Some( message ) = self.decode( )? {
+                return Ok( Async::Ready( message ) );
+            }
+
+            let mut chunk = [ 0; 4096 ];
+            match self.io.read( &mut chunk ) {
+                Ok( 0 ) => return Err( MockError::Disconnected ),
+                Ok( count ) => self.buffer.extend_from_slice( &chunk[ ..count ] ),
+                Err( ref error ) if error.kind( ) == io::ErrorKind::WouldBlock => return Ok( Async::NotReady ),
+                Err( error ) => return Err( MockError::Io( error ) )
+            }
+        }
+    }
+
+    /// Decodes the first message of the buffer, None if it was not entirely received yet.
+    fn decode( &mut self ) -> Result< Option< Value >, MockError > {
+        let message = transport::take_message( &mut self.buffer ).map_err( MockError::InvalidMessage )?;
+        if let Some( ref message ) = message {
+            self.transcript.push( message.clone( ) );
+        }
+
+        Ok( message )
+    }
+
+}
+
+impl fmt::Display for MockError {
+
+    fn fmt( &self, f : &mut fmt::Formatter ) -> fmt::Result {
+        match *self {
+            MockError::Timeout => write!( f, "Timed out waiting for a message" ),
+            MockError::Disconnected => write!( f, "Service closed the transport" ),
+            MockError::Response( ref error ) => write!( f, "Service answered with error {}: {}", error.code, error.message ),
+            MockError::Json( ref error ) => write!( f, "JSON error: {}", error ),
+            MockError::InvalidMessage( ref message ) => write!( f, "Invalid message: {}", message ),
+            MockError::Io( ref error ) => write!( f, "IO error: {}", error )
+        }
+    }
+
+}
+
+impl Error for MockError {
+
+    fn description( &self ) -> &str {
+        match *self {
+            MockError::Timeout => "Timed out waiting for a message",
+            MockError::Disconnected => "Service closed the transport",
+            MockError::Response( _ ) => "Service answered with an error",
+            MockError::Json( ref error ) => error.description( ),
+            MockError::InvalidMessage( _ ) => "Invalid message",
+            MockError::Io( ref error ) => error.description( )
+        }
+    }
+
+}
@@ -0,0 +1,300 @@
+
+use futures::{
+    Async,
+    Future,
+    Poll
+};
+use futures::task::{
+    self,
+    Task
+};
+use logging::{
+    Component
+};
+use lsp_rs::{
+    NumberOrString,
+    ServerNotification,
+    WorkDoneProgress,
+    WorkDoneProgressBegin,
+    WorkDoneProgressCreateParams,
+    WorkDoneProgressEnd,
+    WorkDoneProgressReport
+};
+use method::{
+    WorkDoneProgressCreateRequest
+};
+use service::{
+    ClientRequestFuture,
+    ServiceHandle
+};
+use std::collections::{
+    HashMap
+};
+use std::sync::{
+    Arc,
+    Mutex
+};
+
+/// Allocator of the work done progress tokens created by the server
+///
+/// Each call to begin allocates a unique token and asks the client to create it through
+/// window/workDoneProgress/create. If the client does not support work done progress or fails to create the
+/// token, the returned ProgressReporter silently does nothing, so callers report progress the same way
+/// whatever the client supports. The tokens are cheap to clone, clones share the same allocator:
+///
+/// ```ignore
+/// // In handle_notification
+/// progress_tokens.observe( &notification );
+///
+/// let indexing = progress_tokens.begin( &service, "Indexing" ).and_then( move | reporter | {
+///     index_workspace( &reporter )
+/// } );
+/// ```
+#[derive( Clone, Default )]
+pub struct ProgressTokens {
+    inner : Arc< Mutex< ProgressTokensInner > >
+}
+
+#[derive( Default )]
+struct ProgressTokensInner {
+    next_token : u64,
+    active     : HashMap< String, CancellationToken >
+}
+
+/// Token signalled when the work it was handed to should stop, e.g. because the user canceled its progress
+///
+/// The token is a Future resolving once it is canceled, clones share the same state.
+#[derive( Clone, Default )]
+pub struct CancellationToken {
+    inner : Arc< Mutex< CancellationState > >
+}
+
+#[derive( Default )]
+struct CancellationState {
+    canceled : bool,
+    tasks    : Vec< Task >
+}
+
+/// Future resolving to the ProgressReporter of a new token once the client created it, returned by
+/// ProgressTokens::begin
+///
+/// The future never fails, the reporter does nothing if the token could not be created.
+pub struct ProgressBegin {
+    create   : Option< ClientRequestFuture< WorkDoneProgressCreateRequest > >,
+    title    : String,
+    reporter : Option< ProgressReporter >
+}
+
+/// Reports the progress of a piece of work under a token allocated by ProgressTokens
+///
+/// The progress is ended when the reporter is dropped, if it was not ended explicitly.
+pub struct ProgressReporter {
+    service : ServiceHandle,
+    tokens  : ProgressTokens,
+    key     : String,
+    token   : Option< NumberOrString >,
+    cancel  : CancellationToken,
+    ended   : bool
+}
+
+impl ProgressTokens {
+
+    pub fn new( ) -> Self {
+        ProgressTokens::default( )
+    }
+
+    /// Allocates a new token and starts reporting progress under title once the client created the token.
+    pub fn begin< S : Into< String > >( &self, service : &ServiceHandle, title : S ) -> ProgressBegin {
+        self.begin_with_cancellation( service, title, CancellationToken::new( ) )
+    }
+
+    /// Allocates a new token like begin, signalling cancel instead of a new CancellationToken when the user
+    /// cancels the progress.
+    pub fn begin_with_cancellation< S : Into< String > >( &self, service : &ServiceHandle, title : S, cancel : CancellationToken ) -> ProgressBegin {
+        let key = {
+            let mut inner = self.inner.lock( ).unwrap( );
+            inner.next_token += 1;
+
+            let key = format!( "ls_service/progress/{}", inner.next_token );
+            inner.active.insert( key.clone( ), cancel.clone( ) );
+
+            key
+        };
+
+        let create = if service.supports_work_done_progress( ) {
+            Some( service.request::< WorkDoneProgressCreateRequest >( WorkDoneProgressCreateParams {
+                token : NumberOrString::String( key.clone( ) )
+            } ) )
+        }
+        else {
+            None
+        };
+
+        ProgressBegin {
+            create   : create,
+            title    : title.into( ),
+            reporter : Some( ProgressReporter {
+                service : service.clone( ),
+                tokens  : self.clone( ),
+                key     : key,
+                token   : None,
+                cancel  : cancel,
+                ended   : false
+            } )
+        }
+    }
+
+    /// Signals the cancellation token of the progress canceled by a window/workDoneProgress/cancel
+    /// notification, ignoring other notifications.
+    pub fn observe( &self, notification : &ServerNotification ) {
+        if let ServerNotification::WorkDoneProgressCancel( ref params ) = *notification {
+            if let NumberOrString::String( ref key ) = params.token {
+                let cancel = self.inner.lock( ).unwrap( ).active.get( key ).cloned( );
+                match cancel {
+                    Some( cancel ) => cancel.cancel( ),
+                    None => component_trace!( Component::Service, "Ignoring cancellation of unknown progress token {}.", key )
+                }
+            }
+        }
+    }
+
+    fn release( &self, key : &str ) {
+        self.inner.lock( ).unwrap( ).active.remove( key );
+    }
+
+}
+
+impl CancellationToken {
+
+    pub fn new( ) -> Self {
+        CancellationToken::default( )
+    }
+
+    /// Cancels the token, waking every task waiting on it.
+    pub fn cancel( &self ) {
+        let mut inner = self.inner.lock( ).unwrap( );
+        inner.canceled = true;
+
+        for task in inner.tasks.drain( .. ) {
+            task.notify( );
+        }
+    }
+
+    pub fn is_canceled( &self ) -> bool {
+        self.inner.lock( ).unwrap( ).canceled
+    }
+
+}
+
+impl Future for CancellationToken {
+
+    type Item  = ( );
+    type Error = ( );
+
+    fn poll( &mut self ) -> Poll< Self::Item, Self::Error > {
+        let mut inner = self.inner.lock( ).unwrap( );
+        if inner.canceled {
+            return Ok( Async::Ready( ( ) ) );
+        }
+
+        let current = task::current( );
+        if !inner.tasks.iter( ).any( | task | task.will_notify( &current ) ) {
+            inner.tasks.push( current );
+        }
+
+        Ok( Async::NotReady )
+    }
+
+}
+
+impl Future for ProgressBegin {
+
+    type Item  = ProgressReporter;
+    type Error = ( );
+
+    fn poll( &mut self ) -> Poll< Self::Item, Self::Error > {
+        let created = match self.create {
+            Some( ref mut create ) => match create.poll( ) {
+                Ok( Async::Ready( ( ) ) ) => true,
+                Ok( Async::NotReady ) => return Ok( Async::NotReady ),
+                Err( error ) => {
+                    component_trace!( Component::Service, "Client failed to create progress token, progress will not be reported: {:?}", error );
+
+                    false
+                }
+            },
+            None => false
+        };
+
+        let mut reporter = self.reporter.take( ).expect( "ProgressBegin polled after completion" );
+        if created {
+            let token = NumberOrString::String( reporter.key.clone( ) );
+            reporter.service.progress( token.clone( ), WorkDoneProgress::Begin( WorkDoneProgressBegin {
+                title       : self.title.clone( ),
+                cancellable : Some( true ),
+                message     : None,
+                percentage  : None
+            } ) );
+            reporter.token = Some( token );
+        }
+
+        Ok( Async::Ready( reporter ) )
+    }
+
+}
+
+impl ProgressReporter {
+
+    /// Returns true if progress is shown by the client, false if the reporter does nothing.
+    pub fn is_active( &self ) -> bool {
+        self.token.is_some( )
+    }
+
+    /// Returns the token signalled when the user cancels the progress.
+    pub fn cancellation_token( &self ) -> CancellationToken {
+        self.cancel.clone( )
+    }
+
+    pub fn is_canceled( &self ) -> bool {
+        self.cancel.is_canceled( )
+    }
+
+    /// Reports the progress of the work, percentage ranging from 0 to 100.
+    pub fn report( &self, message : Option< String >, percentage : Option< f64 > ) {
+        if let Some( ref token ) = self.token {
+            self.service.progress( token.clone( ), WorkDoneProgress::Report( WorkDoneProgressReport {
+                cancellable : Some( true ),
+                message     : message,
+                percentage  : percentage
+            } ) );
+        }
+    }
+
+    /// Ends the progress with a final message.
+    pub fn end( mut self, message : Option< String > ) {
+        self.finish( message );
+    }
+
+    fn finish( &mut self, message : Option< String > ) {
+        if self.ended {
+            return;
+        }
+        self.ended = true;
+
+        self.tokens.release( &self.key );
+        if let Some( token ) = self.token.take( ) {
+            self.service.progress( token, WorkDoneProgress::End( WorkDoneProgressEnd {
+                message : message
+            } ) );
+        }
+    }
+
+}
+
+impl Drop for ProgressReporter {
+
+    fn drop( &mut self ) {
+        self.finish( None );
+    }
+
+}
@@ -0,0 +1,644 @@
+
+use futures::{
+    Async,
+    Poll,
+    Stream
+};
+use futures::task::{
+    self,
+    AtomicTask,
+    Task
+};
+use logging::{
+    Component
+};
+use std::cell::{
+    Cell,
+    UnsafeCell
+};
+use std::marker::{
+    PhantomData
+};
+use std::ptr;
+use std::sync::{
+    Arc,
+    Mutex
+};
+use std::sync::atomic::{
+    AtomicBool,
+    AtomicPtr,
+    AtomicUsize,
+    Ordering
+};
+
+/// Number of items held by a segment of an spsc queue
+const SEGMENT_LEN : usize = 32;
+/// Capacity of an internal queue of a service, see ServiceBuilder::response_queue_capacity and
+/// ServiceBuilder::write_queue_capacity
+#[derive( Clone, Copy, Debug, PartialEq, Eq )]
+pub enum QueueCapacity {
+    /// Holds at most the given number of items, the task pushing to a full queue waits for room
+    Bounded( usize ),
+    /// Never makes the task pushing to the queue wait
+    Unbounded,
+    /// Starts holding initial items, doubling the capacity every time the queue fills up until it reaches
+    /// max, and halving it back towards initial every time the queue is drained
+    Adaptive {
+        initial : usize,
+        max     : usize
+    }
+}
+
+/// Current limit of a queue of the given capacity
+#[derive( Clone, Debug )]
+pub(crate) struct QueueLimit {
+    capacity : QueueCapacity,
+    limit    : usize
+}
+
+impl QueueCapacity {
+
+    /// Returns the capacity raised to hold at least one item, a queue that can never hold an item would
+    /// stall the service.
+    fn normalized( self ) -> Self {
+        match self {
+            QueueCapacity::Bounded( capacity ) => QueueCapacity::Bounded( capacity.max( 1 ) ),
+            QueueCapacity::Unbounded => QueueCapacity::Unbounded,
+            QueueCapacity::Adaptive { initial, max } => {
+                let initial = initial.max( 1 );
+
+                QueueCapacity::Adaptive {
+                    initial : initial,
+                    max     : max.max( initial )
+                }
+            }
+        }
+    }
+
+    /// Returns the limit of an empty queue.
+    fn initial_limit( self ) -> usize {
+        match self {
+            QueueCapacity::Bounded( capacity ) => capacity,
+            QueueCapacity::Unbounded => usize::max_value( ),
+            QueueCapacity::Adaptive { initial, .. } => initial
+        }
+    }
+
+    /// Returns the limit of a queue holding len items, growing an adaptive capacity if the queue is full.
+    fn grown( self, limit : usize, len : usize ) -> usize {
+        match self {
+            QueueCapacity::Adaptive { max, .. } if len >= limit && limit < max => limit.saturating_mul( 2 ).min( max ),
+            _ => limit
+        }
+    }
+
+    /// Returns the limit of a queue that was drained, shrinking an adaptive capacity.
+    fn shrunk( self, limit : usize ) -> usize {
+        match self {
+            QueueCapacity::Adaptive { initial, .. } if limit > initial => ( limit / 2 ).max( initial ),
+            _ => limit
+        }
+    }
+
+}
+
+impl QueueLimit {
+
+    pub fn new( capacity : QueueCapacity ) -> Self {
+        let capacity = capacity.normalized( );
+
+        QueueLimit {
+            capacity : capacity,
+            limit    : capacity.initial_limit( )
+        }
+    }
+
+    /// Returns true if an item can be pushed to the queue holding len items, growing an adaptive capacity
+    /// if the queue is full.
+    pub fn has_room( &mut self, len : usize ) -> bool {
+        let limit = self.capacity.grown( self.limit, len );
+        if limit != self.limit {
+            self.limit = limit;
+
+            component_debug!( Component::Service, "Growing queue capacity to {}.", self.limit );
+        }
+
+        len < self.limit
+    }
+
+    /// Shrinks an adaptive capacity once the queue was drained.
+    pub fn drained( &mut self ) {
+        let limit = self.capacity.shrunk( self.limit );
+        if limit != self.limit {
+            self.limit = limit;
+
+            component_debug!( Component::Service, "Shrinking queue capacity to {}.", self.limit );
+        }
+    }
+
+}
+
+/// Producer end of an spsc queue
+///
+/// Neither Clone nor Sync, so items are only ever pushed by one thread. Tasks on the same thread may share it
+/// behind an Rc.
+pub(crate) struct SpscSender< T > {
+    inner     : Arc< SpscInner< T > >,
+    _not_sync : PhantomData< Cell< ( ) > >
+}
+
+/// Consumer end of an spsc queue, a stream of the items pushed, ending once the SpscSender is dropped
+pub(crate) struct SpscReceiver< T > {
+    inner : Arc< SpscInner< T > >
+}
+
+/// Number of items in an spsc queue, readable from any thread
+#[derive( Clone )]
+pub(crate) struct QueueLen {
+    inner : Arc< dyn QueueCounters + Send + Sync >
+}
+
+trait QueueCounters {
+
+    fn len( &self ) -> usize;
+
+}
+
+/// Lock-free queue between a single producer and a single consumer
+///
+/// Items are stored in fixed size segments linked as they fill up. The producer only writes to the tail
+/// segment and publishes an item by incrementing pushed, the consumer only reads from the head segment and
+/// frees a segment once it moves past it. The consumer is only woken if it parked waiting for an item,
+/// a producer pushing to a busy consumer touches neither a lock nor its task.
+///
+/// The capacity of the queue is only checked by SpscSender::poll_ready, its limit is an atomic grown by the
+/// producer and shrunk by the consumer. Producer tasks waiting for room are likewise only woken if one of
+/// them parked, the waiting tasks are only locked by a producer finding the queue full and by the consumer
+/// waking them.
+struct SpscInner< T > {
+    /// Segment the next item is taken out of, only touched by the consumer
+    head           : UnsafeCell< *mut Segment< T > >,
+    /// Segment the next item is pushed to, only touched by the producer
+    tail           : UnsafeCell< *mut Segment< T > >,
+    /// Number of items pushed, incremented once the item is in its slot
+    pushed         : AtomicUsize,
+    /// Number of items taken out, only written by the consumer
+    popped         : AtomicUsize,
+    /// Set by the consumer before waiting for an item, cleared by the producer notifying it
+    parked         : AtomicBool,
+    consumer_task  : AtomicTask,
+    capacity       : QueueCapacity,
+    /// Current limit of the number of items in the queue, see QueueCapacity
+    limit          : AtomicUsize,
+    /// Set by a producer task before waiting for room, cleared by the consumer waking the waiting tasks
+    full           : AtomicBool,
+    producer_tasks : Mutex< Vec< Task > >,
+    sender_alive   : AtomicBool,
+    receiver_alive : AtomicBool
+}
+
+struct Segment< T > {
+    slots : Vec< UnsafeCell< Option< T > > >,
+    next  : AtomicPtr< Segment< T > >
+}
+
+// The producer and the consumer never touch the same slot at the same time, a slot is only read once its
+// item was published through pushed and only written again once its segment was freed and reallocated
+unsafe impl < T : Send > Send for SpscInner< T > { }
+unsafe impl < T : Send > Sync for SpscInner< T > { }
+
+/// Creates a lock-free queue of the given capacity between a single producer and a single consumer, see
+/// SpscInner.
+pub(crate) fn spsc< T >( capacity : QueueCapacity ) -> ( SpscSender< T >, SpscReceiver< T > ) {
+    let capacity = capacity.normalized( );
+    let segment = Segment::allocate( );
+    let inner = Arc::new( SpscInner {
+        head           : UnsafeCell::new( segment ),
+        tail           : UnsafeCell::new( segment ),
+        pushed         : AtomicUsize::new( 0 ),
+        popped         : AtomicUsize::new( 0 ),
+        parked         : AtomicBool::new( false ),
+        consumer_task  : AtomicTask::new( ),
+        capacity       : capacity,
+        limit          : AtomicUsize::new( capacity.initial_limit( ) ),
+        full           : AtomicBool::new( false ),
+        producer_tasks : Mutex::new( Vec::new( ) ),
+        sender_alive   : AtomicBool::new( true ),
+        receiver_alive : AtomicBool::new( true )
+    } );
+
+    ( SpscSender { inner : inner.clone( ), _not_sync : PhantomData }, SpscReceiver { inner : inner } )
+}
+
+impl < T > Segment< T > {
+
+    fn allocate( ) -> *mut Segment< T > {
+        Box::into_raw( Box::new( Segment {
+            slots : ( 0..SEGMENT_LEN ).map( | _ | UnsafeCell::new( None ) ).collect( ),
+            next  : AtomicPtr::new( ptr::null_mut( ) )
+        } ) )
+    }
+
+}
+
+impl QueueLen {
+
+    /// Returns the number of items pushed to the queue and not taken out yet.
+    pub fn len( &self ) -> usize {
+        self.inner.len( )
+    }
+
+}
+
+impl < T > QueueCounters for SpscInner< T > {
+
+    fn len( &self ) -> usize {
+        // Read popped first, pushed is never behind it
+        let popped = self.popped.load( Ordering::SeqCst );
+
+        self.pushed.load( Ordering::SeqCst ) - popped
+    }
+
+}
+
+impl < T > SpscInner< T > {
+
+    /// Returns true if an item can be pushed, growing an adaptive capacity if the queue is full.
+    fn has_room( &self ) -> bool {
+        let len = self.len( );
+        let mut limit = self.limit.load( Ordering::SeqCst );
+        loop {
+            let grown = self.capacity.grown( limit, len );
+            if grown == limit {
+                return len < limit;
+            }
+
+            // The consumer may have shrunk the limit meanwhile
+            match self.limit.compare_exchange( limit, grown, Ordering::SeqCst, Ordering::SeqCst ) {
+                Ok( _ ) => {
+                    component_debug!( Component::Service, "Growing queue capacity to {}.", grown );
+
+                    return len < grown;
+                },
+                Err( current ) => limit = current
+            }
+        }
+    }
+
+    /// Shrinks an adaptive capacity once the queue was drained.
+    fn drained( &self ) {
+        let mut limit = self.limit.load( Ordering::SeqCst );
+        loop {
+            let shrunk = self.capacity.shrunk( limit );
+            if shrunk == limit {
+                return;
+            }
+
+            // The producer may have grown the limit meanwhile
+            match self.limit.compare_exchange( limit, shrunk, Ordering::SeqCst, Ordering::SeqCst ) {
+                Ok( _ ) => {
+                    component_debug!( Component::Service, "Shrinking queue capacity to {}.", shrunk );
+
+                    return;
+                },
+                Err( current ) => limit = current
+            }
+        }
+    }
+
+    /// Wakes the producer tasks waiting for room, if any.
+    fn notify_producers( &self ) {
+        if self.full.swap( false, Ordering::SeqCst ) {
+            for task in self.producer_tasks.lock( ).unwrap( ).drain( .. ) {
+                task.notify( );
+            }
+        }
+    }
+
+}
+
+impl < T : Send + 'static > SpscSender< T > {
+
+    /// Returns a handle to the number of items in the queue.
+    pub fn len_handle( &self ) -> QueueLen {
+        QueueLen {
+            inner : self.inner.clone( )
+        }
+    }
+
+}
+
+impl < T > SpscSender< T > {
+
+    /// Polls for room in the queue, scheduling the current task to be notified once an item was taken out
+    /// of the queue. Ready if the receiver was dropped, for push to return the item.
+    pub fn poll_ready( &self ) -> Async< ( ) > {
+        let inner = &*self.inner;
+        if inner.has_room( ) || !inner.receiver_alive.load( Ordering::SeqCst ) {
+            return Async::Ready( ( ) );
+        }
+
+        inner.producer_tasks.lock( ).unwrap( ).push( task::current( ) );
+        inner.full.store( true, Ordering::SeqCst );
+
+        // An item taken out before full was set did not notify the task
+        if inner.has_room( ) || !inner.receiver_alive.load( Ordering::SeqCst ) {
+            return Async::Ready( ( ) );
+        }
+
+        Async::NotReady
+    }
+
+    /// Pushes item to the queue, returning it if the receiver was dropped.
+    pub fn push( &self, item : T ) -> Result< ( ), T > {
+        let inner = &*self.inner;
+        if !inner.receiver_alive.load( Ordering::Acquire ) {
+            return Err( item );
+        }
+
+        let pushed = inner.pushed.load( Ordering::Relaxed );
+        let offset = pushed % SEGMENT_LEN;
+        // Only the producer touches tail, and the slot at pushed is not published to the consumer yet
+        unsafe {
+            if offset == 0 && pushed != 0 {
+                let segment = Segment::allocate( );
+                ( **inner.tail.get( ) ).next.store( segment, Ordering::Release );
+                *inner.tail.get( ) = segment;
+            }
+
+            *( **inner.tail.get( ) ).slots[ offset ].get( ) = Some( item );
+        }
+        inner.pushed.store( pushed + 1, Ordering::SeqCst );
+
+        if inner.parked.swap( false, Ordering::SeqCst ) {
+            inner.consumer_task.notify( );
+        }
+
+        Ok( ( ) )
+    }
+
+}
+
+impl < T > Drop for SpscSender< T > {
+
+    fn drop( &mut self ) {
+        self.inner.sender_alive.store( false, Ordering::SeqCst );
+        self.inner.consumer_task.notify( );
+    }
+
+}
+
+impl < T > SpscReceiver< T > {
+
+    /// Takes the next item out of the queue, None if no item was pushed yet.
+    fn pop( &mut self ) -> Option< T > {
+        let inner = &*self.inner;
+        let popped = inner.popped.load( Ordering::Relaxed );
+        if popped == inner.pushed.load( Ordering::SeqCst ) {
+            return None;
+        }
+
+        let offset = popped % SEGMENT_LEN;
+        // Only the consumer touches head. The producer linked the next segment before publishing its first
+        // item and never touches a segment the consumer moved past.
+        let item = unsafe {
+            if offset == 0 && popped != 0 {
+                let head = *inner.head.get( );
+                *inner.head.get( ) = ( *head ).next.load( Ordering::Acquire );
+                drop( Box::from_raw( head ) );
+            }
+
+            ( *( **inner.head.get( ) ).slots[ offset ].get( ) ).take( )
+        };
+        inner.popped.store( popped + 1, Ordering::SeqCst );
+
+        if inner.pushed.load( Ordering::SeqCst ) == popped + 1 {
+            inner.drained( );
+        }
+        inner.notify_producers( );
+
+        Some( item.expect( "Published slots hold an item" ) )
+    }
+
+}
+
+impl < T > Stream for SpscReceiver< T > {
+
+    type Item  = T;
+    type Error = ( );
+
+    fn poll( &mut self ) -> Poll< Option< Self::Item >, Self::Error > {
+        loop {
+            if let Some( item ) = self.pop( ) {
+                return Ok( Async::Ready( Some( item ) ) );
+            }
+
+            let sender_alive = self.inner.sender_alive.load( Ordering::SeqCst );
+            if !sender_alive {
+                // Items pushed before the sender was dropped are still taken out
+                return Ok( Async::Ready( self.pop( ) ) );
+            }
+
+            self.inner.consumer_task.register( );
+            self.inner.parked.store( true, Ordering::SeqCst );
+
+            // An item pushed before parked was set did not notify the task
+            let popped = self.inner.popped.load( Ordering::Relaxed );
+            if popped == self.inner.pushed.load( Ordering::SeqCst ) && self.inner.sender_alive.load( Ordering::SeqCst ) {
+                return Ok( Async::NotReady );
+            }
+            self.inner.parked.store( false, Ordering::SeqCst );
+        }
+    }
+
+}
+
+impl < T > Drop for SpscReceiver< T > {
+
+    fn drop( &mut self ) {
+        self.inner.receiver_alive.store( false, Ordering::SeqCst );
+
+        // Waiting producers find out the receiver was dropped when pushing
+        for task in self.inner.producer_tasks.lock( ).unwrap( ).drain( .. ) {
+            task.notify( );
+        }
+    }
+
+}
+
+impl < T > Drop for SpscInner< T > {
+
+    fn drop( &mut self ) {
+        // Items never taken out are dropped with their segment
+        let mut segment = *self.head.get_mut( );
+        while !segment.is_null( ) {
+            let boxed = unsafe { Box::from_raw( segment ) };
+            segment = boxed.next.load( Ordering::Relaxed );
+        }
+    }
+
+}
+
+#[cfg( test )]
+mod tests {
+
+    use futures::{
+        future,
+        Async
+    };
+    use futures::executor::{
+        self,
+        Notify
+    };
+    use std::sync::{
+        Arc
+    };
+    use std::sync::atomic::{
+        AtomicUsize,
+        Ordering
+    };
+    use super::{
+        spsc,
+        QueueCapacity,
+        SpscSender,
+        SEGMENT_LEN
+    };
+
+    /// Counts the times a task was notified
+    #[derive( Default )]
+    struct Wakeups {
+        count : AtomicUsize
+    }
+
+    /// Counts the items dropped
+    struct Tracked( Arc< AtomicUsize > );
+
+    impl Notify for Wakeups {
+
+        fn notify( &self, _ : usize ) {
+            self.count.fetch_add( 1, Ordering::SeqCst );
+        }
+
+    }
+
+    impl Wakeups {
+
+        fn count( &self ) -> usize {
+            self.count.load( Ordering::SeqCst )
+        }
+
+    }
+
+    impl Drop for Tracked {
+
+        fn drop( &mut self ) {
+            self.0.fetch_add( 1, Ordering::SeqCst );
+        }
+
+    }
+
+    /// Polls sender for room from a task notifying wakeups.
+    fn poll_ready< T >( sender : &SpscSender< T >, wakeups : &Arc< Wakeups > ) -> Async< ( ) > {
+        let mut task = executor::spawn( future::poll_fn( || Ok::< _, ( ) >( sender.poll_ready( ) ) ) );
+
+        task.poll_future_notify( wakeups, 0 ).unwrap( )
+    }
+
+    #[test]
+    fn items_wrap_around_segments_in_order( ) {
+        let ( sender, mut receiver ) = spsc( QueueCapacity::Unbounded );
+        let len = sender.len_handle( );
+
+        let mut next = 0;
+        for pushed in 0..SEGMENT_LEN * 5 {
+            sender.push( pushed ).unwrap( );
+
+            // The consumer lags behind the producer, taking items out of the segments the producer left
+            if pushed % 3 == 2 {
+                for _ in 0..2 {
+                    assert_eq!( receiver.pop( ), Some( next ) );
+                    next += 1;
+                }
+            }
+            assert_eq!( len.len( ), pushed + 1 - next );
+        }
+        while let Some( item ) = receiver.pop( ) {
+            assert_eq!( item, next );
+            next += 1;
+        }
+
+        assert_eq!( next, SEGMENT_LEN * 5 );
+        assert_eq!( len.len( ), 0 );
+    }
+
+    #[test]
+    fn full_queue_parks_the_producer_until_an_item_is_taken_out( ) {
+        let ( sender, mut receiver ) = spsc( QueueCapacity::Bounded( 2 ) );
+        let wakeups = Arc::new( Wakeups::default( ) );
+
+        for item in 0..2 {
+            assert_eq!( poll_ready( &sender, &wakeups ), Async::Ready( ( ) ) );
+            sender.push( item ).unwrap( );
+        }
+        assert_eq!( poll_ready( &sender, &wakeups ), Async::NotReady );
+
+        assert_eq!( receiver.pop( ), Some( 0 ) );
+        assert_eq!( wakeups.count( ), 1 );
+        assert_eq!( poll_ready( &sender, &wakeups ), Async::Ready( ( ) ) );
+
+        // Taking an item out while no producer waits wakes nobody
+        assert_eq!( receiver.pop( ), Some( 1 ) );
+        assert_eq!( wakeups.count( ), 1 );
+    }
+
+    #[test]
+    fn adaptive_limit_grows_when_full_and_shrinks_when_drained( ) {
+        let ( sender, mut receiver ) = spsc( QueueCapacity::Adaptive { initial : 2, max : 8 } );
+        let wakeups = Arc::new( Wakeups::default( ) );
+
+        for item in 0..8 {
+            assert_eq!( poll_ready( &sender, &wakeups ), Async::Ready( ( ) ) );
+            sender.push( item ).unwrap( );
+        }
+        assert_eq!( poll_ready( &sender, &wakeups ), Async::NotReady );
+        assert_eq!( sender.inner.limit.load( Ordering::SeqCst ), 8 );
+
+        while receiver.pop( ).is_some( ) { }
+        assert_eq!( sender.inner.limit.load( Ordering::SeqCst ), 4 );
+        assert_eq!( wakeups.count( ), 1 );
+    }
+
+    #[test]
+    fn dropped_receiver_wakes_the_producer_and_fails_pushes( ) {
+        let ( sender, receiver ) = spsc( QueueCapacity::Bounded( 1 ) );
+        let wakeups = Arc::new( Wakeups::default( ) );
+
+        sender.push( 0 ).unwrap( );
+        assert_eq!( poll_ready( &sender, &wakeups ), Async::NotReady );
+
+        drop( receiver );
+        assert_eq!( wakeups.count( ), 1 );
+        assert_eq!( poll_ready( &sender, &wakeups ), Async::Ready( ( ) ) );
+        assert_eq!( sender.push( 1 ), Err( 1 ) );
+    }
+
+    #[test]
+    fn items_left_in_the_queue_are_dropped_with_it( ) {
+        let dropped = Arc::new( AtomicUsize::new( 0 ) );
+        let ( sender, mut receiver ) = spsc( QueueCapacity::Unbounded );
+
+        let pushed = SEGMENT_LEN * 2 + 3;
+        for _ in 0..pushed {
+            assert!( sender.push( Tracked( dropped.clone( ) ) ).is_ok( ) );
+        }
+        for _ in 0..SEGMENT_LEN + 1 {
+            drop( receiver.pop( ) );
+        }
+        assert_eq!( dropped.load( Ordering::SeqCst ), SEGMENT_LEN + 1 );
+
+        drop( sender );
+        drop( receiver );
+        assert_eq!( dropped.load( Ordering::SeqCst ), pushed );
+    }
+
+}
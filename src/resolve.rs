@@ -0,0 +1,303 @@
+
+use composite::{
+    MethodComponent
+};
+use context::{
+    Context
+};
+use futures::{
+    Future,
+    IntoFuture
+};
+use handler::{
+    method_not_found,
+    HandlerError
+};
+use logging::{
+    Component
+};
+use lsp_rs::{
+    INTERNAL_ERROR,
+    INVALID_PARAMS,
+    CodeAction,
+    CompletionItem,
+    ResponseError,
+    ServerNotification,
+    ServerRequest,
+    ServerResponse
+};
+use method::{
+    MethodName,
+    RequestMethod,
+    ResolveCodeActionRequest,
+    ResolveCompletionItemRequest
+};
+use serde_json::{
+    Map,
+    Value
+};
+use service::{
+    MessageHandler,
+    ResponseOutput,
+    ServiceHandle
+};
+use std::collections::{
+    HashMap
+};
+
+/// Field of the data of a lazy item holding the id of its resolver
+const RESOLVER_FIELD : &str = "ls_service.resolver";
+/// Field of the data of a lazy item holding the data attached by its provider
+const DATA_FIELD     : &str = "ls_service.data";
+
+type Resolver< T > = Box< dyn Fn( T, Context ) -> Box< dyn Future< Item = T, Error = ResponseError > + Send > >;
+
+const CODE_ACTION_METHODS : &[ &str ] = &[ ResolveCodeActionRequest::METHOD ];
+const COMPLETION_METHODS  : &[ &str ] = &[ ResolveCompletionItemRequest::METHOD ];
+
+/// Registry routing codeAction/resolve requests to the resolver of the provider that returned the code action
+///
+/// Providers return lazy code actions without their edit or command, tagged with attach_code_action. When
+/// the client resolves the code action, the registry restores the data attached by the provider and calls
+/// the resolver the action was tagged with. Resolved actions that still have neither an edit nor a command
+/// are answered with an INTERNAL_ERROR instead of being sent to the client.
+///
+/// ```ignore
+/// // In the textDocument/codeAction handler
+/// let mut action = CodeAction { title : "Extract function".to_string( ), .. CodeAction::default( ) };
+/// resolve::attach_code_action( &mut action, "refactor", json!( { "range" : range } ) );
+///
+/// let resolvers = CodeActionResolvers::new( )
+///     .resolver( "refactor", | action, context | compute_refactoring( action, context ) );
+/// ```
+pub struct CodeActionResolvers {
+    resolvers : HashMap< String, Resolver< CodeAction > >
+}
+
+/// Registry routing completionItem/resolve requests to the resolver of the provider that returned the item
+///
+/// Providers return completion items without their documentation or additional text edits, tagged with
+/// attach_completion_item. When the client resolves an item, the registry restores the data attached by the
+/// provider and calls the resolver the item was tagged with, so several completion providers can return
+/// lazy items without sharing state.
+///
+/// ```ignore
+/// // In the textDocument/completion handler
+/// resolve::attach_completion_item( &mut item, "imports", json!( { "module" : module } ) );
+///
+/// let resolvers = CompletionResolvers::new( )
+///     .resolver( "imports", | item, context | add_import_edit( item, context ) );
+/// ```
+pub struct CompletionResolvers {
+    resolvers : HashMap< String, Resolver< CompletionItem > >
+}
+
+/// Tags a lazy code action with the resolver that fills it in on codeAction/resolve, wrapping data so it is
+/// restored before the resolver is called.
+pub fn attach_code_action( action : &mut CodeAction, resolver : &str, data : Value ) {
+    action.data = Some( wrap_data( resolver, data ) );
+}
+
+/// Tags a lazy completion item with the resolver that fills it in on completionItem/resolve, wrapping data
+/// so it is restored before the resolver is called.
+pub fn attach_completion_item( item : &mut CompletionItem, resolver : &str, data : Value ) {
+    item.data = Some( wrap_data( resolver, data ) );
+}
+
+impl CodeActionResolvers {
+
+    pub fn new( ) -> Self {
+        CodeActionResolvers {
+            resolvers : HashMap::new( )
+        }
+    }
+
+    /// Registers the resolver with the given id, replacing any resolver previously registered with it.
+    pub fn resolver< F, R >( mut self, id : &str, resolver : F ) -> Self
+        where F : Fn( CodeAction, Context ) -> R + 'static,
+              R : IntoFuture< Item = CodeAction, Error = HandlerError >,
+              R::Future : Send + 'static {
+        self.resolvers.insert( id.to_string( ), boxed_resolver( resolver ) );
+
+        self
+    }
+
+    fn resolve( &self, mut action : CodeAction, context : Context, output : ResponseOutput ) {
+        let ( id, data ) = match unwrap_data( action.data.take( ) ) {
+            Some( tagged ) => tagged,
+            None => return output.send_error( untagged_item( "code action" ) )
+        };
+        let resolver = match self.resolvers.get( &id ) {
+            Some( resolver ) => resolver,
+            None => return output.send_error( unknown_resolver( &id ) )
+        };
+        action.data = data;
+
+        output.complete_with( resolver( action, context ).and_then( | action | {
+            if action.edit.is_none( ) && action.command.is_none( ) {
+                return Err( ResponseError {
+                    code    : INTERNAL_ERROR,
+                    message : format!( "Code action '{}' was resolved without an edit or a command", action.title )
+                } );
+            }
+
+            Ok( ServerResponse::ResolveCodeAction( action ) )
+        } ) );
+    }
+
+}
+
+impl MessageHandler for CodeActionResolvers {
+
+    fn handle_request( &self, service : ServiceHandle, request : ServerRequest, output : ResponseOutput ) {
+        match ResolveCodeActionRequest::from_request( request ) {
+            Ok( action ) => {
+                let context = Context::for_request( service, ResolveCodeActionRequest::METHOD, &output );
+
+                self.resolve( action, context, output );
+            },
+            Err( request ) => {
+                component_error!( Component::Router, "Request {} sent to CodeActionResolvers.", request.method_name( ) );
+
+                output.send_error( method_not_found( request.method_name( ) ) );
+            }
+        }
+    }
+
+    fn handle_notification( &self, _service : ServiceHandle, _notification : ServerNotification ) {
+    }
+
+    fn claims_request( &self, request : &ServerRequest ) -> bool {
+        match *request {
+            ServerRequest::ResolveCodeAction( .. ) => true,
+            _ => false
+        }
+    }
+
+}
+
+impl MethodComponent for CodeActionResolvers {
+
+    fn methods( &self ) -> &[ &'static str ] {
+        CODE_ACTION_METHODS
+    }
+
+}
+
+impl CompletionResolvers {
+
+    pub fn new( ) -> Self {
+        CompletionResolvers {
+            resolvers : HashMap::new( )
+        }
+    }
+
+    /// Registers the resolver with the given id, replacing any resolver previously registered with it.
+    pub fn resolver< F, R >( mut self, id : &str, resolver : F ) -> Self
+        where F : Fn( CompletionItem, Context ) -> R + 'static,
+              R : IntoFuture< Item = CompletionItem, Error = HandlerError >,
+              R::Future : Send + 'static {
+        self.resolvers.insert( id.to_string( ), boxed_resolver( resolver ) );
+
+        self
+    }
+
+    fn resolve( &self, mut item : CompletionItem, context : Context, output : ResponseOutput ) {
+        let ( id, data ) = match unwrap_data( item.data.take( ) ) {
+            Some( tagged ) => tagged,
+            None => return output.send_error( untagged_item( "completion item" ) )
+        };
+        let resolver = match self.resolvers.get( &id ) {
+            Some( resolver ) => resolver,
+            None => return output.send_error( unknown_resolver( &id ) )
+        };
+        item.data = data;
+
+        output.complete_with( resolver( item, context ).map( ServerResponse::ResolveCompletionItem ) );
+    }
+
+}
+
+impl MessageHandler for CompletionResolvers {
+
+    fn handle_request( &self, service : ServiceHandle, request : ServerRequest, output : ResponseOutput ) {
+        match ResolveCompletionItemRequest::from_request( request ) {
+            Ok( item ) => {
+                let context = Context::for_request( service, ResolveCompletionItemRequest::METHOD, &output );
+
+                self.resolve( item, context, output );
+            },
+            Err( request ) => {
+                component_error!( Component::Router, "Request {} sent to CompletionResolvers.", request.method_name( ) );
+
+                output.send_error( method_not_found( request.method_name( ) ) );
+            }
+        }
+    }
+
+    fn handle_notification( &self, _service : ServiceHandle, _notification : ServerNotification ) {
+    }
+
+    fn claims_request( &self, request : &ServerRequest ) -> bool {
+        match *request {
+            ServerRequest::ResolveCompletionItem( .. ) => true,
+            _ => false
+        }
+    }
+
+}
+
+impl MethodComponent for CompletionResolvers {
+
+    fn methods( &self ) -> &[ &'static str ] {
+        COMPLETION_METHODS
+    }
+
+}
+
+fn boxed_resolver< T, F, R >( resolver : F ) -> Resolver< T >
+    where F : Fn( T, Context ) -> R + 'static,
+          R : IntoFuture< Item = T, Error = HandlerError >,
+          R::Future : Send + 'static {
+    Box::new( move | item, context | {
+        Box::new( resolver( item, context ).into_future( ).map_err( | error | error.response_error( ).clone( ) ) )
+    } )
+}
+
+fn wrap_data( resolver : &str, data : Value ) -> Value {
+    let mut wrapped = Map::new( );
+    wrapped.insert( RESOLVER_FIELD.to_string( ), Value::String( resolver.to_string( ) ) );
+    wrapped.insert( DATA_FIELD.to_string( ), data );
+
+    Value::Object( wrapped )
+}
+
+/// Splits the data of a lazy item into the id of its resolver and the data attached by its provider, None
+/// if the item was not tagged.
+fn unwrap_data( data : Option< Value > ) -> Option< ( String, Option< Value > ) > {
+    let mut data = match data {
+        Some( Value::Object( data ) ) => data,
+        _ => return None
+    };
+
+    let resolver = match data.remove( RESOLVER_FIELD ) {
+        Some( Value::String( resolver ) ) => resolver,
+        _ => return None
+    };
+
+    Some( ( resolver, data.remove( DATA_FIELD ) ) )
+}
+
+fn untagged_item( kind : &str ) -> ResponseError {
+    ResponseError {
+        code    : INVALID_PARAMS,
+        message : format!( "Cannot resolve a {} without resolver data", kind )
+    }
+}
+
+fn unknown_resolver( id : &str ) -> ResponseError {
+    ResponseError {
+        code    : INVALID_PARAMS,
+        message : format!( "Unknown resolver {}", id )
+    }
+}
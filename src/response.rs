@@ -0,0 +1,43 @@
+
+use futures::{
+    Future
+};
+use lsp_rs::{
+    INTERNAL_ERROR,
+    ResponseError,
+    ServerResponse
+};
+use service::{
+    ResponseOutput
+};
+use std::fmt::{
+    Display
+};
+
+/// Completes the request of output with the result of future, mapping errors with internal_error.
+///
+/// The future is spawned on the service's event loop, see ResponseOutput::complete_with.
+pub fn respond< F >( output : ResponseOutput, future : F )
+    where F : Future + Send + 'static, F::Item : Into< ServerResponse >, F::Error : Display {
+    respond_with( output, future, internal_error );
+}
+
+/// Completes the request of output with the result of future, converting errors to response errors with
+/// map_error.
+///
+/// Using the same mapping function for every method of a server keeps the error codes and messages sent
+/// to the client consistent.
+pub fn respond_with< F, M >( output : ResponseOutput, future : F, map_error : M )
+    where F : Future + Send + 'static, F::Item : Into< ServerResponse >, M : Fn( F::Error ) -> ResponseError + Send + 'static {
+    output.complete_with( future.map( | result | {
+        result.into( )
+    } ).map_err( map_error ) );
+}
+
+/// Default error mapping, responding with an INTERNAL_ERROR containing the Display text of the error.
+pub fn internal_error< E : Display >( error : E ) -> ResponseError {
+    ResponseError {
+        code    : INTERNAL_ERROR,
+        message : error.to_string( )
+    }
+}
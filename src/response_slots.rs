@@ -0,0 +1,733 @@
+
+use futures::{
+    Async,
+    AsyncSink,
+    Future,
+    Poll,
+    Sink,
+    StartSend,
+    Stream
+};
+use futures::task::{
+    self,
+    Task
+};
+use logging::{
+    CorrelationId
+};
+use lsp_rs::{
+    ResponseMessage,
+    ServerResponse
+};
+use queue::{
+    QueueCapacity,
+    QueueLimit
+};
+use std::mem;
+use std::sync::{
+    Arc,
+    Mutex,
+    MutexGuard
+};
+
+type Response   = ResponseMessage< ServerResponse >;
+type SharedSlab = Arc< Mutex< Slab > >;
+type SharedSlot = Arc< Mutex< Slot > >;
+
+/// Callback called with the response of a request once it is sent, see ResponseOutput::observe_response
+pub(crate) type ResponseObserver = Box< dyn FnOnce( &Response ) + Send >;
+
+/// Slab of reusable slots holding the responses of the requests being handled by a service
+///
+/// Every request takes a free slot, released once its response was taken out, instead of allocating a
+/// oneshot channel. The slots of the requests handed to the ResponseWriter are linked in the order the
+/// requests were received, forming the response queue without allocating a node per request either.
+///
+/// Every slot has its own lock, answering, canceling or observing a request only locks the slot of the
+/// request. The slab is locked to allocate, queue and release slots, by the reader and the ResponseWriter.
+struct Slab {
+    slots        : Vec< SharedSlot >,
+    /// Slot of the request queued after the request of each slot
+    next         : Vec< Option< usize > >,
+    free         : Vec< usize >,
+    head         : Option< usize >,
+    tail         : Option< usize >,
+    queued       : usize,
+    limit        : QueueLimit,
+    /// Reader waiting for room in the queue
+    reader_task  : Option< Task >,
+    /// Writer waiting for a request to be queued
+    writer_task  : Option< Task >,
+    reader_alive : bool,
+    writer_alive : bool
+}
+
+struct Slot {
+    /// Incremented every time the slot is released, so handles of a previous request cannot touch it
+    generation     : u64,
+    state          : SlotState,
+    /// Set once a ResponseSlot was taken, at most one response can be sent per request
+    taken          : bool,
+    /// Set once the client canceled the request
+    canceled       : bool,
+    request_id     : i64,
+    correlation_id : CorrelationId,
+    method         : &'static str,
+    /// Task of the ResponseOutput polling for the request to be closed or canceled
+    sender_task    : Option< Task >,
+    /// Task waiting for the response
+    receiver_task  : Option< Task >,
+    /// Called with the response once sent, cleared on release so the next request reuses the storage
+    observers      : Vec< ResponseObserver >
+}
+
+enum SlotState {
+    Vacant,
+    /// Waiting for the response of the request
+    Pending,
+    Completed( Response ),
+    /// The request was dropped without a response
+    Canceled,
+    /// The response will never be taken out, its PendingResponse was dropped
+    Closed
+}
+
+/// Reader's end of the response queue, allocating the slots of the requests
+pub(crate) struct SlotQueueSend {
+    slab : SharedSlab
+}
+
+/// ResponseWriter's end of the response queue, yielding pending requests in the order they were queued
+pub(crate) struct SlotQueueRead {
+    slab : SharedSlab
+}
+
+/// Handle to the response slot of a request, shared by its ResponseOutput and the service
+#[derive( Clone )]
+pub(crate) struct ResponseSender {
+    slab       : SharedSlab,
+    slot       : SharedSlot,
+    index      : usize,
+    generation : u64
+}
+
+/// Right to send the response of a request, taken out of a ResponseSender
+///
+/// Dropping it without sending a response cancels the request, its PendingResponse resolves with an error.
+pub(crate) struct ResponseSlot {
+    sender : Option< ResponseSender >
+}
+
+/// Request waiting for its response, resolving with the response once sent or with an error if the request
+/// was canceled without a response
+pub(crate) struct PendingResponse {
+    pub request_id     : i64,
+    pub correlation_id : CorrelationId,
+    pub method         : &'static str,
+
+    slab               : SharedSlab,
+    slot               : SharedSlot,
+    index              : usize,
+    /// Cleared once the response was taken out or the request queued, when the slot is no longer owned
+    armed              : bool
+}
+
+/// Creates the slots of the requests of a service, returning both ends of the queue the reader hands
+/// pending requests to the ResponseWriter through, holding as many requests as capacity allows.
+pub(crate) fn response_queue( capacity : QueueCapacity ) -> ( SlotQueueSend, SlotQueueRead ) {
+    let slab = Arc::new( Mutex::new( Slab {
+        slots        : Vec::new( ),
+        next         : Vec::new( ),
+        free         : Vec::new( ),
+        head         : None,
+        tail         : None,
+        queued       : 0,
+        limit        : QueueLimit::new( capacity ),
+        reader_task  : None,
+        writer_task  : None,
+        reader_alive : true,
+        writer_alive : true
+    } ) );
+
+    ( SlotQueueSend { slab : slab.clone( ) }, SlotQueueRead { slab : slab } )
+}
+
+impl Slab {
+
+    fn allocate( &mut self, request_id : i64, correlation_id : CorrelationId, method : &'static str ) -> ( usize, SharedSlot, u64 ) {
+        let index = match self.free.pop( ) {
+            Some( index ) => index,
+            None => {
+                self.slots.push( Arc::new( Mutex::new( Slot {
+                    generation     : 0,
+                    state          : SlotState::Vacant,
+                    taken          : false,
+                    canceled       : false,
+                    request_id     : 0,
+                    correlation_id : correlation_id,
+                    method         : method,
+                    sender_task    : None,
+                    receiver_task  : None,
+                    observers      : Vec::new( )
+                } ) ) );
+                self.next.push( None );
+
+                self.slots.len( ) - 1
+            }
+        };
+
+        let slot = self.slots[ index ].clone( );
+        let generation = {
+            let mut slot = slot.lock( ).unwrap( );
+            slot.state = SlotState::Pending;
+            slot.taken = false;
+            slot.canceled = false;
+            slot.request_id = request_id;
+            slot.correlation_id = correlation_id;
+            slot.method = method;
+
+            slot.generation
+        };
+
+        ( index, slot, generation )
+    }
+
+    fn release( &mut self, index : usize ) {
+        {
+            let mut slot = self.slots[ index ].lock( ).unwrap( );
+            slot.generation += 1;
+            slot.state = SlotState::Vacant;
+            slot.sender_task = None;
+            slot.receiver_task = None;
+            slot.observers.clear( );
+        }
+
+        self.next[ index ] = None;
+        self.free.push( index );
+    }
+
+    /// Marks the response of a slot no longer owned by a PendingResponse as never taken out, releasing it
+    /// right away if the request is already answered.
+    fn close( &mut self, index : usize ) {
+        let answered = {
+            let mut guard = self.slots[ index ].lock( ).unwrap( );
+            let slot = &mut *guard;
+            match slot.state {
+                SlotState::Pending => {
+                    slot.state = SlotState::Closed;
+                    if let Some( task ) = slot.sender_task.take( ) {
+                        task.notify( );
+                    }
+
+                    false
+                },
+                _ => true
+            }
+        };
+
+        if answered {
+            self.release( index );
+        }
+    }
+
+}
+
+impl SlotQueueSend {
+
+    /// Allocates the response slot of a request, returning the handle to answer the request with and the
+    /// request waiting for its response.
+    pub fn insert( &self, request_id : i64, correlation_id : CorrelationId, method : &'static str ) -> ( ResponseSender, PendingResponse ) {
+        let ( index, slot, generation ) = self.slab.lock( ).unwrap( ).allocate( request_id, correlation_id, method );
+
+        let sender = ResponseSender {
+            slab       : self.slab.clone( ),
+            slot       : slot.clone( ),
+            index      : index,
+            generation : generation
+        };
+        let pending = PendingResponse {
+            request_id     : request_id,
+            correlation_id : correlation_id,
+            method         : method,
+
+            slab           : self.slab.clone( ),
+            slot           : slot,
+            index          : index,
+            armed          : true
+        };
+
+        ( sender, pending )
+    }
+
+}
+
+impl Sink for SlotQueueSend {
+
+    type SinkItem  = PendingResponse;
+    type SinkError = ( );
+
+    fn start_send( &mut self, mut request : PendingResponse ) -> StartSend< Self::SinkItem, Self::SinkError > {
+        let mut slab = self.slab.lock( ).unwrap( );
+        if !slab.writer_alive {
+            return Err( ( ) );
+        }
+        let queued = slab.queued;
+        if !slab.limit.has_room( queued ) {
+            slab.reader_task = Some( task::current( ) );
+
+            return Ok( AsyncSink::NotReady( request ) );
+        }
+
+        // The queue owns the slot from now on
+        request.armed = false;
+        match slab.tail {
+            Some( tail ) => slab.next[ tail ] = Some( request.index ),
+            None => slab.head = Some( request.index )
+        }
+        slab.tail = Some( request.index );
+        slab.queued += 1;
+
+        if let Some( task ) = slab.writer_task.take( ) {
+            task.notify( );
+        }
+
+        Ok( AsyncSink::Ready )
+    }
+
+    fn poll_complete( &mut self ) -> Poll< ( ), Self::SinkError > {
+        Ok( Async::Ready( ( ) ) )
+    }
+
+}
+
+impl Drop for SlotQueueSend {
+
+    fn drop( &mut self ) {
+        let mut slab = self.slab.lock( ).unwrap( );
+        slab.reader_alive = false;
+
+        if let Some( task ) = slab.writer_task.take( ) {
+            task.notify( );
+        }
+    }
+
+}
+
+impl Stream for SlotQueueRead {
+
+    type Item  = PendingResponse;
+    type Error = ( );
+
+    fn poll( &mut self ) -> Poll< Option< Self::Item >, Self::Error > {
+        let mut slab = self.slab.lock( ).unwrap( );
+        let index = match slab.head {
+            Some( index ) => index,
+            None if !slab.reader_alive => return Ok( Async::Ready( None ) ),
+            None => {
+                slab.writer_task = Some( task::current( ) );
+
+                return Ok( Async::NotReady );
+            }
+        };
+
+        slab.head = slab.next[ index ].take( );
+        if slab.head.is_none( ) {
+            slab.tail = None;
+        }
+        slab.queued -= 1;
+        if slab.queued == 0 {
+            slab.limit.drained( );
+        }
+
+        if let Some( task ) = slab.reader_task.take( ) {
+            task.notify( );
+        }
+
+        let slot = slab.slots[ index ].clone( );
+        let ( request_id, correlation_id, method ) = {
+            let slot = slot.lock( ).unwrap( );
+
+            ( slot.request_id, slot.correlation_id, slot.method )
+        };
+        Ok( Async::Ready( Some( PendingResponse {
+            request_id     : request_id,
+            correlation_id : correlation_id,
+            method         : method,
+
+            slab           : self.slab.clone( ),
+            slot           : slot,
+            index          : index,
+            armed          : true
+        } ) ) )
+    }
+
+}
+
+impl Drop for SlotQueueRead {
+
+    fn drop( &mut self ) {
+        let mut slab = self.slab.lock( ).unwrap( );
+        slab.writer_alive = false;
+
+        // Responses of the queued requests will never be written
+        let mut next = slab.head.take( );
+        while let Some( index ) = next {
+            next = slab.next[ index ].take( );
+            slab.close( index );
+        }
+        slab.tail = None;
+        slab.queued = 0;
+
+        if let Some( task ) = slab.reader_task.take( ) {
+            task.notify( );
+        }
+    }
+
+}
+
+impl ResponseSender {
+
+    /// Takes the right to send the response of the request, None if it was already taken.
+    pub fn take( &self ) -> Option< ResponseSlot > {
+        {
+            let mut slot = match self.lock( ) {
+                Some( slot ) => slot,
+                None => return None
+            };
+            if slot.taken {
+                return None;
+            }
+            slot.taken = true;
+        }
+
+        Some( ResponseSlot {
+            sender : Some( self.clone( ) )
+        } )
+    }
+
+    /// Returns true once the response of the request was taken, or will never be written because the
+    /// service was shutdown.
+    pub fn is_closed( &self ) -> bool {
+        match self.lock( ) {
+            Some( slot ) => match slot.state {
+                SlotState::Pending => slot.taken,
+                _ => true
+            },
+            None => true
+        }
+    }
+
+    /// Returns true if the client canceled the request.
+    pub fn is_canceled( &self ) -> bool {
+        self.lock( ).map_or( false, | slot | slot.canceled )
+    }
+
+    /// Marks the request as canceled by the client, notifying the task polling for its cancellation.
+    pub fn cancel( &self ) {
+        if let Some( mut slot ) = self.lock( ) {
+            slot.canceled = true;
+
+            if let Some( task ) = slot.sender_task.take( ) {
+                task.notify( );
+            }
+        }
+    }
+
+    /// Polls whether the request was canceled or closed, see is_canceled and is_closed, scheduling the
+    /// current task to be notified once it is.
+    pub fn poll_cancel( &self ) -> Poll< ( ), ( ) > {
+        let mut slot = match self.lock( ) {
+            Some( slot ) => slot,
+            None => return Ok( Async::Ready( ( ) ) )
+        };
+
+        let pending = match slot.state {
+            SlotState::Pending => !slot.taken && !slot.canceled,
+            _ => false
+        };
+        if !pending {
+            return Ok( Async::Ready( ( ) ) );
+        }
+
+        slot.sender_task = Some( task::current( ) );
+        Ok( Async::NotReady )
+    }
+
+    /// Adds an observer called with the response of the request by notify_observers.
+    pub fn observe( &self, observer : ResponseObserver ) {
+        if let Some( mut slot ) = self.lock( ) {
+            slot.observers.push( observer );
+        }
+    }
+
+    /// Calls the observers of the request with its response.
+    ///
+    /// Observers are called with the slot locked and must not use the ResponseOutput of the request.
+    pub fn notify_observers( &self, response : &Response ) {
+        if let Some( mut slot ) = self.lock( ) {
+            for observer in slot.observers.drain( .. ) {
+                observer( response );
+            }
+        }
+    }
+
+    /// Locks the slot of the request, None if the slot was released and now belongs to another request.
+    fn lock( &self ) -> Option< MutexGuard< Slot > > {
+        let slot = self.slot.lock( ).unwrap( );
+        if slot.generation == self.generation {
+            Some( slot )
+        }
+        else {
+            None
+        }
+    }
+
+    /// Answers the request, releasing the slot if its response will never be taken out.
+    fn answer( &self, state : SlotState ) {
+        let closed = match self.lock( ) {
+            Some( mut guard ) => {
+                let slot = &mut *guard;
+                match slot.state {
+                    SlotState::Pending => {
+                        slot.state = state;
+                        if let Some( task ) = slot.receiver_task.take( ) {
+                            task.notify( );
+                        }
+
+                        false
+                    },
+                    SlotState::Closed => true,
+                    _ => false
+                }
+            },
+            None => false
+        };
+
+        // No PendingResponse owns a closed slot, the slab is only locked once the slot is unlocked
+        if closed {
+            self.slab.lock( ).unwrap( ).release( self.index );
+        }
+    }
+
+}
+
+impl ResponseSlot {
+
+    /// Sends the response of the request, discarded if the service was shutdown.
+    pub fn complete( mut self, response : Response ) {
+        if let Some( sender ) = self.sender.take( ) {
+            sender.answer( SlotState::Completed( response ) );
+        }
+    }
+
+}
+
+impl Drop for ResponseSlot {
+
+    fn drop( &mut self ) {
+        if let Some( sender ) = self.sender.take( ) {
+            sender.answer( SlotState::Canceled );
+        }
+    }
+
+}
+
+impl Future for PendingResponse {
+
+    type Item  = Response;
+    type Error = ( );
+
+    fn poll( &mut self ) -> Poll< Self::Item, Self::Error > {
+        if !self.armed {
+            return Err( ( ) );
+        }
+
+        let result = {
+            let mut slot = self.slot.lock( ).unwrap( );
+            match mem::replace( &mut slot.state, SlotState::Vacant ) {
+                SlotState::Pending => {
+                    slot.state = SlotState::Pending;
+                    slot.receiver_task = Some( task::current( ) );
+
+                    return Ok( Async::NotReady );
+                },
+                SlotState::Completed( response ) => Ok( Async::Ready( response ) ),
+                _ => Err( ( ) )
+            }
+        };
+
+        self.armed = false;
+        self.slab.lock( ).unwrap( ).release( self.index );
+
+        result
+    }
+
+}
+
+impl Drop for PendingResponse {
+
+    fn drop( &mut self ) {
+        if self.armed {
+            self.slab.lock( ).unwrap( ).close( self.index );
+        }
+    }
+
+}
+
+#[cfg( test )]
+mod tests {
+
+    use futures::{
+        future,
+        Async,
+        AsyncSink,
+        Future,
+        Sink,
+        Stream
+    };
+    use futures::executor::{
+        self,
+        Notify
+    };
+    use logging::{
+        CorrelationId
+    };
+    use lsp_rs::{
+        ResponseMessage,
+        ServerResponse
+    };
+    use queue::{
+        QueueCapacity
+    };
+    use std::sync::{
+        Arc
+    };
+    use super::{
+        response_queue,
+        PendingResponse,
+        Response
+    };
+
+    struct NoopNotify;
+
+    impl Notify for NoopNotify {
+
+        fn notify( &self, _ : usize ) {
+        }
+
+    }
+
+    fn response( id : i64 ) -> Response {
+        ResponseMessage {
+            id     : id,
+            result : Some( ServerResponse::Shutdown ),
+            error  : None
+        }
+    }
+
+    /// Polls f once from a task, so futures that are not ready can register it.
+    fn poll< T, F : FnMut( ) -> Async< T > >( mut f : F ) -> Async< T > {
+        let mut task = executor::spawn( future::poll_fn( || Ok::< _, ( ) >( f( ) ) ) );
+
+        task.poll_future_notify( &Arc::new( NoopNotify ), 0 ).unwrap( )
+    }
+
+    /// Polls pending once, returning the id of its response, None if the request was canceled.
+    fn poll_response( pending : &mut PendingResponse ) -> Async< Option< i64 > > {
+        poll( | | match pending.poll( ) {
+            Ok( Async::Ready( response ) ) => Async::Ready( Some( response.id ) ),
+            Ok( Async::NotReady ) => Async::NotReady,
+            Err( _ ) => Async::Ready( None )
+        } )
+    }
+
+    #[test]
+    fn released_slots_are_reused( ) {
+        let ( send, _read ) = response_queue( QueueCapacity::Unbounded );
+
+        let ( first, mut first_pending ) = send.insert( 1, CorrelationId::next( ), "textDocument/hover" );
+        first.take( ).unwrap( ).complete( response( 1 ) );
+        assert_eq!( poll_response( &mut first_pending ), Async::Ready( Some( 1 ) ) );
+
+        let ( second, _second_pending ) = send.insert( 2, CorrelationId::next( ), "textDocument/hover" );
+        assert_eq!( second.index, first.index );
+        assert!( second.generation != first.generation );
+        assert_eq!( send.slab.lock( ).unwrap( ).slots.len( ), 1 );
+    }
+
+    #[test]
+    fn stale_senders_cannot_answer_the_request_reusing_their_slot( ) {
+        let ( send, _read ) = response_queue( QueueCapacity::Unbounded );
+
+        let ( stale, mut stale_pending ) = send.insert( 1, CorrelationId::next( ), "textDocument/hover" );
+        stale.take( ).unwrap( ).complete( response( 1 ) );
+        assert_eq!( poll_response( &mut stale_pending ), Async::Ready( Some( 1 ) ) );
+
+        let ( current, mut current_pending ) = send.insert( 2, CorrelationId::next( ), "textDocument/hover" );
+        assert!( stale.take( ).is_none( ) );
+        assert!( stale.is_closed( ) );
+        stale.cancel( );
+        assert!( !current.is_canceled( ) );
+        assert!( !current.is_closed( ) );
+        assert_eq!( poll_response( &mut current_pending ), Async::NotReady );
+
+        current.take( ).unwrap( ).complete( response( 2 ) );
+        assert_eq!( poll_response( &mut current_pending ), Async::Ready( Some( 2 ) ) );
+    }
+
+    #[test]
+    fn response_can_only_be_taken_once( ) {
+        let ( send, _read ) = response_queue( QueueCapacity::Unbounded );
+
+        let ( sender, mut pending ) = send.insert( 1, CorrelationId::next( ), "textDocument/hover" );
+        let slot = sender.take( ).unwrap( );
+        assert!( sender.take( ).is_none( ) );
+        assert!( sender.is_closed( ) );
+
+        drop( slot );
+        assert_eq!( poll_response( &mut pending ), Async::Ready( None ) );
+    }
+
+    #[test]
+    fn queued_requests_are_read_in_order_within_the_capacity( ) {
+        let ( mut send, mut read ) = response_queue( QueueCapacity::Bounded( 2 ) );
+
+        let mut senders = Vec::new( );
+        for id in 1..4 {
+            let ( sender, pending ) = send.insert( id, CorrelationId::next( ), "textDocument/hover" );
+            senders.push( sender );
+
+            let mut pending = Some( pending );
+            let queued = poll( | | match send.start_send( pending.take( ).unwrap( ) ).unwrap( ) {
+                AsyncSink::Ready => Async::Ready( None ),
+                AsyncSink::NotReady( pending ) => Async::Ready( Some( pending ) )
+            } );
+            match queued {
+                Async::Ready( None ) => assert!( id <= 2 ),
+                Async::Ready( Some( _ ) ) => assert_eq!( id, 3 ),
+                Async::NotReady => unreachable!( )
+            }
+        }
+
+        let mut read_ids = Vec::new( );
+        while let Async::Ready( Some( request ) ) = poll( | | read.poll( ).unwrap( ) ) {
+            read_ids.push( request.request_id );
+        }
+        assert_eq!( read_ids, vec![ 1, 2 ] );
+    }
+
+    #[test]
+    fn answering_a_request_whose_response_is_never_taken_releases_its_slot( ) {
+        let ( send, read ) = response_queue( QueueCapacity::Unbounded );
+
+        let ( sender, pending ) = send.insert( 1, CorrelationId::next( ), "textDocument/hover" );
+        drop( read );
+        drop( pending );
+        assert!( sender.is_closed( ) );
+        assert!( send.slab.lock( ).unwrap( ).free.is_empty( ) );
+
+        sender.take( ).unwrap( ).complete( response( 1 ) );
+        assert_eq!( send.slab.lock( ).unwrap( ).free, vec![ 0 ] );
+    }
+
+}
@@ -0,0 +1,773 @@
+
+use composite::{
+    MethodComponent
+};
+use context::{
+    Context
+};
+use futures::{
+    Future,
+    IntoFuture
+};
+use futures::future::{
+    Shared
+};
+use handler::{
+    method_not_found
+};
+use logging::{
+    Component
+};
+use lsp_rs::{
+    INVALID_REQUEST,
+    ClientCapabilities,
+    CodeLensOptions,
+    CompletionOptions,
+    InitializeParams,
+    InitializeResult,
+    ResponseError,
+    ServerCapabilities,
+    ServerNotification,
+    ServerRequest,
+    ServerResponse,
+    SignatureHelpOptions,
+    TextDocumentSyncKind,
+    TextEdit,
+    WillSaveTextDocumentParams
+};
+use method::{
+    self,
+    MethodName,
+    NotificationMethod,
+    RequestMethod
+};
+use service::{
+    SERVER_NOT_INITIALIZED,
+    MessageHandler,
+    ResponseOutput,
+    ServiceHandle
+};
+use std::collections::{
+    HashMap
+};
+use std::sync::{
+    Arc,
+    Mutex
+};
+use std::time::{
+    Duration
+};
+
+type RequestRoute      = Box< dyn Fn( ServerRequest, ServiceHandle, ResponseOutput ) >;
+type NotificationRoute = Box< dyn Fn( ServerNotification, ServiceHandle ) >;
+type SharedResponse    = Shared< Box< dyn Future< Item = ServerResponse, Error = ResponseError > + Send > >;
+
+/// Computations in flight for a method registered with Router::on_coalesced_request, keyed by the
+/// coalescing key of their request
+#[derive( Default )]
+struct InFlightRequests {
+    next_id  : u64,
+    requests : HashMap< String, ( u64, SharedResponse ) >
+}
+
+/// Precondition checked by a Router before dispatching a request to its handler, see Router::guard
+///
+/// Requests failing a guard are answered with an error without calling the handler.
+pub enum Guard {
+    /// The initialize request was received, answers with SERVER_NOT_INITIALIZED otherwise
+    Initialized,
+    /// The document the request operates on is open in the client, answers with INVALID_REQUEST
+    /// otherwise. Requests not tied to a document always pass.
+    DocumentOpen,
+    /// The client capabilities sent with the initialize request satisfy the predicate, answers with
+    /// INVALID_REQUEST otherwise
+    Capability( Box< dyn Fn( &ClientCapabilities ) -> bool > )
+}
+
+/// MessageHandler that dispatches requests and notifications to handlers registered per method
+///
+/// Requests for methods without a registered handler are answered with a METHOD_NOT_FOUND error,
+/// notifications without a registered handler are ignored.
+///
+/// ```ignore
+/// let router = Router::new( )
+///     .on_request::< HoverRequest, _ >( | params, ctx, output | {
+///         ...
+///     } )
+///     .on_notification::< DidOpenTextDocumentNotification, _ >( | params, ctx | {
+///         ...
+///     } );
+/// ```
+///
+/// Requests on a document can also be routed by the languageId the client opened the document with, see
+/// Router::on_language_request.
+pub struct Router {
+    requests          : HashMap< &'static str, RequestRoute >,
+    language_requests : HashMap< &'static str, HashMap< String, RequestRoute > >,
+    notifications     : HashMap< &'static str, NotificationRoute >,
+    guards            : HashMap< &'static str, Vec< Guard > >,
+    methods           : Vec< &'static str >,
+
+    answer_initialize : bool
+}
+
+/// Creates a Router from a list of method names and the functions handling them.
+///
+/// Request handlers are called with the request parameters, a Context and the ResponseOutput for the
+/// request. Notification handlers are called with the notification parameters and a Context. Unless
+/// an `"initialize"` handler is given, the router answers the initialize request with the capabilities
+/// implied by the listed methods, see Router::server_capabilities.
+///
+/// ```ignore
+/// let handler = lsp_handlers! {
+///     "textDocument/hover"  => hover,
+///     "textDocument/didOpen" => did_open
+/// };
+/// ```
+#[macro_export]
+macro_rules! lsp_handlers {
+    (
+        $( $method : tt => $handler : expr ),* $( , )*
+    ) => {
+        {
+            let router = $crate::router::Router::new( ).advertise_capabilities( );
+            $(
+                let router = lsp_handlers!( @register router, $method, $handler );
+            )*
+            router
+        }
+    };
+    ( @register $router : ident, "initialize", $handler : expr ) => { $router.on_request::< $crate::method::InitializeRequest, _ >( $handler ) };
+    ( @register $router : ident, "shutdown", $handler : expr ) => { $router.on_request::< $crate::method::ShutdownRequest, _ >( $handler ) };
+    ( @register $router : ident, "textDocument/completion", $handler : expr ) => { $router.on_request::< $crate::method::CompletionRequest, _ >( $handler ) };
+    ( @register $router : ident, "completionItem/resolve", $handler : expr ) => { $router.on_request::< $crate::method::ResolveCompletionItemRequest, _ >( $handler ) };
+    ( @register $router : ident, "textDocument/hover", $handler : expr ) => { $router.on_request::< $crate::method::HoverRequest, _ >( $handler ) };
+    ( @register $router : ident, "textDocument/signatureHelp", $handler : expr ) => { $router.on_request::< $crate::method::SignatureHelpRequest, _ >( $handler ) };
+    ( @register $router : ident, "textDocument/definition", $handler : expr ) => { $router.on_request::< $crate::method::GotoDefinitionRequest, _ >( $handler ) };
+    ( @register $router : ident, "textDocument/references", $handler : expr ) => { $router.on_request::< $crate::method::FindReferencesRequest, _ >( $handler ) };
+    ( @register $router : ident, "textDocument/documentHighlight", $handler : expr ) => { $router.on_request::< $crate::method::DocumentHighlightRequest, _ >( $handler ) };
+    ( @register $router : ident, "textDocument/documentSymbol", $handler : expr ) => { $router.on_request::< $crate::method::DocumentSymbolsRequest, _ >( $handler ) };
+    ( @register $router : ident, "workspace/symbol", $handler : expr ) => { $router.on_request::< $crate::method::WorkspaceSymbolsRequest, _ >( $handler ) };
+    ( @register $router : ident, "textDocument/codeAction", $handler : expr ) => { $router.on_request::< $crate::method::CodeActionRequest, _ >( $handler ) };
+    ( @register $router : ident, "textDocument/codeLens", $handler : expr ) => { $router.on_request::< $crate::method::CodeLensRequest, _ >( $handler ) };
+    ( @register $router : ident, "codeAction/resolve", $handler : expr ) => { $router.on_request::< $crate::method::ResolveCodeActionRequest, _ >( $handler ) };
+    ( @register $router : ident, "codeLens/resolve", $handler : expr ) => { $router.on_request::< $crate::method::ResolveCodeLensRequest, _ >( $handler ) };
+    ( @register $router : ident, "textDocument/formatting", $handler : expr ) => { $router.on_request::< $crate::method::FormattingRequest, _ >( $handler ) };
+    ( @register $router : ident, "textDocument/rangeFormatting", $handler : expr ) => { $router.on_request::< $crate::method::RangeFormattingRequest, _ >( $handler ) };
+    ( @register $router : ident, "textDocument/onTypeFormatting", $handler : expr ) => { $router.on_request::< $crate::method::OnTypeFormattingRequest, _ >( $handler ) };
+    ( @register $router : ident, "textDocument/rename", $handler : expr ) => { $router.on_request::< $crate::method::RenameRequest, _ >( $handler ) };
+    ( @register $router : ident, "textDocument/willSaveWaitUntil", $handler : expr ) => { $router.on_request::< $crate::method::WillSaveWaitUntilRequest, _ >( $handler ) };
+    ( @register $router : ident, "workspace/executeCommand", $handler : expr ) => { $router.on_request::< $crate::method::ExecuteCommandRequest, _ >( $handler ) };
+    ( @register $router : ident, "initialized", $handler : expr ) => { $router.on_notification::< $crate::method::InitializedNotification, _ >( $handler ) };
+    ( @register $router : ident, "exit", $handler : expr ) => { $router.on_notification::< $crate::method::ExitNotification, _ >( $handler ) };
+    ( @register $router : ident, "$/cancelRequest", $handler : expr ) => { $router.on_notification::< $crate::method::CancelNotification, _ >( $handler ) };
+    ( @register $router : ident, "$/setTrace", $handler : expr ) => { $router.on_notification::< $crate::method::SetTraceNotification, _ >( $handler ) };
+    ( @register $router : ident, "workspace/didChangeConfiguration", $handler : expr ) => { $router.on_notification::< $crate::method::DidChangeConfigurationNotification, _ >( $handler ) };
+    ( @register $router : ident, "textDocument/didOpen", $handler : expr ) => { $router.on_notification::< $crate::method::DidOpenTextDocumentNotification, _ >( $handler ) };
+    ( @register $router : ident, "textDocument/didChange", $handler : expr ) => { $router.on_notification::< $crate::method::DidChangeTextDocumentNotification, _ >( $handler ) };
+    ( @register $router : ident, "textDocument/didClose", $handler : expr ) => { $router.on_notification::< $crate::method::DidCloseTextDocumentNotification, _ >( $handler ) };
+    ( @register $router : ident, "textDocument/didSave", $handler : expr ) => { $router.on_notification::< $crate::method::DidSaveTextDocumentNotification, _ >( $handler ) };
+    ( @register $router : ident, "workspace/didChangeWatchedFiles", $handler : expr ) => { $router.on_notification::< $crate::method::DidChangeWatchedFilesNotification, _ >( $handler ) };
+    ( @register $router : ident, "window/workDoneProgress/cancel", $handler : expr ) => { $router.on_notification::< $crate::method::WorkDoneProgressCancelNotification, _ >( $handler ) };
+    ( @register $router : ident, "workspace/didChangeWorkspaceFolders", $handler : expr ) => { $router.on_notification::< $crate::method::DidChangeWorkspaceFoldersNotification, _ >( $handler ) };
+    ( @register $router : ident, $method : tt, $handler : expr ) => {
+        compile_error!( concat!( "lsp_handlers!: unknown method ", $method ) )
+    };
+}
+
+impl Router {
+
+    /// Creates a new router without any registered handlers.
+    pub fn new( ) -> Self {
+        Router {
+            requests          : HashMap::new( ),
+            language_requests : HashMap::new( ),
+            notifications     : HashMap::new( ),
+            guards            : HashMap::new( ),
+            methods           : Vec::new( ),
+
+            answer_initialize : false
+        }
+    }
+
+    /// Answers the initialize request with the result of Router::server_capabilities, unless a handler for
+    /// initialize is registered.
+    pub fn advertise_capabilities( mut self ) -> Self {
+        self.answer_initialize = true;
+
+        self
+    }
+
+    /// Returns the server capabilities implied by the registered handlers.
+    ///
+    /// Only capabilities that can be expressed without extra options are advertised, servers that need
+    /// trigger characters should handle initialize themselves. Documents are synchronized in full, unless the
+    /// router is wrapped by document::with_documents which applies incremental changes.
+    pub fn server_capabilities( &self ) -> ServerCapabilities {
+        implied_capabilities( | method | {
+            self.requests.contains_key( method ) || self.language_requests.contains_key( method ) ||
+                self.notifications.contains_key( method )
+        } )
+    }
+
+    /// Registers the handler for requests of method R, replacing any handler previously registered for it.
+    pub fn on_request< R, F >( mut self, handler : F ) -> Self
+        where R : RequestMethod + 'static, F : Fn( R::Params, Context, ResponseOutput ) + 'static {
+        self.add_method( R::METHOD );
+        self.requests.insert( R::METHOD, request_route::< R, F >( handler ) );
+
+        self
+    }
+
+    /// Registers the handler for requests of method R on documents opened with language_id, replacing any
+    /// handler previously registered for the method and language.
+    ///
+    /// Requests on a document are dispatched to the handler registered for the languageId the client sent
+    /// in textDocument/didOpen, falling back to the handler registered with Router::on_request for documents
+    /// of other languages, documents that are not open and requests not tied to a document. Without a
+    /// fallback handler, such requests are answered with a METHOD_NOT_FOUND error.
+    ///
+    /// ```ignore
+    /// let router = Router::new( )
+    ///     .on_language_request::< HoverRequest, _ >( "typescript", typescript::hover )
+    ///     .on_language_request::< HoverRequest, _ >( "css", css::hover )
+    ///     .on_request::< HoverRequest, _ >( html::hover );
+    /// ```
+    pub fn on_language_request< R, F >( mut self, language_id : &str, handler : F ) -> Self
+        where R : RequestMethod + 'static, F : Fn( R::Params, Context, ResponseOutput ) + 'static {
+        self.add_method( R::METHOD );
+        self.language_requests.entry( R::METHOD ).or_insert_with( HashMap::new )
+            .insert( language_id.to_string( ), request_route::< R, F >( handler ) );
+
+        self
+    }
+
+    /// Adds a guard checked before requests of method R are dispatched to their handler. Guards are checked
+    /// in the order they were added.
+    ///
+    /// ```ignore
+    /// let router = Router::new( )
+    ///     .on_request::< HoverRequest, _ >( hover )
+    ///     .guard::< HoverRequest >( Guard::Initialized )
+    ///     .guard::< HoverRequest >( Guard::DocumentOpen );
+    /// ```
+    pub fn guard< R : RequestMethod >( mut self, guard : Guard ) -> Self {
+        self.guards.entry( R::METHOD ).or_insert_with( Vec::new ).push( guard );
+
+        self
+    }
+
+    /// Registers the handler for requests of method R, coalescing identical concurrent requests into a single
+    /// computation.
+    ///
+    /// key returns the coalescing key of a request, None if the request must not be coalesced. While the
+    /// computation for a key is in flight, requests with the same key do not call the handler and receive
+    /// the result of the computation once it completes. Canceling one of the coalesced requests does not
+    /// cancel the computation while other requests still wait on it. The key should identify the version of
+    /// the document the request reads, so requests for different versions are never coalesced:
+    ///
+    /// ```ignore
+    /// let router = Router::new( )
+    ///     .on_coalesced_request::< DocumentSymbolsRequest, _, _, _ >( move | params, _ | {
+    ///         documents.get( &params.text_document.uri ).map( | document | {
+    ///             format!( "{}@{}", document.uri( ), document.version( ) )
+    ///         } )
+    ///     }, document_symbols );
+    /// ```
+    pub fn on_coalesced_request< R, K, F, T >( self, key : K, handler : F ) -> Self
+        where R : RequestMethod + 'static,
+              K : Fn( &R::Params, &Context ) -> Option< String > + 'static,
+              F : Fn( R::Params, Context ) -> T + 'static,
+              T : IntoFuture< Item = ServerResponse, Error = ResponseError >,
+              T::Future : Send + 'static {
+        let in_flight = Arc::new( Mutex::new( InFlightRequests::default( ) ) );
+
+        self.on_request::< R, _ >( move | params, context, output | {
+            let key = match key( &params, &context ) {
+                Some( key ) => key,
+                None => return output.complete_with( handler( params, context ).into_future( ) )
+            };
+
+            let ( id, response ) = {
+                let mut in_flight = in_flight.lock( ).unwrap( );
+                match in_flight.requests.get( &key ).cloned( ) {
+                    Some( ( id, response ) ) => {
+                        component_trace!( Component::Router, "Coalescing request {} with the request in flight for {}.", R::METHOD, key );
+
+                        ( id, response )
+                    },
+                    None => {
+                        let future : Box< dyn Future< Item = ServerResponse, Error = ResponseError > + Send > = Box::new( handler( params, context ).into_future( ) );
+                        let response = future.shared( );
+
+                        in_flight.next_id += 1;
+                        let id = in_flight.next_id;
+                        in_flight.requests.insert( key.clone( ), ( id, response.clone( ) ) );
+
+                        ( id, response )
+                    }
+                }
+            };
+
+            let in_flight = in_flight.clone( );
+            output.complete_with( response.then( move | result | {
+                {
+                    // A later computation may have been registered under the same key once this one completed
+                    let mut in_flight = in_flight.lock( ).unwrap( );
+                    if in_flight.requests.get( &key ).map( | &( current, _ ) | current == id ).unwrap_or( false ) {
+                        in_flight.requests.remove( &key );
+                    }
+                }
+
+                match result {
+                    Ok( response ) => Ok( ( *response ).clone( ) ),
+                    Err( error ) => Err( ( *error ).clone( ) )
+                }
+            } ) );
+        } )
+    }
+
+    /// Registers the handler for textDocument/willSaveWaitUntil requests. The edits returned by the handler
+    /// are sent to the client if they are computed within deadline, otherwise the request is answered with
+    /// an empty list of edits so the client does not drop the save.
+    pub fn on_will_save_wait_until< F, R >( self, deadline : Duration, handler : F ) -> Self
+        where F : Fn( WillSaveTextDocumentParams, Context ) -> R + 'static, R : IntoFuture< Item = Vec< TextEdit >, Error = ResponseError >, R::Future : Send + 'static {
+        self.on_request::< method::WillSaveWaitUntilRequest, _ >( move | params, context, output | {
+            let edits = handler( params, context ).into_future( ).map( ServerResponse::WillSaveWaitUntil );
+
+            output.complete_with_deadline( edits, deadline, ServerResponse::WillSaveWaitUntil( Vec::new( ) ) );
+        } )
+    }
+
+    /// Registers the handler for notifications of method N, replacing any handler previously registered for
+    /// it.
+    pub fn on_notification< N, F >( mut self, handler : F ) -> Self
+        where N : NotificationMethod + 'static, F : Fn( N::Params, Context ) + 'static {
+        self.add_method( N::METHOD );
+        self.notifications.insert( N::METHOD, Box::new( move | notification, service | {
+            match N::from_notification( notification ) {
+                Ok( params ) => handler( params, Context::for_notification( service, N::METHOD ) ),
+                Err( notification ) => {
+                    component_error!( Component::Router, "Notification {} routed to handler for {}.", notification.method_name( ), N::METHOD );
+                }
+            }
+        } ) );
+
+        self
+    }
+
+    fn add_method( &mut self, method : &'static str ) {
+        if !self.methods.contains( &method ) {
+            self.methods.push( method );
+        }
+    }
+
+    /// Returns the route of a request, the route registered for the language of its document if there is one.
+    fn find_request_route( &self, service : &ServiceHandle, request : &ServerRequest ) -> Option< &RequestRoute > {
+        let method = request.method_name( );
+        let language_route = self.language_requests.get( method ).and_then( | routes | {
+            method::document_uri( request )
+                .and_then( | uri | service.document_language( uri ) )
+                .and_then( | language_id | routes.get( &language_id ) )
+        } );
+
+        language_route.or_else( | | self.requests.get( method ) )
+    }
+
+    fn check_guards( &self, service : &ServiceHandle, request : &ServerRequest ) -> Option< ResponseError > {
+        let guards = match self.guards.get( request.method_name( ) ) {
+            Some( guards ) => guards,
+            None => return None
+        };
+
+        for guard in guards {
+            let error = match *guard {
+                Guard::Initialized if !service.is_initialized( ) => ResponseError {
+                    code    : SERVER_NOT_INITIALIZED,
+                    message : "Server not initialized".to_string( )
+                },
+                Guard::DocumentOpen => match method::document_uri( request ) {
+                    Some( uri ) if !service.is_document_open( uri ) => ResponseError {
+                        code    : INVALID_REQUEST,
+                        message : format!( "Document {} is not open", uri )
+                    },
+                    _ => continue
+                },
+                Guard::Capability( ref predicate ) => match service.client_capabilities( ) {
+                    Some( ref capabilities ) if predicate( capabilities ) => continue,
+                    _ => ResponseError {
+                        code    : INVALID_REQUEST,
+                        message : "Client does not support a capability required by this request".to_string( )
+                    }
+                },
+                _ => continue
+            };
+
+            return Some( error );
+        }
+
+        None
+    }
+
+}
+
+impl MethodComponent for Router {
+
+    fn methods( &self ) -> &[ &'static str ] {
+        &self.methods
+    }
+
+}
+
+impl MessageHandler for Router {
+
+    fn handle_request( &self, service : ServiceHandle, request : ServerRequest, output : ResponseOutput ) {
+        let method = request.method_name( );
+        match self.find_request_route( &service, &request ) {
+            Some( route ) => {
+                if let Some( error ) = self.check_guards( &service, &request ) {
+                    component_trace!( Component::Router, "Request {} rejected by guard: {}", method, error.message );
+
+                    return output.send_error( error );
+                }
+
+                route( request, service, output )
+            },
+            None if self.answer_initialize && method == method::InitializeRequest::METHOD => {
+                output.send_result( ServerResponse::Initialize( InitializeResult {
+                    capabilities : self.server_capabilities( )
+                } ) );
+            },
+            None => output.send_error( method_not_found( method ) )
+        }
+    }
+
+    fn handle_notification( &self, service : ServiceHandle, notification : ServerNotification ) {
+        let method = notification.method_name( );
+        match self.notifications.get( method ) {
+            Some( route ) => route( notification, service ),
+            None => component_trace!( Component::Router, "Ignoring notification {} without a registered handler.", method )
+        }
+    }
+
+    fn claims_request( &self, request : &ServerRequest ) -> bool {
+        let method = request.method_name( );
+
+        self.requests.contains_key( method ) || self.language_requests.contains_key( method ) ||
+            ( self.answer_initialize && method == method::InitializeRequest::METHOD )
+    }
+
+    fn claims_notification( &self, notification : &ServerNotification ) -> bool {
+        self.notifications.contains_key( notification.method_name( ) )
+    }
+
+    /// Answers the initialize request with the implied capabilities when no handler is registered for it, so
+    /// layers wrapping the router, such as document::with_documents, can amend them.
+    fn on_initialize( &self, _ : ServiceHandle, _ : &InitializeParams ) -> Option< InitializeResult > {
+        if !self.answer_initialize || self.requests.contains_key( method::InitializeRequest::METHOD ) {
+            return None;
+        }
+
+        Some( InitializeResult {
+            capabilities : self.server_capabilities( )
+        } )
+    }
+
+}
+
+fn request_route< R, F >( handler : F ) -> RequestRoute
+    where R : RequestMethod + 'static, F : Fn( R::Params, Context, ResponseOutput ) + 'static {
+    Box::new( move | request, service, output | {
+        match R::from_request( request ) {
+            Ok( params ) => {
+                let context = Context::for_request( service, R::METHOD, &output );
+
+                handler( params, context, output )
+            },
+            Err( request ) => {
+                component_error!( Component::Router, "Request {} routed to handler for {}.", request.method_name( ), R::METHOD );
+
+                output.send_error( method_not_found( request.method_name( ) ) );
+            }
+        }
+    } )
+}
+
+/// Returns the server capabilities implied by a set of implemented methods, has_method returning whether a
+/// method is implemented.
+///
+/// Only capabilities that can be expressed without extra options are advertised, see
+/// Router::server_capabilities.
+pub fn implied_capabilities< F : Fn( &str ) -> bool >( has_method : F ) -> ServerCapabilities {
+    let provider = | method : &str | if has_method( method ) { Some( true ) } else { None };
+
+    let mut capabilities = ServerCapabilities::default( );
+    if has_method( method::DidOpenTextDocumentNotification::METHOD ) || has_method( method::DidChangeTextDocumentNotification::METHOD ) {
+        capabilities.text_document_sync = Some( TextDocumentSyncKind::Full );
+    }
+    if has_method( method::CompletionRequest::METHOD ) {
+        capabilities.completion_provider = Some( CompletionOptions {
+            resolve_provider   : provider( method::ResolveCompletionItemRequest::METHOD ),
+            trigger_characters : None
+        } );
+    }
+    if has_method( method::SignatureHelpRequest::METHOD ) {
+        capabilities.signature_help_provider = Some( SignatureHelpOptions {
+            trigger_characters : None
+        } );
+    }
+    if has_method( method::CodeLensRequest::METHOD ) {
+        capabilities.code_lens_provider = Some( CodeLensOptions {
+            resolve_provider : provider( method::ResolveCodeLensRequest::METHOD )
+        } );
+    }
+    capabilities.hover_provider = provider( method::HoverRequest::METHOD );
+    capabilities.definition_provider = provider( method::GotoDefinitionRequest::METHOD );
+    capabilities.references_provider = provider( method::FindReferencesRequest::METHOD );
+    capabilities.document_highlight_provider = provider( method::DocumentHighlightRequest::METHOD );
+    capabilities.document_symbol_provider = provider( method::DocumentSymbolsRequest::METHOD );
+    capabilities.workspace_symbol_provider = provider( method::WorkspaceSymbolsRequest::METHOD );
+    capabilities.code_action_provider = provider( method::CodeActionRequest::METHOD );
+    capabilities.document_formatting_provider = provider( method::FormattingRequest::METHOD );
+    capabilities.document_range_formatting_provider = provider( method::RangeFormattingRequest::METHOD );
+    capabilities.rename_provider = provider( method::RenameRequest::METHOD );
+
+    capabilities
+}
+
+#[cfg( test )]
+mod tests {
+
+    use futures::{
+        Future
+    };
+    use futures::sync::{
+        oneshot
+    };
+    use lsp_rs::{
+        INVALID_REQUEST,
+        METHOD_NOT_FOUND,
+        ResponseError,
+        ServerResponse,
+        TextDocumentPositionParams
+    };
+    use method::{
+        DidOpenTextDocumentNotification,
+        GotoDefinitionRequest,
+        HoverRequest
+    };
+    use mock_client::{
+        MockClient,
+        MockError
+    };
+    use serde_json::{
+        Value
+    };
+    use service::{
+        ResponseOutput
+    };
+    use std::sync::{
+        Arc,
+        Mutex
+    };
+    use super::{
+        Context,
+        Guard,
+        Router
+    };
+    use testing;
+
+    type Calls = Arc< Mutex< Vec< &'static str > > >;
+
+    /// Returns a request handler recording name in calls and answering with a null result.
+    fn record( calls : &Calls, name : &'static str ) -> impl Fn( TextDocumentPositionParams, Context, ResponseOutput ) + 'static {
+        let calls = calls.clone( );
+
+        move | _, _, output : ResponseOutput | {
+            calls.lock( ).unwrap( ).push( name );
+            output.send_result( ServerResponse::Shutdown );
+        }
+    }
+
+    fn hover( id : i64, uri : &str ) -> Value {
+        json!( {
+            "jsonrpc" : "2.0",
+            "id"      : id,
+            "method"  : "textDocument/hover",
+            "params"  : {
+                "textDocument" : { "uri" : uri },
+                "position"     : { "line" : 0, "character" : 0 }
+            }
+        } )
+    }
+
+    fn did_open( uri : &str, language_id : &str ) -> Value {
+        json!( {
+            "jsonrpc" : "2.0",
+            "method"  : "textDocument/didOpen",
+            "params"  : {
+                "textDocument" : { "uri" : uri, "languageId" : language_id, "version" : 1, "text" : "" }
+            }
+        } )
+    }
+
+    /// Returns the code of the error the request id was answered with.
+    fn error_code( client : &mut MockClient, id : i64 ) -> i64 {
+        match client.response::< Value >( id ) {
+            Err( MockError::Response( error ) ) => error.code,
+            Ok( result ) => panic!( "Request {} succeeded with {}", id, result ),
+            Err( error ) => panic!( "Request {} failed: {}", id, error )
+        }
+    }
+
+    #[test]
+    fn requests_are_dispatched_by_method( ) {
+        let calls = Calls::default( );
+        let router = Router::new( )
+            .on_request::< HoverRequest, _ >( record( &calls, "hover" ) )
+            .on_request::< GotoDefinitionRequest, _ >( record( &calls, "definition" ) );
+        let mut client = MockClient::new( router ).unwrap( );
+
+        let mut definition = testing::hover( 2 );
+        definition[ "method" ] = json!( "textDocument/definition" );
+        client.send_message( &testing::hover( 1 ) ).unwrap( );
+        client.send_message( &definition ).unwrap( );
+        client.response::< Value >( 1 ).unwrap( );
+        client.response::< Value >( 2 ).unwrap( );
+
+        assert_eq!( *calls.lock( ).unwrap( ), vec![ "hover", "definition" ] );
+    }
+
+    #[test]
+    fn requests_without_a_handler_are_answered_with_method_not_found( ) {
+        let calls = Calls::default( );
+        let mut client = MockClient::new( Router::new( ).on_request::< GotoDefinitionRequest, _ >( record( &calls, "definition" ) ) ).unwrap( );
+
+        client.send_message( &testing::hover( 1 ) ).unwrap( );
+        assert_eq!( error_code( &mut client, 1 ), METHOD_NOT_FOUND );
+        assert!( calls.lock( ).unwrap( ).is_empty( ) );
+    }
+
+    #[test]
+    fn document_open_guard_rejects_requests_on_unopened_documents( ) {
+        let calls = Calls::default( );
+        let router = Router::new( )
+            .on_request::< HoverRequest, _ >( record( &calls, "hover" ) )
+            .guard::< HoverRequest >( Guard::DocumentOpen );
+        let mut client = MockClient::new( router ).unwrap( );
+
+        client.send_message( &testing::hover( 1 ) ).unwrap( );
+        assert_eq!( error_code( &mut client, 1 ), INVALID_REQUEST );
+        assert!( calls.lock( ).unwrap( ).is_empty( ) );
+
+        client.send_message( &did_open( "file:///workspace/main.rs", "rust" ) ).unwrap( );
+        client.send_message( &testing::hover( 2 ) ).unwrap( );
+        assert_eq!( client.response::< Value >( 2 ).unwrap( ), Value::Null );
+        assert_eq!( *calls.lock( ).unwrap( ), vec![ "hover" ] );
+    }
+
+    #[test]
+    fn lsp_handlers_answer_initialize_with_the_implied_capabilities( ) {
+        let calls = Calls::default( );
+        let router = lsp_handlers! {
+            "textDocument/hover"   => record( &calls, "hover" ),
+            "textDocument/didOpen" => | _, _ | { }
+        };
+        let mut client = MockClient::new( router ).unwrap( );
+
+        client.send_message( &json!( {
+            "jsonrpc" : "2.0",
+            "id"      : 1,
+            "method"  : "initialize",
+            "params"  : { "processId" : null, "rootUri" : null, "capabilities" : { } }
+        } ) ).unwrap( );
+        let result : Value = client.response( 1 ).unwrap( );
+        assert_eq!( result[ "capabilities" ][ "hoverProvider" ], json!( true ) );
+        assert_eq!( result[ "capabilities" ][ "textDocumentSync" ], json!( 1 ) );
+        assert!( result[ "capabilities" ].get( "definitionProvider" ).map( Value::is_null ).unwrap_or( true ) );
+
+        let mut definition = testing::hover( 2 );
+        definition[ "method" ] = json!( "textDocument/definition" );
+        client.send_message( &definition ).unwrap( );
+        assert_eq!( error_code( &mut client, 2 ), METHOD_NOT_FOUND );
+    }
+
+    #[test]
+    fn concurrent_coalesced_requests_share_one_computation( ) {
+        let senders = Arc::new( Mutex::new( Vec::new( ) ) );
+        let handler_senders = senders.clone( );
+        let router = Router::new( ).on_coalesced_request::< HoverRequest, _, _, _ >( | _, _ | Some( "main.rs".to_string( ) ), move | _, _ | {
+            let ( sender, receiver ) = oneshot::channel::< ( ) >( );
+            handler_senders.lock( ).unwrap( ).push( sender );
+
+            receiver.map( | _ | ServerResponse::Shutdown ).map_err( | _ | ResponseError {
+                code    : INVALID_REQUEST,
+                message : "Computation dropped".to_string( )
+            } )
+        } );
+        let mut client = MockClient::new( router ).unwrap( );
+
+        client.send_message( &testing::hover( 1 ) ).unwrap( );
+        client.send_message( &testing::hover( 2 ) ).unwrap( );
+        testing::run_until_stalled( &mut client );
+        assert_eq!( senders.lock( ).unwrap( ).len( ), 1 );
+        assert!( !client.has_response( 1 ) && !client.has_response( 2 ) );
+
+        senders.lock( ).unwrap( ).pop( ).unwrap( ).send( ( ) ).unwrap( );
+        assert_eq!( client.response::< Value >( 1 ).unwrap( ), Value::Null );
+        assert_eq!( client.response::< Value >( 2 ).unwrap( ), Value::Null );
+
+        // The completed computation is not reused by later requests
+        client.send_message( &testing::hover( 3 ) ).unwrap( );
+        testing::run_until_stalled( &mut client );
+        assert_eq!( senders.lock( ).unwrap( ).len( ), 1 );
+    }
+
+    #[test]
+    fn language_requests_fall_back_to_the_default_handler( ) {
+        let calls = Calls::default( );
+        let router = Router::new( )
+            .on_language_request::< HoverRequest, _ >( "typescript", record( &calls, "typescript" ) )
+            .on_request::< HoverRequest, _ >( record( &calls, "default" ) )
+            .on_notification::< DidOpenTextDocumentNotification, _ >( | _, _ | { } );
+        let mut client = MockClient::new( router ).unwrap( );
+
+        client.send_message( &did_open( "file:///workspace/main.ts", "typescript" ) ).unwrap( );
+        client.send_message( &did_open( "file:///workspace/main.rs", "rust" ) ).unwrap( );
+        client.send_message( &hover( 1, "file:///workspace/main.ts" ) ).unwrap( );
+        client.send_message( &hover( 2, "file:///workspace/main.rs" ) ).unwrap( );
+        client.send_message( &hover( 3, "file:///workspace/closed.ts" ) ).unwrap( );
+        for id in 1..4 {
+            client.response::< Value >( id ).unwrap( );
+        }
+
+        assert_eq!( *calls.lock( ).unwrap( ), vec![ "typescript", "default", "default" ] );
+    }
+
+    #[test]
+    fn language_requests_without_a_default_handler_are_answered_with_method_not_found( ) {
+        let calls = Calls::default( );
+        let mut client = MockClient::new( Router::new( ).on_language_request::< HoverRequest, _ >( "typescript", record( &calls, "typescript" ) ) ).unwrap( );
+
+        client.send_message( &did_open( "file:///workspace/main.rs", "rust" ) ).unwrap( );
+        client.send_message( &hover( 1, "file:///workspace/main.rs" ) ).unwrap( );
+        assert_eq!( error_code( &mut client, 1 ), METHOD_NOT_FOUND );
+        assert!( calls.lock( ).unwrap( ).is_empty( ) );
+    }
+
+    #[test]
+    fn language_routes_match_documents_by_normalized_uri( ) {
+        let calls = Calls::default( );
+        let router = Router::new( )
+            .on_language_request::< HoverRequest, _ >( "typescript", record( &calls, "typescript" ) )
+            .on_request::< HoverRequest, _ >( record( &calls, "default" ) );
+        let mut client = MockClient::new( router ).unwrap( );
+
+        client.send_message( &did_open( "file:///C%3A/workspace/main.ts", "typescript" ) ).unwrap( );
+        client.send_message( &hover( 1, "file:///c:/workspace/main.ts" ) ).unwrap( );
+        client.response::< Value >( 1 ).unwrap( );
+
+        assert_eq!( *calls.lock( ).unwrap( ), vec![ "typescript" ] );
+    }
+
+}
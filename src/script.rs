@@ -0,0 +1,505 @@
+
+use mock_client::{
+    MockClient,
+    MockError
+};
+use serde_json::{
+    self,
+    Map,
+    Value
+};
+use std::error::{
+    Error
+};
+use std::fmt;
+use std::fs;
+use std::io::{
+    self,
+    Read,
+    Write
+};
+use std::path::{
+    Path
+};
+use std::process::{
+    Child,
+    ChildStdin,
+    Command,
+    Stdio
+};
+use std::sync::mpsc::{
+    self,
+    Receiver,
+    RecvTimeoutError
+};
+use std::thread;
+use std::time::{
+    Duration,
+    Instant
+};
+use transport;
+
+/// Time an expectation waits for a message by default
+const DEFAULT_TIMEOUT_MS : u64 = 5000;
+
+/// Conversation with a service described by a script of messages to send and messages to expect
+///
+/// A script is a JSON (or, with the yaml feature, YAML) document of the form:
+///
+/// ```json
+/// {
+///     "name"       : "diagnostics on open",
+///     "timeout_ms" : 5000,
+///     "steps"      : [
+///         { "send"   : { "id" : 1, "method" : "initialize", "params" : { "capabilities" : { } } } },
+///         { "expect" : { "id" : 1, "result" : { "capabilities" : { "hoverProvider" : true } } } },
+///         { "send"   : { "method" : "initialized", "params" : { } } },
+///         { "respond" : { "method" : "workspace/configuration", "result" : [ { } ] } },
+///         { "send"   : { "method" : "textDocument/didOpen", "params" : { "textDocument" : { .. } } } },
+///         { "expect" : { "method" : "textDocument/publishDiagnostics" }, "within_ms" : 200 },
+///         { "expect_none" : { "method" : "window/showMessage" }, "within_ms" : 100 }
+///     ]
+/// }
+/// ```
+///
+/// `send` sends a message as is, adding `"jsonrpc" : "2.0"` if missing. `expect` waits for a message
+/// matching a pattern, see matches, for within_ms or the timeout of the script. Messages received while
+/// waiting that do not match are kept for later expectations. `expect_none` fails if a matching message is
+/// received within within_ms. `respond` waits for a request of the service and answers it with a result or
+/// an error.
+///
+/// Scripts run against a MockClient in tests, or against a server process for conformance checks, see the
+/// ls_conformance binary:
+///
+/// ```ignore
+/// let script = Script::load( "tests/scripts/diagnostics_on_open.json" )?;
+/// script.run( &mut MockClient::new( router )? )?;
+/// ```
+#[derive( Clone, Debug )]
+pub struct Script {
+    name    : String,
+    timeout : Duration,
+    steps   : Vec< Step >
+}
+
+#[derive( Clone, Debug )]
+enum Step {
+    Send( Value ),
+    Expect {
+        pattern : Value,
+        within  : Option< Duration >
+    },
+    ExpectNone {
+        pattern : Value,
+        within  : Duration
+    },
+    Respond {
+        method   : String,
+        response : Result< Value, Value >
+    }
+}
+
+/// Connection to a service a Script is run against
+pub trait Conversation {
+
+    /// Sends a message to the service.
+    fn send( &mut self, message : &Value ) -> Result< ( ), String >;
+
+    /// Waits up to timeout for the next message of the service, None if no message was received in time.
+    fn receive( &mut self, timeout : Duration ) -> Result< Option< Value >, String >;
+
+}
+
+/// Conversation with a server process over its standard input and output
+///
+/// The process is killed when the conversation is dropped.
+pub struct ProcessConversation {
+    child    : Child,
+    stdin    : ChildStdin,
+    messages : Receiver< Result< Value, String > >
+}
+
+/// Errors returned when loading or running a Script
+#[derive( Debug )]
+pub enum ScriptError {
+    /// The script could not be read
+    Io( io::Error ),
+    /// The script is not a valid script
+    Invalid( String ),
+    /// The step at index step of the script failed
+    Failed {
+        step   : usize,
+        reason : String
+    }
+}
+
+impl Script {
+
+    /// Parses a JSON script.
+    pub fn from_json( text : &str ) -> Result< Self, ScriptError > {
+        let script = serde_json::from_str( text ).map_err( | error | ScriptError::Invalid( error.to_string( ) ) )?;
+
+        Script::from_value( script )
+    }
+
+    /// Parses a YAML script.
+    #[cfg( feature = "yaml" )]
+    pub fn from_yaml( text : &str ) -> Result< Self, ScriptError > {
+        let script = ::serde_yaml::from_str( text ).map_err( | error | ScriptError::Invalid( error.to_string( ) ) )?;
+
+        Script::from_value( script )
+    }
+
+    /// Reads a script, parsed as YAML if the extension of path is yaml or yml and as JSON otherwise. Scripts
+    /// are named after their file unless they have a name.
+    pub fn load< P : AsRef< Path > >( path : P ) -> Result< Self, ScriptError > {
+        let path = path.as_ref( );
+        let text = fs::read_to_string( path ).map_err( ScriptError::Io )?;
+
+        let yaml = path.extension( ).map( | extension | extension == "yaml" || extension == "yml" ).unwrap_or( false );
+        let mut script = if yaml {
+            Script::parse_yaml( &text )?
+        }
+        else {
+            Script::from_json( &text )?
+        };
+        if script.name.is_empty( ) {
+            script.name = path.display( ).to_string( );
+        }
+
+        Ok( script )
+    }
+
+    /// Builds a script from its JSON representation.
+    pub fn from_value( script : Value ) -> Result< Self, ScriptError > {
+        let mut script = match script {
+            Value::Object( script ) => script,
+            _ => return Err( ScriptError::Invalid( "A script must be an object".to_string( ) ) )
+        };
+
+        let name = match script.remove( "name" ) {
+            Some( Value::String( name ) ) => name,
+            None => String::new( ),
+            Some( _ ) => return Err( ScriptError::Invalid( "The name of a script must be a string".to_string( ) ) )
+        };
+        let timeout = match script.get( "timeout_ms" ) {
+            Some( timeout ) => duration_ms( timeout ).ok_or_else( | | ScriptError::Invalid( "timeout_ms must be a number of milliseconds".to_string( ) ) )?,
+            None => Duration::from_millis( DEFAULT_TIMEOUT_MS )
+        };
+        let steps = match script.remove( "steps" ) {
+            Some( Value::Array( steps ) ) => steps,
+            _ => return Err( ScriptError::Invalid( "A script must have an array of steps".to_string( ) ) )
+        };
+
+        let steps = steps.into_iter( ).enumerate( ).map( | ( index, step ) | {
+            Step::parse( step ).map_err( | reason | ScriptError::Invalid( format!( "Step {}: {}", index + 1, reason ) ) )
+        } ).collect::< Result< Vec< _ >, _ > >( )?;
+
+        Ok( Script {
+            name    : name,
+            timeout : timeout,
+            steps   : steps
+        } )
+    }
+
+    pub fn name( &self ) -> &str {
+        &self.name
+    }
+
+    /// Runs the steps of the script in order against conversation, stopping at the first failing step.
+    pub fn run< C : Conversation >( &self, conversation : &mut C ) -> Result< ( ), ScriptError > {
+        let mut backlog = Vec::new( );
+        for ( index, step ) in self.steps.iter( ).enumerate( ) {
+            let fail = | reason | ScriptError::Failed {
+                step   : index,
+                reason : reason
+            };
+
+            match *step {
+                Step::Send( ref message ) => {
+                    conversation.send( message ).map_err( fail )?;
+                },
+                Step::Expect { ref pattern, within } => {
+                    let within = within.unwrap_or( self.timeout );
+                    let message = take_matching( conversation, &mut backlog, within, | message | matches( pattern, message ) ).map_err( fail )?;
+                    if message.is_none( ) {
+                        return Err( fail( format!( "No message matching {} within {}ms", pattern, as_millis( within ) ) ) );
+                    }
+                },
+                Step::ExpectNone { ref pattern, within } => {
+                    let message = take_matching( conversation, &mut backlog, within, | message | matches( pattern, message ) ).map_err( fail )?;
+                    if let Some( message ) = message {
+                        return Err( fail( format!( "Unexpected message {}", message ) ) );
+                    }
+                },
+                Step::Respond { ref method, ref response } => {
+                    let request = take_matching( conversation, &mut backlog, self.timeout, | message | {
+                        message.get( "id" ).is_some( ) && message[ "method" ] == method.as_str( )
+                    } ).map_err( fail )?;
+                    let request = match request {
+                        Some( request ) => request,
+                        None => return Err( fail( format!( "No {} request within {}ms", method, as_millis( self.timeout ) ) ) )
+                    };
+
+                    let mut message = json!( {
+                        "jsonrpc" : "2.0",
+                        "id"      : request[ "id" ]
+                    } );
+                    match *response {
+                        Ok( ref result ) => message[ "result" ] = result.clone( ),
+                        Err( ref error ) => message[ "error" ] = error.clone( )
+                    }
+                    conversation.send( &message ).map_err( fail )?;
+                }
+            }
+        }
+
+        Ok( ( ) )
+    }
+
+    #[cfg( feature = "yaml" )]
+    fn parse_yaml( text : &str ) -> Result< Self, ScriptError > {
+        Script::from_yaml( text )
+    }
+
+    #[cfg( not( feature = "yaml" ) )]
+    fn parse_yaml( _text : &str ) -> Result< Self, ScriptError > {
+        Err( ScriptError::Invalid( "YAML scripts require the yaml feature".to_string( ) ) )
+    }
+
+}
+
+impl Step {
+
+    fn parse( step : Value ) -> Result< Self, String > {
+        let mut step = match step {
+            Value::Object( step ) => step,
+            _ => return Err( "A step must be an object".to_string( ) )
+        };
+        let within = match step.get( "within_ms" ) {
+            Some( within ) => Some( duration_ms( within ).ok_or_else( | | "within_ms must be a number of milliseconds".to_string( ) )? ),
+            None => None
+        };
+
+        if let Some( message ) = step.remove( "send" ) {
+            let mut message = match message {
+                Value::Object( message ) => message,
+                _ => return Err( "The message of a send step must be an object".to_string( ) )
+            };
+            message.entry( "jsonrpc" ).or_insert_with( | | Value::String( "2.0".to_string( ) ) );
+
+            Ok( Step::Send( Value::Object( message ) ) )
+        }
+        else if let Some( pattern ) = step.remove( "expect" ) {
+            Ok( Step::Expect {
+                pattern : pattern,
+                within  : within
+            } )
+        }
+        else if let Some( pattern ) = step.remove( "expect_none" ) {
+            Ok( Step::ExpectNone {
+                pattern : pattern,
+                within  : within.ok_or_else( | | "An expect_none step must have within_ms".to_string( ) )?
+            } )
+        }
+        else if let Some( respond ) = step.remove( "respond" ) {
+            Step::parse_respond( respond )
+        }
+        else {
+            Err( "A step must have one of send, expect, expect_none or respond".to_string( ) )
+        }
+    }
+
+    fn parse_respond( respond : Value ) -> Result< Self, String > {
+        let mut respond : Map< String, Value > = match respond {
+            Value::Object( respond ) => respond,
+            _ => return Err( "The response of a respond step must be an object".to_string( ) )
+        };
+
+        let method = match respond.remove( "method" ) {
+            Some( Value::String( method ) ) => method,
+            _ => return Err( "A respond step must have the method of the request to answer".to_string( ) )
+        };
+        let response = match ( respond.remove( "result" ), respond.remove( "error" ) ) {
+            ( Some( result ), None ) => Ok( result ),
+            ( None, Some( error ) ) => Err( error ),
+            _ => return Err( "A respond step must have either a result or an error".to_string( ) )
+        };
+
+        Ok( Step::Respond {
+            method   : method,
+            response : response
+        } )
+    }
+
+}
+
+/// Returns true if value matches pattern.
+///
+/// Objects match if every field of the pattern matches the field of the same name of the value, the value
+/// may have other fields. Arrays match if every element of the pattern matches an element of the value.
+/// Other values match if they are equal.
+pub fn matches( pattern : &Value, value : &Value ) -> bool {
+    match ( pattern, value ) {
+        ( &Value::Object( ref pattern ), &Value::Object( ref value ) ) => pattern.iter( ).all( | ( name, pattern ) | {
+            value.get( name ).map( | value | matches( pattern, value ) ).unwrap_or( false )
+        } ),
+        ( &Value::Array( ref pattern ), &Value::Array( ref values ) ) => pattern.iter( ).all( | pattern | {
+            values.iter( ).any( | value | matches( pattern, value ) )
+        } ),
+        _ => pattern == value
+    }
+}
+
+/// Takes the first message matching predicate from the backlog, or waits up to within for it, keeping the
+/// messages that do not match in the backlog.
+fn take_matching< C, F >( conversation : &mut C, backlog : &mut Vec< Value >, within : Duration, predicate : F ) -> Result< Option< Value >, String >
+    where C : Conversation, F : Fn( &Value ) -> bool {
+    if let Some( index ) = backlog.iter( ).position( | message | predicate( message ) ) {
+        return Ok( Some( backlog.remove( index ) ) );
+    }
+
+    let deadline = Instant::now( ) + within;
+    loop {
+        let now = Instant::now( );
+        if now >= deadline {
+            return Ok( None );
+        }
+
+        match conversation.receive( deadline - now )? {
+            Some( message ) => {
+                if predicate( &message ) {
+                    return Ok( Some( message ) );
+                }
+
+                backlog.push( message );
+            },
+            None => return Ok( None )
+        }
+    }
+}
+
+fn duration_ms( value : &Value ) -> Option< Duration > {
+    value.as_u64( ).map( Duration::from_millis )
+}
+
+fn as_millis( duration : Duration ) -> u64 {
+    duration.as_secs( ) * 1000 + ( duration.subsec_nanos( ) / 1_000_000 ) as u64
+}
+
+impl Conversation for MockClient {
+
+    fn send( &mut self, message : &Value ) -> Result< ( ), String > {
+        self.send_message( message ).map_err( | error | error.to_string( ) )
+    }
+
+    fn receive( &mut self, timeout : Duration ) -> Result< Option< Value >, String > {
+        match self.next_message( timeout ) {
+            Ok( message ) => Ok( Some( message ) ),
+            Err( MockError::Timeout ) => Ok( None ),
+            Err( error ) => Err( error.to_string( ) )
+        }
+    }
+
+}
+
+impl ProcessConversation {
+
+    /// Spawns command with piped standard input and output, e.g. the executable of a language server.
+    pub fn spawn( command : &mut Command ) -> io::Result< Self > {
+        let mut child = command.stdin( Stdio::piped( ) ).stdout( Stdio::piped( ) ).spawn( )?;
+        let stdin = child.stdin.take( ).expect( "Standard input of the server is piped" );
+        let mut stdout = child.stdout.take( ).expect( "Standard output of the server is piped" );
+
+        let ( message_send, message_read ) = mpsc::channel( );
+        thread::spawn( move | | {
+            let mut buffer = Vec::new( );
+            let mut chunk = [ 0; 4096 ];
+            loop {
+                loop {
+                    match transport::take_message( &mut buffer ) {
+                        Ok( Some( message ) ) => {
+                            if message_send.send( Ok( message ) ).is_err( ) {
+                                return;
+                            }
+                        },
+                        Ok( None ) => break,
+                        Err( error ) => {
+                            let _ = message_send.send( Err( format!( "Invalid message: {}", error ) ) );
+
+                            return;
+                        }
+                    }
+                }
+
+                match stdout.read( &mut chunk ) {
+                    Ok( 0 ) => return,
+                    Ok( count ) => buffer.extend_from_slice( &chunk[ ..count ] ),
+                    Err( error ) => {
+                        let _ = message_send.send( Err( format!( "Error reading from the server: {}", error ) ) );
+
+                        return;
+                    }
+                }
+            }
+        } );
+
+        Ok( ProcessConversation {
+            child    : child,
+            stdin    : stdin,
+            messages : message_read
+        } )
+    }
+
+}
+
+impl Conversation for ProcessConversation {
+
+    fn send( &mut self, message : &Value ) -> Result< ( ), String > {
+        transport::write_message( &mut self.stdin, message )
+            .and_then( | _ | self.stdin.flush( ) )
+            .map_err( | error | format!( "Error writing to the server: {}", error ) )
+    }
+
+    fn receive( &mut self, timeout : Duration ) -> Result< Option< Value >, String > {
+        match self.messages.recv_timeout( timeout ) {
+            Ok( message ) => message.map( Some ),
+            Err( RecvTimeoutError::Timeout ) => Ok( None ),
+            Err( RecvTimeoutError::Disconnected ) => Err( "Server closed its output".to_string( ) )
+        }
+    }
+
+}
+
+impl Drop for ProcessConversation {
+
+    fn drop( &mut self ) {
+        let _ = self.child.kill( );
+        let _ = self.child.wait( );
+    }
+
+}
+
+impl fmt::Display for ScriptError {
+
+    fn fmt( &self, f : &mut fmt::Formatter ) -> fmt::Result {
+        match *self {
+            ScriptError::Io( ref error ) => write!( f, "Error reading script: {}", error ),
+            ScriptError::Invalid( ref reason ) => write!( f, "Invalid script: {}", reason ),
+            ScriptError::Failed { step, ref reason } => write!( f, "Step {} failed: {}", step + 1, reason )
+        }
+    }
+
+}
+
+impl Error for ScriptError {
+
+    fn description( &self ) -> &str {
+        match *self {
+            ScriptError::Io( ref error ) => error.description( ),
+            ScriptError::Invalid( _ ) => "Invalid script",
+            ScriptError::Failed { .. } => "Script step failed"
+        }
+    }
+
+}
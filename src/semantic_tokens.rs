@@ -0,0 +1,258 @@
+
+use logging::{
+    Component
+};
+use lsp_rs::{
+    SemanticToken,
+    SemanticTokens,
+    SemanticTokensDelta,
+    SemanticTokensEdit,
+    SemanticTokensFullDeltaResult,
+    ServerNotification,
+    Url
+};
+use std::collections::{
+    HashMap
+};
+use std::sync::{
+    Arc,
+    Mutex
+};
+
+/// Number of integers encoding a SemanticToken on the wire, the unit of the edits of a delta
+const TOKEN_LEN : u32 = 5;
+
+/// Cache of the semantic tokens last sent for each document, answering textDocument/semanticTokens/full/delta
+/// requests with the edits from the previous tokens
+///
+/// Every set of tokens sent through the cache gets a new resultId. A delta request whose previousResultId is
+/// the resultId of the cached tokens is answered with a single edit replacing the tokens between the common
+/// prefix and the common suffix of both sets, any other delta request is answered with the full tokens. The
+/// cache is cheap to clone, clones share the same tokens:
+///
+/// ```ignore
+/// // textDocument/semanticTokens/full
+/// let tokens = cache.full( &params.text_document.uri, compute_tokens( &document ) );
+///
+/// // textDocument/semanticTokens/full/delta
+/// let result = cache.delta( &params.text_document.uri, &params.previous_result_id, compute_tokens( &document ) );
+/// ```
+#[derive( Clone, Default )]
+pub struct SemanticTokensCache {
+    inner : Arc< Mutex< SemanticTokensCacheInner > >
+}
+
+#[derive( Default )]
+struct SemanticTokensCacheInner {
+    next_result_id : u64,
+    documents      : HashMap< Url, CachedTokens >
+}
+
+struct CachedTokens {
+    result_id : String,
+    data      : Vec< SemanticToken >
+}
+
+impl SemanticTokensCache {
+
+    pub fn new( ) -> Self {
+        SemanticTokensCache::default( )
+    }
+
+    /// Caches the full tokens of the document uri, returning them with a new resultId.
+    pub fn full( &self, uri : &Url, data : Vec< SemanticToken > ) -> SemanticTokens {
+        let mut inner = self.inner.lock( ).unwrap( );
+        let result_id = inner.store( uri, data.clone( ) );
+
+        SemanticTokens {
+            result_id : Some( result_id ),
+            data      : data
+        }
+    }
+
+    /// Caches the full tokens of the document uri, returning the edits from the tokens sent with
+    /// previous_result_id, or the full tokens if they are no longer cached.
+    pub fn delta( &self, uri : &Url, previous_result_id : &str, data : Vec< SemanticToken > ) -> SemanticTokensFullDeltaResult {
+        let mut inner = self.inner.lock( ).unwrap( );
+        let edit = match inner.documents.get( uri ) {
+            Some( cached ) if cached.result_id == previous_result_id => Some( compute_edit( &cached.data, &data ) ),
+            _ => None
+        };
+        let result_id = inner.store( uri, data.clone( ) );
+
+        match edit {
+            Some( edit ) => SemanticTokensFullDeltaResult::TokensDelta( SemanticTokensDelta {
+                result_id : Some( result_id ),
+                edits     : edit.into_iter( ).collect( )
+            } ),
+            None => {
+                component_trace!( Component::Document, "Previous semantic tokens {} of {} are not cached, sending full tokens.", previous_result_id, uri );
+
+                SemanticTokensFullDeltaResult::Tokens( SemanticTokens {
+                    result_id : Some( result_id ),
+                    data      : data
+                } )
+            }
+        }
+    }
+
+    /// Removes the tokens cached for the document uri.
+    pub fn remove( &self, uri : &Url ) {
+        self.inner.lock( ).unwrap( ).documents.remove( uri );
+    }
+
+    /// Removes the tokens of documents closed by the client, ignoring other notifications.
+    pub fn observe( &self, notification : &ServerNotification ) {
+        if let ServerNotification::DidCloseTextDocument( ref params ) = *notification {
+            self.remove( &params.text_document.uri );
+        }
+    }
+
+}
+
+impl SemanticTokensCacheInner {
+
+    fn store( &mut self, uri : &Url, data : Vec< SemanticToken > ) -> String {
+        self.next_result_id += 1;
+        let result_id = self.next_result_id.to_string( );
+
+        self.documents.insert( uri.clone( ), CachedTokens {
+            result_id : result_id.clone( ),
+            data      : data
+        } );
+
+        result_id
+    }
+
+}
+
+/// Computes the edit turning previous into current, None if both sets of tokens are equal.
+///
+/// Tokens are relative to the previous token, so the edit covers every token between the common prefix and
+/// the common suffix of both sets.
+fn compute_edit( previous : &[ SemanticToken ], current : &[ SemanticToken ] ) -> Option< SemanticTokensEdit > {
+    let prefix = previous.iter( ).zip( current ).take_while( | &( previous, current ) | previous == current ).count( );
+    if prefix == previous.len( ) && prefix == current.len( ) {
+        return None;
+    }
+
+    let suffix = previous[ prefix.. ].iter( ).rev( )
+        .zip( current[ prefix.. ].iter( ).rev( ) )
+        .take_while( | &( previous, current ) | previous == current )
+        .count( );
+
+    let inserted = &current[ prefix..current.len( ) - suffix ];
+
+    Some( SemanticTokensEdit {
+        start        : prefix as u32 * TOKEN_LEN,
+        delete_count : ( previous.len( ) - prefix - suffix ) as u32 * TOKEN_LEN,
+        data         : if inserted.is_empty( ) { None } else { Some( inserted.to_vec( ) ) }
+    } )
+}
+
+#[cfg( test )]
+mod tests {
+
+    use lsp_rs::{
+        SemanticToken,
+        SemanticTokensFullDeltaResult,
+        Url
+    };
+    use super::{
+        SemanticTokensCache
+    };
+
+    fn token( delta_line : u32 ) -> SemanticToken {
+        SemanticToken {
+            delta_line             : delta_line,
+            delta_start            : 0,
+            length                 : 1,
+            token_type             : 0,
+            token_modifiers_bitset : 0
+        }
+    }
+
+    fn tokens( delta_lines : &[ u32 ] ) -> Vec< SemanticToken > {
+        delta_lines.iter( ).map( | &delta_line | token( delta_line ) ).collect( )
+    }
+
+    /// Returns the start, delete count and inserted tokens of the edit between previous and current.
+    fn edit( previous : &[ u32 ], current : &[ u32 ] ) -> Option< ( u32, u32, Option< Vec< SemanticToken > > ) > {
+        super::compute_edit( &tokens( previous ), &tokens( current ) ).map( | edit | ( edit.start, edit.delete_count, edit.data ) )
+    }
+
+    #[test]
+    fn equal_tokens_need_no_edit( ) {
+        assert!( edit( &[ 1, 2, 3 ], &[ 1, 2, 3 ] ).is_none( ) );
+        assert!( edit( &[ ], &[ ] ).is_none( ) );
+    }
+
+    #[test]
+    fn edit_replaces_the_tokens_between_the_common_prefix_and_suffix( ) {
+        let ( start, delete_count, data ) = edit( &[ 1, 2, 3, 4 ], &[ 1, 7, 8, 4 ] ).unwrap( );
+
+        assert_eq!( ( start, delete_count ), ( 5, 10 ) );
+        assert!( data == Some( tokens( &[ 7, 8 ] ) ) );
+    }
+
+    #[test]
+    fn edit_inserts_and_deletes_tokens( ) {
+        let ( start, delete_count, data ) = edit( &[ 1, 2 ], &[ 1, 2, 3 ] ).unwrap( );
+        assert_eq!( ( start, delete_count ), ( 10, 0 ) );
+        assert!( data == Some( tokens( &[ 3 ] ) ) );
+
+        let ( start, delete_count, data ) = edit( &[ 1, 2, 3 ], &[ 1, 3 ] ).unwrap( );
+        assert_eq!( ( start, delete_count ), ( 5, 5 ) );
+        assert!( data.is_none( ) );
+
+        let ( start, delete_count, data ) = edit( &[ 1, 2 ], &[ ] ).unwrap( );
+        assert_eq!( ( start, delete_count ), ( 0, 10 ) );
+        assert!( data.is_none( ) );
+    }
+
+    #[test]
+    fn prefix_and_suffix_never_overlap( ) {
+        // The remaining 1 is both a prefix and a suffix of the previous tokens, only one of them is kept
+        let ( start, delete_count, data ) = edit( &[ 1, 1 ], &[ 1 ] ).unwrap( );
+
+        assert_eq!( ( start, delete_count ), ( 5, 5 ) );
+        assert!( data.is_none( ) );
+    }
+
+    #[test]
+    fn delta_from_the_cached_result_id_is_an_edit( ) {
+        let cache = SemanticTokensCache::new( );
+        let uri = Url::parse( "file:///src/main.rs" ).unwrap( );
+
+        let full = cache.full( &uri, tokens( &[ 1, 2 ] ) );
+        let previous_result_id = full.result_id.unwrap( );
+        match cache.delta( &uri, &previous_result_id, tokens( &[ 1, 3 ] ) ) {
+            SemanticTokensFullDeltaResult::TokensDelta( delta ) => {
+                assert!( delta.result_id.is_some( ) && delta.result_id != Some( previous_result_id.clone( ) ) );
+                assert_eq!( delta.edits.len( ), 1 );
+            },
+            _ => panic!( "Expected a delta from the cached tokens" )
+        }
+
+        // The previous result id was replaced by the delta
+        match cache.delta( &uri, &previous_result_id, tokens( &[ 1, 3 ] ) ) {
+            SemanticTokensFullDeltaResult::Tokens( full ) => assert!( full.data == tokens( &[ 1, 3 ] ) ),
+            _ => panic!( "Expected the full tokens for a stale result id" )
+        }
+    }
+
+    #[test]
+    fn delta_of_a_removed_document_is_the_full_tokens( ) {
+        let cache = SemanticTokensCache::new( );
+        let uri = Url::parse( "file:///src/main.rs" ).unwrap( );
+
+        let previous_result_id = cache.full( &uri, tokens( &[ 1 ] ) ).result_id.unwrap( );
+        cache.remove( &uri );
+
+        match cache.delta( &uri, &previous_result_id, tokens( &[ 1 ] ) ) {
+            SemanticTokensFullDeltaResult::Tokens( _ ) => { },
+            _ => panic!( "Expected the full tokens of an uncached document" )
+        }
+    }
+
+}
@@ -5,70 +5,207 @@ use futures::{
     Future,
     Poll,
     Sink,
+    StartSend,
     Stream
 };
 use futures::future::{
+    self as future_util,
     Shared
 };
 use futures::stream::{
-    SplitSink,
-    SplitStream
+    FuturesUnordered
 };
 use futures::sync::{
     mpsc,
     oneshot
 };
+use futures::task::{
+    self,
+    Task
+};
 use lsp_rs::{
+    INTERNAL_ERROR,
+    INVALID_PARAMS,
+    INVALID_REQUEST,
+    ClientCapabilities,
     ClientNotification,
+    ClientRequest,
+    ClientResponse,
+    Diagnostic,
     IncomingMessage,
     IncomingServerMessage,
+    InitializeParams,
+    InitializeResult,
+    LogMessageParams,
+    LogTraceParams,
     MessageEnvelope,
+    MessageType,
     NotificationMessage,
+    NumberOrString,
     OutgoingMessage,
     OutgoingServerMessage,
+    ProgressParams,
+    ProgressParamsValue,
+    PublishDiagnosticsParams,
     ResponseError,
     ResponseMessage,
     RequestMessage,
-    ServerCodec,
     ServerNotification,
     ServerResponse,
-    ServerRequest
+    ServerRequest,
+    ShowMessageParams,
+    TraceValue,
+    Url,
+    WorkDoneProgress,
+    WorkDoneProgressBegin,
+    WorkDoneProgressCreateParams,
+    WorkDoneProgressEnd
+};
+use serde_json::{
+    Value
 };
 use std::{
+    error,
+    fmt,
     io
 };
+use std::any::{
+    Any
+};
 use std::cell::{
     RefCell
 };
 use std::collections::{
-    HashMap
+    BTreeMap,
+    HashMap,
+    VecDeque
+};
+use std::io::{
+    Write
+};
+use std::marker::{
+    PhantomData
 };
 use std::rc::{
     Rc
 };
+use std::sync::{
+    Arc,
+    Mutex
+};
+use std::time::{
+    Duration,
+    Instant
+};
 use tokio_core::io::{
     Framed,
     Io
 };
 use tokio_core::reactor::{
+    Core,
     Handle,
     Remote
 };
+#[cfg( all( unix, feature = "signal" ) )]
+use tokio_signal::unix::{
+    Signal,
+    SIGUSR1
+};
+
+use arena::{
+    ArenaPool
+};
+use audit::{
+    ClientRequestLog,
+    ClientRequestRecord,
+    RequestOutcome,
+    ResponseOrderLog,
+    ResponseRecord
+};
+use builder::{
+    ServiceConfig
+};
+use capabilities::{
+    ClientCapabilitiesExt
+};
+use clock::{
+    Clock,
+    Sleep
+};
+use codec::{
+    self,
+    DeadLetters,
+    FrameQueue,
+    QuarantinedMessage,
+    RejectedRequests,
+    ServiceCodec
+};
+use context::{
+    StateMap
+};
+use logging::{
+    Component,
+    CorrelationId
+};
+use method::{
+    self,
+    ClientRequestMethod,
+    MethodName
+};
+use metrics::{
+    LatencyStage,
+    Metrics,
+    MetricsSnapshot
+};
+use queue::{
+    self,
+    QueueLen,
+    QueueLimit,
+    SpscReceiver,
+    SpscSender
+};
+use response_slots::{
+    self,
+    PendingResponse,
+    ResponseObserver,
+    ResponseSender,
+    SlotQueueRead,
+    SlotQueueSend
+};
+#[cfg( feature = "leak-check" )]
+use tasks::{
+    LiveTask,
+    TaskGuard,
+    TaskTracker
+};
+use uri;
 
-type IoRead< I : Io >    = SplitStream< Framed< I, ServerCodec > >;
-type IoWrite< I : Io >   = SplitSink< Framed< I, ServerCodec > >;
+/// Transport of a service, read by the MessageReader through the ServiceCodec and written by the MessageSink
+type Transport< I : Io > = Rc< RefCell< Framed< I, ServiceCodec > > >;
 
 type CommandQueueSend    = mpsc::Sender< ServiceCommand >;
 type CommandQueueRead    = mpsc::Receiver< ServiceCommand >;
 
-type ResponseChannelSend = oneshot::Sender< ResponseMessage< ServerResponse > >;
-type ResponseChannelRead = oneshot::Receiver< ResponseMessage< ServerResponse > >;
+type ClientResponseSend  = oneshot::Sender< Result< Option< ClientResponse >, RequestError > >;
+type ClientResponseRead  = oneshot::Receiver< Result< Option< ClientResponse >, RequestError > >;
+
+type ResponseQueueSend   = SlotQueueSend;
+type ResponseQueueRead   = SlotQueueRead;
+
+type WriteQueueSend      = Rc< SpscSender< OutgoingServerMessage > >;
+type WriteQueueRead      = SpscReceiver< OutgoingServerMessage >;
+type SharedSink          = Rc< RefCell< dyn DirectWrite > >;
 
-type ResponseQueueSend   = mpsc::Sender< ResponseChannelRead >;
-type ResponseQueueRead   = mpsc::Receiver< ResponseChannelRead >;
+type RequeueSend         = mpsc::UnboundedSender< DeferredRequest >;
+type RequeueRead         = mpsc::UnboundedReceiver< DeferredRequest >;
 
-type WriteQueueSend      = mpsc::Sender< OutgoingServerMessage >;
-type WriteQueueRead      = mpsc::Receiver< OutgoingServerMessage >;
+type SharedState         = Arc< Mutex< ServiceState > >;
+
+type ShutdownHook        = Box< dyn FnOnce( &ShutdownReason, MetricsSnapshot ) + Send >;
+type SharedHandler       = Rc< dyn MessageHandler >;
+type HandlerShutdownHook = Box< dyn FnOnce( &ShutdownReason ) >;
+type DisconnectHook      = Box< dyn FnOnce( ) + Send >;
+type NotificationFilter  = Box< dyn Fn( &ServerNotification ) -> bool + Send >;
 
 macro_rules! try_poll {
     (
@@ -83,6 +220,9 @@ macro_rules! try_poll {
 }
 
 /// Main trait implemented by creators of this service to handle incoming requests and notifications
+///
+/// The trait is object safe, a `Box< dyn MessageHandler >` can be passed to start_service to select the
+/// handler of a service at runtime.
 pub trait MessageHandler {
 
     /// Trait method called when a new RequestMessage has been received from the client
@@ -98,19 +238,218 @@ pub trait MessageHandler {
     /// processed.
     fn handle_notification( &self, service : ServiceHandle, notification : ServerNotification );
 
+    /// Trait method called by handler combinators to decide whether this handler should receive a request.
+    ///
+    /// Handlers that only implement part of the protocol return false for requests they do not handle, so
+    /// they can be chained with handlers that do. Defaults to claiming every request.
+    fn claims_request( &self, _request : &ServerRequest ) -> bool {
+        true
+    }
+
+    /// Trait method called by handler combinators to decide whether this handler should receive a
+    /// notification. Defaults to claiming every notification.
+    fn claims_notification( &self, _notification : &ServerNotification ) -> bool {
+        true
+    }
+
+    /// Trait method called for the initialize request, before it is dispatched to handle_request.
+    ///
+    /// Returning Some answers the request with the given result without calling handle_request, the service
+    /// takes care of recording the client capabilities. Defaults to None, dispatching the request to
+    /// handle_request.
+    fn on_initialize( &self, _service : ServiceHandle, _params : &InitializeParams ) -> Option< InitializeResult > {
+        None
+    }
+
+    /// Trait method called when the initialized notification is received, before it is dispatched to
+    /// handle_notification.
+    fn on_initialized( &self, _service : ServiceHandle ) {
+    }
+
+    /// Trait method called for the shutdown request, before it is dispatched to handle_request.
+    ///
+    /// Returning true lets the service answer the request without calling handle_request. Requests received
+    /// after the shutdown request are answered with an INVALID_REQUEST error by the service. Defaults to
+    /// false, dispatching the request to handle_request.
+    fn on_shutdown_request( &self, _service : ServiceHandle ) -> bool {
+        false
+    }
+
+    /// Trait method called when the service begins shutting down, before the hooks registered through
+    /// ServiceHandle::on_shutdown are run.
+    ///
+    /// Handlers can use this to flush caches or persist state. No further messages are dispatched to the
+    /// handler once this method is called.
+    fn on_shutdown( &self, _service : ServiceHandle, _reason : &ShutdownReason ) {
+    }
+
+}
+
+impl < H : MessageHandler + ?Sized > MessageHandler for Box< H > {
+
+    fn handle_request( &self, service : ServiceHandle, request : ServerRequest, output : ResponseOutput ) {
+        ( **self ).handle_request( service, request, output )
+    }
+
+    fn handle_notification( &self, service : ServiceHandle, notification : ServerNotification ) {
+        ( **self ).handle_notification( service, notification )
+    }
+
+    fn claims_request( &self, request : &ServerRequest ) -> bool {
+        ( **self ).claims_request( request )
+    }
+
+    fn claims_notification( &self, notification : &ServerNotification ) -> bool {
+        ( **self ).claims_notification( notification )
+    }
+
+    fn on_initialize( &self, service : ServiceHandle, params : &InitializeParams ) -> Option< InitializeResult > {
+        ( **self ).on_initialize( service, params )
+    }
+
+    fn on_initialized( &self, service : ServiceHandle ) {
+        ( **self ).on_initialized( service )
+    }
+
+    fn on_shutdown_request( &self, service : ServiceHandle ) -> bool {
+        ( **self ).on_shutdown_request( service )
+    }
+
+    fn on_shutdown( &self, service : ServiceHandle, reason : &ShutdownReason ) {
+        ( **self ).on_shutdown( service, reason )
+    }
+
 }
 
 /// Struct that allows replying to a specific request. This struct is Send, allowing requests to be processed
 /// within another thread if needed.
+///
+/// Dropping a ResponseOutput without sending a response answers the request according to the service's
+/// DroppedResponsePolicy, by default with an INTERNAL_ERROR response.
 pub struct ResponseOutput {
     request_id     : i64,
-    result_channel : ResponseChannelSend
+    method         : &'static str,
+    correlation_id : CorrelationId,
+    on_drop        : DroppedResponsePolicy,
+    clock          : Clock,
+    remote_handle  : Remote,
+    result_channel : ResponseSender,
+    #[cfg( feature = "leak-check" )]
+    _pending       : TaskGuard
+}
+
+/// Error code of the response sent for a request whose pinned document was modified before the request
+/// completed, see Context::pin_document
+pub const CONTENT_MODIFIED : i64 = -32801;
+
+/// Error code of the response sent for a deferred request canceled by the client, see
+/// ServiceHandle::defer_request
+pub const REQUEST_CANCELLED : i64 = -32800;
+
+/// Error code of the response sent for a request received before the initialize request
+pub const SERVER_NOT_INITIALIZED : i64 = -32002;
+
+/// Method recorded in the metrics for the requests answered with INVALID_PARAMS because they could not be
+/// decoded, their actual method is unknown to the service
+const MALFORMED_REQUEST : &'static str = "(malformed request)";
+
+/// Bytes of encoded messages waiting to be written past which the MessageSink stops accepting messages until
+/// they are written
+const WRITE_BACKPRESSURE_BOUNDARY : usize = 8 * 1024;
+
+/// Action taken when a document pinned by a request through Context::pin_document changes before the request
+/// is completed
+#[derive( Clone, Copy, Debug, PartialEq, Eq )]
+pub enum PinnedDocumentPolicy {
+    /// Cancel the request and answer it right away with a CONTENT_MODIFIED error, any response sent by the
+    /// handler afterwards is discarded
+    ContentModified,
+    /// Only cancel the request, leaving it to the handler to respond
+    Notify
+}
+
+/// Order in which the responses of requests are written to the client
+#[derive( Clone, Copy, Debug, PartialEq, Eq )]
+pub enum ResponseOrdering {
+    /// Write responses in the order their requests were received, a response completed before the response
+    /// of an earlier request waits for it to be written
+    Received,
+    /// Write every response as soon as it is sent, a slow request does not hold back the responses of later
+    /// requests
+    Completion
+}
+
+/// Handle to the response channel of a request that can answer it with an error without consuming the
+/// ResponseOutput of the request
+pub(crate) struct ErrorResponder {
+    request_id     : i64,
+    correlation_id : CorrelationId,
+    result_channel : ResponseSender
+}
+
+/// Retries of a write to the transport failing with a transient error, before the service fails with the
+/// error, see ServiceBuilder::write_retry
+///
+/// Interrupted, would block, timed out, broken pipe, reset, aborted and not connected errors are retried,
+/// the latter covering a transport reconnecting to a restarted client. Messages already encoded are
+/// written once the transport recovers, each of them exactly once.
+///
+/// A write is only retried while no byte of the message at the front was written, the client could not
+/// tell where the rest of a message cut short by the error starts.
+#[derive( Clone, Copy, Debug, PartialEq, Eq )]
+pub struct WriteRetryPolicy {
+    /// Number of times a failing write is retried
+    pub max_retries     : u32,
+    /// Time waited before the first retry, doubled before every following retry
+    pub initial_backoff : Duration,
+    /// Longest time waited before a retry
+    pub max_backoff     : Duration
+}
+
+/// Action taken when a ResponseOutput is dropped without a response being sent
+#[derive( Clone, Copy, Debug, PartialEq, Eq )]
+pub enum DroppedResponsePolicy {
+    /// Respond to the request with an INTERNAL_ERROR error
+    InternalError,
+    /// Do not respond to the request, the client will not receive a response for it
+    Cancel
+}
+
+/// Future that completes with the result of a request sent to the client through ServiceHandle::send_request
+pub struct ClientResponseFuture {
+    response_read : ClientResponseRead
+}
+
+/// Future that completes with the typed result of a request sent to the client through ServiceHandle::request
+pub struct ClientRequestFuture< R : ClientRequestMethod > {
+    response_future : ClientResponseFuture,
+    method          : PhantomData< R >
+}
+
+/// Future that completes with the first notification matching a predicate, created by
+/// ServiceHandle::wait_for_notification
+pub struct NotificationFuture {
+    notification_read : oneshot::Receiver< ServerNotification >
+}
+
+/// Errors returned when a request sent to the client does not complete with a result
+#[derive( Debug )]
+pub enum RequestError {
+    /// The client responded to the request with an error
+    Error( ResponseError ),
+    /// The client responded with a result that does not match the method of the request
+    InvalidResponse( &'static str ),
+    /// The service was shutdown before the client responded to the request
+    Canceled
 }
 
 /// Future that completes when the service is shutdown and no future requests shall be handled
+///
+/// Resolves with the reason of a normal shutdown, either requested through a ServiceHandle or the client
+/// closing the transport, and fails with the error the service failed with otherwise.
 #[derive( Clone )]
 pub struct ShutdownFuture {
-    shared_future : Shared< oneshot::Receiver< Result< ( ), ServiceError > > >
+    shared_future : Shared< oneshot::Receiver< Result< ShutdownReason, ServiceError > > >
 }
 
 /// A handle to the service to send notifications or to shutdown the running service.
@@ -118,10 +457,66 @@ pub struct ShutdownFuture {
 pub struct ServiceHandle {
     shutdown_future : ShutdownFuture,
     command_send    : CommandQueueSend,
+    requeue_send    : RequeueSend,
+    state           : SharedState,
+    state_map       : Arc< StateMap >,
+    clock           : Clock,
+    arena_pool      : ArenaPool,
+    #[cfg( feature = "leak-check" )]
+    tasks           : TaskTracker,
 
     remote_handle   : Remote
 }
 
+/// Reason a service was shutdown, reported to hooks registered through ServiceHandle::on_shutdown
+#[derive( Clone, Debug )]
+pub enum ShutdownReason {
+    /// The service was shutdown through a ServiceHandle
+    Requested,
+    /// The client closed its end of the transport, e.g. because the editor exited
+    ClientDisconnected,
+    /// The service was shutdown because of an error
+    Error( ServiceError )
+}
+
+/// Current lifecycle state of a running service
+#[derive( Clone, Copy, Debug, PartialEq, Eq )]
+pub enum LifecycleState {
+    /// The service is reading requests and writing responses
+    Running,
+    /// The service was shutdown through a ServiceHandle or because the client disconnected
+    Shutdown,
+    /// The service was shutdown due to an error
+    Failed
+}
+
+/// Guard returned by ServiceHandle::pause_reading, reading of messages from the client resumes once every
+/// guard has been dropped
+pub struct ReadPause {
+    state : SharedState
+}
+
+/// A request that has been received from the client but whose response has not yet been written
+#[derive( Clone, Debug )]
+pub struct PendingRequest {
+    pub id             : i64,
+    pub correlation_id : CorrelationId,
+    pub age            : Duration
+}
+
+/// Snapshot of the internal state of a service, used to diagnose hung or slow services
+///
+/// Queue lengths are the number of items pushed into a queue that have not yet been taken out of it.
+#[derive( Clone, Debug )]
+pub struct DebugDump {
+    pub lifecycle          : LifecycleState,
+    pub pending_requests   : Vec< PendingRequest >,
+
+    pub response_queue_len : usize,
+    pub write_queue_len    : usize,
+    pub command_queue_len  : usize
+}
+
 /// Errors generated by the service while reading/writing messages or processing requests
 #[derive( Clone, Debug )]
 pub enum ServiceError {
@@ -129,6 +524,11 @@ pub enum ServiceError {
     ReadError( Rc< io::Error > ),
     /// Error type generated when there was an IO Error writing to the outgoing stream
     WriteError( Rc< io::Error > ),
+    /// Error type generated when the client closed the transport abruptly, e.g. the connection was reset or
+    /// the pipe broken while reading or writing
+    Disconnected( Rc< io::Error > ),
+    /// Error type generated when a message of the client could not be decoded
+    ProtocolError( Rc< io::Error > ),
     /// Error type generated when the service is unsure of the cause of error.
     ///
     /// Can be generated by:
@@ -138,43 +538,211 @@ pub enum ServiceError {
 }
 
 struct Service {
-    shutdown_send : RefCell< Option< oneshot::Sender< Result< ( ), ServiceError > > > >,
+    shutdown_send : RefCell< Option< oneshot::Sender< Result< ShutdownReason, ServiceError > > > >,
     shutdown_read : ShutdownFuture,
+    handler_hooks : RefCell< Vec< HandlerShutdownHook > >,
 
     command_send  : CommandQueueSend,
+    requeue_send  : RequeueSend,
+    state         : SharedState,
+    state_map     : Arc< StateMap >,
+    config        : ServiceConfig,
+    arena_pool    : ArenaPool,
+    #[cfg( feature = "leak-check" )]
+    tasks         : TaskTracker,
 
     core_handle   : Handle
 }
 
+struct ServiceState {
+    lifecycle          : LifecycleState,
+    shutdown_requested : bool,
+    pending_requests   : HashMap< i64, PendingRequestState >,
+    trace_value        : TraceValue,
+    metrics            : Metrics,
+    shutdown_hooks     : Vec< ShutdownHook >,
+    disconnect_hooks   : Vec< DisconnectHook >,
+
+    client_capabilities  : Option< ClientCapabilities >,
+    client_requests      : HashMap< i64, ClientResponseSend >,
+    client_request_log   : ClientRequestLog,
+    response_order_log   : ResponseOrderLog,
+    dead_letters         : DeadLetters,
+    /// Number of requests received so far, the sequence number of the next request
+    next_request_seq     : u64,
+    progress_tokens      : HashMap< i64, NumberOrString >,
+    deferred_requests    : Vec< DeferredRequest >,
+    /// Documents opened by the client, keyed by their normalized uri, see uri::normalize
+    open_documents       : HashMap< Url, OpenDocument >,
+    notification_waiters : Vec< NotificationWaiter >,
+    /// Method and time the response reached the service of the responses not written yet, by request id
+    unflushed_responses  : HashMap< i64, ( &'static str, Instant ) >,
+
+    response_queue_len : usize,
+    write_queue        : QueueLen,
+    command_queue_len  : usize,
+    // Responses handed to the ResponseWriter that have not yet been pushed to the write queue
+    queued_responses   : usize,
+    // Number of live ReadPause guards, and the reader task to notify once it drops to 0
+    read_pauses        : usize,
+    reader_task        : Option< Task >
+}
+
+/// Document opened by the client through textDocument/didOpen
+struct OpenDocument {
+    version     : i64,
+    language_id : String
+}
+
+struct PendingRequestState {
+    correlation_id : CorrelationId,
+    /// Position of the request among the requests received by the service
+    sequence       : u64,
+    received       : Instant,
+    /// Time the request was handed to the MessageHandler
+    dispatched     : Instant,
+    result_channel : ResponseSender,
+    /// Documents pinned by the handler by normalized uri, with the version the request was computed for
+    pinned         : Vec< ( Url, i64 ) >
+}
+
+struct NotificationWaiter {
+    filter            : NotificationFilter,
+    notification_send : oneshot::Sender< ServerNotification >
+}
+
+/// Request put aside by its handler through ServiceHandle::defer_request, dispatched again by
+/// ServiceHandle::retry_deferred
+struct DeferredRequest {
+    request : ServerRequest,
+    output  : ResponseOutput
+}
+
 enum ServiceCommand {
     SendNotification( ClientNotification ),
+    SendNotifications( Vec< ClientNotification > ),
+    SendRequest( ClientRequest, ClientResponseSend ),
+    ReportSlowRequest( i64 ),
     Shutdown
 }
 
-struct MessageReader< H : MessageHandler + 'static, I : Io + 'static > {
+struct MessageReader< I : Io + 'static > {
+    service             : Rc< Service >,
     service_handle      : ServiceHandle,
-
-    io_read             : IoRead< I >,
+    state               : SharedState,
+
+    io_read             : Transport< I >,
+    /// Requests skipped by the codec, answered before the messages read after them
+    rejected_requests   : RejectedRequests,
+    /// Message read after requests skipped by the codec, dispatched once they were answered
+    /// Message read before the requests the codec skipped ahead of it were answered, with the time it was
+    /// received
+    peeked              : Option< ( IncomingServerMessage, Instant ) >,
+    requeue_read        : RequeueRead,
     response_queue_send : ResponseQueueSend,
-    current_request     : Option< ResponseChannelRead >,
+    message_send        : MessageSend,
+    current_request     : Option< PendingResponse >,
 
-    message_handler     : H
+    message_handler     : SharedHandler
 }
 
 struct ResponseWriter {
-    response_queue_read : ResponseQueueRead,
-    write_queue_send    : WriteQueueSend,
+    service_handle      : ServiceHandle,
 
-    response_future     : Option< ResponseChannelRead >,
+    response_queue_read : ResponseQueueRead,
+    message_send        : MessageSend,
+    state               : SharedState,
+    ordering            : ResponseOrdering,
+
+    /// Responses of the queued requests, polled together so a slow request does not hold back the others
+    pending_responses   : FuturesUnordered< QueuedResponse >,
+    /// Bounds the requests taken out of the response queue and not written yet to the capacity of the
+    /// queue, requests are left in the queue while the limit is reached so the reader waits for room
+    in_flight_limit     : QueueLimit,
+    /// Requests whose response is ready to be written with the time it became ready, by position in the
+    /// response queue
+    completed           : BTreeMap< u64, CompletedResponse >,
+    next_queued         : u64,
+    next_written        : u64,
     response            : Option< OutgoingServerMessage >
 }
 
+type CompletedResponse = ( PendingResponse, Option< ResponseMessage< ServerResponse > >, Instant );
+
+/// Request queued to the ResponseWriter, resolving with its position in the response queue, the request and
+/// its response, None if the request was canceled without a response
+struct QueuedResponse {
+    position : u64,
+    request  : Option< PendingResponse >
+}
+
+/// Sends the messages of the service to the client, writing a message straight to the transport when no
+/// message is waiting to be written and the transport is ready for it, through the write queue otherwise
+#[derive( Clone )]
+struct MessageSend {
+    sink             : SharedSink,
+    /// Single producer of the write queue, shared by the tasks of the event loop sending messages
+    write_queue_send : WriteQueueSend
+}
+
+/// Transport of a service, shared by the message writer and the tasks sending messages
+struct MessageSink< I : Io + 'static > {
+    transport   : Transport< I >,
+    /// Messages encoded and not entirely written to the transport yet
+    frames      : FrameQueue,
+    state       : SharedState,
+    /// Message taken out of the write queue the transport was not ready for
+    buffered    : Option< OutgoingServerMessage >,
+    /// Error writing a message directly, failing the message writer
+    error       : Option< io::Error >,
+    writer_task : Option< Task >,
+    /// Number of messages in the write queue
+    write_queue : QueueLen,
+    /// Set while the message writer waits to retry a failed write, messages go through the write queue
+    /// meanwhile
+    retrying    : bool,
+    clock       : Clock,
+    /// Method and time the response reached the service of the responses encoded since the last flush
+    unflushed   : Vec< ( &'static str, Instant ) >
+}
+
+/// Future writing the messages of the write queue to the transport, and flushing the messages written
+/// directly
+struct MessageWriter< I : Io + 'static > {
+    sink             : Rc< RefCell< MessageSink< I > > >,
+    write_queue_read : WriteQueueRead,
+    retry_policy     : Option< WriteRetryPolicy >,
+    clock            : Clock,
+    handle           : Handle,
+    /// Retries of the failing write so far, reset once a write succeeds
+    retries          : u32,
+    /// Wait before the next retry
+    backoff          : Option< Sleep >
+}
+
+/// Transport a message can be written to without going through the write queue, see MessageSend
+trait DirectWrite {
+
+    /// Writes message to the transport if no message is waiting to be written and the transport is ready
+    /// for it, returns the message otherwise.
+    fn write_direct( &mut self, message : OutgoingServerMessage ) -> Option< OutgoingServerMessage >;
+
+    /// Writes the messages at the front of messages to the transport under the same conditions as
+    /// write_direct, flushing the transport once after the last of them. The messages the transport was not
+    /// ready for are left in messages.
+    fn write_direct_batch( &mut self, messages : &mut VecDeque< OutgoingServerMessage > );
+
+}
+
 struct CommandHandler {
     service_handle       : Rc< Service >,
     command_queue_read   : CommandQueueRead,
-    write_queue_send     : WriteQueueSend,
+    message_send         : MessageSend,
+    state                : SharedState,
 
-    current_notification : Option< OutgoingServerMessage >
+    next_request_id      : i64,
+    /// Messages of the commands handled so far that were not written yet
+    pending_messages     : VecDeque< OutgoingServerMessage >
 }
 
 /// Creates a new service running on the specific tokio Handle, reading and writing messages to the given IO
@@ -185,12 +753,155 @@ struct CommandHandler {
 ///
 /// Returns a ServiceHandle to the provided service. Dropping this handle will not shutdown the server.
 pub fn start_service< H : MessageHandler + 'static, I : Io + 'static >( handle : Handle, message_handler : H, io : I ) -> ServiceHandle {
-    Service::new( handle, message_handler, io )
+    start_service_with_config( handle, ServiceConfig::default( ), message_handler, io )
+}
+
+/// Runs a service on an event loop of its own until the service is shutdown, blocking the current thread.
+///
+/// Returns the reason the service was shutdown, or an error if the event loop could not be created. Meant
+/// for simple servers and tests that do not run other futures alongside the service, others should use
+/// start_service on an event loop they own.
+///
+/// ```ignore
+/// let ( server_io, client_io ) = transport::memory_transport( );
+/// thread::spawn( move | | drive_client( client_io ) );
+///
+/// match service::run_service( handler, server_io )? {
+///     ShutdownReason::Requested => println!( "Client requested shutdown" ),
+///     ShutdownReason::ClientDisconnected => println!( "Client disconnected" ),
+///     ShutdownReason::Error( ref error ) if error.is_disconnect( ) => println!( "Client went away: {}", error ),
+///     ShutdownReason::Error( error ) => println!( "Service failed: {}", error )
+/// }
+/// ```
+pub fn run_service< H : MessageHandler + 'static, I : Io + 'static >( message_handler : H, io : I ) -> io::Result< ShutdownReason > {
+    let mut core = Core::new( )?;
+    let service_handle = start_service( core.handle( ), message_handler, io );
+
+    match core.run( service_handle.get_shutdown_future( ).clone( ) ) {
+        Ok( reason ) => Ok( reason ),
+        Err( error ) => Ok( ShutdownReason::Error( error ) )
+    }
+}
+
+pub(crate) fn start_service_with_config< H : MessageHandler + 'static, I : Io + 'static >( handle : Handle, config : ServiceConfig, message_handler : H, io : I ) -> ServiceHandle {
+    Service::new( handle, config, Rc::new( message_handler ), io )
+}
+
+impl Future for ClientResponseFuture {
+
+    type Item  = Option< ClientResponse >;
+    type Error = RequestError;
+
+    fn poll( &mut self ) -> Poll< Self::Item, Self::Error > {
+        match self.response_read.poll( ) {
+            Ok( Async::Ready( Ok( result ) ) ) => Ok( Async::Ready( result ) ),
+            Ok( Async::Ready( Err( error ) ) ) => Err( error ),
+            Ok( Async::NotReady ) => Ok( Async::NotReady ),
+            // Service dropped the request without a response, only happens on shutdown
+            Err( _ ) => Err( RequestError::Canceled )
+        }
+    }
+
+}
+
+impl < R : ClientRequestMethod > Future for ClientRequestFuture< R > {
+
+    type Item  = R::Result;
+    type Error = RequestError;
+
+    fn poll( &mut self ) -> Poll< Self::Item, Self::Error > {
+        let response = match self.response_future.poll( ) {
+            Ok( Async::Ready( response ) ) => response,
+            Ok( Async::NotReady ) => return Ok( Async::NotReady ),
+            Err( error ) => return Err( error )
+        };
+
+        match R::from_response( response ) {
+            Ok( result ) => Ok( Async::Ready( result ) ),
+            Err( _ ) => {
+                component_error!( Component::Service, "Received invalid response for {} request.", R::METHOD );
+
+                Err( RequestError::InvalidResponse( R::METHOD ) )
+            }
+        }
+    }
+
+}
+
+impl Future for NotificationFuture {
+
+    type Item  = ServerNotification;
+    type Error = RequestError;
+
+    fn poll( &mut self ) -> Poll< Self::Item, Self::Error > {
+        match self.notification_read.poll( ) {
+            Ok( Async::Ready( notification ) ) => Ok( Async::Ready( notification ) ),
+            Ok( Async::NotReady ) => Ok( Async::NotReady ),
+            // Waiter was dropped, only happens on shutdown
+            Err( _ ) => Err( RequestError::Canceled )
+        }
+    }
+
+}
+
+impl ShutdownReason {
+
+    /// Returns true if the service was shutdown because the client went away, either closing the transport
+    /// or dropping the connection, as opposed to a shutdown requested by the server or a fault.
+    pub fn is_client_disconnect( &self ) -> bool {
+        match *self {
+            ShutdownReason::ClientDisconnected => true,
+            ShutdownReason::Error( ref error ) => error.is_disconnect( ),
+            ShutdownReason::Requested => false
+        }
+    }
+
+}
+
+impl ServiceError {
+
+    /// Returns true if the error only means the client went away, e.g. the editor was killed, rather than a
+    /// bug of the client, the transport or the service.
+    pub fn is_disconnect( &self ) -> bool {
+        match *self {
+            ServiceError::Disconnected( _ ) => true,
+            _ => false
+        }
+    }
+
+}
+
+impl fmt::Display for ServiceError {
+
+    fn fmt( &self, f : &mut fmt::Formatter ) -> fmt::Result {
+        match *self {
+            ServiceError::ReadError( ref error ) => write!( f, "Error reading from the transport: {}", error ),
+            ServiceError::WriteError( ref error ) => write!( f, "Error writing to the transport: {}", error ),
+            ServiceError::Disconnected( ref error ) => write!( f, "Client disconnected: {}", error ),
+            ServiceError::ProtocolError( ref error ) => write!( f, "{}", error ),
+            ServiceError::Unknown => write!( f, "Unknown service error" )
+        }
+    }
+
+}
+
+impl error::Error for ServiceError {
+
+    fn description( &self ) -> &str {
+        match *self {
+            ServiceError::ReadError( _ ) => "Error reading from the transport",
+            ServiceError::WriteError( _ ) => "Error writing to the transport",
+            ServiceError::Disconnected( _ ) => "Client disconnected",
+            ServiceError::ProtocolError( _ ) => "Malformed message",
+            ServiceError::Unknown => "Unknown service error"
+        }
+    }
+
 }
 
 impl Future for ShutdownFuture {
 
-    type Item  = ( );
+    type Item  = ShutdownReason;
     type Error = ServiceError;
 
     fn poll( &mut self ) -> Poll< Self::Item, Self::Error > {
@@ -198,20 +909,53 @@ impl Future for ShutdownFuture {
             Ok( Async::Ready( result ) ) => result,
             Ok( Async::NotReady ) => return Ok( Async::NotReady ),
             Err( _ ) => {
-                error!( "Unknown error occured while polling for shutdown." );
+                component_error!( Component::Service, "Unknown error occured while polling for shutdown." );
 
                 return Err( ServiceError::Unknown )
             }
         };
 
         match *result {
-            Ok( _ ) => Ok( Async::Ready( ( ) ) ),
+            Ok( ref reason ) => Ok( Async::Ready( reason.clone( ) ) ),
             Err( ref error ) => Err( error.clone( ) )
         }
     }
 
 }
 
+impl WriteRetryPolicy {
+
+    /// Retries a failing write up to max_retries times, waiting initial_backoff before the first retry and
+    /// up to 32 times longer before the last ones.
+    pub fn new( max_retries : u32, initial_backoff : Duration ) -> Self {
+        WriteRetryPolicy {
+            max_retries     : max_retries,
+            initial_backoff : initial_backoff,
+            max_backoff     : initial_backoff * 32
+        }
+    }
+
+    pub fn max_backoff( mut self, max_backoff : Duration ) -> Self {
+        self.max_backoff = max_backoff;
+
+        self
+    }
+
+    /// Returns the time waited before the given retry, counted from 0.
+    fn backoff( &self, retry : u32 ) -> Duration {
+        let mut backoff = self.initial_backoff;
+        for _ in 0..retry {
+            backoff = match backoff.checked_mul( 2 ) {
+                Some( backoff ) if backoff < self.max_backoff => backoff,
+                _ => return self.max_backoff
+            };
+        }
+
+        backoff.min( self.max_backoff )
+    }
+
+}
+
 impl ResponseOutput {
 
     pub fn send_result( self, result : ServerResponse ) {
@@ -234,115 +978,744 @@ impl ResponseOutput {
         } );
     }
 
-    fn complete( self, response : ResponseMessage< ServerResponse > ) {
-        let ResponseOutput { request_id , result_channel } = self;
-        trace!( "Completing request {} with response {:?}", request_id, response );
+    /// Spawns the given future on the service's event loop and completes this request with its result or
+    /// error once it resolves.
+    pub fn complete_with< F >( self, future : F ) where F : Future< Item = ServerResponse, Error = ResponseError > + Send + 'static {
+        let remote_handle = self.remote_handle.clone( );
+        remote_handle.spawn( move | _ | {
+            future.then( move | result | {
+                match result {
+                    Ok( result ) => self.send_result( result ),
+                    Err( error ) => self.send_error( error )
+                }
 
-        result_channel.complete( response );
+                Ok( ( ) )
+            } )
+        } );
     }
 
-}
+    /// Spawns the given future on the service's event loop like complete_with, answering the request with
+    /// fallback if the future does not resolve within deadline.
+    ///
+    /// Useful for requests the client only waits on for a limited time, e.g. textDocument/willSaveWaitUntil.
+    pub fn complete_with_deadline< F >( self, future : F, deadline : Duration, fallback : ServerResponse ) where F : Future< Item = ServerResponse, Error = ResponseError > + Send + 'static {
+        let remote_handle = self.remote_handle.clone( );
+        remote_handle.spawn( move | handle | {
+            let correlation_id = self.correlation_id;
+            let timeout = future_util::result( self.clock.sleep( deadline, handle ) ).flatten( ).then( move | result | {
+                if let Err( error ) = result {
+                    component_error!( Component::Writer, "[{}] Error creating deadline timer: {}", correlation_id, error );
+                }
 
-impl ServiceHandle {
+                Ok( None )
+            } );
 
-    pub fn get_shutdown_future( &self ) -> &ShutdownFuture {
-        &self.shutdown_future
-    }
+            future.map( Some ).select( timeout ).then( move | result | {
+                match result {
+                    Ok( ( Some( result ), _ ) ) => self.send_result( result ),
+                    Ok( ( None, _ ) ) => {
+                        component_trace!( Component::Writer, "[{}] Request {} missed its deadline.", self.correlation_id, self.request_id );
 
-    pub fn shutdown( &self ) {
-        let moved_command_send = self.command_send.clone( );
-        self.remote_handle.spawn( move | _ | {
-            moved_command_send.send( ServiceCommand::Shutdown ).then( | _ | {
-                Ok( ( ) )
-            } )
-        } );
-    }
+                        self.send_result( fallback )
+                    },
+                    Err( ( error, _ ) ) => self.send_error( error )
+                }
 
-    pub fn send_notification( &self, notification : ClientNotification ) {
-        let moved_command_send = self.command_send.clone( );
-        self.remote_handle.spawn( move | _ | {
-            moved_command_send.send( ServiceCommand::SendNotification( notification ) ).then( | _ | {
                 Ok( ( ) )
             } )
         } );
     }
 
-}
-
-impl Service {
+    /// Returns true if the response to this request will never be written, either because the client
+    /// canceled the request or because the service was shutdown.
+    ///
+    /// Handlers performing expensive work should check this periodically and stop early once it returns true.
+    /// A canceled request should still be completed, the client expects a response to canceled requests.
+    pub fn is_closed( &self ) -> bool {
+        self.result_channel.is_canceled( ) || self.result_channel.is_closed( )
+    }
 
-    fn new< H : MessageHandler + 'static, I : Io + 'static >( core_handle : Handle, message_handler : H, io : I ) -> ServiceHandle {
-        let ( response_queue_send, response_queue_read ) = mpsc::channel( 1024 );
-        let ( write_queue_send, write_queue_read ) = mpsc::channel( 1024 );
-        let ( shutdown_send, shutdown_read ) = oneshot::channel( );
-        let ( command_send, command_read ) = mpsc::channel( 16 );
+    /// Polls whether this request was canceled by the client or the service was shutdown, see is_closed.
+    ///
+    /// Returns NotReady and schedules the current task to be notified when the request is canceled, so
+    /// handlers can select on cancellation while computing a result.
+    pub fn poll_cancel( &mut self ) -> Poll< ( ), ( ) > {
+        self.result_channel.poll_cancel( )
+    }
 
-        let ( io_write, io_read ) = io.framed( ServerCodec::new( ) ).split( );
+    /// Returns the id of this request as sent by the client.
+    pub fn request_id( &self ) -> i64 {
+        self.request_id
+    }
 
-        let shutdown_future = ShutdownFuture {
-            shared_future : shutdown_read.shared( )
-        };
+    /// Returns the method name of this request, e.g. "textDocument/hover".
+    pub fn method( &self ) -> &'static str {
+        self.method
+    }
 
-        let service = Rc::new( Service {
-            shutdown_send : RefCell::new( Some( shutdown_send ) ),
-            shutdown_read : shutdown_future.clone( ),
+    /// Returns the correlation id assigned to this request by the service.
+    pub fn correlation_id( &self ) -> CorrelationId {
+        self.correlation_id
+    }
 
-            command_send  : command_send.clone( ),
+    pub(crate) fn error_responder( &self ) -> ErrorResponder {
+        ErrorResponder {
+            request_id     : self.request_id,
+            correlation_id : self.correlation_id,
+            result_channel : self.result_channel.clone( )
+        }
+    }
 
-            core_handle   : core_handle
-        } );
-        let service_handle = ServiceHandle {
-            shutdown_future : shutdown_future,
-            command_send    : command_send,
+    /// Calls observer with the response of this request once it is sent, including the error sent when the
+    /// output is dropped without a response.
+    pub(crate) fn observe_response< F >( &mut self, observer : F ) where F : FnOnce( &ResponseMessage< ServerResponse > ) + Send + 'static {
+        let observer : ResponseObserver = Box::new( observer );
+        self.result_channel.observe( observer );
+    }
 
-            remote_handle   : service.core_handle.remote( ).clone( )
-        };
+    fn complete( mut self, response : ResponseMessage< ServerResponse > ) {
+        component_trace!( Component::Writer, "[{}] Completing request {} with response {:?}", self.correlation_id, self.request_id, response );
 
-        Service::spawn_message_reader( service.clone( ), service_handle.clone( ), io_read, response_queue_send, message_handler );
-        Service::spawn_response_writer( service.clone( ), response_queue_read, write_queue_send.clone( ) );
-        Service::spawn_message_writer( service.clone( ), write_queue_read, io_write );
-        Service::spawn_command_handler( service.clone( ), command_read, write_queue_send );
+        let result_channel = self.result_channel.take( );
+        match result_channel {
+            Some( result_channel ) => {
+                self.result_channel.notify_observers( &response );
 
-        service_handle
+                result_channel.complete( response )
+            },
+            None => {
+                component_trace!( Component::Writer, "[{}] Discarding response for request {} which was already answered.", self.correlation_id, self.request_id );
+            }
+        }
     }
 
-    fn spawn_message_reader< H : MessageHandler + 'static, I : Io + 'static >( this : Rc< Self >, service_handle : ServiceHandle, io_read : IoRead< I >, response_queue_send : ResponseQueueSend, message_handler : H ) {
-        let reader = MessageReader::new( service_handle, io_read, response_queue_send, message_handler );
+}
+
+impl ErrorResponder {
 
-        Service::spawn_handler_future( this, reader );
+    /// Answers the request with error unless a response was already sent, returns whether the error was sent.
+    ///
+    /// Like ResponseOutput::send_error, the observers of the request are called with the response.
+    pub fn send_error( self, error : ResponseError ) -> bool {
+        match self.result_channel.take( ) {
+            Some( result_channel ) => {
+                component_trace!( Component::Writer, "[{}] Completing request {} with error {:?}", self.correlation_id, self.request_id, error );
+
+                let response = ResponseMessage {
+                    id     : self.request_id,
+                    result : None,
+                    error  : Some( error )
+                };
+                self.result_channel.notify_observers( &response );
+
+                result_channel.complete( response );
+
+                true
+            },
+            None => false
+        }
     }
 
-    fn spawn_message_writer< I : Io + 'static >( this : Rc< Self >, write_queue_read : WriteQueueRead, io_write : IoWrite< I > ) {
-        let write_queue_read_map = write_queue_read.map( | message | {
-            MessageEnvelope {
-                headers : HashMap::new( ),
-                message : message
+}
+
+impl Drop for ReadPause {
+
+    fn drop( &mut self ) {
+        let mut state = self.state.lock( ).unwrap( );
+        state.read_pauses -= 1;
+
+        if state.read_pauses == 0 {
+            component_trace!( Component::Reader, "Resuming reading of messages." );
+
+            if let Some( task ) = state.reader_task.take( ) {
+                task.notify( );
             }
-        } ).map_err( | _ | {
-            io::Error::new( io::ErrorKind::Other, "Error reading from write queue." )
+        }
+    }
+
+}
+
+impl Drop for ResponseOutput {
+
+    fn drop( &mut self ) {
+        let result_channel = match self.result_channel.take( ) {
+            Some( result_channel ) => result_channel,
+            None => return
+        };
+
+        match self.on_drop {
+            DroppedResponsePolicy::InternalError => {
+                component_error!( Component::Writer, "[{}] Request {} dropped without a response.", self.correlation_id, self.request_id );
+
+                let response = ResponseMessage {
+                    id     : self.request_id,
+                    result : None,
+                    error  : Some( ResponseError {
+                        code    : INTERNAL_ERROR,
+                        message : "Request dropped by the server without a response".to_string( )
+                    } )
+                };
+                self.result_channel.notify_observers( &response );
+
+                result_channel.complete( response );
+            },
+            DroppedResponsePolicy::Cancel => {
+                component_trace!( Component::Writer, "[{}] Request {} dropped without a response.", self.correlation_id, self.request_id );
+            }
+        }
+    }
+
+}
+
+impl ServiceHandle {
+
+    pub fn get_shutdown_future( &self ) -> &ShutdownFuture {
+        &self.shutdown_future
+    }
+
+    pub fn shutdown( &self ) {
+        self.send_command( ServiceCommand::Shutdown );
+    }
+
+    pub fn send_notification( &self, notification : ClientNotification ) {
+        self.send_command( ServiceCommand::SendNotification( notification ) );
+    }
+
+    /// Sends a batch of notifications to the client, e.g. the diagnostics of every file after a workspace
+    /// scan. The batch is handed to the service at once, so no other message is written between the
+    /// notifications of the batch, and the notifications are encoded into the transport before it is
+    /// flushed once.
+    pub fn send_notifications( &self, notifications : Vec< ClientNotification > ) {
+        if notifications.is_empty( ) {
+            return;
+        }
+
+        self.send_command( ServiceCommand::SendNotifications( notifications ) );
+    }
+
+    /// Spawns the given future on the service's event loop and completes the request of output with its
+    /// result or error once it resolves, see ResponseOutput::complete_with.
+    pub fn spawn_response< F >( &self, output : ResponseOutput, future : F ) where F : Future< Item = ServerResponse, Error = ResponseError > + Send + 'static {
+        output.complete_with( future );
+    }
+
+    /// Publishes the diagnostics for the document at uri, replacing any diagnostics previously published for it.
+    pub fn publish_diagnostics( &self, uri : Url, diagnostics : Vec< Diagnostic > ) {
+        self.send_notification( ClientNotification::PublishDiagnostics( PublishDiagnosticsParams {
+            uri         : uri,
+            diagnostics : diagnostics
+        } ) );
+    }
+
+    /// Asks the client to display a message to the user.
+    pub fn show_message< S : Into< String > >( &self, typ : MessageType, message : S ) {
+        self.send_notification( ClientNotification::ShowMessage( ShowMessageParams {
+            typ     : typ,
+            message : message.into( )
+        } ) );
+    }
+
+    /// Asks the client to log a message.
+    pub fn log_message< S : Into< String > >( &self, typ : MessageType, message : S ) {
+        self.send_notification( ClientNotification::LogMessage( LogMessageParams {
+            typ     : typ,
+            message : message.into( )
+        } ) );
+    }
+
+    /// Sends a telemetry event to the client.
+    pub fn telemetry( &self, event : Value ) {
+        self.send_notification( ClientNotification::Telemetry( event ) );
+    }
+
+    /// Reports work done progress for a token previously created with window/workDoneProgress/create.
+    pub fn progress( &self, token : NumberOrString, progress : WorkDoneProgress ) {
+        self.send_notification( ClientNotification::Progress( ProgressParams {
+            token : token,
+            value : ProgressParamsValue::WorkDone( progress )
+        } ) );
+    }
+
+    /// Puts a request aside instead of answering it, e.g. because the index it needs is still being built.
+    ///
+    /// The request is passed to the MessageHandler again on the next call to retry_deferred. Deferred
+    /// requests canceled by the client are answered with a REQUEST_CANCELLED error, deferred requests still
+    /// pending on shutdown are answered according to the DroppedResponsePolicy of the service.
+    pub fn defer_request( &self, request : ServerRequest, output : ResponseOutput ) {
+        component_trace!( Component::Service, "[{}] Deferring request {}.", output.correlation_id, output.request_id );
+
+        self.state.lock( ).unwrap( ).deferred_requests.push( DeferredRequest {
+            request : request,
+            output  : output
         } );
-        let writer = io_write.send_all( write_queue_read_map ).map( | _ | {
-            ( )
-        } ).map_err( | err | {
-            ServiceError::WriteError( Rc::new( err ) )
+    }
+
+    /// Dispatches every request deferred through defer_request to the MessageHandler again, in the order
+    /// they were deferred.
+    pub fn retry_deferred( &self ) {
+        let deferred = self.state.lock( ).unwrap( ).deferred_requests.drain( .. ).collect::< Vec< _ > >( );
+        for request in deferred {
+            if let Err( error ) = self.requeue_send.unbounded_send( request ) {
+                component_trace!( Component::Service, "Service shutdown before deferred request {} was retried.", error.into_inner( ).output.request_id );
+            }
+        }
+    }
+
+    /// Pins the pending request with the given id to version of the document uri, see Context::pin_document.
+    pub(crate) fn pin_document( &self, request_id : i64, uri : Url, version : i64 ) {
+        if let Some( request ) = self.state.lock( ).unwrap( ).pending_requests.get_mut( &request_id ) {
+            request.pinned.push( ( uri::normalize( &uri ), version ) );
+        }
+    }
+
+    /// Sends a request to the client, returning a future that completes with the result sent by the client.
+    ///
+    /// A result of None means the client responded with a null result.
+    pub fn send_request( &self, request : ClientRequest ) -> ClientResponseFuture {
+        let ( response_send, response_read ) = oneshot::channel( );
+        self.send_command( ServiceCommand::SendRequest( request, response_send ) );
+
+        ClientResponseFuture {
+            response_read : response_read
+        }
+    }
+
+    /// Sends a request of method R to the client, returning a future that completes with the typed result
+    /// sent by the client.
+    ///
+    /// ```ignore
+    /// service.request::< ApplyEditRequest >( ApplyWorkspaceEditParams { edit : edit } )
+    /// ```
+    pub fn request< R : ClientRequestMethod >( &self, params : R::Params ) -> ClientRequestFuture< R > {
+        ClientRequestFuture {
+            response_future : self.send_request( R::into_request( params ) ),
+            method          : PhantomData
+        }
+    }
+
+    /// Spawns future on the event loop of the service.
+    pub fn spawn< F >( &self, future : F ) where F : Future< Item = ( ), Error = ( ) > + Send + 'static {
+        self.remote_handle.spawn( move | _ | future );
+    }
+
+    /// Returns the internal tasks of the service that are still alive, e.g. the message reader and writer.
+    ///
+    /// Requests whose ResponseOutput was neither completed nor dropped are reported as "pending response"
+    /// tasks, a handler holding on to an output after the shutdown leaks it.
+    ///
+    /// Every internal task stops once the ShutdownFuture of the service resolves, as soon as the event loop
+    /// runs it again, so tests can check for leaked tasks after a shutdown:
+    ///
+    /// ```ignore
+    /// service.shutdown( );
+    /// core.run( service.get_shutdown_future( ).clone( ) )?;
+    /// core.turn( Some( Duration::from_millis( 0 ) ) );
+    ///
+    /// assert_eq!( service.live_tasks( ), vec![ ] );
+    /// ```
+    #[cfg( feature = "leak-check" )]
+    pub fn live_tasks( &self ) -> Vec< LiveTask > {
+        self.tasks.live( )
+    }
+
+    /// Returns the clock of the service, see ServiceBuilder::clock.
+    pub fn clock( &self ) -> &Clock {
+        &self.clock
+    }
+
+    /// Returns the pool the arenas of the messages handled by the service take their chunks from.
+    pub(crate) fn arena_pool( &self ) -> &ArenaPool {
+        &self.arena_pool
+    }
+
+    /// Takes a snapshot of the internal state of the service.
+    pub fn debug_dump( &self ) -> DebugDump {
+        let now = self.clock.now( );
+        let state = self.state.lock( ).unwrap( );
+
+        let mut pending_requests : Vec< PendingRequest > = state.pending_requests.iter( ).map( | ( &id, request ) | {
+            PendingRequest {
+                id             : id,
+                correlation_id : request.correlation_id,
+                age            : now.duration_since( request.received )
+            }
+        } ).collect( );
+        pending_requests.sort_by_key( | request | request.id );
+
+        DebugDump {
+            lifecycle          : state.lifecycle,
+            pending_requests   : pending_requests,
+
+            response_queue_len : state.response_queue_len,
+            write_queue_len    : state.write_queue.len( ),
+            command_queue_len  : state.command_queue_len
+        }
+    }
+
+    /// Logs a debug dump of the service every time the process receives SIGUSR1, until the service is
+    /// shutdown.
+    #[cfg( all( unix, feature = "signal" ) )]
+    pub fn dump_on_sigusr1( &self, handle : &Handle ) {
+        let service_handle = self.clone( );
+        let dumper = Signal::new( SIGUSR1, handle ).flatten_stream( ).for_each( move | _ | {
+            component_info!( Component::Service, "Service state: {:?}", service_handle.debug_dump( ) );
+
+            Ok( ( ) )
+        } ).map_err( | error | {
+            component_error!( Component::Service, "Error listening for SIGUSR1: {}", error );
+        } );
+
+        let shutdown_notif = self.shutdown_future.clone( ).then( | _ | {
+            Ok( ( ) )
+        } );
+
+        handle.spawn( dumper.select( shutdown_notif ).then( | _ | {
+            Ok( ( ) )
+        } ) );
+    }
+
+    /// Returns the shared state of type T registered through ServiceBuilder::shared_state, if any.
+    #[deprecated( note = "Use ServiceHandle::extension, shared state and extensions are the same values" )]
+    pub fn shared_state< T : Any + Send + Sync >( &self ) -> Option< Arc< T > > {
+        self.extension::< T >( )
+    }
+
+    /// Returns the extension of type T inserted through ServiceBuilder::insert_extension, if any.
+    pub fn extension< T : Any + Send + Sync >( &self ) -> Option< Arc< T > > {
+        self.state_map.get::< T >( )
+    }
+
+    /// Returns a snapshot of the per-method request counters and latency histograms collected by the service.
+    pub fn metrics( &self ) -> MetricsSnapshot {
+        self.state.lock( ).unwrap( ).metrics.snapshot( )
+    }
+
+    /// Returns the most recent requests sent to the client, oldest first, with their latency and outcome.
+    pub fn client_request_log( &self ) -> Vec< ClientRequestRecord > {
+        self.state.lock( ).unwrap( ).client_request_log.records( )
+    }
+
+    /// Returns the most recent responses written by the service, in the order they were written, with the
+    /// sequence numbers of their requests. Empty unless enabled with
+    /// ServiceBuilder::response_order_log_capacity, see audit::check_response_order.
+    pub fn response_order_log( &self ) -> Vec< ResponseRecord > {
+        self.state.lock( ).unwrap( ).response_order_log.records( )
+    }
+
+    /// Returns the most recent messages skipped because they could not be decoded, oldest first. Empty
+    /// unless enabled with ServiceBuilder::quarantine_malformed_messages.
+    pub fn quarantined_messages( &self ) -> Vec< QuarantinedMessage > {
+        self.state.lock( ).unwrap( ).dead_letters.messages( )
+    }
+
+    /// Registers a hook that is called with the shutdown reason and the final metrics of the service when
+    /// the service shuts down.
+    ///
+    /// Hooks are called on the IO thread in the order they were registered. Hooks registered after the
+    /// service has shutdown are never called.
+    pub fn on_shutdown< F >( &self, hook : F ) where F : FnOnce( &ShutdownReason, MetricsSnapshot ) + Send + 'static {
+        let mut state = self.state.lock( ).unwrap( );
+        if state.lifecycle == LifecycleState::Running {
+            state.shutdown_hooks.push( Box::new( hook ) );
+        }
+    }
+
+    /// Returns a future that completes with the next notification received from the client for which filter
+    /// returns true, e.g. to wait for the initialized notification from a background task.
+    ///
+    /// The notification is still dispatched to the MessageHandler. The future fails with
+    /// RequestError::Canceled if the service shuts down first.
+    ///
+    /// ```ignore
+    /// let initialized = service.wait_for_notification( | notification | {
+    ///     notification.method_name( ) == InitializedNotification::METHOD
+    /// } );
+    /// ```
+    pub fn wait_for_notification< F >( &self, filter : F ) -> NotificationFuture where F : Fn( &ServerNotification ) -> bool + Send + 'static {
+        let ( notification_send, notification_read ) = oneshot::channel( );
+        self.state.lock( ).unwrap( ).notification_waiters.push( NotificationWaiter {
+            filter            : Box::new( filter ),
+            notification_send : notification_send
+        } );
+
+        NotificationFuture {
+            notification_read : notification_read
+        }
+    }
+
+    /// Registers a hook that is called when the client closes its end of the transport or the connection is
+    /// dropped while reading or writing a message, e.g. because the editor crashed.
+    ///
+    /// Disconnect hooks are called before the service shuts down and the hooks registered through
+    /// on_shutdown run, they are not called for a shutdown requested through ServiceHandle::shutdown or
+    /// a shutdown on any other error.
+    pub fn on_disconnect< F >( &self, hook : F ) where F : FnOnce( ) + Send + 'static {
+        let mut state = self.state.lock( ).unwrap( );
+        if state.lifecycle == LifecycleState::Running {
+            state.disconnect_hooks.push( Box::new( hook ) );
+        }
+    }
+
+    /// Stops reading messages from the client until the returned guard is dropped, e.g. while a handler is
+    /// busy processing a flood of didChange notifications.
+    ///
+    /// Messages already read are still dispatched, further messages queue up in the transport so the client
+    /// is slowed down instead of the service buffering unbounded work.
+    pub fn pause_reading( &self ) -> ReadPause {
+        let mut state = self.state.lock( ).unwrap( );
+        if state.read_pauses == 0 {
+            component_trace!( Component::Reader, "Pausing reading of messages." );
+        }
+        state.read_pauses += 1;
+
+        ReadPause {
+            state : self.state.clone( )
+        }
+    }
+
+    /// Returns the capabilities sent by the client in its initialize request, None if the service has not
+    /// been initialized yet.
+    pub fn client_capabilities( &self ) -> Option< ClientCapabilities > {
+        self.state.lock( ).unwrap( ).client_capabilities.clone( )
+    }
+
+    /// Returns true once the service received the initialize request.
+    pub fn is_initialized( &self ) -> bool {
+        self.state.lock( ).unwrap( ).client_capabilities.is_some( )
+    }
+
+    /// Returns true if the client supports server initiated work done progress through
+    /// window/workDoneProgress/create.
+    pub fn supports_work_done_progress( &self ) -> bool {
+        self.state.lock( ).unwrap( ).supports_work_done_progress( )
+    }
+
+    /// Returns true if the client opened the document uri through textDocument/didOpen and has not closed
+    /// it since.
+    pub fn is_document_open( &self, uri : &Url ) -> bool {
+        self.state.lock( ).unwrap( ).open_documents.contains_key( &uri::normalize( uri ) )
+    }
+
+    /// Returns the version of the document uri sent in the last textDocument/didOpen or didChange
+    /// notification, None if the document is not open.
+    pub fn document_version( &self, uri : &Url ) -> Option< i64 > {
+        self.state.lock( ).unwrap( ).open_documents.get( &uri::normalize( uri ) ).map( | document | document.version )
+    }
+
+    /// Returns the languageId the client sent when it opened the document uri, None if the document is not
+    /// open.
+    pub fn document_language( &self, uri : &Url ) -> Option< String > {
+        self.state.lock( ).unwrap( ).open_documents.get( &uri::normalize( uri ) ).map( | document | document.language_id.clone( ) )
+    }
+
+    /// Returns the trace level last requested by the client, either in its initialize request or through a
+    /// $/setTrace notification.
+    pub fn trace_value( &self ) -> TraceValue {
+        self.state.lock( ).unwrap( ).trace_value
+    }
+
+    /// Mirrors a message level trace to the client as a $/logTrace notification, if the client has enabled
+    /// tracing. The verbose details are only generated when the client requested verbose tracing.
+    fn log_trace< F : FnOnce( ) -> String >( &self, message : String, verbose : F ) {
+        let verbose = match self.trace_value( ) {
+            TraceValue::Off => return,
+            TraceValue::Messages => None,
+            TraceValue::Verbose => Some( verbose( ) )
+        };
+
+        self.send_notification( ClientNotification::LogTrace( LogTraceParams {
+            message : message,
+            verbose : verbose
+        } ) );
+    }
+
+    fn send_command( &self, command : ServiceCommand ) {
+        self.state.lock( ).unwrap( ).command_queue_len += 1;
+
+        let moved_command_send = self.command_send.clone( );
+        let moved_state = self.state.clone( );
+        self.remote_handle.spawn( move | _ | {
+            moved_command_send.send( command ).then( move | result | {
+                // The command handler is gone once the service is shutdown, the command never reaches the queue
+                if result.is_err( ) {
+                    moved_state.lock( ).unwrap( ).command_queue_len -= 1;
+                }
+
+                Ok( ( ) )
+            } )
+        } );
+    }
+
+}
+
+impl Service {
+
+    fn new< I : Io + 'static >( core_handle : Handle, config : ServiceConfig, message_handler : SharedHandler, io : I ) -> ServiceHandle {
+        let ( response_queue_send, response_queue_read ) = response_slots::response_queue( config.response_queue_capacity );
+        let ( write_queue_send, write_queue_read ) = queue::spsc( config.write_queue_capacity );
+        let ( shutdown_send, shutdown_read ) = oneshot::channel( );
+        let ( command_send, command_read ) = mpsc::channel( 16 );
+        let ( requeue_send, requeue_read ) = mpsc::unbounded( );
+
+        let dead_letters = DeadLetters::new( );
+        let rejected_requests = RejectedRequests::default( );
+        let transport = Rc::new( RefCell::new( io.framed( ServiceCodec::new( config.quarantine_threshold, dead_letters.clone( ), rejected_requests.clone( ), config.clock.clone( ) ) ) ) );
+
+        let shutdown_future = ShutdownFuture {
+            shared_future : shutdown_read.shared( )
+        };
+        let state = Arc::new( Mutex::new( ServiceState {
+            lifecycle          : LifecycleState::Running,
+            shutdown_requested : false,
+            pending_requests   : HashMap::new( ),
+            trace_value        : TraceValue::Off,
+            metrics            : Metrics::new( ),
+            shutdown_hooks     : Vec::new( ),
+            disconnect_hooks   : Vec::new( ),
+
+            client_capabilities  : None,
+            client_requests      : HashMap::new( ),
+            client_request_log   : ClientRequestLog::new( config.client_request_log_capacity, config.clock.clone( ) ),
+            response_order_log   : ResponseOrderLog::new( config.response_order_log_capacity ),
+            dead_letters         : dead_letters,
+            next_request_seq     : 0,
+            progress_tokens      : HashMap::new( ),
+            deferred_requests    : Vec::new( ),
+            open_documents       : HashMap::new( ),
+            notification_waiters : Vec::new( ),
+            unflushed_responses  : HashMap::new( ),
+
+            response_queue_len : 0,
+            write_queue        : write_queue_send.len_handle( ),
+            command_queue_len  : 0,
+            queued_responses   : 0,
+            read_pauses        : 0,
+            reader_task        : None
+        } ) );
+
+        let service = Rc::new( Service {
+            shutdown_send : RefCell::new( Some( shutdown_send ) ),
+            shutdown_read : shutdown_future.clone( ),
+            handler_hooks : RefCell::new( Vec::new( ) ),
+
+            command_send  : command_send,
+            requeue_send  : requeue_send,
+            state         : state,
+            state_map     : Arc::new( config.state_map.clone( ) ),
+            config        : config,
+            arena_pool    : ArenaPool::new( ),
+            #[cfg( feature = "leak-check" )]
+            tasks         : TaskTracker::new( ),
+
+            core_handle   : core_handle
         } );
+        let service_handle = service.service_handle( );
+
+        let sink = Rc::new( RefCell::new( MessageSink {
+            transport   : transport.clone( ),
+            frames      : FrameQueue::new( ),
+            state       : service.state.clone( ),
+            buffered    : None,
+            error       : None,
+            writer_task : None,
+            write_queue : write_queue_send.len_handle( ),
+            retrying    : false,
+            clock       : service.config.clock.clone( ),
+            unflushed   : Vec::new( )
+        } ) );
+        let message_send = MessageSend {
+            sink             : sink.clone( ),
+            write_queue_send : Rc::new( write_queue_send )
+        };
+
+        Service::spawn_message_reader( service.clone( ), service_handle.clone( ), transport, rejected_requests, requeue_read, response_queue_send, message_send.clone( ), message_handler );
+        Service::spawn_response_writer( service.clone( ), service_handle.clone( ), response_queue_read, message_send.clone( ) );
+        Service::spawn_message_writer( service.clone( ), write_queue_read, sink );
+        Service::spawn_command_handler( service.clone( ), command_read, message_send );
+        Service::spawn_heartbeat( service.clone( ) );
+
+        service_handle
+    }
+
+    fn spawn_message_reader< I : Io + 'static >( this : Rc< Self >, service_handle : ServiceHandle, io_read : Transport< I >, rejected_requests : RejectedRequests, requeue_read : RequeueRead, response_queue_send : ResponseQueueSend, message_send : MessageSend, message_handler : SharedHandler ) {
+        let hook_handler = message_handler.clone( );
+        let hook_service_handle = service_handle.clone( );
+        this.handler_hooks.borrow_mut( ).push( Box::new( move | reason | {
+            hook_handler.on_shutdown( hook_service_handle, reason );
+        } ) );
 
-        Service::spawn_handler_future( this, writer );
+        let reader = MessageReader::new( this.clone( ), service_handle, io_read, rejected_requests, requeue_read, response_queue_send, message_send, message_handler );
+
+        Service::spawn_handler_future( this, "message reader", reader );
+    }
+
+    fn spawn_message_writer< I : Io + 'static >( this : Rc< Self >, write_queue_read : WriteQueueRead, sink : Rc< RefCell< MessageSink< I > > > ) {
+        let writer = MessageWriter {
+            sink             : sink,
+            write_queue_read : write_queue_read,
+            retry_policy     : this.config.write_retry,
+            clock            : this.config.clock.clone( ),
+            handle           : this.core_handle.clone( ),
+            retries          : 0,
+            backoff          : None
+        };
+
+        Service::spawn_handler_future( this, "message writer", writer );
+    }
+
+    fn spawn_response_writer( this : Rc< Self >, service_handle : ServiceHandle, response_queue_read : ResponseQueueRead, message_send : MessageSend ) {
+        let writer = ResponseWriter::new( service_handle, this.state.clone( ), &this.config, response_queue_read, message_send );
+
+        Service::spawn_handler_future( this, "response writer", writer );
     }
 
-    fn spawn_response_writer( this : Rc< Self >, response_queue_read : ResponseQueueRead, write_queue_send : WriteQueueSend ) {
-        let writer = ResponseWriter::new( response_queue_read, write_queue_send );
+    fn spawn_command_handler( this : Rc< Self >, command_queue_read : CommandQueueRead, message_send : MessageSend ) {
+        let handler = CommandHandler::new( this.clone( ), this.state.clone( ), command_queue_read, message_send );
 
-        Service::spawn_handler_future( this, writer );
+        Service::spawn_handler_future( this, "command handler", handler );
     }
 
-    fn spawn_command_handler( this : Rc< Self >, command_queue_read : CommandQueueRead, write_queue_send : WriteQueueSend ) {
-        let handler = CommandHandler::new( this.clone( ), command_queue_read, write_queue_send );
+    fn spawn_heartbeat( this : Rc< Self > ) {
+        let interval = match this.config.heartbeat_interval {
+            Some( interval ) => interval,
+            None => return
+        };
+        let ticks = match this.config.clock.interval( interval, &this.core_handle ) {
+            Ok( ticks ) => ticks,
+            Err( error ) => {
+                component_error!( Component::Service, "Error creating heartbeat timer: {}", error );
+
+                return;
+            }
+        };
+
+        let clock = this.config.clock.clone( );
+        let started = clock.now( );
+        let service_handle = this.service_handle( );
+        let mut sequence : u64 = 0;
+        let heartbeat = ticks.for_each( move | _ | {
+            sequence += 1;
+
+            let uptime = clock.elapsed( started );
+            let pending_requests = service_handle.state.lock( ).unwrap( ).pending_requests.len( );
+            service_handle.telemetry( json!( {
+                "type"             : "ls_service/heartbeat",
+                "sequence"         : sequence,
+                "uptime_ms"        : uptime.as_secs( ) * 1000 + ( uptime.subsec_nanos( ) / 1_000_000 ) as u64,
+                "pending_requests" : pending_requests
+            } ) );
+
+            Ok( ( ) )
+        } ).or_else( | error | {
+            component_error!( Component::Service, "Error waiting for heartbeat timer: {}", error );
+
+            Ok( ( ) )
+        } );
 
-        Service::spawn_handler_future( this, handler );
+        Service::spawn_handler_future( this, "heartbeat", heartbeat );
     }
 
-    fn spawn_handler_future< F >( this : Rc< Self >, f : F ) where F : Future< Item = ( ), Error = ServiceError > + 'static {
+    fn spawn_handler_future< F >( this : Rc< Self >, name : &'static str, f : F ) where F : Future< Item = ( ), Error = ServiceError > + 'static {
         let our_this = this.clone( );
 
         let mapped_err = f.map_err( move | service_err | {
@@ -361,20 +1734,46 @@ impl Service {
             ( )
         } );
 
-        our_this.spawn( select );
+        our_this.spawn( name, select );
+    }
+
+    fn service_handle( &self ) -> ServiceHandle {
+        ServiceHandle {
+            shutdown_future : self.shutdown_read.clone( ),
+            command_send    : self.command_send.clone( ),
+            requeue_send    : self.requeue_send.clone( ),
+            state           : self.state.clone( ),
+            state_map       : self.state_map.clone( ),
+            clock           : self.config.clock.clone( ),
+            arena_pool      : self.arena_pool.clone( ),
+            #[cfg( feature = "leak-check" )]
+            tasks           : self.tasks.clone( ),
+
+            remote_handle   : self.core_handle.remote( ).clone( )
+        }
     }
 
-    fn spawn< F >( &self, f : F ) where F : Future< Item = ( ), Error = ( ) > + 'static {
+    fn spawn< F >( &self, name : &'static str, f : F ) where F : Future< Item = ( ), Error = ( ) > + 'static {
+        #[cfg( feature = "leak-check" )]
+        let f = self.tasks.track( name, f );
+        #[cfg( not( feature = "leak-check" ) )]
+        let _ = name;
+
         self.core_handle.spawn( f );
     }
 
     fn shutdown( &self ) {
+        self.shutdown_with_reason( ShutdownReason::Requested );
+    }
+
+    fn shutdown_with_reason( &self, reason : ShutdownReason ) {
         let channel = self.shutdown_send.borrow_mut( ).take( );
         match channel {
             Some( channel ) => {
-                trace!( "Shutting down service." );
+                component_trace!( Component::Service, "Shutting down service: {:?}", reason );
+                self.run_shutdown_hooks( LifecycleState::Shutdown, reason.clone( ) );
 
-                channel.complete( Ok( ( ) ) );
+                channel.complete( Ok( reason ) );
             },
             None => { }
         }
@@ -384,7 +1783,11 @@ impl Service {
         let channel = self.shutdown_send.borrow_mut( ).take( );
         match channel {
             Some( channel ) => {
-                error!( "Server shutting down with error {:?}", error );
+                component_error!( Component::Service, "Server shutting down with error {:?}", error );
+                if error.is_disconnect( ) {
+                    self.run_disconnect_hooks( );
+                }
+                self.run_shutdown_hooks( LifecycleState::Failed, ShutdownReason::Error( error.clone( ) ) );
 
                 channel.complete( Err( error ) )
             },
@@ -392,187 +1795,1060 @@ impl Service {
         }
     }
 
+    fn run_disconnect_hooks( &self ) {
+        let hooks = self.state.lock( ).unwrap( ).disconnect_hooks.drain( .. ).collect::< Vec< _ > >( );
+        for hook in hooks {
+            hook( );
+        }
+    }
+
+    fn run_shutdown_hooks( &self, lifecycle : LifecycleState, reason : ShutdownReason ) {
+        let ( hooks, metrics, deferred ) = {
+            let mut state = self.state.lock( ).unwrap( );
+            state.lifecycle = lifecycle;
+
+            state.notification_waiters.clear( );
+
+            ( state.shutdown_hooks.drain( .. ).collect::< Vec< _ > >( ), state.metrics.snapshot( ), state.deferred_requests.drain( .. ).collect::< Vec< _ > >( ) )
+        };
+        // Answers deferred requests according to the DroppedResponsePolicy
+        drop( deferred );
+
+        let handler_hooks = self.handler_hooks.borrow_mut( ).drain( .. ).collect::< Vec< _ > >( );
+        for hook in handler_hooks {
+            hook( &reason );
+        }
+
+        for hook in hooks {
+            hook( &reason, metrics.clone( ) );
+        }
+    }
+
+}
+
+impl < I : Io + 'static > MessageReader< I > {
+
+    fn new( service : Rc< Service >, service_handle : ServiceHandle, io_read : Transport< I >, rejected_requests : RejectedRequests, requeue_read : RequeueRead, response_queue_send : ResponseQueueSend, message_send : MessageSend, message_handler : SharedHandler ) -> Self {
+        MessageReader {
+            state               : service.state.clone( ),
+            service             : service,
+            service_handle      : service_handle,
+
+            io_read             : io_read,
+            rejected_requests   : rejected_requests,
+            peeked              : None,
+            requeue_read        : requeue_read,
+            response_queue_send : response_queue_send,
+            message_send        : message_send,
+            current_request     : None,
+
+            message_handler     : message_handler
+        }
+    }
+
+    /// Reads the next message from the client with the time its frame started arriving, None once the client
+    /// closed its end of the transport.
+    fn next_message( &mut self ) -> Poll< Option< ( IncomingServerMessage, Instant ) >, ServiceError > {
+        let message = self.io_read.borrow_mut( ).poll( );
+        match message {
+            Ok( Async::Ready( Some( val ) ) ) => {
+                component_trace!( Component::Codec, "Decoded message with headers {:?}", val.envelope.headers );
+
+                Ok( Async::Ready( Some( ( val.envelope.message, val.received ) ) ) )
+            },
+            Ok( Async::Ready( None ) ) => {
+                component_info!( Component::Reader, "Client closed the transport." );
+
+                Ok( Async::Ready( None ) )
+            },
+            Ok( Async::NotReady ) => {
+                // The codec may have skipped requests before running out of bytes, they are answered right away
+                if !self.rejected_requests.borrow( ).is_empty( ) {
+                    task::current( ).notify( );
+                }
+
+                Ok( Async::NotReady )
+            },
+            Err( error ) => {
+                component_error!( Component::Codec, "Error reading message: {}", error );
+
+                if codec::is_protocol_error( &error ) {
+                    Err( ServiceError::ProtocolError( Rc::new( error ) ) )
+                }
+                else if is_disconnect( &error ) {
+                    Err( ServiceError::Disconnected( Rc::new( error ) ) )
+                }
+                else {
+                    Err( ServiceError::ReadError( Rc::new( error ) ) )
+                }
+            }
+        }
+    }
+
+    /// Answers request id, skipped by the codec because it could not be decoded, with an INVALID_PARAMS error.
+    /// Returns the request if its response has to go through the response queue.
+    fn reject_request( &mut self, id : i64 ) -> Option< PendingResponse > {
+        let correlation_id = CorrelationId::next( );
+        component_info!( Component::Reader, "[{}] Rejecting request {} that could not be decoded.", correlation_id, id );
+
+        let ( result_channel, pending_response ) = self.response_queue_send.insert( id, correlation_id, MALFORMED_REQUEST );
+        if let Some( result_channel ) = result_channel.take( ) {
+            result_channel.complete( ResponseMessage {
+                id     : id,
+                result : None,
+                error  : Some( ResponseError {
+                    code    : INVALID_PARAMS,
+                    message : "Request could not be decoded".to_string( )
+                } )
+            } );
+        }
+
+        self.write_immediate_response( pending_response )
+    }
+
+    /// Records the time request id spent between its frame starting to arrive and being handed to the
+    /// MessageHandler.
+    fn record_dispatch( &self, id : i64, method : &'static str, received : Instant ) {
+        let dispatched = self.service.config.clock.now( );
+
+        let mut state = self.state.lock( ).unwrap( );
+        state.metrics.record_latency( method, LatencyStage::Dispatch, dispatched.duration_since( received ) );
+        if let Some( pending ) = state.pending_requests.get_mut( &id ) {
+            pending.dispatched = dispatched;
+        }
+    }
+
+    /// Dispatches a request to the lifecycle callbacks of the MessageHandler, falling back to handle_request.
+    fn dispatch_request( &self, request : ServerRequest, output : ResponseOutput ) {
+        match request {
+            ServerRequest::Initialize( params ) => {
+                match self.message_handler.on_initialize( self.service_handle.clone( ), &params ) {
+                    Some( result ) => output.send_result( ServerResponse::Initialize( result ) ),
+                    None => self.message_handler.handle_request( self.service_handle.clone( ), ServerRequest::Initialize( params ), output )
+                }
+            },
+            ServerRequest::Shutdown => {
+                if self.message_handler.on_shutdown_request( self.service_handle.clone( ) ) {
+                    output.send_result( ServerResponse::Shutdown );
+                }
+                else {
+                    self.message_handler.handle_request( self.service_handle.clone( ), ServerRequest::Shutdown, output );
+                }
+            },
+            request => {
+                if self.state.lock( ).unwrap( ).shutdown_requested {
+                    component_trace!( Component::Reader, "[{}] Rejecting request {} received after shutdown.", output.correlation_id, output.request_id );
+
+                    return output.send_error( ResponseError {
+                        code    : INVALID_REQUEST,
+                        message : "Request received after shutdown".to_string( )
+                    } );
+                }
+
+                self.message_handler.handle_request( self.service_handle.clone( ), request, output );
+            }
+        }
+    }
+
+    /// Completes the futures returned by ServiceHandle::wait_for_notification that are waiting for the given
+    /// notification, dropping waiters whose future was dropped.
+    fn notify_waiters( &self, notification : &ServerNotification ) {
+        let mut state = self.state.lock( ).unwrap( );
+        if state.notification_waiters.is_empty( ) {
+            return;
+        }
+
+        let waiters = state.notification_waiters.drain( .. ).collect::< Vec< _ > >( );
+        for waiter in waiters {
+            if waiter.notification_send.is_canceled( ) {
+                continue;
+            }
+
+            if ( waiter.filter )( notification ) {
+                waiter.notification_send.complete( notification.clone( ) );
+            }
+            else {
+                state.notification_waiters.push( waiter );
+            }
+        }
+    }
+
+    fn push_response_future( &mut self, response_future : PendingResponse ) -> Poll< ( ), ServiceError > {
+        match self.response_queue_send.start_send( response_future ) {
+            Ok( AsyncSink::Ready ) => {
+                self.state.lock( ).unwrap( ).response_queue_len += 1;
+
+                Ok( Async::Ready( ( ) ) )
+            },
+            Ok( AsyncSink::NotReady( response_future ) ) => {
+                self.current_request = Some( response_future );
+
+                Ok( Async::NotReady )
+            },
+            Err( _ ) => {
+                component_error!( Component::Reader, "Error pushing response future to response channel." );
+
+                Err( ServiceError::Unknown )
+            }
+        }
+    }
+
+    /// Writes the response of a request straight to the write queue if the handler completed it before
+    /// returning and no earlier response is waiting to be written, or responses are written as they
+    /// complete, skipping the hop through the ResponseWriter. Returns the request if its response has to go through the response queue.
+    fn write_immediate_response( &mut self, mut request : PendingResponse ) -> Option< PendingResponse > {
+        let can_skip_queue = self.service.config.response_ordering == ResponseOrdering::Completion || self.state.lock( ).unwrap( ).queued_responses == 0;
+        if can_skip_queue {
+            if let Ok( Async::Ready( ( ) ) ) = self.message_send.poll_ready( ) {
+                match request.poll( ) {
+                    Ok( Async::Ready( response ) ) => {
+                        let responded = self.service.config.clock.now( );
+                        finish_request( &self.service_handle, &request, Some( &response ), responded );
+
+                        match self.message_send.start_send( OutgoingMessage::Response( response ) ) {
+                            Ok( AsyncSink::Ready ) => { },
+                            _ => {
+                                component_error!( Component::Reader, "[{}] Error writing response to write queue.", request.correlation_id );
+                            }
+                        }
+
+                        return None;
+                    },
+                    Ok( Async::NotReady ) => { },
+                    Err( _ ) => {
+                        finish_request( &self.service_handle, &request, None, self.service.config.clock.now( ) );
+
+                        return None;
+                    }
+                }
+            }
+        }
+
+        self.state.lock( ).unwrap( ).queued_responses += 1;
+        Some( request )
+    }
+
+    fn watch_slow_request( &self, request_id : i64, correlation_id : CorrelationId ) {
+        let threshold = match self.service.config.slow_request_progress {
+            Some( threshold ) => threshold,
+            None => return
+        };
+        if !self.state.lock( ).unwrap( ).supports_work_done_progress( ) {
+            return;
+        }
+
+        let timeout = match self.service.config.clock.sleep( threshold, &self.service.core_handle ) {
+            Ok( timeout ) => timeout,
+            Err( error ) => {
+                component_error!( Component::Reader, "[{}] Error creating slow request timer: {}", correlation_id, error );
+
+                return;
+            }
+        };
+
+        let service_handle = self.service_handle.clone( );
+        let watcher = timeout.then( move | _ | {
+            service_handle.send_command( ServiceCommand::ReportSlowRequest( request_id ) );
+
+            Ok( ( ) )
+        } );
+
+        Service::spawn_handler_future( self.service.clone( ), "slow request watcher", watcher );
+    }
+
+    fn cancel_request( &self, request_id : i64 ) {
+        let deferred = {
+            let mut state = self.state.lock( ).unwrap( );
+            match state.pending_requests.get( &request_id ) {
+                Some( request ) => {
+                    component_trace!( Component::Reader, "[{}] Client canceled request {}.", request.correlation_id, request_id );
+
+                    request.result_channel.cancel( );
+                },
+                None => {
+                    component_trace!( Component::Reader, "Client canceled request {} which is not pending.", request_id );
+                }
+            }
+
+            let position = state.deferred_requests.iter( ).position( | deferred | deferred.output.request_id == request_id );
+            position.map( | position | state.deferred_requests.remove( position ) )
+        };
+
+        if let Some( deferred ) = deferred {
+            deferred.output.send_error( ResponseError {
+                code    : REQUEST_CANCELLED,
+                message : "Request canceled by the client".to_string( )
+            } );
+        }
+    }
+
+    /// Cancels the pending requests that pinned uri to a version other than version, answering them with a
+    /// CONTENT_MODIFIED error under PinnedDocumentPolicy::ContentModified.
+    fn document_changed( &self, uri : &Url, version : i64 ) {
+        let normalized = uri::normalize( uri );
+        let mut modified_requests = Vec::new( );
+        {
+            let state = self.state.lock( ).unwrap( );
+            for ( request_id, request ) in &state.pending_requests {
+                let modified = request.pinned.iter( ).any( | &( ref pinned_uri, pinned_version ) | {
+                    *pinned_uri == normalized && pinned_version != version
+                } );
+                if !modified {
+                    continue;
+                }
+
+                component_trace!( Component::Reader, "[{}] Document {} pinned by request {} was modified.", request.correlation_id, uri, request_id );
+
+                request.result_channel.cancel( );
+                modified_requests.push( ErrorResponder {
+                    request_id     : *request_id,
+                    correlation_id : request.correlation_id,
+                    result_channel : request.result_channel.clone( )
+                } );
+            }
+        }
+
+        // Answered once the state is unlocked, the observers of the requests are called with their response
+        if self.service.config.pinned_document_policy == PinnedDocumentPolicy::ContentModified {
+            for responder in modified_requests {
+                responder.send_error( ResponseError {
+                    code    : CONTENT_MODIFIED,
+                    message : format!( "Document {} was modified", uri )
+                } );
+            }
+        }
+    }
+
+    fn complete_client_request( &mut self, response : ResponseMessage< ClientResponse > ) {
+        let response_send = {
+            let mut state = self.state.lock( ).unwrap( );
+            let outcome = match response.error {
+                Some( ref error ) => RequestOutcome::Error( error.code ),
+                None => RequestOutcome::Result
+            };
+            state.client_request_log.record_response( response.id, outcome );
+
+            state.client_requests.remove( &response.id )
+        };
+        let response_send = match response_send {
+            Some( response_send ) => response_send,
+            None => {
+                component_error!( Component::Reader, "Received response for unknown request {}.", response.id );
+
+                return;
+            }
+        };
+
+        let result = match response.error {
+            Some( error ) => Err( RequestError::Error( error ) ),
+            None => Ok( response.result )
+        };
+        response_send.complete( result );
+    }
+
+}
+
+impl < I : Io + 'static > Future for MessageReader< I > {
+
+    type Item  = ( );
+    type Error = ServiceError;
+
+    fn poll( &mut self ) -> Poll< Self::Item, Self::Error > {
+        loop {
+            if let Some( current_response ) = self.current_request.take( ) {
+                try_poll!( self.push_response_future( current_response ) );
+            }
+
+            while let Ok( Async::Ready( Some( deferred ) ) ) = self.requeue_read.poll( ) {
+                component_trace!( Component::Reader, "[{}] Retrying deferred request {}.", deferred.output.correlation_id, deferred.output.request_id );
+
+                self.dispatch_request( deferred.request, deferred.output );
+            }
+
+            {
+                let mut state = self.state.lock( ).unwrap( );
+                if state.read_pauses > 0 {
+                    state.reader_task = Some( task::current( ) );
+
+                    return Ok( Async::NotReady );
+                }
+            }
+
+            let rejected = self.rejected_requests.borrow_mut( ).pop_front( );
+            if let Some( id ) = rejected {
+                self.current_request = self.reject_request( id );
+
+                continue;
+            }
+
+            let message = match self.peeked.take( ) {
+                Some( message ) => Some( message ),
+                None => try_poll!( self.next_message( ) )
+            };
+            let ( message, received ) = match message {
+                Some( message ) => message,
+                None => {
+                    self.service.run_disconnect_hooks( );
+                    self.service.shutdown_with_reason( ShutdownReason::ClientDisconnected );
+
+                    return Ok( Async::Ready( ( ) ) );
+                }
+            };
+            // Requests skipped while decoding message were received before it, so are answered first
+            if !self.rejected_requests.borrow( ).is_empty( ) {
+                self.peeked = Some( ( message, received ) );
+
+                continue;
+            }
+            match message {
+                IncomingMessage::Request( request ) => {
+                    let correlation_id = CorrelationId::next( );
+                    component_trace!( Component::Reader, "[{}] Received request message: {:?}", correlation_id, request );
+
+                    self.service_handle.log_trace( format!( "Received request '{} - ({})'.", request.method.method_name( ), request.id ), || {
+                        format!( "{:?}", request.method )
+                    } );
+
+                    let RequestMessage{ id, method } = request;
+                    let method_name = method.method_name( );
+                    let ( result_channel, pending_response ) = self.response_queue_send.insert( id, correlation_id, method_name );
+                    {
+                        let mut state = self.state.lock( ).unwrap( );
+
+                        // Requests of the methods configured through ServiceBuilder::cancel_on_change pin the
+                        // current version of their document
+                        let mut pinned = Vec::new( );
+                        if self.service.config.cancel_on_change.contains( &method_name ) {
+                            if let Some( uri ) = method::document_uri( &method ).map( uri::normalize ) {
+                                if let Some( document ) = state.open_documents.get( &uri ) {
+                                    pinned.push( ( uri, document.version ) );
+                                }
+                            }
+                        }
+
+                        let sequence = state.next_request_seq;
+                        state.next_request_seq += 1;
+
+                        state.pending_requests.insert( id, PendingRequestState {
+                            correlation_id : correlation_id,
+                            sequence       : sequence,
+                            received       : received,
+                            dispatched     : received,
+                            result_channel : result_channel.clone( ),
+                            pinned         : pinned
+                        } );
+
+                        match method {
+                            ServerRequest::Initialize( ref params ) => {
+                                state.client_capabilities = Some( params.capabilities.clone( ) );
+                                // Applied before on_initialize, so the handler sees the level negotiated by
+                                // the client
+                                if let Some( trace ) = params.trace {
+                                    state.trace_value = trace;
+                                }
+                            },
+                            ServerRequest::Shutdown => {
+                                state.shutdown_requested = true;
+                            },
+                            _ => { }
+                        }
+                    }
+                    self.watch_slow_request( id, correlation_id );
+
+                    let output = ResponseOutput {
+                        request_id     : id,
+                        method         : method_name,
+                        correlation_id : correlation_id,
+                        on_drop        : self.service.config.dropped_response_policy,
+                        clock          : self.service.config.clock.clone( ),
+                        remote_handle  : self.service_handle.remote_handle.clone( ),
+                        result_channel : result_channel,
+                        #[cfg( feature = "leak-check" )]
+                        _pending       : self.service_handle.tasks.guard( "pending response" )
+                    };
+
+                    self.record_dispatch( id, method_name, received );
+                    self.dispatch_request( method, output );
+                    self.current_request = self.write_immediate_response( pending_response );
+                },
+                IncomingMessage::Notification( notification ) => {
+                    component_trace!( Component::Reader, "Received notification message: {:?}", notification );
+
+                    match notification.method {
+                        ServerNotification::SetTrace( ref params ) => {
+                            self.state.lock( ).unwrap( ).trace_value = params.value;
+                        },
+                        ServerNotification::Cancel( ref params ) => {
+                            self.cancel_request( params.id );
+                        },
+                        ServerNotification::DidOpenTextDocument( ref params ) => {
+                            self.state.lock( ).unwrap( ).open_documents.insert( uri::normalize( &params.text_document.uri ), OpenDocument {
+                                version     : params.text_document.version,
+                                language_id : params.text_document.language_id.clone( )
+                            } );
+                        },
+                        ServerNotification::DidChangeTextDocument( ref params ) => {
+                            if let Some( document ) = self.state.lock( ).unwrap( ).open_documents.get_mut( &uri::normalize( &params.text_document.uri ) ) {
+                                document.version = params.text_document.version;
+                            }
+                            self.document_changed( &params.text_document.uri, params.text_document.version );
+                        },
+                        ServerNotification::DidCloseTextDocument( ref params ) => {
+                            self.state.lock( ).unwrap( ).open_documents.remove( &uri::normalize( &params.text_document.uri ) );
+                        },
+                        _ => { }
+                    }
+                    self.service_handle.log_trace( format!( "Received notification '{}'.", notification.method.method_name( ) ), || {
+                        format!( "{:?}", notification.method )
+                    } );
+                    self.notify_waiters( &notification.method );
+
+                    if let ServerNotification::Initialized( .. ) = notification.method {
+                        self.message_handler.on_initialized( self.service_handle.clone( ) );
+                    }
+
+                    self.message_handler.handle_notification( self.service_handle.clone( ), notification.method );
+                },
+                IncomingMessage::Response( response ) => {
+                    component_trace!( Component::Reader, "Received response message: {:?}", response );
+
+                    self.complete_client_request( response );
+                }
+            }
+        }
+    }
+
+}
+
+impl ResponseWriter {
+
+    fn new( service_handle : ServiceHandle, state : SharedState, config : &ServiceConfig, response_queue_read : ResponseQueueRead, message_send : MessageSend ) -> Self {
+        ResponseWriter {
+            service_handle      : service_handle,
+
+            response_queue_read : response_queue_read,
+            message_send        : message_send,
+            state               : state,
+            ordering            : config.response_ordering,
+
+            pending_responses   : FuturesUnordered::new( ),
+            in_flight_limit     : QueueLimit::new( config.response_queue_capacity ),
+            completed           : BTreeMap::new( ),
+            next_queued         : 0,
+            next_written        : 0,
+            response            : None
+        }
+    }
+
+    fn poll_for_response_future( &mut self ) -> Poll< PendingResponse, ServiceError > {
+        match self.response_queue_read.poll( ) {
+            Ok( Async::Ready( Some( response_future ) ) ) => {
+                self.state.lock( ).unwrap( ).response_queue_len -= 1;
+
+                Ok( Async::Ready( response_future ) )
+            },
+            Ok( Async::Ready( None ) ) => {
+                component_error!( Component::Writer, "Response channel unexpectedly closed." );
+
+                Err( ServiceError::Unknown )
+            },
+            Ok( Async::NotReady ) => Ok( Async::NotReady ),
+            Err( _ ) => {
+                component_error!( Component::Writer, "Error reading from response queue." );
+
+                Err( ServiceError::Unknown )
+            }
+        }
+    }
+
+    /// Polls the responses of the queued requests, taking the requests queued since the last poll first as
+    /// long as the in flight limit allows. Returns NotReady once no response is ready.
+    fn poll_for_response( &mut self ) -> Poll< ( ), ServiceError > {
+        while self.in_flight_limit.has_room( self.in_flight( ) ) {
+            let request = match self.poll_for_response_future( )? {
+                Async::Ready( request ) => request,
+                Async::NotReady => break
+            };
+
+            self.pending_responses.push( QueuedResponse {
+                position : self.next_queued,
+                request  : Some( request )
+            } );
+            self.next_queued += 1;
+        }
+
+        match self.pending_responses.poll( )? {
+            Async::Ready( Some( ( position, request, response ) ) ) => {
+                let responded = self.service_handle.clock( ).now( );
+                self.completed.insert( position, ( request, response, responded ) );
+
+                Ok( Async::Ready( ( ) ) )
+            },
+            Async::Ready( None ) | Async::NotReady => Ok( Async::NotReady )
+        }
+    }
+
+    /// Returns the number of requests taken out of the response queue whose response was not written yet.
+    fn in_flight( &self ) -> usize {
+        self.pending_responses.len( ) + self.completed.len( )
+    }
+
+    /// Takes the next request whose response can be written according to the ResponseOrdering.
+    fn next_completed( &mut self ) -> Option< CompletedResponse > {
+        let position = match self.ordering {
+            ResponseOrdering::Received => self.next_written,
+            ResponseOrdering::Completion => *self.completed.keys( ).next( )?
+        };
+
+        let completed = self.completed.remove( &position )?;
+        self.next_written += 1;
+        if self.in_flight( ) == 0 {
+            self.in_flight_limit.drained( );
+        }
+
+        Some( completed )
+    }
+
+    fn finish_response( &mut self, request : PendingResponse, response : Option< ResponseMessage< ServerResponse > >, responded : Instant ) {
+        finish_request( &self.service_handle, &request, response.as_ref( ), responded );
+
+        match response {
+            Some( response ) => self.response = Some( OutgoingMessage::Response( response ) ),
+            None => self.state.lock( ).unwrap( ).queued_responses -= 1
+        }
+    }
+
+    fn write_response( &mut self, response : OutgoingServerMessage ) -> Poll< ( ), ServiceError > {
+        match self.message_send.start_send( response ) {
+            Ok( AsyncSink::Ready ) => {
+                self.state.lock( ).unwrap( ).queued_responses -= 1;
+
+                Ok( Async::Ready( ( ) ) )
+            },
+            Ok( AsyncSink::NotReady( response ) ) => {
+                self.response = Some( response );
+
+                Ok( Async::NotReady )
+            },
+            Err( _ ) => {
+                component_error!( Component::Writer, "Error writing response to write queue." );
+
+                Err( ServiceError::Unknown )
+            }
+        }
+    }
+
+}
+
+impl Future for ResponseWriter {
+
+    type Item  = ( );
+    type Error = ServiceError;
+
+    fn poll( &mut self ) -> Poll< Self::Item, Self::Error > {
+        loop {
+            if let Some( response ) = self.response.take( ) {
+                try_poll!( self.write_response( response ) );
+            }
+
+            if let Some( ( request, response, responded ) ) = self.next_completed( ) {
+                self.finish_response( request, response, responded );
+
+                continue;
+            }
+
+            try_poll!( self.poll_for_response( ) );
+        }
+    }
+
+}
+
+impl Future for QueuedResponse {
+
+    type Item  = ( u64, PendingResponse, Option< ResponseMessage< ServerResponse > > );
+    type Error = ServiceError;
+
+    fn poll( &mut self ) -> Poll< Self::Item, Self::Error > {
+        let response = match self.request.as_mut( ).unwrap( ).poll( ) {
+            Ok( Async::Ready( response ) ) => Some( response ),
+            Ok( Async::NotReady ) => return Ok( Async::NotReady ),
+            // Request dropped without a response, assume request canceled
+            Err( _ ) => None
+        };
+
+        Ok( Async::Ready( ( self.position, self.request.take( ).unwrap( ), response ) ) )
+    }
+
+}
+
+impl MessageSend {
+
+    /// Polls for room in the write queue, scheduling the current task to be notified once a message was
+    /// taken out of the queue.
+    fn poll_ready( &mut self ) -> Poll< ( ), ( ) > {
+        Ok( self.write_queue_send.poll_ready( ) )
+    }
+
+    /// Sends the messages in order, written straight to the transport with a single flush when possible.
+    /// Ready once every message was sent, the messages that could not be sent yet are left in messages.
+    fn send_batch( &mut self, messages : &mut VecDeque< OutgoingServerMessage > ) -> Poll< ( ), ( ) > {
+        self.sink.borrow_mut( ).write_direct_batch( messages );
+
+        while let Some( message ) = messages.pop_front( ) {
+            if let AsyncSink::NotReady( message ) = self.start_send( message )? {
+                messages.push_front( message );
+
+                return Ok( Async::NotReady );
+            }
+        }
+
+        Ok( Async::Ready( ( ) ) )
+    }
+
 }
 
-impl < H : MessageHandler + 'static, I : Io + 'static > MessageReader< H, I > {
+impl Sink for MessageSend {
 
-    fn new( service_handle : ServiceHandle, io_read : IoRead< I >, response_queue_send : ResponseQueueSend, message_handler : H ) -> Self {
-        MessageReader {
-            service_handle      : service_handle,
+    type SinkItem  = OutgoingServerMessage;
+    type SinkError = ( );
 
-            io_read             : io_read,
-            response_queue_send : response_queue_send,
-            current_request     : None,
+    fn start_send( &mut self, message : OutgoingServerMessage ) -> StartSend< Self::SinkItem, Self::SinkError > {
+        let message = match self.sink.borrow_mut( ).write_direct( message ) {
+            Some( message ) => message,
+            None => return Ok( AsyncSink::Ready )
+        };
 
-            message_handler     : message_handler
+        if let Async::NotReady = self.poll_ready( )? {
+            return Ok( AsyncSink::NotReady( message ) );
         }
-    }
 
-    fn next_message( &mut self ) -> Poll< IncomingServerMessage, ServiceError > {
-        match self.io_read.poll( ) {
-            Ok( Async::Ready( Some( val ) ) ) => Ok( Async::Ready( val.message ) ),
-            Ok( Async::Ready( None ) ) => {
-                error!( "Incoming stream out of messages." );
-
-                Err( ServiceError::Unknown )
-            },
-            Ok( Async::NotReady ) => Ok( Async::NotReady ),
-            Err( error ) => Err( ServiceError::ReadError( Rc::new( error ) ) )
+        match self.write_queue_send.push( message ) {
+            Ok( ( ) ) => Ok( AsyncSink::Ready ),
+            Err( _ ) => Err( ( ) )
         }
     }
 
-    fn push_response_future( &mut self, response_future : ResponseChannelRead ) -> Poll< ( ), ServiceError > {
-        match self.response_queue_send.start_send( response_future ) {
-            Ok( AsyncSink::Ready ) => Ok( Async::Ready( ( ) ) ),
-            Ok( AsyncSink::NotReady( response_future ) ) => {
-                self.current_request = Some( response_future );
+    fn poll_complete( &mut self ) -> Poll< ( ), Self::SinkError > {
+        Ok( Async::Ready( ( ) ) )
+    }
 
-                Ok( Async::NotReady )
-            },
-            Err( _ ) => {
-                error!( "Error pushing response future to response channel." );
+}
 
-                Err( ServiceError::Unknown )
+impl < I : Io + 'static > MessageSink< I > {
+
+    /// Encodes message for the transport, returns the message if too many bytes are waiting to be written
+    /// already.
+    fn start_send( &mut self, message : OutgoingServerMessage ) -> Result< Option< OutgoingServerMessage >, io::Error > {
+        if self.frames.len( ) >= WRITE_BACKPRESSURE_BOUNDARY {
+            return Ok( Some( message ) );
+        }
+        component_trace!( Component::Codec, "Encoding message {:?}", message );
+
+        let response_id = match message {
+            OutgoingMessage::Response( ref response ) => Some( response.id ),
+            _ => None
+        };
+        self.frames.encode( MessageEnvelope {
+            headers : HashMap::new( ),
+            message : message
+        } )?;
+
+        if let Some( id ) = response_id {
+            if let Some( unflushed ) = self.state.lock( ).unwrap( ).unflushed_responses.remove( &id ) {
+                self.unflushed.push( unflushed );
             }
         }
-    }
 
-}
+        Ok( None )
+    }
 
-impl < H : MessageHandler + 'static, I : Io + 'static > Future for MessageReader< H, I > {
+    /// Writes the encoded messages to the transport and flushes it, recording the time the responses encoded
+    /// since the last flush took to be flushed once it completes.
+    fn poll_flush( &mut self ) -> Poll< ( ), io::Error > {
+        {
+            let mut transport = self.transport.borrow_mut( );
+            let io = transport.get_mut( );
+            while !self.frames.is_empty( ) {
+                match self.frames.write_to( io ) {
+                    Ok( _ ) => { },
+                    Err( ref error ) if error.kind( ) == io::ErrorKind::WouldBlock => return Ok( Async::NotReady ),
+                    Err( ref error ) if error.kind( ) == io::ErrorKind::Interrupted => { },
+                    Err( error ) => return Err( error )
+                }
+            }
 
-    type Item  = ( );
-    type Error = ServiceError;
+            match io.flush( ) {
+                Ok( ( ) ) => { },
+                Err( ref error ) if error.kind( ) == io::ErrorKind::WouldBlock => return Ok( Async::NotReady ),
+                Err( error ) => return Err( error )
+            }
+        }
 
-    fn poll( &mut self ) -> Poll< Self::Item, Self::Error > {
-        loop {
-            if let Some( current_response ) = self.current_request.take( ) {
-                try_poll!( self.push_response_future( current_response ) );
+        if !self.unflushed.is_empty( ) {
+            let mut state = self.state.lock( ).unwrap( );
+            for ( method, responded ) in self.unflushed.drain( .. ) {
+                state.metrics.record_latency( method, LatencyStage::Flush, self.clock.elapsed( responded ) );
             }
+        }
 
-            let message = try_poll!( self.next_message( ) );
-            match message {
-                IncomingMessage::Request( request ) => {
-                    trace!( "Received request message: {:?}", request );
+        Ok( Async::Ready( ( ) ) )
+    }
 
-                    let RequestMessage{ id, method } = request;
+    fn poll_write( &mut self, write_queue_read : &mut WriteQueueRead ) -> Poll< ( ), io::Error > {
+        self.writer_task = Some( task::current( ) );
+        if let Some( error ) = self.error.take( ) {
+            return Err( error );
+        }
 
-                    let ( response_send, response_read ) = oneshot::channel( );
-                    let output = ResponseOutput {
-                        request_id     : id,
-                        result_channel : response_send
-                    };
+        loop {
+            if let Some( message ) = self.buffered.take( ) {
+                if let Some( message ) = self.start_send( message )? {
+                    self.buffered = Some( message );
+                    try_poll!( self.poll_flush( ) );
+
+                    continue;
+                }
+            }
+
+            match write_queue_read.poll( ) {
+                Ok( Async::Ready( Some( message ) ) ) => self.buffered = Some( message ),
+                Ok( Async::Ready( None ) ) => {
+                    try_poll!( self.poll_flush( ) );
 
-                    self.message_handler.handle_request( self.service_handle.clone( ), method, output );
-                    self.current_request = Some( response_read );
+                    return Ok( Async::Ready( ( ) ) );
                 },
-                IncomingMessage::Notification( notification ) => {
-                    trace!( "Received notification message: {:?}", notification );
+                Ok( Async::NotReady ) => {
+                    try_poll!( self.poll_flush( ) );
 
-                    self.message_handler.handle_notification( self.service_handle.clone( ), notification.method );
+                    return Ok( Async::NotReady );
                 },
-                IncomingMessage::Response( response ) => {
-                    unimplemented!( );
-                }
+                Err( _ ) => return Err( io::Error::new( io::ErrorKind::Other, "Error reading from write queue." ) )
             }
         }
     }
 
-}
-
-impl ResponseWriter {
+    /// Returns true if messages can be written without going through the write queue, messages are never
+    /// written ahead of a message waiting in the write queue.
+    fn can_write_direct( &self ) -> bool {
+        self.buffered.is_none( ) && self.error.is_none( ) && !self.retrying && self.write_queue.len( ) == 0
+    }
 
-    fn new( response_queue_read : ResponseQueueRead, write_queue_send : WriteQueueSend ) -> Self {
-        ResponseWriter {
-            response_queue_read : response_queue_read,
-            write_queue_send    : write_queue_send,
+    /// Flushes the messages written directly, leaving it to the message writer to finish flushing them or
+    /// to fail the service with the error writing them.
+    fn flush_direct( &mut self ) {
+        if self.error.is_none( ) {
+            match self.poll_flush( ) {
+                Ok( Async::Ready( ( ) ) ) => return,
+                Ok( Async::NotReady ) => { },
+                Err( error ) => self.error = Some( error )
+            }
+        }
 
-            response_future     : None,
-            response            : None
+        if let Some( ref task ) = self.writer_task {
+            task.notify( );
         }
     }
 
-    fn poll_for_response_future( &mut self ) -> Poll< ResponseChannelRead, ServiceError > {
-        match self.response_queue_read.poll( ) {
-            Ok( Async::Ready( Some( response_future ) ) ) => Ok( Async::Ready( response_future ) ),
-            Ok( Async::Ready( None ) ) => {
-                error!( "Response channel unexpectedly closed." );
+}
 
-                Err( ServiceError::Unknown )
-            },
-            Ok( Async::NotReady ) => Ok( Async::NotReady ),
-            Err( _ ) => {
-                error!( "Error reading from response queue." );
+impl < I : Io + 'static > DirectWrite for MessageSink< I > {
 
-                Err( ServiceError::Unknown )
-            }
+    fn write_direct( &mut self, message : OutgoingServerMessage ) -> Option< OutgoingServerMessage > {
+        if !self.can_write_direct( ) {
+            return Some( message );
         }
-    }
 
-    fn poll_for_response( &mut self, mut response_future : ResponseChannelRead ) -> Poll< ( ), ServiceError > {
-        let response = match response_future.poll( ) {
-            Ok( Async::Ready( response ) ) => response,
-            Ok( Async::NotReady ) => {
-                self.response_future = Some( response_future );
+        let message = match self.start_send( message ) {
+            Ok( message ) => message,
+            Err( error ) => {
+                self.error = Some( error );
 
-                return Ok( Async::NotReady );
-            },
-            // Sender was dropped, assume request canceled
-            Err( _ ) => return Ok( Async::Ready( ( ) ) )
+                None
+            }
         };
+        // Also flushed when too many bytes are waiting, so the message writer finishes writing them
+        self.flush_direct( );
 
-        self.response = Some( OutgoingMessage::Response( response ) );
-        Ok( Async::Ready( ( ) ) )
+        message
     }
 
-    fn write_response( &mut self, response : OutgoingServerMessage ) -> Poll< ( ), ServiceError > {
-        match self.write_queue_send.start_send( response ) {
-            Ok( AsyncSink::Ready ) => Ok( Async::Ready( ( ) ) ),
-            Ok( AsyncSink::NotReady( response ) ) => {
-                self.response = Some( response );
+    fn write_direct_batch( &mut self, messages : &mut VecDeque< OutgoingServerMessage > ) {
+        if !self.can_write_direct( ) {
+            return;
+        }
 
-                Ok( Async::NotReady )
-            },
-            Err( _ ) => {
-                error!( "Error writing response to write queue." );
+        let mut written = false;
+        while let Some( message ) = messages.pop_front( ) {
+            match self.start_send( message ) {
+                Ok( Some( message ) ) => {
+                    messages.push_front( message );
 
-                Err( ServiceError::Unknown )
+                    break;
+                },
+                Ok( None ) => written = true,
+                Err( error ) => {
+                    self.error = Some( error );
+
+                    break;
+                }
             }
         }
+
+        // Messages left behind are waiting for the bytes already encoded to be written
+        if written || !messages.is_empty( ) || self.error.is_some( ) {
+            self.flush_direct( );
+        }
     }
 
 }
 
-impl Future for ResponseWriter {
+impl < I : Io + 'static > Future for MessageWriter< I > {
 
     type Item  = ( );
     type Error = ServiceError;
 
     fn poll( &mut self ) -> Poll< Self::Item, Self::Error > {
         loop {
-            if let Some( response_future ) = self.response_future.take( ) {
-                try_poll!( self.poll_for_response( response_future ) );
+            if let Some( mut backoff ) = self.backoff.take( ) {
+                match backoff.poll( ) {
+                    Ok( Async::Ready( ( ) ) ) => self.sink.borrow_mut( ).retrying = false,
+                    Ok( Async::NotReady ) => {
+                        self.backoff = Some( backoff );
+
+                        return Ok( Async::NotReady );
+                    },
+                    Err( error ) => {
+                        component_error!( Component::Writer, "Error waiting to retry write: {}", error );
+
+                        return Err( ServiceError::WriteError( Rc::new( error ) ) );
+                    }
+                }
             }
-            if let Some( response ) = self.response.take( ) {
-                try_poll!( self.write_response( response ) );
+
+            let error = match self.sink.borrow_mut( ).poll_write( &mut self.write_queue_read ) {
+                Ok( result ) => {
+                    self.retries = 0;
+
+                    return Ok( result );
+                },
+                Err( error ) => error
+            };
+
+            if let Some( backoff ) = self.retry_backoff( &error ) {
+                component_info!( Component::Writer, "Retrying write in {:?} after error: {}", backoff, error );
+
+                match self.clock.sleep( backoff, &self.handle ) {
+                    Ok( sleep ) => {
+                        self.retries += 1;
+                        self.backoff = Some( sleep );
+                        self.sink.borrow_mut( ).retrying = true;
+
+                        continue;
+                    },
+                    Err( timer_error ) => {
+                        component_error!( Component::Writer, "Error creating retry timer: {}", timer_error );
+                    }
+                }
+            }
+
+            component_error!( Component::Codec, "Error writing message: {}", error );
+
+            return Err( if is_disconnect( &error ) {
+                ServiceError::Disconnected( Rc::new( error ) )
             }
+            else {
+                ServiceError::WriteError( Rc::new( error ) )
+            } );
+        }
+    }
+
+}
+
+impl < I : Io + 'static > MessageWriter< I > {
+
+    /// Returns the time to wait before retrying the write that failed with error, None if it is not retried.
+    fn retry_backoff( &self, error : &io::Error ) -> Option< Duration > {
+        let policy = self.retry_policy?;
+        if self.retries >= policy.max_retries || !is_transient( error ) {
+            return None;
+        }
+        if self.sink.borrow( ).frames.is_partially_written( ) {
+            component_error!( Component::Writer, "Not retrying write failing in the middle of a message." );
 
-            self.response_future = Some( try_poll!( self.poll_for_response_future( ) ) );
+            return None;
         }
+
+        Some( policy.backoff( self.retries ) )
     }
 
 }
 
 impl CommandHandler {
 
-    fn new( service_handle : Rc< Service >, command_queue_read : CommandQueueRead, write_queue_send : WriteQueueSend ) -> Self {
+    fn new( service_handle : Rc< Service >, state : SharedState, command_queue_read : CommandQueueRead, message_send : MessageSend ) -> Self {
         CommandHandler {
             service_handle       : service_handle,
             command_queue_read   : command_queue_read,
-            write_queue_send     : write_queue_send,
+            message_send         : message_send,
+            state                : state,
+
+            next_request_id      : 0,
+            pending_messages     : VecDeque::new( )
+        }
+    }
 
-            current_notification : None
+    fn register_request( &mut self, request : ClientRequest, response_send : ClientResponseSend ) -> OutgoingServerMessage {
+        let id = self.next_request_id;
+        self.next_request_id += 1;
+
+        {
+            let mut state = self.state.lock( ).unwrap( );
+            state.client_requests.insert( id, response_send );
+            state.client_request_log.record_sent( id, request.method_name( ) );
+        }
+
+        OutgoingMessage::Request( RequestMessage {
+            id     : id,
+            method : request
+        } )
+    }
+
+    fn report_slow_request( &mut self, request_id : i64 ) {
+        if !self.state.lock( ).unwrap( ).pending_requests.contains_key( &request_id ) {
+            return;
         }
+
+        let token = NumberOrString::String( format!( "ls_service/slow-request/{}", request_id ) );
+        let ( response_send, response_read ) = oneshot::channel( );
+        let create_request = ClientRequest::WorkDoneProgressCreate( WorkDoneProgressCreateParams {
+            token : token.clone( )
+        } );
+        let message = self.register_request( create_request, response_send );
+        self.pending_messages.push_back( message );
+
+        let state = self.state.clone( );
+        let service_handle = self.service_handle.service_handle( );
+        let begin = response_read.then( move | result | {
+            if let Ok( Ok( _ ) ) = result {
+                let mut state = state.lock( ).unwrap( );
+                // The response may have been sent while the client was creating the progress token
+                if state.pending_requests.contains_key( &request_id ) {
+                    state.progress_tokens.insert( request_id, token.clone( ) );
+                    drop( state );
+
+                    service_handle.progress( token, WorkDoneProgress::Begin( WorkDoneProgressBegin {
+                        title       : "Processing request".to_string( ),
+                        cancellable : Some( false ),
+                        message     : None,
+                        percentage  : None
+                    } ) );
+                }
+            }
+
+            Ok( ( ) )
+        } );
+
+        Service::spawn_handler_future( self.service_handle.clone( ), "slow request progress", begin );
     }
 
 }
@@ -584,16 +2860,12 @@ impl Future for CommandHandler {
 
     fn poll( &mut self ) -> Poll< Self::Item, Self::Error > {
         loop {
-            if let Some( notification ) = self.current_notification.take( ) {
-                match self.write_queue_send.start_send( notification ) {
-                    Ok( AsyncSink::Ready ) => { },
-                    Ok( AsyncSink::NotReady( notification ) ) => {
-                        self.current_notification = Some( notification );
-
-                        return Ok( Async::NotReady );
-                    },
+            if !self.pending_messages.is_empty( ) {
+                match self.message_send.send_batch( &mut self.pending_messages ) {
+                    Ok( Async::Ready( ( ) ) ) => { },
+                    Ok( Async::NotReady ) => return Ok( Async::NotReady ),
                     Err( _ ) => {
-                        error!( "Error sending notification to write queue." );
+                        component_error!( Component::Commands, "Error sending message to write queue." );
 
                         return Err( ServiceError::Unknown )
                     }
@@ -601,15 +2873,19 @@ impl Future for CommandHandler {
             }
 
             let command = match self.command_queue_read.poll( ) {
-                Ok( Async::Ready( Some( command ) ) ) => command,
+                Ok( Async::Ready( Some( command ) ) ) => {
+                    self.state.lock( ).unwrap( ).command_queue_len -= 1;
+
+                    command
+                },
                 Ok( Async::Ready( None ) ) => {
-                    error!( "Unexpected end of command queue." );
+                    component_error!( Component::Commands, "Unexpected end of command queue." );
 
                     return Err( ServiceError::Unknown )
                 },
                 Ok( Async::NotReady ) => return Ok( Async::NotReady ),
                 Err( _ ) => {
-                    error!( "Error reading from command queue." );
+                    component_error!( Component::Commands, "Error reading from command queue." );
 
                     return Err( ServiceError::Unknown )
                 }
@@ -622,10 +2898,312 @@ impl Future for CommandHandler {
                     return Ok( Async::NotReady );
                 },
                 ServiceCommand::SendNotification( notification ) => {
-                    self.current_notification = Some( OutgoingMessage::Notification( NotificationMessage { method : notification } ) );
+                    self.pending_messages.push_back( OutgoingMessage::Notification( NotificationMessage { method : notification } ) );
+                },
+                ServiceCommand::SendNotifications( notifications ) => {
+                    self.pending_messages.extend( notifications.into_iter( ).map( | notification | {
+                        OutgoingMessage::Notification( NotificationMessage { method : notification } )
+                    } ) );
+                },
+                ServiceCommand::SendRequest( request, response_send ) => {
+                    let message = self.register_request( request, response_send );
+                    self.pending_messages.push_back( message );
+                },
+                ServiceCommand::ReportSlowRequest( request_id ) => {
+                    self.report_slow_request( request_id );
+                }
+            }
+        }
+    }
+
+}
+
+impl ServiceState {
+
+    fn supports_work_done_progress( &self ) -> bool {
+        self.client_capabilities.as_ref( ).map( | capabilities | {
+            capabilities.supports_work_done_progress( )
+        } ).unwrap_or( false )
+    }
+
+}
+
+/// Records the completion of a request, either with a response or by cancellation, before its response is
+/// pushed to the write queue. responded is the time the response reached the service.
+fn finish_request( service_handle : &ServiceHandle, request : &PendingResponse, response : Option< &ResponseMessage< ServerResponse > >, responded : Instant ) {
+    let progress_token = {
+        let mut state = service_handle.state.lock( ).unwrap( );
+        let pending = state.pending_requests.remove( &request.request_id );
+        if let ( Some( pending ), Some( _ ) ) = ( pending, response ) {
+            state.response_order_log.record_written( request.request_id, pending.sequence );
+
+            let latency = if responded > pending.dispatched {
+                responded.duration_since( pending.dispatched )
+            }
+            else {
+                Duration::from_secs( 0 )
+            };
+            state.metrics.record_latency( request.method, LatencyStage::Response, latency );
+            state.unflushed_responses.insert( request.request_id, ( request.method, responded ) );
+        }
+
+        match response {
+            Some( &ResponseMessage { error : Some( ref error ), .. } ) => state.metrics.record_error( request.method, error.code ),
+            Some( _ ) => state.metrics.record_success( request.method ),
+            None => state.metrics.record_cancellation( request.method )
+        }
+
+        state.progress_tokens.remove( &request.request_id )
+    };
+
+    match response {
+        Some( response ) => {
+            component_trace!( Component::Writer, "[{}] Writing response for request {}.", request.correlation_id, response.id );
+            service_handle.log_trace( format!( "Sending response '{} - ({})'.", request.method, response.id ), || {
+                format!( "{:?}", response )
+            } );
+        },
+        None => {
+            component_trace!( Component::Writer, "[{}] Request {} was canceled without a response.", request.correlation_id, request.request_id );
+        }
+    }
+
+    if let Some( token ) = progress_token {
+        service_handle.progress( token, WorkDoneProgress::End( WorkDoneProgressEnd {
+            message : None
+        } ) );
+    }
+}
+
+/// Returns true if error may go away by retrying the write that failed, see WriteRetryPolicy.
+fn is_transient( error : &io::Error ) -> bool {
+    match error.kind( ) {
+        io::ErrorKind::Interrupted |
+        io::ErrorKind::WouldBlock |
+        io::ErrorKind::TimedOut |
+        io::ErrorKind::BrokenPipe |
+        io::ErrorKind::ConnectionReset |
+        io::ErrorKind::ConnectionAborted |
+        io::ErrorKind::NotConnected => true,
+        _ => false
+    }
+}
+
+/// Returns true if error means the client closed its end of the transport.
+fn is_disconnect( error : &io::Error ) -> bool {
+    match error.kind( ) {
+        io::ErrorKind::BrokenPipe |
+        io::ErrorKind::ConnectionReset |
+        io::ErrorKind::ConnectionAborted |
+        io::ErrorKind::NotConnected |
+        io::ErrorKind::UnexpectedEof => true,
+        _ => false
+    }
+}
+
+#[cfg( test )]
+mod tests {
+
+    use lsp_rs::{
+        INVALID_REQUEST,
+        ServerNotification,
+        ServerRequest,
+        ServerResponse,
+        Url
+    };
+    use mock_client::{
+        MockClient,
+        MockError
+    };
+    use queue::{
+        QueueCapacity
+    };
+    use serde_json::{
+        Value
+    };
+    use spy::{
+        HandlerSpy,
+        SpyOutcome
+    };
+    use std::sync::{
+        Arc,
+        Mutex
+    };
+    use super::{
+        CONTENT_MODIFIED,
+        MessageHandler,
+        ResponseOrdering,
+        ResponseOutput,
+        ServiceHandle
+    };
+    use testing;
+
+    /// MessageHandler holding on to the ResponseOutput of every request, so the test answers them in any
+    /// order once the handler returned
+    #[derive( Clone, Default )]
+    struct DeferredHandler {
+        outputs : Arc< Mutex< Vec< ResponseOutput > > >
+    }
+
+    impl MessageHandler for DeferredHandler {
+
+        fn handle_request( &self, _ : ServiceHandle, _ : ServerRequest, output : ResponseOutput ) {
+            self.outputs.lock( ).unwrap( ).push( output );
+        }
+
+        fn handle_notification( &self, _ : ServiceHandle, _ : ServerNotification ) {
+        }
+
+    }
+
+    /// MessageHandler pinning every request to version 1 of file:///workspace/main.rs, holding on to its
+    /// ResponseOutput without answering it
+    #[derive( Default )]
+    struct PinningHandler {
+        outputs : Arc< Mutex< Vec< ResponseOutput > > >
+    }
+
+    impl MessageHandler for PinningHandler {
+
+        fn handle_request( &self, service : ServiceHandle, _ : ServerRequest, output : ResponseOutput ) {
+            service.pin_document( output.request_id( ), Url::parse( "file:///workspace/main.rs" ).unwrap( ), 1 );
+            self.outputs.lock( ).unwrap( ).push( output );
+        }
+
+        fn handle_notification( &self, _ : ServiceHandle, _ : ServerNotification ) {
+        }
+
+    }
+
+    /// MessageHandler deferring every request but shutdown through ServiceHandle::defer_request, counting the
+    /// requests it was called with
+    #[derive( Clone, Default )]
+    struct DeferringHandler {
+        calls : Arc< Mutex< usize > >
+    }
+
+    impl MessageHandler for DeferringHandler {
+
+        fn handle_request( &self, service : ServiceHandle, request : ServerRequest, output : ResponseOutput ) {
+            match request {
+                ServerRequest::Shutdown => output.send_result( ServerResponse::Shutdown ),
+                request => {
+                    *self.calls.lock( ).unwrap( ) += 1;
+                    service.defer_request( request, output );
                 }
             }
         }
+
+        fn handle_notification( &self, _ : ServiceHandle, _ : ServerNotification ) {
+        }
+
+    }
+
+    impl DeferredHandler {
+
+        fn answer( &self, id : i64 ) {
+            let output = {
+                let mut outputs = self.outputs.lock( ).unwrap( );
+                let position = outputs.iter( ).position( | output | output.request_id( ) == id ).unwrap( );
+
+                outputs.remove( position )
+            };
+
+            output.send_result( ServerResponse::Shutdown );
+        }
+
+    }
+
+    /// Sends requests 1 to 3, answers them in the given order and returns the ids of the responses in the
+    /// order the client received them.
+    fn response_order( ordering : ResponseOrdering, answers : &[ i64 ] ) -> Vec< i64 > {
+        let handler = DeferredHandler::default( );
+        let mut client = MockClient::with_builder( handler.clone( ), | builder | {
+            builder.response_ordering( ordering ).response_queue_capacity( QueueCapacity::Bounded( 2 ) )
+        } ).unwrap( );
+
+        for id in 1..4 {
+            client.send_message( &testing::hover( id ) ).unwrap( );
+        }
+        testing::run_until_stalled( &mut client );
+        assert_eq!( handler.outputs.lock( ).unwrap( ).len( ), 3 );
+
+        for &id in answers {
+            handler.answer( id );
+            testing::run_until_stalled( &mut client );
+        }
+
+        client.transcript( ).iter( )
+            .filter( | message | message.get( "method" ).is_none( ) )
+            .filter_map( | message | message[ "id" ].as_i64( ) )
+            .collect( )
+    }
+
+    #[test]
+    fn responses_are_written_in_the_order_requests_were_received( ) {
+        assert_eq!( response_order( ResponseOrdering::Received, &[ 3, 2, 1 ] ), vec![ 1, 2, 3 ] );
+    }
+
+    #[test]
+    fn responses_are_written_as_they_complete_within_the_in_flight_limit( ) {
+        // Only requests 1 and 2 are in flight, the completed response of request 3 waits for one of them
+        assert_eq!( response_order( ResponseOrdering::Completion, &[ 3, 2, 1 ] ), vec![ 2, 3, 1 ] );
+    }
+
+    #[test]
+    fn slow_request_does_not_hold_back_later_responses( ) {
+        assert_eq!( response_order( ResponseOrdering::Completion, &[ 2, 1 ] ), vec![ 2, 1 ] );
+    }
+
+    #[test]
+    fn requests_on_modified_documents_are_answered_through_their_observers( ) {
+        let spy = HandlerSpy::new( PinningHandler::default( ) );
+        let log = spy.log( );
+        let mut client = MockClient::new( spy ).unwrap( );
+
+        client.send_message( &json!( {
+            "jsonrpc" : "2.0",
+            "method"  : "textDocument/didOpen",
+            "params"  : {
+                "textDocument" : { "uri" : "file:///workspace/main.rs", "languageId" : "rust", "version" : 1, "text" : "" }
+            }
+        } ) ).unwrap( );
+        client.send_message( &testing::hover( 1 ) ).unwrap( );
+        client.send_message( &json!( {
+            "jsonrpc" : "2.0",
+            "method"  : "textDocument/didChange",
+            "params"  : {
+                "textDocument"   : { "uri" : "file:///workspace/main.rs", "version" : 2 },
+                "contentChanges" : [ { "text" : "fn main( ) { }" } ]
+            }
+        } ) ).unwrap( );
+
+        match client.response::< Value >( 1 ) {
+            Err( MockError::Response( ref error ) ) => assert_eq!( error.code, CONTENT_MODIFIED ),
+            _ => panic!( "Request on a modified document was not answered with CONTENT_MODIFIED" )
+        }
+        match log.requests( )[ 0 ].outcome {
+            SpyOutcome::Error( code, _ ) => assert_eq!( code, CONTENT_MODIFIED ),
+            ref outcome => panic!( "Spy recorded {:?}", outcome )
+        }
+    }
+
+    #[test]
+    fn deferred_requests_retried_after_shutdown_are_rejected( ) {
+        let handler = DeferringHandler::default( );
+        let mut client = MockClient::new( handler.clone( ) ).unwrap( );
+
+        client.send_message( &testing::hover( 1 ) ).unwrap( );
+        client.send_message( &json!( { "jsonrpc" : "2.0", "id" : 2, "method" : "shutdown" } ) ).unwrap( );
+        assert_eq!( client.response::< Value >( 2 ).unwrap( ), Value::Null );
+        assert_eq!( *handler.calls.lock( ).unwrap( ), 1 );
+
+        client.service( ).retry_deferred( );
+        match client.response::< Value >( 1 ) {
+            Err( MockError::Response( ref error ) ) => assert_eq!( error.code, INVALID_REQUEST ),
+            _ => panic!( "Deferred request retried after shutdown was not rejected" )
+        }
+        assert_eq!( *handler.calls.lock( ).unwrap( ), 1 );
     }
 
-}
\ No newline at end of file
+}
@@ -0,0 +1,152 @@
+
+use futures::sync::{
+    mpsc
+};
+use logging::{
+    Component
+};
+use lsp_rs::{
+    ServerNotification
+};
+use serde::{
+    Serialize
+};
+use serde::de::{
+    DeserializeOwned
+};
+use serde_json::{
+    self,
+    Value
+};
+use std::sync::{
+    Arc,
+    Mutex
+};
+
+/// Typed settings of the server, kept up to date from workspace/didChangeConfiguration notifications
+///
+/// The settings start from the defaults given to Settings::new. The payload of every didChangeConfiguration
+/// notification is merged into the current settings, so a client sending only the settings that changed
+/// does not reset the others, before being deserialized into T. Components subscribe to the settings
+/// through watch instead of polling them. The settings are cheap to clone, clones share the same values:
+///
+/// ```ignore
+/// #[derive( Clone, Default, Deserialize, PartialEq, Serialize )]
+/// struct LintSettings {
+///     enabled   : bool,
+///     max_lines : usize
+/// }
+///
+/// let settings = Settings::new( Some( "mylang.lint" ), LintSettings::default( ) );
+///
+/// // In handle_notification
+/// settings.observe( &notification );
+///
+/// // In a component
+/// let changes = settings.watch( ).for_each( | lint_settings | { ... } );
+/// ```
+pub struct Settings< T > {
+    inner : Arc< Mutex< SettingsInner< T > > >
+}
+
+struct SettingsInner< T > {
+    section  : Vec< String >,
+    raw      : Value,
+    current  : T,
+    watchers : Vec< mpsc::UnboundedSender< T > >
+}
+
+impl < T > Settings< T > where T : Serialize + DeserializeOwned + Clone {
+
+    /// Creates the settings read from section of the didChangeConfiguration payloads, starting from
+    /// defaults.
+    ///
+    /// The section is a dotted path into the payload, e.g. `"mylang.lint"` reads the settings from
+    /// `{ "mylang" : { "lint" : { ... } } }`. Without a section the whole payload is read.
+    pub fn new( section : Option< &str >, defaults : T ) -> Self {
+        let raw = serde_json::to_value( &defaults ).unwrap_or( Value::Null );
+
+        Settings {
+            inner : Arc::new( Mutex::new( SettingsInner {
+                section  : section.map( | section | section.split( '.' ).map( String::from ).collect( ) ).unwrap_or_else( Vec::new ),
+                raw      : raw,
+                current  : defaults,
+                watchers : Vec::new( )
+            } ) )
+        }
+    }
+
+    /// Returns the current settings.
+    pub fn get( &self ) -> T {
+        self.inner.lock( ).unwrap( ).current.clone( )
+    }
+
+    /// Returns a stream of the settings, yielding the new settings every time they change.
+    pub fn watch( &self ) -> mpsc::UnboundedReceiver< T > {
+        let ( settings_send, settings_read ) = mpsc::unbounded( );
+        self.inner.lock( ).unwrap( ).watchers.push( settings_send );
+
+        settings_read
+    }
+
+    /// Merges the payload of a didChangeConfiguration notification into the settings.
+    ///
+    /// Returns true if the settings changed. If the merged settings cannot be deserialized into T, the
+    /// settings are left unchanged and the error is returned.
+    pub fn update( &self, payload : &Value ) -> Result< bool, serde_json::Error > {
+        let mut inner = self.inner.lock( ).unwrap( );
+
+        let mut update = payload;
+        for key in &inner.section {
+            update = match update.get( key ) {
+                Some( value ) => value,
+                None => return Ok( false )
+            };
+        }
+
+        let mut raw = inner.raw.clone( );
+        merge( &mut raw, update );
+        if raw == inner.raw {
+            return Ok( false );
+        }
+
+        let current = serde_json::from_value::< T >( raw.clone( ) )?;
+        inner.raw = raw;
+        inner.current = current.clone( );
+        inner.watchers.retain( | watcher | watcher.unbounded_send( current.clone( ) ).is_ok( ) );
+
+        Ok( true )
+    }
+
+    /// Updates the settings from a didChangeConfiguration notification, ignoring other notifications.
+    pub fn observe( &self, notification : &ServerNotification ) {
+        if let ServerNotification::DidChangeConfiguration( ref params ) = *notification {
+            if let Err( error ) = self.update( &params.settings ) {
+                component_error!( Component::Document, "Error reading settings from didChangeConfiguration: {}", error );
+            }
+        }
+    }
+
+}
+
+impl < T > Clone for Settings< T > {
+
+    fn clone( &self ) -> Self {
+        Settings {
+            inner : self.inner.clone( )
+        }
+    }
+
+}
+
+/// Merges update into base, recursively merging objects and replacing any other value.
+fn merge( base : &mut Value, update : &Value ) {
+    match ( base, update ) {
+        ( &mut Value::Object( ref mut base ), &Value::Object( ref update ) ) => {
+            for ( key, value ) in update {
+                merge( base.entry( key.clone( ) ).or_insert( Value::Null ), value );
+            }
+        },
+        ( base, update ) => *base = update.clone( )
+    }
+}
@@ -0,0 +1,403 @@
+
+use clock::{
+    Clock,
+    Sleep
+};
+use futures::{
+    Async,
+    Future
+};
+use futures::task;
+use std::collections::{
+    VecDeque
+};
+use std::io::{
+    self,
+    Read,
+    Write
+};
+use std::time::{
+    Duration,
+    Instant
+};
+use tokio_core::io::{
+    Io
+};
+use tokio_core::reactor::{
+    Handle
+};
+
+/// Characteristics of one direction of a link simulated by a ShapedStream
+#[derive( Clone, Debug )]
+pub struct LinkShape {
+    latency          : Duration,
+    jitter           : Duration,
+    bytes_per_second : Option< u64 >,
+    seed             : u64
+}
+
+/// Wrapper around the Io of a service simulating a slow link to a remote client, for testing the
+/// backpressure of the write queue and the notification dropping policies
+///
+/// Bytes go through each direction of the link after its latency plus a random jitter, without being
+/// reordered, at most at the throughput of the direction. Writes fail with WouldBlock once the throughput
+/// is exhausted, and flushes until every written byte went through the link, as they would with a slow
+/// socket. Timers run on the Clock of the stream, so a ManualClock can drive the link in tests.
+///
+/// ```ignore
+/// let shape = LinkShape::new( )
+///     .latency( Duration::from_millis( 80 ) )
+///     .jitter( Duration::from_millis( 20 ) )
+///     .bytes_per_second( 64 * 1024 );
+///
+/// let ( server_io, client_io ) = transport::memory_transport( );
+/// let service = ServiceBuilder::new( core.handle( ) ).start( handler, ShapedStream::new( server_io, core.handle( ), shape ) );
+/// ```
+pub struct ShapedStream< S > {
+    inner    : S,
+    handle   : Handle,
+    clock    : Clock,
+    incoming : Link,
+    outgoing : Link,
+    eof      : bool
+}
+
+/// State of one direction of a ShapedStream
+struct Link {
+    shape        : LinkShape,
+    /// Bytes in flight with the time they come out of the link
+    chunks       : VecDeque< ( Instant, Vec< u8 > ) >,
+    last_release : Option< Instant >,
+    budget       : f64,
+    last_refill  : Option< Instant >,
+    rng          : u64,
+    timer        : Option< Sleep >
+}
+
+impl LinkShape {
+
+    /// Creates a shape without latency, jitter or throughput cap.
+    pub fn new( ) -> Self {
+        LinkShape {
+            latency          : Duration::from_secs( 0 ),
+            jitter           : Duration::from_secs( 0 ),
+            bytes_per_second : None,
+            seed             : 0x2545_f491_4f6c_dd1d
+        }
+    }
+
+    /// Sets the time bytes take to go through the link.
+    pub fn latency( mut self, latency : Duration ) -> Self {
+        self.latency = latency;
+
+        self
+    }
+
+    /// Sets the maximum random delay added to the latency of every chunk of bytes.
+    pub fn jitter( mut self, jitter : Duration ) -> Self {
+        self.jitter = jitter;
+
+        self
+    }
+
+    /// Caps the throughput of the link.
+    pub fn bytes_per_second( mut self, bytes_per_second : u64 ) -> Self {
+        self.bytes_per_second = Some( bytes_per_second.max( 1 ) );
+
+        self
+    }
+
+    /// Sets the seed of the jitter, so a run can be reproduced.
+    pub fn seed( mut self, seed : u64 ) -> Self {
+        self.seed = seed.max( 1 );
+
+        self
+    }
+
+}
+
+impl Default for LinkShape {
+
+    fn default( ) -> Self {
+        LinkShape::new( )
+    }
+
+}
+
+impl < S > ShapedStream< S > {
+
+    /// Wraps inner, shaping both directions of the link with shape. Timers run on the event loop of handle.
+    pub fn new( inner : S, handle : Handle, shape : LinkShape ) -> Self {
+        ShapedStream::with_shapes( inner, handle, shape.clone( ), shape )
+    }
+
+    /// Wraps inner, shaping the bytes read from inner with incoming and the bytes written to inner with
+    /// outgoing.
+    pub fn with_shapes( inner : S, handle : Handle, incoming : LinkShape, outgoing : LinkShape ) -> Self {
+        ShapedStream {
+            inner    : inner,
+            handle   : handle,
+            clock    : Clock::system( ),
+            incoming : Link::new( incoming ),
+            outgoing : Link::new( outgoing ),
+            eof      : false
+        }
+    }
+
+    /// Sets the clock timing the link, e.g. the clock of a ManualClock.
+    pub fn clock( mut self, clock : Clock ) -> Self {
+        self.clock = clock;
+
+        self
+    }
+
+    /// Returns the number of written bytes that did not go through the link yet.
+    pub fn bytes_in_flight( &self ) -> usize {
+        self.outgoing.in_flight( )
+    }
+
+    pub fn get_ref( &self ) -> &S {
+        &self.inner
+    }
+
+}
+
+impl Link {
+
+    fn new( shape : LinkShape ) -> Self {
+        let rng = shape.seed;
+
+        Link {
+            shape        : shape,
+            chunks       : VecDeque::new( ),
+            last_release : None,
+            budget       : 0.0,
+            last_refill  : None,
+            rng          : rng,
+            timer        : None
+        }
+    }
+
+    fn in_flight( &self ) -> usize {
+        self.chunks.iter( ).map( | &( _, ref bytes ) | bytes.len( ) ).sum( )
+    }
+
+    /// Returns the number of bytes, at most len, the throughput of the link lets through at now.
+    fn allowance( &mut self, now : Instant, len : usize ) -> usize {
+        let rate = match self.shape.bytes_per_second {
+            Some( rate ) => rate as f64,
+            None => return len
+        };
+
+        // The link bursts at most a tenth of a second of bytes
+        let capacity = ( rate / 10.0 ).max( 1.0 );
+        let elapsed = match self.last_refill {
+            Some( last_refill ) if now > last_refill => duration_secs( now.duration_since( last_refill ) ),
+            Some( _ ) => 0.0,
+            None => capacity / rate
+        };
+        self.budget = ( self.budget + elapsed * rate ).min( capacity );
+        self.last_refill = Some( now );
+
+        ( self.budget.floor( ) as usize ).min( len )
+    }
+
+    fn consume( &mut self, count : usize ) {
+        if self.shape.bytes_per_second.is_some( ) {
+            self.budget -= count as f64;
+        }
+    }
+
+    /// Returns when the throughput of the link lets a byte through again.
+    fn next_allowance( &self, now : Instant ) -> Instant {
+        match self.shape.bytes_per_second {
+            Some( rate ) => now + secs_duration( ( 1.0 - self.budget ).max( 0.0 ) / rate as f64 ),
+            None => now
+        }
+    }
+
+    /// Sends bytes through the link at now.
+    fn push( &mut self, now : Instant, bytes : Vec< u8 > ) {
+        let mut release = now + self.shape.latency + self.next_jitter( );
+        // Bytes are never reordered by the jitter
+        if let Some( last_release ) = self.last_release {
+            if last_release > release {
+                release = last_release;
+            }
+        }
+        self.last_release = Some( release );
+
+        self.chunks.push_back( ( release, bytes ) );
+    }
+
+    /// Takes up to len bytes that went through the link at now.
+    fn pop( &mut self, now : Instant, len : usize ) -> Vec< u8 > {
+        let mut bytes = Vec::new( );
+        while bytes.len( ) < len {
+            let due = self.chunks.front( ).map( | &( release, _ ) | release <= now ).unwrap_or( false );
+            if !due {
+                break;
+            }
+
+            let ( release, mut chunk ) = self.chunks.pop_front( ).unwrap( );
+            let take = ( len - bytes.len( ) ).min( chunk.len( ) );
+            bytes.extend( chunk.drain( ..take ) );
+            if !chunk.is_empty( ) {
+                self.chunks.push_front( ( release, chunk ) );
+            }
+        }
+
+        bytes
+    }
+
+    /// Returns bytes taken by pop that could not be used to the front of the link.
+    fn unpop( &mut self, now : Instant, bytes : Vec< u8 > ) {
+        if !bytes.is_empty( ) {
+            self.chunks.push_front( ( now, bytes ) );
+        }
+    }
+
+    fn next_release( &self ) -> Option< Instant > {
+        self.chunks.front( ).map( | &( release, _ ) | release )
+    }
+
+    /// Wakes the current task at the given time.
+    fn wake_at( &mut self, clock : &Clock, handle : &Handle, at : Instant ) -> io::Result< ( ) > {
+        let mut timer = clock.sleep( clock.until( at ), handle )?;
+        if let Async::Ready( ( ) ) = timer.poll( )? {
+            task::current( ).notify( );
+        }
+        self.timer = Some( timer );
+
+        Ok( ( ) )
+    }
+
+    fn next_jitter( &mut self ) -> Duration {
+        if self.shape.jitter == Duration::from_secs( 0 ) {
+            return self.shape.jitter;
+        }
+
+        // xorshift64, good enough to spread the delays of the chunks
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+
+        let fraction = ( self.rng >> 11 ) as f64 / ( 1u64 << 53 ) as f64;
+        secs_duration( duration_secs( self.shape.jitter ) * fraction )
+    }
+
+}
+
+impl < S : Write > ShapedStream< S > {
+
+    /// Writes the written bytes that went through the link to inner.
+    fn deliver( &mut self ) -> io::Result< ( ) > {
+        let now = self.clock.now( );
+        loop {
+            let bytes = self.outgoing.pop( now, usize::max_value( ) );
+            if bytes.is_empty( ) {
+                return Ok( ( ) );
+            }
+
+            match self.inner.write( &bytes ) {
+                Ok( count ) if count < bytes.len( ) => {
+                    self.outgoing.unpop( now, bytes[ count.. ].to_vec( ) );
+                },
+                Ok( _ ) => { },
+                Err( error ) => {
+                    self.outgoing.unpop( now, bytes );
+
+                    return Err( error );
+                }
+            }
+        }
+    }
+
+}
+
+impl < S : Read > Read for ShapedStream< S > {
+
+    fn read( &mut self, buf : &mut [ u8 ] ) -> io::Result< usize > {
+        let now = self.clock.now( );
+
+        // Every byte available from inner enters the link right away
+        let mut chunk = [ 0; 4096 ];
+        while !self.eof {
+            match self.inner.read( &mut chunk ) {
+                Ok( 0 ) => self.eof = true,
+                Ok( count ) => self.incoming.push( now, chunk[ ..count ].to_vec( ) ),
+                Err( ref error ) if error.kind( ) == io::ErrorKind::WouldBlock => break,
+                Err( error ) => return Err( error )
+            }
+        }
+
+        let allowance = self.incoming.allowance( now, buf.len( ) );
+        let bytes = self.incoming.pop( now, allowance );
+        if !bytes.is_empty( ) {
+            self.incoming.consume( bytes.len( ) );
+            buf[ ..bytes.len( ) ].copy_from_slice( &bytes );
+
+            return Ok( bytes.len( ) );
+        }
+
+        let wake = match self.incoming.next_release( ) {
+            Some( release ) if allowance == 0 => release.max( self.incoming.next_allowance( now ) ),
+            Some( release ) => release,
+            None if self.eof => return Ok( 0 ),
+            // Inner registered the task to be woken when bytes are available
+            None => return Err( io::Error::new( io::ErrorKind::WouldBlock, "No bytes available" ) )
+        };
+        self.incoming.wake_at( &self.clock, &self.handle, wake )?;
+
+        Err( io::Error::new( io::ErrorKind::WouldBlock, "Bytes in flight" ) )
+    }
+
+}
+
+impl < S : Write > Write for ShapedStream< S > {
+
+    fn write( &mut self, buf : &[ u8 ] ) -> io::Result< usize > {
+        self.deliver( )?;
+
+        let now = self.clock.now( );
+        let allowance = self.outgoing.allowance( now, buf.len( ) );
+        if allowance == 0 && !buf.is_empty( ) {
+            let wake = self.outgoing.next_allowance( now );
+            self.outgoing.wake_at( &self.clock, &self.handle, wake )?;
+
+            return Err( io::Error::new( io::ErrorKind::WouldBlock, "Link throughput exhausted" ) );
+        }
+
+        self.outgoing.consume( allowance );
+        self.outgoing.push( now, buf[ ..allowance ].to_vec( ) );
+
+        Ok( allowance )
+    }
+
+    fn flush( &mut self ) -> io::Result< ( ) > {
+        self.deliver( )?;
+
+        match self.outgoing.next_release( ) {
+            Some( release ) => {
+                self.outgoing.wake_at( &self.clock, &self.handle, release )?;
+
+                Err( io::Error::new( io::ErrorKind::WouldBlock, "Bytes in flight" ) )
+            },
+            None => self.inner.flush( )
+        }
+    }
+
+}
+
+impl < S : Io > Io for ShapedStream< S > {
+}
+
+fn duration_secs( duration : Duration ) -> f64 {
+    duration.as_secs( ) as f64 + duration.subsec_nanos( ) as f64 / 1_000_000_000.0
+}
+
+fn secs_duration( secs : f64 ) -> Duration {
+    let whole = secs.trunc( );
+
+    Duration::new( whole as u64, ( ( secs - whole ) * 1_000_000_000.0 ) as u32 )
+}
@@ -0,0 +1,212 @@
+
+use lsp_rs::{
+    InitializeParams,
+    InitializeResult,
+    ServerNotification,
+    ServerRequest
+};
+use method::{
+    MethodName
+};
+use service::{
+    MessageHandler,
+    ResponseOutput,
+    ServiceHandle,
+    ShutdownReason
+};
+use std::sync::{
+    Arc,
+    Mutex
+};
+use std::time::{
+    Duration,
+    Instant
+};
+
+/// Message dispatched to the handler wrapped by a HandlerSpy
+#[derive( Clone, Debug, PartialEq, Eq )]
+pub enum InvocationKind {
+    Request( i64 ),
+    Notification
+}
+
+/// Response sent for a request recorded by a HandlerSpy
+#[derive( Clone, Debug, PartialEq, Eq )]
+pub enum SpyOutcome {
+    /// No response was sent yet, always the outcome of notifications
+    Pending,
+    /// The request was answered with a result, formatted with Debug
+    Result( String ),
+    /// The request was answered with an error
+    Error( i64, String )
+}
+
+/// Call of handle_request or handle_notification recorded by a HandlerSpy
+#[derive( Clone, Debug )]
+pub struct Invocation {
+    pub kind     : InvocationKind,
+    pub method   : &'static str,
+    /// The request or notification formatted with Debug
+    pub params   : String,
+    pub received : Instant,
+    /// Time between the call and the response being sent, None while pending
+    pub duration : Option< Duration >,
+    pub outcome  : SpyOutcome
+}
+
+/// Log of the invocations recorded by a HandlerSpy, shared with the spy
+#[derive( Clone, Default )]
+pub struct SpyLog {
+    invocations : Arc< Mutex< Vec< Invocation > > >
+}
+
+/// Handler wrapper recording every request and notification dispatched to the wrapped handler, with the
+/// response sent for requests, so tests can verify the dispatch of routers and middlewares
+///
+/// ```ignore
+/// let spy = HandlerSpy::new( router );
+/// let log = spy.log( );
+/// let mut client = MockClient::new( spy )?;
+/// client.request::< HoverRequest, Value >( params )?;
+///
+/// assert_eq!( log.methods( ), vec![ "textDocument/hover" ] );
+/// assert!( log.requests( )[ 0 ].duration.is_some( ) );
+/// ```
+pub struct HandlerSpy< H > {
+    inner : H,
+    log   : SpyLog
+}
+
+impl SpyLog {
+
+    pub fn new( ) -> Self {
+        SpyLog::default( )
+    }
+
+    /// Returns every invocation in the order they were dispatched.
+    pub fn invocations( &self ) -> Vec< Invocation > {
+        self.invocations.lock( ).unwrap( ).clone( )
+    }
+
+    /// Returns the request invocations in the order they were dispatched.
+    pub fn requests( &self ) -> Vec< Invocation > {
+        self.filter( | invocation | invocation.kind != InvocationKind::Notification )
+    }
+
+    /// Returns the notification invocations in the order they were dispatched.
+    pub fn notifications( &self ) -> Vec< Invocation > {
+        self.filter( | invocation | invocation.kind == InvocationKind::Notification )
+    }
+
+    /// Returns the invocations of method in the order they were dispatched.
+    pub fn calls( &self, method : &str ) -> Vec< Invocation > {
+        self.filter( | invocation | invocation.method == method )
+    }
+
+    /// Returns the methods of the invocations in the order they were dispatched.
+    pub fn methods( &self ) -> Vec< &'static str > {
+        self.invocations.lock( ).unwrap( ).iter( ).map( | invocation | invocation.method ).collect( )
+    }
+
+    pub fn clear( &self ) {
+        self.invocations.lock( ).unwrap( ).clear( );
+    }
+
+    fn filter< F >( &self, predicate : F ) -> Vec< Invocation > where F : Fn( &Invocation ) -> bool {
+        self.invocations.lock( ).unwrap( ).iter( ).filter( | invocation | predicate( invocation ) ).cloned( ).collect( )
+    }
+
+    fn push( &self, invocation : Invocation ) -> usize {
+        let mut invocations = self.invocations.lock( ).unwrap( );
+        invocations.push( invocation );
+
+        invocations.len( ) - 1
+    }
+
+}
+
+impl < H : MessageHandler > HandlerSpy< H > {
+
+    pub fn new( inner : H ) -> Self {
+        HandlerSpy {
+            inner : inner,
+            log   : SpyLog::new( )
+        }
+    }
+
+    /// Returns the log of the spy, to keep before handing the spy to a service.
+    pub fn log( &self ) -> SpyLog {
+        self.log.clone( )
+    }
+
+}
+
+impl < H : MessageHandler > MessageHandler for HandlerSpy< H > {
+
+    fn handle_request( &self, service : ServiceHandle, request : ServerRequest, mut output : ResponseOutput ) {
+        let clock = service.clock( ).clone( );
+        let received = clock.now( );
+        let index = self.log.push( Invocation {
+            kind     : InvocationKind::Request( output.request_id( ) ),
+            method   : output.method( ),
+            params   : format!( "{:?}", request ),
+            received : received,
+            duration : None,
+            outcome  : SpyOutcome::Pending
+        } );
+
+        let log = self.log.clone( );
+        output.observe_response( move | response | {
+            let outcome = match ( &response.result, &response.error ) {
+                ( _, &Some( ref error ) ) => SpyOutcome::Error( error.code, error.message.clone( ) ),
+                ( &Some( ref result ), &None ) => SpyOutcome::Result( format!( "{:?}", result ) ),
+                ( &None, &None ) => SpyOutcome::Result( String::new( ) )
+            };
+
+            if let Some( invocation ) = log.invocations.lock( ).unwrap( ).get_mut( index ) {
+                invocation.duration = Some( clock.elapsed( received ) );
+                invocation.outcome = outcome;
+            }
+        } );
+
+        self.inner.handle_request( service, request, output )
+    }
+
+    fn handle_notification( &self, service : ServiceHandle, notification : ServerNotification ) {
+        self.log.push( Invocation {
+            kind     : InvocationKind::Notification,
+            method   : notification.method_name( ),
+            params   : format!( "{:?}", notification ),
+            received : service.clock( ).now( ),
+            duration : None,
+            outcome  : SpyOutcome::Pending
+        } );
+
+        self.inner.handle_notification( service, notification )
+    }
+
+    fn claims_request( &self, request : &ServerRequest ) -> bool {
+        self.inner.claims_request( request )
+    }
+
+    fn claims_notification( &self, notification : &ServerNotification ) -> bool {
+        self.inner.claims_notification( notification )
+    }
+
+    fn on_initialize( &self, service : ServiceHandle, params : &InitializeParams ) -> Option< InitializeResult > {
+        self.inner.on_initialize( service, params )
+    }
+
+    fn on_initialized( &self, service : ServiceHandle ) {
+        self.inner.on_initialized( service )
+    }
+
+    fn on_shutdown_request( &self, service : ServiceHandle ) -> bool {
+        self.inner.on_shutdown_request( service )
+    }
+
+    fn on_shutdown( &self, service : ServiceHandle, reason : &ShutdownReason ) {
+        self.inner.on_shutdown( service, reason )
+    }
+
+}
@@ -0,0 +1,212 @@
+
+use futures::{
+    Future,
+    Poll
+};
+use std::collections::{
+    HashMap
+};
+use std::sync::{
+    Arc,
+    Mutex
+};
+
+/// Internal tasks and pending responses of a service still alive, see ServiceHandle::live_tasks
+#[derive( Clone, Debug, PartialEq, Eq )]
+pub struct LiveTask {
+    /// Name of the task, e.g. "message reader"
+    pub name  : &'static str,
+    /// Number of tasks of this name still alive
+    pub count : usize
+}
+
+/// Counts the tasks spawned by a service whose future was not dropped yet
+#[derive( Clone, Default )]
+pub(crate) struct TaskTracker {
+    live : Arc< Mutex< HashMap< &'static str, usize > > >
+}
+
+/// Future of a tracked task, counted as alive until dropped
+pub(crate) struct Tracked< F > {
+    future : F,
+    _guard : TaskGuard
+}
+
+/// Counts a task as alive until dropped
+pub(crate) struct TaskGuard {
+    name    : &'static str,
+    tracker : TaskTracker
+}
+
+impl TaskTracker {
+
+    pub fn new( ) -> Self {
+        TaskTracker::default( )
+    }
+
+    /// Wraps the future of a task about to be spawned.
+    pub fn track< F : Future >( &self, name : &'static str, future : F ) -> Tracked< F > {
+        Tracked {
+            future : future,
+            _guard : self.guard( name )
+        }
+    }
+
+    /// Counts a task of the given name as alive until the returned guard is dropped, for state that outlives
+    /// the future it was created by, e.g. the ResponseOutput of a request.
+    pub fn guard( &self, name : &'static str ) -> TaskGuard {
+        *self.live.lock( ).unwrap( ).entry( name ).or_insert( 0 ) += 1;
+
+        TaskGuard {
+            name    : name,
+            tracker : self.clone( )
+        }
+    }
+
+    /// Returns the tasks still alive, sorted by name.
+    pub fn live( &self ) -> Vec< LiveTask > {
+        let mut live : Vec< LiveTask > = self.live.lock( ).unwrap( ).iter( ).map( | ( &name, &count ) | LiveTask {
+            name  : name,
+            count : count
+        } ).collect( );
+        live.sort_by_key( | task | task.name );
+
+        live
+    }
+
+}
+
+impl < F : Future > Future for Tracked< F > {
+
+    type Item  = F::Item;
+    type Error = F::Error;
+
+    fn poll( &mut self ) -> Poll< Self::Item, Self::Error > {
+        self.future.poll( )
+    }
+
+}
+
+impl Drop for TaskGuard {
+
+    fn drop( &mut self ) {
+        let mut live = self.tracker.live.lock( ).unwrap( );
+        let remaining = match live.get_mut( self.name ) {
+            Some( count ) => {
+                *count -= 1;
+
+                *count
+            },
+            None => return
+        };
+        if remaining == 0 {
+            live.remove( self.name );
+        }
+    }
+
+}
+
+#[cfg( test )]
+mod tests {
+
+    use futures::future;
+    use lsp_rs::{
+        ServerNotification,
+        ServerRequest
+    };
+    use mock_client::{
+        MockClient
+    };
+    use serde_json::{
+        Value
+    };
+    use service::{
+        MessageHandler,
+        ResponseOutput,
+        ServiceHandle
+    };
+    use std::cell::{
+        RefCell
+    };
+    use std::rc::{
+        Rc
+    };
+    use super::{
+        LiveTask,
+        TaskTracker
+    };
+    use testing::{
+        self,
+        NullHandler
+    };
+
+    /// MessageHandler keeping the output of every request without ever answering it
+    struct HoldingHandler {
+        held : Rc< RefCell< Vec< ResponseOutput > > >
+    }
+
+    impl MessageHandler for HoldingHandler {
+
+        fn handle_request( &self, _ : ServiceHandle, _ : ServerRequest, output : ResponseOutput ) {
+            self.held.borrow_mut( ).push( output );
+        }
+
+        fn handle_notification( &self, _ : ServiceHandle, _ : ServerNotification ) {
+        }
+
+    }
+
+    #[test]
+    fn tracked_future_is_alive_until_dropped( ) {
+        let tracker = TaskTracker::new( );
+        let first = tracker.track( "leaked", future::empty::< ( ), ( ) >( ) );
+        let second = tracker.track( "leaked", future::empty::< ( ), ( ) >( ) );
+        let guard = tracker.guard( "guarded" );
+
+        assert_eq!( tracker.live( ), vec![
+            LiveTask { name : "guarded", count : 1 },
+            LiveTask { name : "leaked", count : 2 }
+        ] );
+
+        drop( first );
+        drop( guard );
+        assert_eq!( tracker.live( ), vec![ LiveTask { name : "leaked", count : 1 } ] );
+
+        drop( second );
+        assert_eq!( tracker.live( ), vec![ ] );
+    }
+
+    #[test]
+    fn clean_shutdown_leaves_no_live_task( ) {
+        let mut client = MockClient::new( NullHandler ).unwrap( );
+
+        client.send_message( &testing::hover( 1 ) ).unwrap( );
+        let result : Value = client.response( 1 ).unwrap( );
+        assert_eq!( result, Value::Null );
+        assert!( client.service( ).live_tasks( ).iter( ).any( | task | task.name == "message reader" ) );
+
+        client.service( ).shutdown( );
+        testing::run_until_stalled( &mut client );
+
+        assert_eq!( client.service( ).live_tasks( ), vec![ ] );
+    }
+
+    #[test]
+    fn unanswered_request_is_reported_after_shutdown( ) {
+        let held = Rc::new( RefCell::new( Vec::new( ) ) );
+        let mut client = MockClient::new( HoldingHandler {
+            held : held.clone( )
+        } ).unwrap( );
+
+        client.send_message( &testing::hover( 1 ) ).unwrap( );
+        testing::run_until_stalled( &mut client );
+        client.service( ).shutdown( );
+        testing::run_until_stalled( &mut client );
+
+        assert_eq!( client.service( ).live_tasks( ), vec![ LiveTask { name : "pending response", count : 1 } ] );
+
+        held.borrow_mut( ).clear( );
+        assert_eq!( client.service( ).live_tasks( ), vec![ ] );
+    }
+
+}
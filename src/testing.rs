@@ -0,0 +1,119 @@
+
+use lsp_rs::{
+    ServerNotification,
+    ServerRequest,
+    ServerResponse
+};
+use mock_client::{
+    MockClient,
+    MockError
+};
+use serde_json::{
+    Value
+};
+use service::{
+    MessageHandler,
+    ResponseOutput,
+    ServiceError,
+    ServiceHandle,
+    ShutdownReason
+};
+use std::sync::{
+    Arc,
+    Mutex
+};
+use std::sync::atomic::{
+    AtomicBool,
+    Ordering
+};
+use transport;
+
+/// How a service shut down, recorded by watch_shutdown
+#[derive( Clone, Copy, Debug, PartialEq, Eq )]
+pub enum Outcome {
+    Requested,
+    ClientDisconnected,
+    ReadError,
+    WriteError,
+    Disconnected,
+    ProtocolError,
+    Unknown
+}
+
+/// MessageHandler answering every request with a null result and ignoring notifications
+pub struct NullHandler;
+
+impl MessageHandler for NullHandler {
+
+    fn handle_request( &self, _ : ServiceHandle, _ : ServerRequest, output : ResponseOutput ) {
+        output.send_result( ServerResponse::Shutdown );
+    }
+
+    fn handle_notification( &self, _ : ServiceHandle, _ : ServerNotification ) {
+    }
+
+}
+
+impl < 'a > From< &'a ShutdownReason > for Outcome {
+
+    fn from( reason : &'a ShutdownReason ) -> Self {
+        match *reason {
+            ShutdownReason::Requested => Outcome::Requested,
+            ShutdownReason::ClientDisconnected => Outcome::ClientDisconnected,
+            ShutdownReason::Error( ServiceError::ReadError( _ ) ) => Outcome::ReadError,
+            ShutdownReason::Error( ServiceError::WriteError( _ ) ) => Outcome::WriteError,
+            ShutdownReason::Error( ServiceError::Disconnected( _ ) ) => Outcome::Disconnected,
+            ShutdownReason::Error( ServiceError::ProtocolError( _ ) ) => Outcome::ProtocolError,
+            ShutdownReason::Error( ServiceError::Unknown ) => Outcome::Unknown
+        }
+    }
+
+}
+
+/// Returns the outcome of the service of client once it shuts down, None while it is running.
+pub fn watch_shutdown( client : &MockClient ) -> Arc< Mutex< Option< Outcome > > > {
+    let outcome = Arc::new( Mutex::new( None ) );
+    let hook_outcome = outcome.clone( );
+    client.service( ).on_shutdown( move | reason, _ | {
+        *hook_outcome.lock( ).unwrap( ) = Some( Outcome::from( reason ) );
+    } );
+
+    outcome
+}
+
+/// Returns true once the disconnect hooks of the service of client ran.
+pub fn watch_disconnect( client : &MockClient ) -> Arc< AtomicBool > {
+    let disconnected = Arc::new( AtomicBool::new( false ) );
+    let hook_disconnected = disconnected.clone( );
+    client.service( ).on_disconnect( move | | {
+        hook_disconnected.store( true, Ordering::SeqCst );
+    } );
+
+    disconnected
+}
+
+/// Runs the service of client until it stalls, the service closing the transport is not an error.
+pub fn run_until_stalled( client : &mut MockClient ) {
+    match client.run_until_stalled( ) {
+        Ok( _ ) | Err( MockError::Disconnected ) => { },
+        Err( error ) => panic!( "Error running the service: {}", error )
+    }
+}
+
+/// Returns a textDocument/hover request with the given id.
+pub fn hover( id : i64 ) -> Value {
+    json!( {
+        "jsonrpc" : "2.0",
+        "id"      : id,
+        "method"  : "textDocument/hover",
+        "params"  : {
+            "textDocument" : { "uri" : "file:///workspace/main.rs" },
+            "position"     : { "line" : 0, "character" : 0 }
+        }
+    } )
+}
+
+/// Returns the length of message once framed, to schedule faults at message boundaries.
+pub fn framed_len( message : &Value ) -> usize {
+    transport::frame_message( message ).len( )
+}
@@ -0,0 +1,312 @@
+
+use futures::task::{
+    self,
+    Task
+};
+use memchr::{
+    self,
+    memmem
+};
+use serde_json::{
+    self,
+    Value
+};
+use std::collections::{
+    VecDeque
+};
+use std::io::{
+    self,
+    IoSlice,
+    Read,
+    Write
+};
+use std::sync::{
+    Arc,
+    Mutex
+};
+use tokio_core::io::{
+    Io
+};
+
+/// Size of the buffer the Content-Length header of a message is formatted in, enough for any length
+pub(crate) const HEADER_CAPACITY : usize = 64;
+
+/// End of an in-memory connection created by memory_transport
+///
+/// Bytes written to one end are read from the other end. Reading an end with no pending bytes fails with
+/// WouldBlock and wakes the reading task once bytes are written, so an end can be given to a service as its
+/// Io. Reading must therefore happen from within a task, e.g. a future run by a Core. Reading an end returns
+/// 0 bytes once the other end is dropped and all pending bytes were read.
+pub struct MemoryStream {
+    incoming : Arc< Mutex< Pipe > >,
+    outgoing : Arc< Mutex< Pipe > >
+}
+
+/// Bytes flowing in one direction of a memory transport
+#[derive( Default )]
+struct Pipe {
+    buffer : VecDeque< u8 >,
+    reader : Option< Task >,
+    closed : bool
+}
+
+/// Creates an in-memory connection, returning its two ends. One end is usually given to a service while
+/// the other end plays the client, e.g. in tests or benchmarks:
+///
+/// ```ignore
+/// let ( server_io, client_io ) = transport::memory_transport( );
+/// let service = ServiceBuilder::new( core.handle( ) ).start( handler, server_io );
+/// ```
+pub fn memory_transport( ) -> ( MemoryStream, MemoryStream ) {
+    let first = Arc::new( Mutex::new( Pipe::default( ) ) );
+    let second = Arc::new( Mutex::new( Pipe::default( ) ) );
+
+    let first_end = MemoryStream {
+        incoming : first.clone( ),
+        outgoing : second.clone( )
+    };
+    let second_end = MemoryStream {
+        incoming : second,
+        outgoing : first
+    };
+
+    ( first_end, second_end )
+}
+
+/// Encodes a JSON-RPC message with the Content-Length header expected by the service.
+pub fn frame_message( message : &Value ) -> Vec< u8 > {
+    let body = serde_json::to_vec( message ).expect( "Serializing a JSON value cannot fail" );
+    let mut header = [ 0; HEADER_CAPACITY ];
+    let header_len = format_header( &mut header, body.len( ) );
+
+    let mut framed = Vec::with_capacity( header_len + body.len( ) );
+    framed.extend_from_slice( &header[ ..header_len ] );
+    framed.extend( body );
+
+    framed
+}
+
+/// Writes a JSON-RPC message with the Content-Length header expected by the service to writer.
+///
+/// The header and the body are handed to the writer as separate buffers through a vectored write instead
+/// of being concatenated first, so transports supporting vectored IO write both without copying the body.
+pub fn write_message< W : Write >( writer : &mut W, message : &Value ) -> io::Result< ( ) > {
+    let body = serde_json::to_vec( message ).expect( "Serializing a JSON value cannot fail" );
+    let mut header = [ 0; HEADER_CAPACITY ];
+    let header_len = format_header( &mut header, body.len( ) );
+
+    write_frame( writer, &header[ ..header_len ], &body )
+}
+
+/// Writes header then body to writer with vectored writes, until both were entirely written.
+fn write_frame< W : Write >( writer : &mut W, mut header : &[ u8 ], mut body : &[ u8 ] ) -> io::Result< ( ) > {
+    while !header.is_empty( ) {
+        let written = match writer.write_vectored( &[ IoSlice::new( header ), IoSlice::new( body ) ] ) {
+            Ok( 0 ) => return Err( io::Error::new( io::ErrorKind::WriteZero, "Failed to write the whole message" ) ),
+            Ok( written ) => written,
+            Err( ref error ) if error.kind( ) == io::ErrorKind::Interrupted => continue,
+            Err( error ) => return Err( error )
+        };
+
+        if written < header.len( ) {
+            header = &header[ written.. ];
+        }
+        else {
+            body = &body[ written - header.len( ).. ];
+            header = &[ ];
+        }
+    }
+
+    writer.write_all( body )
+}
+
+/// Formats the Content-Length header of a body of length bytes into buffer, returning the length of the
+/// header.
+pub(crate) fn format_header( buffer : &mut [ u8; HEADER_CAPACITY ], length : usize ) -> usize {
+    let mut cursor = &mut buffer[ .. ];
+    write!( cursor, "Content-Length: {}\r\n\r\n", length ).expect( "Header buffer holds any length" );
+
+    HEADER_CAPACITY - cursor.len( )
+}
+
+/// Removes the first message framed with a Content-Length header from buffer, None if it was not entirely
+/// received yet.
+///
+/// The bytes of a message whose body is not valid JSON are removed before the error is returned, so the
+/// next message can still be decoded.
+pub fn take_message( buffer : &mut Vec< u8 > ) -> Result< Option< Value >, String > {
+    let ( body_start, end ) = match frame_bounds( buffer )? {
+        Some( bounds ) => bounds,
+        None => return Ok( None )
+    };
+
+    let message = serde_json::from_slice( &buffer[ body_start..end ] ).map_err( | error | error.to_string( ) );
+    buffer.drain( ..end );
+
+    message.map( Some )
+}
+
+/// Returns the length of the first message framed with a Content-Length header in buffer, header included,
+/// None if it was not entirely received yet, so messages can be skipped without copying their body.
+pub fn frame_length( buffer : &[ u8 ] ) -> Result< Option< usize >, String > {
+    Ok( frame_bounds( buffer )?.map( | ( _, end ) | end ) )
+}
+
+/// Returns the id of the request framed in frame, None if frame does not hold a request, e.g. to answer a
+/// request whose params could not be decoded.
+pub(crate) fn request_id( frame : &[ u8 ] ) -> Option< i64 > {
+    let ( body_start, end ) = frame_bounds( frame ).ok( )??;
+    let message : Value = serde_json::from_slice( &frame[ body_start..end ] ).ok( )?;
+    message.get( "method" )?.as_str( )?;
+
+    message.get( "id" )?.as_i64( )
+}
+
+/// Returns the start of the body and the end of the first message of buffer.
+fn frame_bounds( buffer : &[ u8 ] ) -> Result< Option< ( usize, usize ) >, String > {
+    let header_end = match memmem::find( buffer, b"\r\n\r\n" ) {
+        Some( header_end ) => header_end,
+        None => return Ok( None )
+    };
+
+    let length = match content_length( &buffer[ ..header_end ] ) {
+        Some( length ) => length,
+        None => return Err( format!( "Missing Content-Length in header {:?}", String::from_utf8_lossy( &buffer[ ..header_end ] ) ) )
+    };
+
+    let body_start = header_end + 4;
+    if buffer.len( ) < body_start + length {
+        return Ok( None );
+    }
+
+    Ok( Some( ( body_start, body_start + length ) ) )
+}
+
+/// Returns the value of the Content-Length field of header, None if it is missing or not a valid length.
+fn content_length( mut header : &[ u8 ] ) -> Option< usize > {
+    while !header.is_empty( ) {
+        let line_end = memchr::memchr( b'\n', header ).unwrap_or( header.len( ) );
+        let line = &header[ ..line_end ];
+        header = &header[ ( line_end + 1 ).min( header.len( ) ).. ];
+
+        if let Some( colon ) = memchr::memchr( b':', line ) {
+            if trim_ascii( &line[ ..colon ] ).eq_ignore_ascii_case( b"Content-Length" ) {
+                return parse_length( trim_ascii( &line[ colon + 1.. ] ) );
+            }
+        }
+    }
+
+    None
+}
+
+fn parse_length( digits : &[ u8 ] ) -> Option< usize > {
+    if digits.is_empty( ) {
+        return None;
+    }
+
+    digits.iter( ).try_fold( 0usize, | length, &digit | {
+        if !digit.is_ascii_digit( ) {
+            return None;
+        }
+
+        length.checked_mul( 10 )?.checked_add( ( digit - b'0' ) as usize )
+    } )
+}
+
+fn trim_ascii( bytes : &[ u8 ] ) -> &[ u8 ] {
+    let start = bytes.iter( ).position( | byte | !byte.is_ascii_whitespace( ) ).unwrap_or( bytes.len( ) );
+    let end = bytes.iter( ).rposition( | byte | !byte.is_ascii_whitespace( ) ).map_or( start, | end | end + 1 );
+
+    &bytes[ start..end ]
+}
+
+impl MemoryStream {
+
+    /// Returns the number of bytes written by the other end that were not read yet.
+    pub fn pending( &self ) -> usize {
+        self.incoming.lock( ).unwrap( ).buffer.len( )
+    }
+
+}
+
+impl Read for MemoryStream {
+
+    fn read( &mut self, buf : &mut [ u8 ] ) -> io::Result< usize > {
+        let mut incoming = self.incoming.lock( ).unwrap( );
+        if incoming.buffer.is_empty( ) {
+            if incoming.closed || buf.is_empty( ) {
+                return Ok( 0 );
+            }
+
+            incoming.reader = Some( task::current( ) );
+            return Err( io::Error::new( io::ErrorKind::WouldBlock, "No bytes available" ) );
+        }
+
+        let count = buf.len( ).min( incoming.buffer.len( ) );
+        for ( byte, value ) in buf.iter_mut( ).zip( incoming.buffer.drain( ..count ) ) {
+            *byte = value;
+        }
+
+        Ok( count )
+    }
+
+}
+
+impl Write for MemoryStream {
+
+    fn write( &mut self, buf : &[ u8 ] ) -> io::Result< usize > {
+        let mut outgoing = self.outgoing.lock( ).unwrap( );
+        if outgoing.closed {
+            return Err( io::Error::new( io::ErrorKind::BrokenPipe, "Other end of the transport was dropped" ) );
+        }
+
+        outgoing.buffer.extend( buf.iter( ).cloned( ) );
+        if let Some( reader ) = outgoing.reader.take( ) {
+            reader.notify( );
+        }
+
+        Ok( buf.len( ) )
+    }
+
+    fn write_vectored( &mut self, bufs : &[ IoSlice ] ) -> io::Result< usize > {
+        let mut outgoing = self.outgoing.lock( ).unwrap( );
+        if outgoing.closed {
+            return Err( io::Error::new( io::ErrorKind::BrokenPipe, "Other end of the transport was dropped" ) );
+        }
+
+        let mut written = 0;
+        for buf in bufs {
+            outgoing.buffer.extend( buf.iter( ).cloned( ) );
+            written += buf.len( );
+        }
+        if let Some( reader ) = outgoing.reader.take( ) {
+            reader.notify( );
+        }
+
+        Ok( written )
+    }
+
+    fn flush( &mut self ) -> io::Result< ( ) > {
+        Ok( ( ) )
+    }
+
+}
+
+impl Io for MemoryStream {
+}
+
+impl Drop for MemoryStream {
+
+    fn drop( &mut self ) {
+        // The other end reads the remaining bytes then end of file, and fails to write
+        for pipe in &[ &self.incoming, &self.outgoing ] {
+            let mut pipe = pipe.lock( ).unwrap( );
+            pipe.closed = true;
+
+            if let Some( reader ) = pipe.reader.take( ) {
+                reader.notify( );
+            }
+        }
+    }
+
+}
@@ -0,0 +1,296 @@
+
+use lsp_rs::{
+    Url
+};
+use std::error::{
+    Error
+};
+use std::fmt;
+use std::path::{
+    Path,
+    PathBuf
+};
+
+/// Errors returned when converting between file uris and paths
+#[derive( Clone, Debug )]
+pub enum UriError {
+    /// The uri does not use the file scheme
+    NotAFileUri( Url ),
+    /// The path of the uri is not valid percent-encoded UTF-8
+    InvalidEncoding( Url ),
+    /// The path is not absolute
+    RelativePath( PathBuf ),
+    /// The path is not valid unicode
+    NotUnicode( PathBuf )
+}
+
+/// Converts a file uri to a path on the local file system.
+///
+/// The path of the uri is percent-decoded. On Windows, drive letters, escaped or not (`file:///c%3A/src`),
+/// become drive paths (`c:\src`) and uris with a host become UNC paths (`file://server/share` to
+/// `\\server\share`). A host of `localhost` is ignored.
+pub fn to_file_path( uri : &Url ) -> Result< PathBuf, UriError > {
+    if uri.scheme( ) != "file" {
+        return Err( UriError::NotAFileUri( uri.clone( ) ) );
+    }
+
+    let path = match percent_decode( uri.path( ) ) {
+        Some( path ) => path,
+        None => return Err( UriError::InvalidEncoding( uri.clone( ) ) )
+    };
+    let host = match uri.host_str( ) {
+        Some( host ) if !host.is_empty( ) && host != "localhost" => Some( host ),
+        _ => None
+    };
+
+    Ok( native_path( host, &path ) )
+}
+
+/// Converts an absolute path to a file uri, in the form returned by normalize.
+pub fn from_file_path( path : &Path ) -> Result< Url, UriError > {
+    if !path.is_absolute( ) {
+        return Err( UriError::RelativePath( path.to_path_buf( ) ) );
+    }
+    let path_str = match path.to_str( ) {
+        Some( path_str ) => path_str,
+        None => return Err( UriError::NotUnicode( path.to_path_buf( ) ) )
+    };
+
+    let ( host, uri_path ) = uri_path( path_str );
+    match file_uri( &host, &uri_path ) {
+        Some( uri ) => Ok( uri ),
+        None => Err( UriError::NotUnicode( path.to_path_buf( ) ) )
+    }
+}
+
+/// Returns the canonical form of a file uri, so uris sent by different clients for the same file compare
+/// equal.
+///
+/// Drive letters are lowercased and unescaped (`file:///C%3A/src` becomes `file:///c:/src`) and the path
+/// is percent-encoded the same way whatever the client escaped. Uris of other schemes and uris that cannot
+/// be decoded are returned unchanged.
+pub fn normalize( uri : &Url ) -> Url {
+    if uri.scheme( ) != "file" {
+        return uri.clone( );
+    }
+
+    let path = match percent_decode( uri.path( ) ) {
+        Some( path ) => path,
+        None => return uri.clone( )
+    };
+    let host = match uri.host_str( ) {
+        Some( host ) if host != "localhost" => host.to_lowercase( ),
+        _ => String::new( )
+    };
+
+    file_uri( &host, &normalize_drive_letter( &path ) ).unwrap_or_else( | | uri.clone( ) )
+}
+
+/// Returns true if the path of a uri starts with a drive letter, e.g. `/c:/src`.
+fn has_drive_letter( path : &str ) -> bool {
+    let bytes = path.as_bytes( );
+
+    bytes.len( ) >= 3 && bytes[ 0 ] == b'/' && ( bytes[ 1 ] as char ).is_ascii_alphabetic( ) && bytes[ 2 ] == b':' &&
+        ( bytes.len( ) == 3 || bytes[ 3 ] == b'/' )
+}
+
+fn normalize_drive_letter( path : &str ) -> String {
+    if has_drive_letter( path ) {
+        format!( "/{}{}", path[ 1..2 ].to_lowercase( ), &path[ 2.. ] )
+    }
+    else {
+        path.to_string( )
+    }
+}
+
+#[cfg( windows )]
+fn native_path( host : Option< &str >, path : &str ) -> PathBuf {
+    match host {
+        Some( host ) => PathBuf::from( format!( "\\\\{}{}", host, path.replace( '/', "\\" ) ) ),
+        None if has_drive_letter( path ) => PathBuf::from( path[ 1.. ].replace( '/', "\\" ) ),
+        None => PathBuf::from( path.replace( '/', "\\" ) )
+    }
+}
+
+#[cfg( not( windows ) )]
+fn native_path( host : Option< &str >, path : &str ) -> PathBuf {
+    match host {
+        Some( host ) => PathBuf::from( format!( "//{}{}", host, path ) ),
+        None => PathBuf::from( path )
+    }
+}
+
+/// Splits an absolute native path into the host and the path of its uri.
+#[cfg( windows )]
+fn uri_path( path : &str ) -> ( String, String ) {
+    let path = path.replace( '\\', "/" );
+    if path.starts_with( "//" ) {
+        let mut parts = path[ 2.. ].splitn( 2, '/' );
+        let host = parts.next( ).unwrap_or( "" ).to_lowercase( );
+        let rest = parts.next( ).unwrap_or( "" );
+
+        return ( host, format!( "/{}", rest ) );
+    }
+
+    ( String::new( ), normalize_drive_letter( &format!( "/{}", path ) ) )
+}
+
+#[cfg( not( windows ) )]
+fn uri_path( path : &str ) -> ( String, String ) {
+    ( String::new( ), path.to_string( ) )
+}
+
+fn file_uri( host : &str, path : &str ) -> Option< Url > {
+    Url::parse( &format!( "file://{}{}", host, percent_encode( path ) ) ).ok( )
+}
+
+/// Percent-encodes the bytes of a path that may not appear unescaped in the path of a uri.
+fn percent_encode( path : &str ) -> String {
+    let mut encoded = String::with_capacity( path.len( ) );
+    for &byte in path.as_bytes( ) {
+        match byte {
+            b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' |
+            b'/' | b':' | b'@' | b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b';' | b'=' |
+            b'-' | b'.' | b'_' | b'~' => encoded.push( byte as char ),
+            _ => encoded.push_str( &format!( "%{:02X}", byte ) )
+        }
+    }
+
+    encoded
+}
+
+/// Decodes the percent-encoded path of a uri, None if it is not valid UTF-8 once decoded.
+fn percent_decode( path : &str ) -> Option< String > {
+    let bytes = path.as_bytes( );
+    let mut decoded = Vec::with_capacity( bytes.len( ) );
+
+    let mut index = 0;
+    while index < bytes.len( ) {
+        if bytes[ index ] == b'%' && index + 2 < bytes.len( ) {
+            if let ( Some( high ), Some( low ) ) = ( hex_value( bytes[ index + 1 ] ), hex_value( bytes[ index + 2 ] ) ) {
+                decoded.push( high << 4 | low );
+                index += 3;
+
+                continue;
+            }
+        }
+
+        decoded.push( bytes[ index ] );
+        index += 1;
+    }
+
+    String::from_utf8( decoded ).ok( )
+}
+
+fn hex_value( digit : u8 ) -> Option< u8 > {
+    ( digit as char ).to_digit( 16 ).map( | value | value as u8 )
+}
+
+impl fmt::Display for UriError {
+
+    fn fmt( &self, f : &mut fmt::Formatter ) -> fmt::Result {
+        match *self {
+            UriError::NotAFileUri( ref uri ) => write!( f, "{} is not a file uri", uri ),
+            UriError::InvalidEncoding( ref uri ) => write!( f, "Path of {} is not valid UTF-8", uri ),
+            UriError::RelativePath( ref path ) => write!( f, "Path {} is not absolute", path.display( ) ),
+            UriError::NotUnicode( ref path ) => write!( f, "Path {} is not valid unicode", path.display( ) )
+        }
+    }
+
+}
+
+impl Error for UriError {
+
+    fn description( &self ) -> &str {
+        match *self {
+            UriError::NotAFileUri( _ ) => "Not a file uri",
+            UriError::InvalidEncoding( _ ) => "Invalid uri encoding",
+            UriError::RelativePath( _ ) => "Relative path",
+            UriError::NotUnicode( _ ) => "Path is not unicode"
+        }
+    }
+
+}
+
+#[cfg( test )]
+mod tests {
+
+    use lsp_rs::{
+        Url
+    };
+    use std::path::{
+        Path
+    };
+    use super::{
+        UriError
+    };
+
+    fn url( uri : &str ) -> Url {
+        Url::parse( uri ).unwrap( )
+    }
+
+    fn normalized( uri : &str ) -> String {
+        super::normalize( &url( uri ) ).to_string( )
+    }
+
+    #[test]
+    fn drive_letters_are_lowercased( ) {
+        assert_eq!( normalized( "file:///C:/src/main.rs" ), "file:///c:/src/main.rs" );
+        assert_eq!( normalized( "file:///c:/src/main.rs" ), "file:///c:/src/main.rs" );
+        // Only the drive letter is lowercased
+        assert_eq!( normalized( "file:///C:/Src/Main.rs" ), "file:///c:/Src/Main.rs" );
+        assert_eq!( normalized( "file:///Cd:/src" ), "file:///Cd:/src" );
+    }
+
+    #[test]
+    fn escaped_drive_letters_are_unescaped( ) {
+        assert_eq!( normalized( "file:///c%3A/src/main.rs" ), "file:///c:/src/main.rs" );
+        assert_eq!( normalized( "file:///C%3a/src/main.rs" ), "file:///c:/src/main.rs" );
+        assert_eq!( normalized( "file:///c%3A/src/main.rs" ), normalized( "file:///C:/src/main.rs" ) );
+    }
+
+    #[test]
+    fn paths_are_escaped_the_same_way_whatever_the_client_escaped( ) {
+        assert_eq!( normalized( "file:///src/my%20file.rs" ), "file:///src/my%20file.rs" );
+        assert_eq!( normalized( "file:///src/main%2Ers" ), "file:///src/main.rs" );
+        assert_eq!( normalized( "file:///src/%C3%A9t%C3%A9.rs" ), normalized( "file:///src/été.rs" ) );
+    }
+
+    #[test]
+    fn trailing_slashes_are_kept( ) {
+        assert_eq!( normalized( "file:///src/" ), "file:///src/" );
+        assert_eq!( normalized( "file:///src" ), "file:///src" );
+        assert_eq!( normalized( "file:///C%3A/" ), "file:///c:/" );
+    }
+
+    #[test]
+    fn other_schemes_are_returned_unchanged( ) {
+        for uri in &[ "untitled:Untitled-1", "https://example.com/C%3A/src", "jar:file:///C%3A/lib.jar!/Main.class" ] {
+            assert_eq!( normalized( uri ), url( uri ).to_string( ) );
+        }
+    }
+
+    #[test]
+    fn only_absolute_file_uris_convert_to_paths( ) {
+        match super::to_file_path( &url( "untitled:Untitled-1" ) ) {
+            Err( UriError::NotAFileUri( _ ) ) => { },
+            other => panic!( "Expected NotAFileUri, got {:?}", other )
+        }
+        match super::from_file_path( Path::new( "src/main.rs" ) ) {
+            Err( UriError::RelativePath( _ ) ) => { },
+            other => panic!( "Expected RelativePath, got {:?}", other )
+        }
+    }
+
+    #[cfg( not( windows ) )]
+    #[test]
+    fn paths_round_trip_through_file_uris( ) {
+        let path = Path::new( "/src/my file.rs" );
+        let uri = super::from_file_path( path ).unwrap( );
+
+        assert_eq!( uri.as_str( ), "file:///src/my%20file.rs" );
+        assert_eq!( super::to_file_path( &uri ).unwrap( ), path );
+        assert_eq!( super::to_file_path( &url( "file://localhost/src/main.rs" ) ).unwrap( ), Path::new( "/src/main.rs" ) );
+    }
+
+}
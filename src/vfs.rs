@@ -0,0 +1,477 @@
+
+use document::{
+    DocumentSnapshot,
+    TextDocumentStore
+};
+use futures::sync::{
+    mpsc
+};
+use logging::{
+    Component
+};
+use lsp_rs::{
+    ClientCapabilities,
+    FileChangeType,
+    ServerNotification,
+    Url
+};
+#[cfg( feature = "watch" )]
+use lsp_rs::{
+    InitializeParams
+};
+use memory::{
+    MemoryBudget,
+    MemoryConsumer
+};
+#[cfg( feature = "watch" )]
+use notify::{
+    self,
+    DebouncedEvent,
+    RecommendedWatcher,
+    RecursiveMode,
+    Watcher
+};
+use std::collections::{
+    HashMap
+};
+use std::fs;
+use std::io;
+#[cfg( feature = "watch" )]
+use std::path::{
+    Path,
+    PathBuf
+};
+#[cfg( feature = "watch" )]
+use std::sync::mpsc as std_mpsc;
+use std::sync::{
+    Arc,
+    Mutex
+};
+#[cfg( feature = "watch" )]
+use std::thread;
+#[cfg( feature = "watch" )]
+use std::time::{
+    Duration
+};
+use uri;
+
+/// Virtual file system layering the documents open in the editor over the files on disk
+///
+/// Reading a file returns the content of the editor buffer while the document is open and the content on
+/// disk otherwise, so analysis code does not need to care where a file comes from. The Vfs is cheap to
+/// clone, clones share the same documents and subscribers.
+///
+/// Files read from disk can be kept in a cache bounded in bytes, see Vfs::disk_cache_limit. Cached files
+/// are dropped when a Created, Changed or Deleted event is emitted for them, the cache should only be
+/// enabled when those events are emitted, through Vfs::observe or Vfs::watch.
+#[derive( Clone )]
+pub struct Vfs {
+    documents   : TextDocumentStore,
+    disk_cache  : Arc< Mutex< DiskCache > >,
+    subscribers : Arc< Mutex< Vec< mpsc::UnboundedSender< VfsEvent > > > >
+}
+
+/// Memory held by the content of the files of a Vfs, returned by Vfs::memory_usage
+#[derive( Clone, Copy, Debug, Default, PartialEq, Eq )]
+pub struct MemoryUsage {
+    /// Bytes of text held by the documents open in the editor
+    pub open_documents   : usize,
+    /// Bytes of text held by the files cached from disk
+    pub disk_cache       : usize,
+    /// Number of files cached from disk
+    pub disk_cache_files : usize
+}
+
+/// Files read from disk, evicted in least recently used order once they hold more than limit bytes
+#[derive( Default )]
+struct DiskCache {
+    limit  : usize,
+    bytes  : usize,
+    clock  : u64,
+    files  : HashMap< Url, CachedFile >,
+    budget : Option< MemoryBudget >
+}
+
+struct CachedFile {
+    text      : Arc< String >,
+    last_used : u64
+}
+
+/// Content of a file read from the Vfs
+#[derive( Clone, Debug )]
+pub enum FileContent {
+    /// The file is open in the editor, content of the editor buffer
+    Open( DocumentSnapshot ),
+    /// The file is not open in the editor, content read from disk
+    Disk( Arc< String > )
+}
+
+/// Change to a file observed by the Vfs
+#[derive( Clone, Debug )]
+pub struct VfsEvent {
+    pub uri  : Url,
+    pub kind : VfsEventKind
+}
+
+#[derive( Clone, Copy, Debug, PartialEq, Eq )]
+pub enum VfsEventKind {
+    /// The file was opened in the editor, reads now return the editor buffer
+    Opened,
+    /// The editor buffer of an open file was edited
+    Edited,
+    /// The file was closed in the editor, reads now return the content on disk
+    Closed,
+    /// The file was created on disk
+    Created,
+    /// The file was changed on disk
+    Changed,
+    /// The file was deleted from disk
+    Deleted
+}
+
+/// Watches directories on disk, emitting the changes to their files through the Vfs it was created by
+///
+/// The changes are emitted as Created, Changed and Deleted events, the same events emitted for the
+/// didChangeWatchedFiles notification, so subscribers do not need to know which of the two is in use.
+/// Watching stops when the VfsWatcher is dropped.
+#[cfg( feature = "watch" )]
+pub struct VfsWatcher {
+    watcher : RecommendedWatcher
+}
+
+/// Returns true if the client can be asked to watch files and send didChangeWatchedFiles notifications,
+/// which requires it to support dynamic registration of the notification.
+pub fn client_watches_files( capabilities : &ClientCapabilities ) -> bool {
+    capabilities.workspace.as_ref( )
+        .and_then( | workspace | workspace.did_change_watched_files.as_ref( ) )
+        .and_then( | watched_files | watched_files.dynamic_registration )
+        .unwrap_or( false )
+}
+
+impl Vfs {
+
+    /// Creates a Vfs layering the documents of store over the files on disk.
+    pub fn new( documents : TextDocumentStore ) -> Self {
+        Vfs {
+            documents   : documents,
+            disk_cache  : Arc::new( Mutex::new( DiskCache::default( ) ) ),
+            subscribers : Arc::new( Mutex::new( Vec::new( ) ) )
+        }
+    }
+
+    /// Caches the files read from disk, holding at most limit bytes of text. Files larger than limit are
+    /// never cached. The cache is disabled by default.
+    pub fn disk_cache_limit( self, limit : usize ) -> Self {
+        {
+            let mut disk_cache = self.disk_cache.lock( ).unwrap( );
+            disk_cache.limit = limit;
+            disk_cache.evict( );
+        }
+
+        self
+    }
+
+    /// Registers the files cached from disk with budget, so the least recently used files are evicted when
+    /// the components registered with the budget exceed it. The open documents are registered through
+    /// TextDocumentStore::memory_budget.
+    pub fn memory_budget( self, budget : &MemoryBudget ) -> Self {
+        self.disk_cache.lock( ).unwrap( ).budget = Some( budget.clone( ) );
+        budget.register( "vfs disk cache", &self.disk_cache );
+
+        self
+    }
+
+    /// Returns the store holding the documents open in the editor.
+    pub fn documents( &self ) -> &TextDocumentStore {
+        &self.documents
+    }
+
+    /// Reads the file uri, from the editor buffer if the document is open and from disk otherwise.
+    pub fn read( &self, uri : &Url ) -> io::Result< FileContent > {
+        if let Some( snapshot ) = self.documents.get( uri ) {
+            return Ok( FileContent::Open( snapshot ) );
+        }
+
+        let uri = uri::normalize( uri );
+        if let Some( text ) = self.disk_cache.lock( ).unwrap( ).get( &uri ) {
+            return Ok( FileContent::Disk( text ) );
+        }
+
+        let path = uri::to_file_path( &uri ).map_err( | error | {
+            io::Error::new( io::ErrorKind::InvalidInput, error.to_string( ) )
+        } )?;
+
+        let text = Arc::new( fs::read_to_string( path )? );
+        let budget = {
+            let mut disk_cache = self.disk_cache.lock( ).unwrap( );
+            disk_cache.insert( uri, text.clone( ) );
+
+            disk_cache.budget.clone( )
+        };
+        if let Some( budget ) = budget {
+            budget.enforce( );
+        }
+
+        Ok( FileContent::Disk( text ) )
+    }
+
+    /// Returns the memory held by the open documents and the files cached from disk.
+    pub fn memory_usage( &self ) -> MemoryUsage {
+        let disk_cache = self.disk_cache.lock( ).unwrap( );
+
+        MemoryUsage {
+            open_documents   : self.documents.memory_usage( ),
+            disk_cache       : disk_cache.bytes,
+            disk_cache_files : disk_cache.files.len( )
+        }
+    }
+
+    /// Drops every file cached from disk.
+    pub fn clear_disk_cache( &self ) {
+        let mut disk_cache = self.disk_cache.lock( ).unwrap( );
+        disk_cache.files.clear( );
+        disk_cache.bytes = 0;
+    }
+
+    /// Returns a stream of the changes observed by the Vfs from now on.
+    pub fn subscribe( &self ) -> mpsc::UnboundedReceiver< VfsEvent > {
+        let ( event_send, event_read ) = mpsc::unbounded( );
+        self.subscribers.lock( ).unwrap( ).push( event_send );
+
+        event_read
+    }
+
+    /// Emits the events for a notification received from the client, ignoring notifications unrelated to
+    /// files.
+    ///
+    /// The documents are not updated by this method, the store is expected to be kept up to date by a
+    /// DocumentLayer in front of the handler.
+    pub fn observe( &self, notification : &ServerNotification ) {
+        match *notification {
+            ServerNotification::DidOpenTextDocument( ref params ) => self.emit( params.text_document.uri.clone( ), VfsEventKind::Opened ),
+            ServerNotification::DidChangeTextDocument( ref params ) => self.emit( params.text_document.uri.clone( ), VfsEventKind::Edited ),
+            ServerNotification::DidCloseTextDocument( ref params ) => self.emit( params.text_document.uri.clone( ), VfsEventKind::Closed ),
+            ServerNotification::DidChangeWatchedFiles( ref params ) => {
+                for change in &params.changes {
+                    let kind = match change.typ {
+                        FileChangeType::Created => VfsEventKind::Created,
+                        FileChangeType::Changed => VfsEventKind::Changed,
+                        FileChangeType::Deleted => VfsEventKind::Deleted
+                    };
+
+                    self.emit( change.uri.clone( ), kind );
+                }
+            },
+            _ => { }
+        }
+    }
+
+    /// Starts watching directories on disk, emitting the changes reported by the file system once no
+    /// change was reported for a file for the delay duration.
+    #[cfg( feature = "watch" )]
+    pub fn watch( &self, roots : &[ PathBuf ], delay : Duration ) -> notify::Result< VfsWatcher > {
+        let ( event_send, event_read ) = std_mpsc::channel( );
+        let mut watcher = notify::watcher( event_send, delay )?;
+        for root in roots {
+            watcher.watch( root, RecursiveMode::Recursive )?;
+        }
+
+        // The thread exits once the watcher, which owns the sending half of the channel, is dropped
+        let vfs = self.clone( );
+        thread::spawn( move | | {
+            for event in event_read {
+                vfs.emit_disk_event( event );
+            }
+        } );
+
+        Ok( VfsWatcher {
+            watcher : watcher
+        } )
+    }
+
+    /// Watches the root of the workspace on disk if the client cannot watch files itself, returning None if
+    /// the client watches files or the workspace has no root on disk.
+    ///
+    /// ```ignore
+    /// fn on_initialize( &self, service : ServiceHandle, params : &InitializeParams ) -> Option< InitializeResult > {
+    ///     *self.watcher.borrow_mut( ) = self.vfs.watch_workspace( params, Duration::from_millis( 200 ) ).ok( ).and_then( | watcher | watcher );
+    ///     ...
+    /// }
+    /// ```
+    #[cfg( feature = "watch" )]
+    pub fn watch_workspace( &self, params : &InitializeParams, delay : Duration ) -> notify::Result< Option< VfsWatcher > > {
+        if client_watches_files( &params.capabilities ) {
+            return Ok( None );
+        }
+
+        match params.root_uri.as_ref( ).and_then( | root | uri::to_file_path( root ).ok( ) ) {
+            Some( root ) => self.watch( &[ root ], delay ).map( Some ),
+            None => Ok( None )
+        }
+    }
+
+    /// Sends an event to every subscriber, dropping subscribers whose stream was dropped. The uri of the
+    /// event is normalized, see uri::normalize.
+    ///
+    /// Created, Changed and Deleted events drop the content cached from disk for the file.
+    pub fn emit( &self, uri : Url, kind : VfsEventKind ) {
+        let event = VfsEvent {
+            uri  : uri::normalize( &uri ),
+            kind : kind
+        };
+
+        match kind {
+            VfsEventKind::Created | VfsEventKind::Changed | VfsEventKind::Deleted => {
+                self.disk_cache.lock( ).unwrap( ).remove( &event.uri );
+            },
+            _ => { }
+        }
+
+        self.subscribers.lock( ).unwrap( ).retain( | subscriber | {
+            subscriber.unbounded_send( event.clone( ) ).is_ok( )
+        } );
+    }
+
+    #[cfg( feature = "watch" )]
+    fn emit_disk_event( &self, event : DebouncedEvent ) {
+        match event {
+            DebouncedEvent::Create( path ) => self.emit_path( &path, VfsEventKind::Created ),
+            DebouncedEvent::Write( path ) => self.emit_path( &path, VfsEventKind::Changed ),
+            DebouncedEvent::Remove( path ) => self.emit_path( &path, VfsEventKind::Deleted ),
+            DebouncedEvent::Rename( from, to ) => {
+                self.emit_path( &from, VfsEventKind::Deleted );
+                self.emit_path( &to, VfsEventKind::Created );
+            },
+            DebouncedEvent::Error( error, path ) => component_error!( Component::Document, "Error watching {:?}: {}", path, error ),
+            _ => { }
+        }
+    }
+
+    #[cfg( feature = "watch" )]
+    fn emit_path( &self, path : &Path, kind : VfsEventKind ) {
+        match uri::from_file_path( path ) {
+            Ok( uri ) => self.emit( uri, kind ),
+            Err( error ) => component_error!( Component::Document, "Ignoring change to {:?}: {}", path, error )
+        }
+    }
+
+}
+
+#[cfg( feature = "watch" )]
+impl VfsWatcher {
+
+    /// Starts watching another directory.
+    pub fn add( &mut self, root : &Path ) -> notify::Result< ( ) > {
+        self.watcher.watch( root, RecursiveMode::Recursive )
+    }
+
+    /// Stops watching a directory.
+    pub fn remove( &mut self, root : &Path ) -> notify::Result< ( ) > {
+        self.watcher.unwatch( root )
+    }
+
+}
+
+impl MemoryUsage {
+
+    /// Returns the bytes held by the open documents and the files cached from disk.
+    pub fn total( &self ) -> usize {
+        self.open_documents + self.disk_cache
+    }
+
+}
+
+impl DiskCache {
+
+    fn get( &mut self, uri : &Url ) -> Option< Arc< String > > {
+        self.clock += 1;
+
+        let clock = self.clock;
+        self.files.get_mut( uri ).map( | file | {
+            file.last_used = clock;
+
+            file.text.clone( )
+        } )
+    }
+
+    fn insert( &mut self, uri : Url, text : Arc< String > ) {
+        if text.len( ) > self.limit {
+            return;
+        }
+
+        self.remove( &uri );
+        self.clock += 1;
+        self.bytes += text.len( );
+        self.files.insert( uri, CachedFile {
+            text      : text,
+            last_used : self.clock
+        } );
+        self.evict( );
+    }
+
+    fn remove( &mut self, uri : &Url ) {
+        if let Some( file ) = self.files.remove( uri ) {
+            self.bytes -= file.text.len( );
+        }
+    }
+
+    /// Evicts the least recently used files until the cache holds at most limit bytes.
+    fn evict( &mut self ) {
+        let limit = self.limit;
+
+        self.evict_to( limit );
+    }
+
+    /// Evicts the least recently used files until the cache holds at most target bytes.
+    fn evict_to( &mut self, target : usize ) {
+        while self.bytes > target {
+            let oldest = self.files.iter( ).min_by_key( | &( _, file ) | file.last_used ).map( | ( uri, _ ) | uri.clone( ) );
+            match oldest {
+                Some( uri ) => {
+                    component_trace!( Component::Document, "Evicting cached content of {}.", uri );
+
+                    self.remove( &uri );
+                },
+                None => return
+            }
+        }
+    }
+
+}
+
+impl MemoryConsumer for Mutex< DiskCache > {
+
+    fn memory_usage( &self ) -> usize {
+        self.lock( ).unwrap( ).bytes
+    }
+
+    fn evict( &self, bytes : usize ) -> usize {
+        let mut disk_cache = self.lock( ).unwrap( );
+        let before = disk_cache.bytes;
+        let target = before.saturating_sub( bytes );
+        disk_cache.evict_to( target );
+
+        before - disk_cache.bytes
+    }
+
+}
+
+impl FileContent {
+
+    /// Copies the content of the file into a String.
+    pub fn text( &self ) -> String {
+        match *self {
+            FileContent::Open( ref snapshot ) => snapshot.text( ),
+            FileContent::Disk( ref text ) => ( **text ).clone( )
+        }
+    }
+
+    /// Returns the version of the editor buffer, None if the content was read from disk.
+    pub fn version( &self ) -> Option< i64 > {
+        match *self {
+            FileContent::Open( ref snapshot ) => Some( snapshot.version( ) ),
+            FileContent::Disk( _ ) => None
+        }
+    }
+
+}
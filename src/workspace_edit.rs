@@ -0,0 +1,390 @@
+
+use document::{
+    apply_edit
+};
+use lsp_rs::{
+    CreateFile,
+    DeleteFile,
+    DocumentChangeOperation,
+    DocumentChanges,
+    RenameFile,
+    ResourceOp,
+    TextDocumentEdit,
+    TextEdit,
+    Url,
+    WorkspaceEdit
+};
+use ropey::{
+    Rope
+};
+use std::collections::{
+    HashMap
+};
+use std::error::{
+    Error
+};
+use std::fmt;
+use std::io;
+use vfs::{
+    FileContent,
+    Vfs
+};
+
+/// Content of a file after a WorkspaceEdit was applied, see EditedWorkspace
+#[derive( Clone, Debug )]
+pub enum EditedFile {
+    /// The file was created or edited, holding its new content
+    Contents( Rope ),
+    /// The file was deleted
+    Deleted
+}
+
+/// Files affected by a WorkspaceEdit, with their content after the edit, returned by apply_workspace_edit
+///
+/// Files not affected by the edit are not part of the result, their content is the content read from the
+/// Vfs.
+#[derive( Clone, Debug, Default )]
+pub struct EditedWorkspace {
+    files : HashMap< Url, EditedFile >
+}
+
+/// Errors returned when a WorkspaceEdit cannot be applied
+#[derive( Debug )]
+pub enum EditError {
+    /// The edit targets a version of an open document other than its current version, or a version of a
+    /// document that is not open
+    VersionMismatch {
+        uri      : Url,
+        expected : i64,
+        current  : Option< i64 >
+    },
+    /// The edit modifies, renames or deletes a file that does not exist
+    FileNotFound( Url ),
+    /// The edit creates a file, or renames a file to a uri, that already exists without allowing overwrites
+    FileExists( Url ),
+    /// Two text edits of the same document overlap
+    OverlappingEdits( Url ),
+    /// A file could not be read
+    Io( Url, io::Error )
+}
+
+/// Applies edit to the files read from vfs, returning the content of every affected file after the edit
+/// without modifying the Vfs or the files on disk.
+///
+/// Text edits targeting a document version are only applied if the document is open at that version. The
+/// edits of a document are applied as if they were all made against its content before the edit, as the
+/// client applies them. Servers use the result to preview or validate an edit before sending it through
+/// workspace/applyEdit, the documents are updated once the client sends the matching didChange
+/// notifications.
+pub fn apply_workspace_edit( vfs : &Vfs, edit : &WorkspaceEdit ) -> Result< EditedWorkspace, EditError > {
+    let mut workspace = EditedWorkspace::default( );
+
+    match edit.document_changes {
+        Some( DocumentChanges::Edits( ref edits ) ) => {
+            for edit in edits {
+                workspace.apply_document_edit( vfs, edit )?;
+            }
+        },
+        Some( DocumentChanges::Operations( ref operations ) ) => {
+            for operation in operations {
+                match *operation {
+                    DocumentChangeOperation::Edit( ref edit ) => workspace.apply_document_edit( vfs, edit )?,
+                    DocumentChangeOperation::Op( ResourceOp::Create( ref create ) ) => workspace.create( vfs, create )?,
+                    DocumentChangeOperation::Op( ResourceOp::Rename( ref rename ) ) => workspace.rename( vfs, rename )?,
+                    DocumentChangeOperation::Op( ResourceOp::Delete( ref delete ) ) => workspace.delete( vfs, delete )?
+                }
+            }
+        },
+        // Clients supporting documentChanges ignore changes when both are set
+        None => {
+            if let Some( ref changes ) = edit.changes {
+                for ( uri, edits ) in changes {
+                    workspace.apply_text_edits( vfs, uri, edits )?;
+                }
+            }
+        }
+    }
+
+    Ok( workspace )
+}
+
+impl EditedWorkspace {
+
+    /// Returns the content of uri after the edit, None if the file is not affected by the edit.
+    pub fn get( &self, uri : &Url ) -> Option< &EditedFile > {
+        self.files.get( uri )
+    }
+
+    /// Returns every file affected by the edit.
+    pub fn files( &self ) -> &HashMap< Url, EditedFile > {
+        &self.files
+    }
+
+    fn apply_document_edit( &mut self, vfs : &Vfs, edit : &TextDocumentEdit ) -> Result< ( ), EditError > {
+        let uri = &edit.text_document.uri;
+        if let Some( expected ) = edit.text_document.version {
+            let current = vfs.documents( ).get( uri ).map( | document | document.version( ) );
+            if current != Some( expected ) {
+                return Err( EditError::VersionMismatch {
+                    uri      : uri.clone( ),
+                    expected : expected,
+                    current  : current
+                } );
+            }
+        }
+
+        self.apply_text_edits( vfs, uri, &edit.edits )
+    }
+
+    fn apply_text_edits( &mut self, vfs : &Vfs, uri : &Url, edits : &[ TextEdit ] ) -> Result< ( ), EditError > {
+        let mut text = match self.load( vfs, uri )? {
+            Some( text ) => text,
+            None => return Err( EditError::FileNotFound( uri.clone( ) ) )
+        };
+
+        // Sort by start, keeping the order of edits inserting at the same position
+        let mut edits : Vec< &TextEdit > = edits.iter( ).collect( );
+        edits.sort_by_key( | edit | ( edit.range.start.line, edit.range.start.character ) );
+        for pair in edits.windows( 2 ) {
+            let end = ( pair[ 0 ].range.end.line, pair[ 0 ].range.end.character );
+            let next_start = ( pair[ 1 ].range.start.line, pair[ 1 ].range.start.character );
+            if end > next_start {
+                return Err( EditError::OverlappingEdits( uri.clone( ) ) );
+            }
+        }
+
+        // Applying the edits from the end keeps the positions of the remaining edits valid
+        for edit in edits.iter( ).rev( ) {
+            apply_edit( &mut text, &edit.range, &edit.new_text );
+        }
+        self.files.insert( uri.clone( ), EditedFile::Contents( text ) );
+
+        Ok( ( ) )
+    }
+
+    fn create( &mut self, vfs : &Vfs, create : &CreateFile ) -> Result< ( ), EditError > {
+        let ( overwrite, ignore_if_exists ) = create.options.as_ref( ).map( | options | {
+            ( options.overwrite.unwrap_or( false ), options.ignore_if_exists.unwrap_or( false ) )
+        } ).unwrap_or( ( false, false ) );
+
+        if self.load( vfs, &create.uri )?.is_some( ) && !overwrite {
+            if ignore_if_exists {
+                return Ok( ( ) );
+            }
+
+            return Err( EditError::FileExists( create.uri.clone( ) ) );
+        }
+
+        self.files.insert( create.uri.clone( ), EditedFile::Contents( Rope::new( ) ) );
+
+        Ok( ( ) )
+    }
+
+    fn rename( &mut self, vfs : &Vfs, rename : &RenameFile ) -> Result< ( ), EditError > {
+        let ( overwrite, ignore_if_exists ) = rename.options.as_ref( ).map( | options | {
+            ( options.overwrite.unwrap_or( false ), options.ignore_if_exists.unwrap_or( false ) )
+        } ).unwrap_or( ( false, false ) );
+
+        let text = match self.load( vfs, &rename.old_uri )? {
+            Some( text ) => text,
+            None => return Err( EditError::FileNotFound( rename.old_uri.clone( ) ) )
+        };
+        if self.load( vfs, &rename.new_uri )?.is_some( ) && !overwrite {
+            if ignore_if_exists {
+                return Ok( ( ) );
+            }
+
+            return Err( EditError::FileExists( rename.new_uri.clone( ) ) );
+        }
+
+        self.files.insert( rename.old_uri.clone( ), EditedFile::Deleted );
+        self.files.insert( rename.new_uri.clone( ), EditedFile::Contents( text ) );
+
+        Ok( ( ) )
+    }
+
+    fn delete( &mut self, vfs : &Vfs, delete : &DeleteFile ) -> Result< ( ), EditError > {
+        let ignore_if_not_exists = delete.options.as_ref( ).and_then( | options | options.ignore_if_not_exists ).unwrap_or( false );
+
+        if self.load( vfs, &delete.uri )?.is_none( ) {
+            if ignore_if_not_exists {
+                return Ok( ( ) );
+            }
+
+            return Err( EditError::FileNotFound( delete.uri.clone( ) ) );
+        }
+
+        self.files.insert( delete.uri.clone( ), EditedFile::Deleted );
+
+        Ok( ( ) )
+    }
+
+    /// Returns the content of uri as modified by the previous operations of the edit, None if the file does
+    /// not exist.
+    fn load( &self, vfs : &Vfs, uri : &Url ) -> Result< Option< Rope >, EditError > {
+        match self.files.get( uri ) {
+            Some( &EditedFile::Contents( ref text ) ) => return Ok( Some( text.clone( ) ) ),
+            Some( &EditedFile::Deleted ) => return Ok( None ),
+            None => { }
+        }
+
+        match vfs.read( uri ) {
+            Ok( FileContent::Open( snapshot ) ) => Ok( Some( snapshot.rope( ).clone( ) ) ),
+            Ok( FileContent::Disk( text ) ) => Ok( Some( Rope::from_str( &text ) ) ),
+            Err( ref error ) if error.kind( ) == io::ErrorKind::NotFound => Ok( None ),
+            Err( error ) => Err( EditError::Io( uri.clone( ), error ) )
+        }
+    }
+
+}
+
+impl fmt::Display for EditError {
+
+    fn fmt( &self, f : &mut fmt::Formatter ) -> fmt::Result {
+        match *self {
+            EditError::VersionMismatch { ref uri, expected, current : Some( current ) } => {
+                write!( f, "Edit targets version {} of {} at version {}", expected, uri, current )
+            },
+            EditError::VersionMismatch { ref uri, expected, current : None } => {
+                write!( f, "Edit targets version {} of {} which is not open", expected, uri )
+            },
+            EditError::FileNotFound( ref uri ) => write!( f, "File {} does not exist", uri ),
+            EditError::FileExists( ref uri ) => write!( f, "File {} already exists", uri ),
+            EditError::OverlappingEdits( ref uri ) => write!( f, "Edits of {} overlap", uri ),
+            EditError::Io( ref uri, ref error ) => write!( f, "Error reading {}: {}", uri, error )
+        }
+    }
+
+}
+
+impl Error for EditError {
+
+    fn description( &self ) -> &str {
+        match *self {
+            EditError::VersionMismatch { .. } => "Edit targets another version of the document",
+            EditError::FileNotFound( _ ) => "File does not exist",
+            EditError::FileExists( _ ) => "File already exists",
+            EditError::OverlappingEdits( _ ) => "Overlapping edits",
+            EditError::Io( _, ref error ) => error.description( )
+        }
+    }
+
+}
+
+#[cfg( test )]
+mod tests {
+
+    use document::{
+        TextDocumentStore
+    };
+    use lsp_rs::{
+        DidOpenTextDocumentParams,
+        Position,
+        Range,
+        TextDocumentItem,
+        TextEdit,
+        Url
+    };
+    use super::{
+        EditError,
+        EditedFile,
+        EditedWorkspace
+    };
+    use vfs::{
+        Vfs
+    };
+
+    fn open( text : &str ) -> ( Vfs, Url ) {
+        let store = TextDocumentStore::new( );
+        let uri = Url::parse( "file:///workspace/main.rs" ).unwrap( );
+        store.open( &DidOpenTextDocumentParams {
+            text_document : TextDocumentItem {
+                uri         : uri.clone( ),
+                language_id : "rust".to_string( ),
+                version     : 1,
+                text        : text.to_string( )
+            }
+        } );
+
+        ( Vfs::new( store ), uri )
+    }
+
+    fn edit( start : ( u64, u64 ), end : ( u64, u64 ), text : &str ) -> TextEdit {
+        TextEdit {
+            range    : Range {
+                start : Position { line : start.0, character : start.1 },
+                end   : Position { line : end.0, character : end.1 }
+            },
+            new_text : text.to_string( )
+        }
+    }
+
+    fn apply( text : &str, edits : &[ TextEdit ] ) -> Result< String, EditError > {
+        let ( vfs, uri ) = open( text );
+        let mut workspace = EditedWorkspace::default( );
+        workspace.apply_text_edits( &vfs, &uri, edits )?;
+
+        match workspace.get( &uri ) {
+            Some( &EditedFile::Contents( ref text ) ) => Ok( text.to_string( ) ),
+            _ => panic!( "Edited document has no contents" )
+        }
+    }
+
+    #[test]
+    fn edits_are_applied_against_the_original_text( ) {
+        let edits = [
+            edit( ( 1, 0 ), ( 1, 3 ), "second" ),
+            edit( ( 0, 0 ), ( 0, 3 ), "first line" )
+        ];
+
+        assert_eq!( apply( "one\ntwo\n", &edits ).unwrap( ), "first line\nsecond\n" );
+    }
+
+    #[test]
+    fn inserts_at_the_same_position_keep_their_order( ) {
+        let edits = [
+            edit( ( 0, 1 ), ( 0, 1 ), "b" ),
+            edit( ( 0, 0 ), ( 0, 1 ), "" ),
+            edit( ( 0, 1 ), ( 0, 1 ), "c" )
+        ];
+
+        assert_eq!( apply( "ad", &edits ).unwrap( ), "bcd" );
+    }
+
+    #[test]
+    fn adjacent_edits_do_not_overlap( ) {
+        let edits = [
+            edit( ( 0, 0 ), ( 0, 2 ), "x" ),
+            edit( ( 0, 2 ), ( 0, 4 ), "y" )
+        ];
+
+        assert_eq!( apply( "abcd", &edits ).unwrap( ), "xy" );
+    }
+
+    #[test]
+    fn overlapping_edits_are_rejected( ) {
+        let edits = [
+            edit( ( 0, 2 ), ( 1, 1 ), "x" ),
+            edit( ( 0, 0 ), ( 0, 3 ), "y" )
+        ];
+
+        match apply( "abcd\nefgh", &edits ) {
+            Err( EditError::OverlappingEdits( ref uri ) ) => assert_eq!( uri.as_str( ), "file:///workspace/main.rs" ),
+            _ => panic!( "Overlapping edits were applied" )
+        }
+    }
+
+    #[test]
+    fn insert_inside_a_replaced_range_is_rejected( ) {
+        let edits = [
+            edit( ( 0, 0 ), ( 0, 4 ), "x" ),
+            edit( ( 0, 2 ), ( 0, 2 ), "y" )
+        ];
+
+        match apply( "abcd", &edits ) {
+            Err( EditError::OverlappingEdits( _ ) ) => { },
+            _ => panic!( "Overlapping edits were applied" )
+        }
+    }
+
+}
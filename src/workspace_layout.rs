@@ -0,0 +1,207 @@
+
+use futures::sync::{
+    mpsc
+};
+use logging::{
+    Component
+};
+use lsp_rs::{
+    InitializeParams,
+    ServerNotification,
+    Url,
+    WorkspaceFolder
+};
+use std::path::{
+    PathBuf
+};
+use std::sync::{
+    Arc,
+    Mutex
+};
+use uri;
+
+/// Workspace folders opened by the client, answering which folder owns a file
+///
+/// A file is owned by the innermost folder containing it, so a file of a nested folder belongs to the nested
+/// folder and not to its parent. The folders are set from the initialize request and kept up to date from
+/// workspace/didChangeWorkspaceFolders notifications. Subscribers are notified of every change to the
+/// folders, e.g. to reload the configuration scoped to a folder or to watch the files of a new folder. The
+/// layout is cheap to clone, clones share the same folders and subscribers:
+///
+/// ```ignore
+/// // In on_initialize
+/// layout.initialize( params );
+///
+/// // In handle_notification
+/// layout.observe( &notification );
+///
+/// // In a request handler
+/// let scope = layout.owner( &params.text_document.uri ).map( | folder | folder.uri );
+/// ```
+#[derive( Clone, Default )]
+pub struct WorkspaceLayout {
+    inner : Arc< Mutex< WorkspaceLayoutInner > >
+}
+
+#[derive( Default )]
+struct WorkspaceLayoutInner {
+    folders     : Vec< WorkspaceFolder >,
+    subscribers : Vec< mpsc::UnboundedSender< LayoutChange > >
+}
+
+/// Change to the workspace folders of a WorkspaceLayout
+#[derive( Clone, Debug )]
+pub struct LayoutChange {
+    /// Folders added to the workspace
+    pub added   : Vec< WorkspaceFolder >,
+    /// Folders removed from the workspace
+    pub removed : Vec< WorkspaceFolder >,
+    previous    : Vec< WorkspaceFolder >,
+    current     : Vec< WorkspaceFolder >
+}
+
+impl WorkspaceLayout {
+
+    pub fn new( ) -> Self {
+        WorkspaceLayout::default( )
+    }
+
+    /// Sets the folders of the workspace from the initialize request, the workspace folders if the client
+    /// sent any and the root uri otherwise.
+    pub fn initialize( &self, params : &InitializeParams ) {
+        let folders = match params.workspace_folders {
+            Some( ref folders ) => folders.clone( ),
+            None => params.root_uri.iter( ).map( | root | WorkspaceFolder {
+                uri  : root.clone( ),
+                name : folder_name( root )
+            } ).collect( )
+        };
+
+        self.update( folders, Vec::new( ) );
+    }
+
+    /// Returns the folders of the workspace, in the order they were added.
+    pub fn folders( &self ) -> Vec< WorkspaceFolder > {
+        self.inner.lock( ).unwrap( ).folders.clone( )
+    }
+
+    /// Returns the paths of the folders on disk, e.g. the roots to watch with Vfs::watch. Folders that are
+    /// not on disk are skipped.
+    pub fn folder_paths( &self ) -> Vec< PathBuf > {
+        self.inner.lock( ).unwrap( ).folders.iter( ).filter_map( | folder | uri::to_file_path( &folder.uri ).ok( ) ).collect( )
+    }
+
+    /// Returns the innermost folder containing uri, None if uri is outside of every folder.
+    pub fn owner( &self, uri : &Url ) -> Option< WorkspaceFolder > {
+        owner( &self.inner.lock( ).unwrap( ).folders, uri ).cloned( )
+    }
+
+    /// Adds a folder to the workspace, replacing any folder with the same uri.
+    pub fn add_folder( &self, folder : WorkspaceFolder ) {
+        self.update( vec![ folder ], Vec::new( ) );
+    }
+
+    /// Removes the folder with the given uri from the workspace.
+    pub fn remove_folder( &self, uri : &Url ) {
+        self.update( Vec::new( ), vec![ uri.clone( ) ] );
+    }
+
+    /// Returns a stream of the changes to the folders from now on.
+    pub fn subscribe( &self ) -> mpsc::UnboundedReceiver< LayoutChange > {
+        let ( change_send, change_read ) = mpsc::unbounded( );
+        self.inner.lock( ).unwrap( ).subscribers.push( change_send );
+
+        change_read
+    }
+
+    /// Updates the folders from a workspace/didChangeWorkspaceFolders notification, ignoring other
+    /// notifications.
+    pub fn observe( &self, notification : &ServerNotification ) {
+        if let ServerNotification::DidChangeWorkspaceFolders( ref params ) = *notification {
+            let removed = params.event.removed.iter( ).map( | folder | folder.uri.clone( ) ).collect( );
+
+            self.update( params.event.added.clone( ), removed );
+        }
+    }
+
+    fn update( &self, added : Vec< WorkspaceFolder >, removed : Vec< Url > ) {
+        let mut inner = self.inner.lock( ).unwrap( );
+        let previous = inner.folders.clone( );
+
+        let mut removed_folders = Vec::new( );
+        for uri in removed.iter( ).chain( added.iter( ).map( | folder | &folder.uri ) ) {
+            let uri = uri::normalize( uri );
+            if let Some( index ) = inner.folders.iter( ).position( | folder | uri::normalize( &folder.uri ) == uri ) {
+                removed_folders.push( inner.folders.remove( index ) );
+            }
+        }
+        inner.folders.extend( added.iter( ).cloned( ) );
+
+        // A folder replaced by a folder with the same uri is neither added nor removed
+        let added_folders : Vec< WorkspaceFolder > = added.into_iter( ).filter( | folder | !previous.contains( folder ) ).collect( );
+        let removed_folders : Vec< WorkspaceFolder > = removed_folders.into_iter( ).filter( | folder | !inner.folders.contains( folder ) ).collect( );
+        if added_folders.is_empty( ) && removed_folders.is_empty( ) {
+            return;
+        }
+
+        component_trace!( Component::Document, "Workspace folders changed, added {:?}, removed {:?}.", added_folders, removed_folders );
+        let change = LayoutChange {
+            added    : added_folders,
+            removed  : removed_folders,
+            previous : previous,
+            current  : inner.folders.clone( )
+        };
+        inner.subscribers.retain( | subscriber | {
+            subscriber.unbounded_send( change.clone( ) ).is_ok( )
+        } );
+    }
+
+}
+
+impl LayoutChange {
+
+    /// Returns the folder owning uri before the change.
+    pub fn previous_owner( &self, uri : &Url ) -> Option< &WorkspaceFolder > {
+        owner( &self.previous, uri )
+    }
+
+    /// Returns the folder owning uri after the change.
+    pub fn owner( &self, uri : &Url ) -> Option< &WorkspaceFolder > {
+        owner( &self.current, uri )
+    }
+
+    /// Returns true if the folder owning uri changed, e.g. because a folder nested in its previous owner was
+    /// added.
+    pub fn owner_changed( &self, uri : &Url ) -> bool {
+        self.previous_owner( uri ).map( | folder | &folder.uri ) != self.owner( uri ).map( | folder | &folder.uri )
+    }
+
+}
+
+/// Returns the innermost of folders containing uri.
+fn owner< 'a >( folders : &'a [ WorkspaceFolder ], uri : &Url ) -> Option< &'a WorkspaceFolder > {
+    let uri = uri::normalize( uri );
+
+    folders.iter( )
+        .map( | folder | ( folder, uri::normalize( &folder.uri ) ) )
+        .filter( | &( _, ref folder_uri ) | contains( folder_uri, &uri ) )
+        .max_by_key( | &( _, ref folder_uri ) | folder_uri.path( ).trim_right_matches( '/' ).len( ) )
+        .map( | ( folder, _ ) | folder )
+}
+
+/// Returns true if uri is folder or a file under folder, both uris being normalized.
+fn contains( folder : &Url, uri : &Url ) -> bool {
+    if folder.scheme( ) != uri.scheme( ) || folder.host_str( ) != uri.host_str( ) {
+        return false;
+    }
+
+    let folder_path = folder.path( ).trim_right_matches( '/' );
+    let path = uri.path( );
+
+    path.starts_with( folder_path ) && ( path.len( ) == folder_path.len( ) || path[ folder_path.len( ).. ].starts_with( '/' ) )
+}
+
+/// Returns the name of a folder created from a root uri, the last segment of its path.
+fn folder_name( root : &Url ) -> String {
+    root.path( ).trim_right_matches( '/' ).rsplit( '/' ).next( ).unwrap_or( "" ).to_string( )
+}